@@ -35,7 +35,17 @@ fn main() {
 
     // Import only SongData for generated code (other types use full path)
     writeln!(out, "use e_midi_shared::types::SongData;\n").unwrap();
+    // Song indices whose original SMF bytes are embedded
+    let mut midi_data_indices = Vec::new();
     for song in &midi_songs {
+        // The original file, for what the note arrays leave out (tempo map, signatures)
+        writeln!(
+            out,
+            "static SONG_{}_MIDI_DATA: &[u8] = include_bytes!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \"/midi/\", {:?}));",
+            song_idx, song.filename
+        )
+        .unwrap();
+        midi_data_indices.push(song_idx);
         // Compute duration_ms for MIDI: max end time of all notes
         let mut max_end = 0u32;
         for track in &song.track_notes {
@@ -419,6 +429,15 @@ fn main() {
     writeln!(out, "        _ => None,").unwrap();
     writeln!(out, "    }}\n}}\n").unwrap();
 
+    writeln!(out, "/// Returns the original Standard MIDI File bytes for a static MIDI song index\npub fn get_embedded_midi_bytes(song_index: usize) -> Option<&'static [u8]> {{
+    match song_index {{
+").unwrap();
+    for idx in &midi_data_indices {
+        writeln!(out, "        {} => Some(SONG_{}_MIDI_DATA),", idx, idx).unwrap();
+    }
+    writeln!(out, "        _ => None,").unwrap();
+    writeln!(out, "    }}\n}}\n").unwrap();
+
     use std::fs::OpenOptions;
     use std::io::Write;
     let debug_path = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
@@ -0,0 +1,473 @@
+//! Beats and bars of a song.
+//!
+//! `BeatGrid` places every beat of a song on its playback timeline: bars come
+//! from the file's time signature meta events (4/4 when there are none), and
+//! beats are spaced by the file's tempo map (tempo meta events), scaled so the
+//! song's opening tempo plays at the playback tempo. Playback builds a grid for
+//! the tempo it plays at and walks it from the start position, so beats stay in
+//! step after tempo changes and seeks.
+//!
+//! Notes still play at one tempo for the whole song, so in a file whose tempo
+//! changes the beats follow the written tempo map rather than the notes.
+
+use crate::{MidiPlayer, SongInfo};
+use e_midi_shared::ipc_protocol::MidiBeatEvent;
+use midly::{MetaMessage, Smf, TrackEventKind};
+
+/// Tick resolution used when a song doesn't say
+const DEFAULT_TICKS_PER_Q: u32 = 480;
+
+/// Tempo of a file with no tempo meta event before its first change (120 BPM)
+const DEFAULT_USEC_PER_Q: u32 = 500_000;
+
+/// A time signature taking effect at a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeterChange {
+    pub tick: u64,
+    pub beats_per_bar: u8,
+    pub beat_unit: u8,
+}
+
+/// A tempo taking effect at a tick, in microseconds per quarter note
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TempoChange {
+    pub tick: u64,
+    pub usec_per_q: u32,
+}
+
+/// A beat on the song timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beat {
+    pub tick: u64,
+    pub position_ms: u32,
+    /// Bar number, counting from 1
+    pub bar: u32,
+    /// Beat within the bar, counting from 1
+    pub beat: u8,
+    pub beats_per_bar: u8,
+    pub beat_unit: u8,
+    /// Playback tempo at this beat
+    pub tempo_bpm: u32,
+}
+
+/// Meter in effect from `tick`, whose first bar is `bar`
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    meter: MeterChange,
+    bar: u32,
+}
+
+/// Playback tempo in effect from `tick`, which plays at `start_us`
+#[derive(Debug, Clone, Copy)]
+struct TempoSegment {
+    tick: u64,
+    start_us: u64,
+    usec_per_q: u64,
+}
+
+/// Bars and beats of one song at one playback tempo
+#[derive(Debug, Clone)]
+pub struct BeatGrid {
+    tempo_bpm: u32,
+    ticks_per_q: u32,
+    segments: Vec<Segment>,
+    tempos: Vec<TempoSegment>,
+}
+
+impl BeatGrid {
+    /// Grid at one constant tempo
+    pub fn new(tempo_bpm: u32, ticks_per_q: u32, meters: Vec<MeterChange>) -> Self {
+        Self::with_tempo_map(tempo_bpm, ticks_per_q, meters, Vec::new())
+    }
+
+    /// Grid following a tempo map, scaled so its opening tempo plays at `tempo_bpm`
+    pub fn with_tempo_map(
+        tempo_bpm: u32,
+        ticks_per_q: u32,
+        mut meters: Vec<MeterChange>,
+        mut tempo_map: Vec<TempoChange>,
+    ) -> Self {
+        meters.retain(|m| m.beats_per_bar > 0 && m.beat_unit.is_power_of_two());
+        meters.sort_by_key(|m| m.tick);
+        // A later change at the same tick wins
+        meters.dedup_by(|later, earlier| {
+            let same = later.tick == earlier.tick;
+            if same {
+                *earlier = *later;
+            }
+            same
+        });
+        if meters.first().map_or(true, |m| m.tick > 0) {
+            meters.insert(
+                0,
+                MeterChange {
+                    tick: 0,
+                    beats_per_bar: 4,
+                    beat_unit: 4,
+                },
+            );
+        }
+        let ticks_per_q = ticks_per_q.max(1);
+        let mut segments: Vec<Segment> = Vec::with_capacity(meters.len());
+        for meter in meters {
+            let bar = match segments.last() {
+                Some(prev) => {
+                    let beat_len = beat_ticks(ticks_per_q, prev.meter.beat_unit);
+                    let beats = (meter.tick - prev.meter.tick).div_ceil(beat_len);
+                    // A change in the middle of a bar starts a new one
+                    prev.bar + beats.div_ceil(prev.meter.beats_per_bar as u64) as u32
+                }
+                None => 1,
+            };
+            segments.push(Segment { meter, bar });
+        }
+
+        let tempo_bpm = tempo_bpm.max(1);
+        tempo_map.retain(|t| t.usec_per_q > 0);
+        tempo_map.sort_by_key(|t| t.tick);
+        tempo_map.dedup_by(|later, earlier| {
+            let same = later.tick == earlier.tick;
+            if same {
+                *earlier = *later;
+            }
+            same
+        });
+        if tempo_map.first().map_or(true, |t| t.tick > 0) {
+            tempo_map.insert(
+                0,
+                TempoChange {
+                    tick: 0,
+                    usec_per_q: DEFAULT_USEC_PER_Q,
+                },
+            );
+        }
+        // The opening tempo plays at tempo_bpm; later ones keep their ratio to it
+        let opening = tempo_map[0].usec_per_q as u64;
+        let mut tempos: Vec<TempoSegment> = Vec::with_capacity(tempo_map.len());
+        for change in tempo_map {
+            let usec_per_q =
+                (change.usec_per_q as u64 * 60_000_000 / (tempo_bpm as u64 * opening)).max(1);
+            let start_us = match tempos.last() {
+                Some(prev) => prev.start_us + prev.us_for(change.tick - prev.tick, ticks_per_q),
+                None => 0,
+            };
+            tempos.push(TempoSegment {
+                tick: change.tick,
+                start_us,
+                usec_per_q,
+            });
+        }
+        BeatGrid {
+            tempo_bpm,
+            ticks_per_q,
+            segments,
+            tempos,
+        }
+    }
+
+    /// Grid from the time signatures and tempo map in Standard MIDI File data
+    pub fn from_smf(data: &[u8], tempo_bpm: u32) -> Self {
+        let Ok(smf) = Smf::parse(data) else {
+            return Self::new(tempo_bpm, DEFAULT_TICKS_PER_Q, Vec::new());
+        };
+        let ticks_per_q = match smf.header.timing {
+            midly::Timing::Metrical(t) => t.as_int() as u32,
+            _ => DEFAULT_TICKS_PER_Q,
+        };
+        let mut meters = Vec::new();
+        let mut tempo_map = Vec::new();
+        for track in &smf.tracks {
+            let mut tick = 0u64;
+            for event in track {
+                tick += event.delta.as_int() as u64;
+                match event.kind {
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(beats, denom_pow, ..)) => {
+                        if let Some(beat_unit) = 1u8.checked_shl(denom_pow as u32) {
+                            meters.push(MeterChange {
+                                tick,
+                                beats_per_bar: beats,
+                                beat_unit,
+                            });
+                        }
+                    }
+                    TrackEventKind::Meta(MetaMessage::Tempo(usec_per_q)) => {
+                        tempo_map.push(TempoChange {
+                            tick,
+                            usec_per_q: usec_per_q.as_int(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Self::with_tempo_map(tempo_bpm, ticks_per_q, meters, tempo_map)
+    }
+
+    /// Grid for a song, from its MIDI data when there is any
+    ///
+    /// MusicXML songs carry no time signatures or tempo map; they count in 4/4
+    /// at the playback tempo.
+    pub fn for_song(song: &SongInfo, midi_data: Option<&[u8]>, tempo_bpm: u32) -> Self {
+        match midi_data.filter(|data| !data.is_empty()) {
+            Some(data) => Self::from_smf(data, tempo_bpm),
+            None => Self::new(
+                tempo_bpm,
+                song.ticks_per_q.unwrap_or(DEFAULT_TICKS_PER_Q),
+                Vec::new(),
+            ),
+        }
+    }
+
+    /// Playback tempo of the song's opening
+    pub fn tempo_bpm(&self) -> u32 {
+        self.tempo_bpm
+    }
+
+    /// Tempo segment in effect at a tick
+    fn tempo_at_tick(&self, tick: u64) -> &TempoSegment {
+        let index = self.tempos.partition_point(|t| t.tick <= tick);
+        &self.tempos[index.saturating_sub(1)]
+    }
+
+    fn tick_to_ms(&self, tick: u64) -> u32 {
+        let tempo = self.tempo_at_tick(tick);
+        let us = tempo.start_us + tempo.us_for(tick - tempo.tick, self.ticks_per_q);
+        (us / 1000).min(u32::MAX as u64) as u32
+    }
+
+    /// First tick at or after a song position
+    fn ms_to_tick(&self, position_ms: u32) -> u64 {
+        let us = position_ms as u64 * 1000;
+        let index = self.tempos.partition_point(|t| t.start_us <= us);
+        let tempo = &self.tempos[index.saturating_sub(1)];
+        tempo.tick + ((us - tempo.start_us) * self.ticks_per_q as u64).div_ceil(tempo.usec_per_q)
+    }
+
+    /// First beat at or after a tick
+    fn beat_at_or_after_tick(&self, tick: u64) -> Beat {
+        let index = self
+            .segments
+            .iter()
+            .rposition(|s| s.meter.tick <= tick)
+            .unwrap_or(0);
+        let segment = self.segments[index];
+        let meter = segment.meter;
+        let beat_len = beat_ticks(self.ticks_per_q, meter.beat_unit);
+        let beats = (tick.saturating_sub(meter.tick)).div_ceil(beat_len);
+        let beat_tick = meter.tick + beats * beat_len;
+        if let Some(next) = self.segments.get(index + 1) {
+            if beat_tick >= next.meter.tick {
+                return self.beat_at_or_after_tick(next.meter.tick);
+            }
+        }
+        let per_bar = meter.beats_per_bar as u64;
+        Beat {
+            tick: beat_tick,
+            position_ms: self.tick_to_ms(beat_tick),
+            bar: segment.bar + (beats / per_bar) as u32,
+            beat: (beats % per_bar) as u8 + 1,
+            beats_per_bar: meter.beats_per_bar,
+            beat_unit: meter.beat_unit,
+            tempo_bpm: self.tempo_at_tick(beat_tick).bpm(),
+        }
+    }
+
+    /// First beat at or after a song position
+    pub fn beat_at_or_after(&self, position_ms: u32) -> Beat {
+        self.beat_at_or_after_tick(self.ms_to_tick(position_ms))
+    }
+
+    /// The beat following `beat`
+    pub fn next(&self, beat: &Beat) -> Beat {
+        self.beat_at_or_after_tick(beat.tick + 1)
+    }
+
+    /// The zero-copy IPC form of a beat of this grid
+    pub fn beat_event(&self, song_index: usize, beat: &Beat) -> MidiBeatEvent {
+        MidiBeatEvent {
+            position_ms: beat.position_ms,
+            bar: beat.bar,
+            song_index: song_index.min(u16::MAX as usize) as u16,
+            beat: beat.beat,
+            beats_per_bar: beat.beats_per_bar,
+            beat_unit: beat.beat_unit,
+            tempo_bpm: beat.tempo_bpm.min(u16::MAX as u32) as u16,
+            ..MidiBeatEvent::default()
+        }
+    }
+}
+
+impl TempoSegment {
+    /// Playing time of `ticks` at this tempo
+    fn us_for(&self, ticks: u64, ticks_per_q: u32) -> u64 {
+        ticks * self.usec_per_q / ticks_per_q as u64
+    }
+
+    fn bpm(&self) -> u32 {
+        ((60_000_000 + self.usec_per_q / 2) / self.usec_per_q) as u32
+    }
+}
+
+/// Ticks per beat for a time signature denominator
+fn beat_ticks(ticks_per_q: u32, beat_unit: u8) -> u64 {
+    (ticks_per_q as u64 * 4 / beat_unit.max(1) as u64).max(1)
+}
+
+impl MidiPlayer {
+    /// Beat grid of a song played at `tempo_bpm`
+    pub fn beat_grid(&self, song_index: usize, tempo_bpm: u32) -> Option<BeatGrid> {
+        let song = self.get_song(song_index)?;
+        Some(BeatGrid::for_song(
+            song,
+            self.song_midi_data(song_index),
+            tempo_bpm,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u15, u24, u28};
+    use midly::{Format, Header, Timing, TrackEvent};
+
+    fn meter(tick: u64, beats_per_bar: u8, beat_unit: u8) -> MeterChange {
+        MeterChange {
+            tick,
+            beats_per_bar,
+            beat_unit,
+        }
+    }
+
+    fn tempo(tick: u64, bpm: u32) -> TempoChange {
+        TempoChange {
+            tick,
+            usec_per_q: 60_000_000 / bpm,
+        }
+    }
+
+    #[test]
+    fn bars_count_from_one_in_the_default_meter() {
+        let grid = BeatGrid::new(120, 480, Vec::new());
+        let first = grid.beat_at_or_after(0);
+        assert_eq!((first.bar, first.beat, first.position_ms), (1, 1, 0));
+        assert_eq!((first.beats_per_bar, first.beat_unit), (4, 4));
+
+        let fourth = grid.beat_at_or_after(1500);
+        assert_eq!((fourth.bar, fourth.beat), (1, 4));
+        let downbeat = grid.next(&fourth);
+        assert_eq!(
+            (downbeat.bar, downbeat.beat, downbeat.position_ms),
+            (2, 1, 2000)
+        );
+        assert_eq!(grid.beat_at_or_after(4000).bar, 3);
+    }
+
+    #[test]
+    fn compound_meters_count_in_their_beat_unit() {
+        // 6/8: eighth-note beats, six to the bar
+        let grid = BeatGrid::new(120, 480, vec![meter(0, 6, 8)]);
+        let sixth = grid.beat_at_or_after(1250);
+        assert_eq!((sixth.tick, sixth.bar, sixth.beat), (1200, 1, 6));
+        let downbeat = grid.next(&sixth);
+        assert_eq!((downbeat.tick, downbeat.bar, downbeat.beat), (1440, 2, 1));
+    }
+
+    #[test]
+    fn a_meter_change_mid_bar_starts_a_new_bar() {
+        // 4/4 changes to 3/4 on beat 3 of the first bar
+        let grid = BeatGrid::new(120, 480, vec![meter(0, 4, 4), meter(960, 3, 4)]);
+        let second = grid.beat_at_or_after(500);
+        assert_eq!((second.bar, second.beat, second.beats_per_bar), (1, 2, 4));
+
+        let changed = grid.next(&second);
+        assert_eq!(changed.tick, 960);
+        assert_eq!(
+            (changed.bar, changed.beat, changed.beats_per_bar),
+            (2, 1, 3)
+        );
+
+        let mut beat = changed;
+        for _ in 0..3 {
+            beat = grid.next(&beat);
+        }
+        assert_eq!((beat.bar, beat.beat, beat.tick), (3, 1, 960 + 3 * 480));
+    }
+
+    #[test]
+    fn seeking_lands_on_the_next_beat_and_keeps_counting() {
+        let grid = BeatGrid::new(120, 480, vec![meter(0, 3, 4)]);
+        // Between beats 2 and 3 of bar 2
+        let beat = grid.beat_at_or_after(2250);
+        assert_eq!((beat.bar, beat.beat, beat.position_ms), (2, 3, 2500));
+        // Exactly on a beat
+        let beat = grid.beat_at_or_after(3000);
+        assert_eq!((beat.bar, beat.beat, beat.position_ms), (3, 1, 3000));
+        let beat = grid.next(&beat);
+        assert_eq!((beat.bar, beat.beat, beat.position_ms), (3, 2, 3500));
+    }
+
+    #[test]
+    fn beats_follow_the_tempo_map() {
+        // 120 BPM, slowing to 60 BPM at bar 2
+        let tempos = vec![tempo(0, 120), tempo(1920, 60)];
+        let grid = BeatGrid::with_tempo_map(120, 480, Vec::new(), tempos.clone());
+        let downbeat = grid.beat_at_or_after(1900);
+        assert_eq!((downbeat.bar, downbeat.position_ms), (2, 2000));
+        assert_eq!(downbeat.tempo_bpm, 60);
+        let second = grid.next(&downbeat);
+        assert_eq!((second.beat, second.position_ms), (2, 3000));
+        // A seek into the slow part finds the beat by its real position
+        let beat = grid.beat_at_or_after(3500);
+        assert_eq!((beat.bar, beat.beat, beat.position_ms), (2, 3, 4000));
+
+        // Playing at 240 BPM doubles every tempo in the map
+        let fast = BeatGrid::with_tempo_map(240, 480, Vec::new(), tempos);
+        assert_eq!(fast.beat_at_or_after(0).tempo_bpm, 240);
+        let downbeat = fast.beat_at_or_after(900);
+        assert_eq!((downbeat.bar, downbeat.position_ms), (2, 1000));
+        assert_eq!(downbeat.tempo_bpm, 120);
+        assert_eq!(fast.next(&downbeat).position_ms, 1500);
+
+        let event = fast.beat_event(3, &downbeat);
+        assert_eq!((event.song_index, event.tempo_bpm), (3, 120));
+    }
+
+    #[test]
+    fn reads_meters_and_tempos_from_smf() {
+        let conductor = vec![
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::TimeSignature(3, 2, 24, 8)),
+            },
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000))),
+            },
+            TrackEvent {
+                delta: u28::new(1440),
+                kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(1_000_000))),
+            },
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            },
+        ];
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(480)),
+        ));
+        smf.tracks.push(conductor);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let grid = BeatGrid::from_smf(&bytes, 120);
+        let downbeat = grid.beat_at_or_after(1400);
+        assert_eq!(
+            (downbeat.bar, downbeat.beat, downbeat.beats_per_bar),
+            (2, 1, 3)
+        );
+        assert_eq!((downbeat.position_ms, downbeat.tempo_bpm), (1500, 60));
+        assert_eq!(grid.next(&downbeat).position_ms, 2500);
+    }
+}
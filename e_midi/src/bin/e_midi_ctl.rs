@@ -0,0 +1,408 @@
+//! e_midi_ctl: control a running `e_midi serve` over IPC
+//!
+//! Publishes the `MidiCommand*` events on the shared event service and prints
+//! what the player answers, so shell scripts and keyboard shortcuts can drive
+//! playback:
+//!
+//! ```text
+//! e_midi_ctl play 3          e_midi_ctl tempo 140
+//! e_midi_ctl play winners    e_midi_ctl status
+//! e_midi_ctl stop            e_midi_ctl list --json
+//! e_midi_ctl next            e_midi_ctl watch
+//! e_midi_ctl apps           e_midi_ctl load song.mid
+//! ```
+//!
+//! Exits with an error when the player doesn't answer in time.
+
+use clap::{Parser, Subcommand};
+use e_midi_shared::ipc::{
+    handshake, request_state, send_chunked, AppId, Event, EventPublisher, EventSubscriber,
+    IpcSongInfo, MidiPlaybackState, PeerInfo, StateType, DEFAULT_CHUNK_TIMEOUT,
+    DEFAULT_STATE_TIMEOUT, TOPIC_SONG,
+};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long a command waits for the player to confirm it
+const CONFIRM_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(name = "e_midi_ctl")]
+#[command(about = "Control a running e_midi player over IPC")]
+struct Cli {
+    /// Print JSON instead of text (one object per line)
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// How long to wait for the player, in ms
+    #[arg(long, global = true, default_value_t = DEFAULT_STATE_TIMEOUT.as_millis() as u64)]
+    timeout_ms: u64,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play a song by index or (partial) name
+    Play { song: String },
+    /// Send a MIDI or MusicXML file to the player and play it
+    Load { file: PathBuf },
+    /// Stop and rewind
+    Stop,
+    /// Pause at the current position
+    Pause,
+    /// Resume (or start the current song)
+    Resume,
+    /// Play the next song in the playlist
+    Next,
+    /// Play the previous song in the playlist
+    Previous,
+    /// Change the tempo of the current song
+    Tempo { bpm: u32 },
+    /// Show the playback state
+    Status,
+    /// List the player's songs
+    List,
+    /// List the apps on the event service and what they support
+    Apps,
+    /// Print events as they arrive until interrupted
+    Watch {
+        /// Include beat events
+        #[arg(long)]
+        beats: bool,
+    },
+}
+
+struct Ctl {
+    publisher: EventPublisher,
+    subscriber: EventSubscriber,
+    json: bool,
+    timeout: Duration,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("❌ {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    // The publisher creates the event service if no player has yet
+    let publisher = EventPublisher::new(AppId::EMidiCtl)?;
+    let subscriber = EventSubscriber::new(AppId::EMidi, AppId::EMidiCtl)?;
+    let mut ctl = Ctl {
+        publisher,
+        subscriber,
+        json: cli.json,
+        timeout: Duration::from_millis(cli.timeout_ms),
+    };
+    match cli.command {
+        Command::Play { song } => {
+            let song_index = ctl.resolve_song(&song)?;
+            ctl.command(Event::midi_command_play(song_index), |e| {
+                matches!(e, Event::MidiPlaybackStarted { song_index: i, .. } if *i == song_index)
+            })
+        }
+        Command::Load { file } => ctl.load(&file),
+        Command::Stop => ctl.command(Event::midi_command_stop(), |e| {
+            matches!(e, Event::MidiPlaybackStopped { .. })
+        }),
+        Command::Pause => ctl.command(
+            Event::MidiCommandPause {
+                timestamp: now_ms(),
+            },
+            |e| matches!(e, Event::MidiPlaybackPaused { .. }),
+        ),
+        Command::Resume => ctl.command(
+            Event::MidiCommandResume {
+                timestamp: now_ms(),
+            },
+            |e| {
+                matches!(
+                    e,
+                    Event::MidiPlaybackResumed { .. } | Event::MidiPlaybackStarted { .. }
+                )
+            },
+        ),
+        Command::Next => ctl.command(Event::midi_command_next(), |e| {
+            matches!(e, Event::MidiPlaybackStarted { .. })
+        }),
+        Command::Previous => ctl.command(Event::midi_command_previous(), |e| {
+            matches!(e, Event::MidiPlaybackStarted { .. })
+        }),
+        Command::Tempo { bpm } => ctl.command(
+            Event::midi_command_set_tempo(bpm),
+            |e| matches!(e, Event::MidiTempoChanged { new_tempo, .. } if *new_tempo == bpm),
+        ),
+        Command::Status => {
+            let state = ctl.status()?;
+            ctl.print_status(&state)
+        }
+        Command::List => {
+            let songs = ctl.songs()?;
+            ctl.print_songs(&songs)
+        }
+        Command::Apps => {
+            let peers = ctl.apps()?;
+            ctl.print_apps(&peers)
+        }
+        Command::Watch { beats } => ctl.watch(beats),
+    }
+}
+
+impl Ctl {
+    fn status(&mut self) -> Result<MidiPlaybackState, Box<dyn Error>> {
+        let replies = request_state(
+            &self.publisher,
+            &mut self.subscriber,
+            AppId::EMidiCtl,
+            StateType::MidiPlayback,
+            self.timeout,
+        )
+        .map_err(no_player)?;
+        Ok(replies[0].decode()?)
+    }
+
+    fn songs(&mut self) -> Result<Vec<IpcSongInfo>, Box<dyn Error>> {
+        let replies = request_state(
+            &self.publisher,
+            &mut self.subscriber,
+            AppId::EMidiCtl,
+            StateType::MidiSongList,
+            self.timeout,
+        )
+        .map_err(no_player)?;
+        Ok(replies[0].decode()?)
+    }
+
+    /// Every app answering the capability handshake within the timeout
+    fn apps(&mut self) -> Result<Vec<PeerInfo>, Box<dyn Error>> {
+        let peers = handshake(
+            &self.publisher,
+            &mut self.subscriber,
+            AppId::EMidiCtl,
+            &[],
+            self.timeout,
+        )?;
+        if peers.is_empty() {
+            return Err("No app answered (is `e_midi serve` running?)".into());
+        }
+        Ok(peers)
+    }
+
+    /// Song index from an index or a (partial, case-insensitive) name
+    fn resolve_song(&mut self, query: &str) -> Result<usize, Box<dyn Error>> {
+        if let Ok(index) = query.trim().parse::<usize>() {
+            return Ok(index);
+        }
+        let query = query.trim().to_lowercase();
+        let songs = self.songs()?;
+        songs
+            .iter()
+            .find(|s| s.name.to_lowercase() == query)
+            .or_else(|| {
+                songs
+                    .iter()
+                    .find(|s| s.name.to_lowercase().contains(&query))
+            })
+            .map(|s| s.index)
+            .ok_or_else(|| format!("No song matching '{}'", query).into())
+    }
+
+    /// Send a song file in chunks, then have the player add and play it
+    fn load(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("Not a file: {}", path.display()))?;
+        let data = std::fs::read(path)?;
+        let transfer_id = send_chunked(
+            &self.publisher,
+            &mut self.subscriber,
+            TOPIC_SONG,
+            &data,
+            DEFAULT_CHUNK_TIMEOUT.max(self.timeout),
+        )
+        .map_err(no_player)?;
+        self.command(Event::midi_command_load_song(transfer_id, file_name), |e| {
+            matches!(e, Event::MidiPlaybackStarted { .. })
+        })
+    }
+
+    /// Send a command and print the event confirming it
+    ///
+    /// Commands that change nothing (stopping while stopped) aren't confirmed;
+    /// the playback state is printed instead, which also shows the player is up.
+    fn command(
+        &mut self,
+        command: Event,
+        confirms: impl Fn(&Event) -> bool,
+    ) -> Result<(), Box<dyn Error>> {
+        self.publisher.publish(command)?;
+        let deadline = Instant::now() + CONFIRM_TIMEOUT.min(self.timeout);
+        while Instant::now() < deadline {
+            for event in self.subscriber.try_receive()? {
+                if confirms(&event) {
+                    return self.print_event(&event);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let state = self.status()?;
+        self.print_status(&state)
+    }
+
+    fn watch(&mut self, beats: bool) -> Result<(), Box<dyn Error>> {
+        if !self.json {
+            eprintln!("👀 Watching e_midi events (Ctrl+C to exit)");
+        }
+        loop {
+            for event in self
+                .subscriber
+                .receive_timeout(Duration::from_millis(100))?
+            {
+                if matches!(event, Event::MidiBeat { .. }) && !beats {
+                    continue;
+                }
+                self.print_event(&event)?;
+            }
+        }
+    }
+
+    fn print_event(&self, event: &Event) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            println!("{}", serde_json::to_string(event)?);
+        } else {
+            println!("{}", describe(event));
+        }
+        Ok(())
+    }
+
+    fn print_status(&self, state: &MidiPlaybackState) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            println!("{}", serde_json::to_string(state)?);
+            return Ok(());
+        }
+        match state.current_song_index {
+            Some(index) => println!(
+                "{} {}: {} ({} / {}) at {} BPM",
+                if state.is_playing { "▶️" } else { "⏹️" },
+                index,
+                state.current_song_name,
+                format_ms(state.progress_ms),
+                format_ms(state.total_duration_ms),
+                state.tempo_bpm
+            ),
+            None => println!("⏹️ No song selected"),
+        }
+        Ok(())
+    }
+
+    fn print_apps(&self, peers: &[PeerInfo]) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            println!("{}", serde_json::to_string(peers)?);
+            return Ok(());
+        }
+        for peer in peers {
+            println!(
+                "🔗 {:?} (pid {}, protocol v{}): {}",
+                peer.app_id,
+                peer.process_id,
+                peer.protocol_version,
+                if peer.capabilities.is_empty() {
+                    "-".to_string()
+                } else {
+                    peer.capabilities.join(", ")
+                }
+            );
+        }
+        Ok(())
+    }
+
+    fn print_songs(&self, songs: &[IpcSongInfo]) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            println!("{}", serde_json::to_string(songs)?);
+            return Ok(());
+        }
+        for song in songs {
+            println!(
+                "{:3}: {} ({} tracks, {} BPM{}){}",
+                song.index,
+                song.name,
+                song.track_count,
+                song.default_tempo,
+                song.duration_ms
+                    .map(|ms| format!(", {}", format_ms(ms)))
+                    .unwrap_or_default(),
+                if song.is_dynamic { " [dynamic]" } else { "" }
+            );
+        }
+        Ok(())
+    }
+}
+
+/// One line of text for an event
+fn describe(event: &Event) -> String {
+    match event {
+        Event::MidiPlaybackStarted {
+            song_index,
+            song_name,
+            ..
+        } => format!("▶️ Playing {}: {}", song_index, song_name),
+        Event::MidiPlaybackStopped { .. } => "⏹️ Stopped".to_string(),
+        Event::MidiPlaybackPaused { .. } => "⏸️ Paused".to_string(),
+        Event::MidiPlaybackResumed { .. } => "▶️ Resumed".to_string(),
+        Event::MidiTempoChanged { new_tempo, .. } => format!("🎶 Tempo {} BPM", new_tempo),
+        Event::MidiSongChanged {
+            song_index,
+            song_name,
+            ..
+        } => format!("🎵 Song {}: {}", song_index, song_name),
+        Event::MidiProgressUpdate {
+            progress_ms,
+            total_ms,
+            ..
+        } => format!("⏱️ {} / {}", format_ms(*progress_ms), format_ms(*total_ms)),
+        Event::MidiSongListUpdated { song_count, .. } => {
+            format!("📋 Song list updated ({} songs)", song_count)
+        }
+        Event::MidiBeat {
+            bar,
+            beat,
+            beats_per_bar,
+            beat_unit,
+            tempo_bpm,
+            ..
+        } => format!(
+            "🥁 Bar {} beat {} ({}/{} at {} BPM)",
+            bar, beat, beats_per_bar, beat_unit, tempo_bpm
+        ),
+        Event::ServiceJoined {
+            app_id, process_id, ..
+        } => format!("🔗 {:?} joined (pid {})", app_id, process_id),
+        Event::ServiceLeft {
+            app_id, process_id, ..
+        } => format!("💤 {:?} left (pid {})", app_id, process_id),
+        other => format!("{:?}", other),
+    }
+}
+
+fn no_player(e: e_midi_shared::ipc::IpcError) -> Box<dyn Error> {
+    format!("{} (is `e_midi serve` running?)", e).into()
+}
+
+fn format_ms(ms: u32) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -67,7 +67,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // --- Use shared IPC library to create event subscriber ---
     let node = NodeBuilder::new().create::<iceoryx2::service::ipc::Service>()?;
     let event_service = node
-        .service_builder(&ServiceName::new(ipc::EMIDI_EVENTS_SERVICE)?)
+        .service_builder(&ServiceName::new(ipc::EMIDI_NOTE_EVENTS_SERVICE)?)
         .publish_subscribe::<MidiNoteEvent>()
         .open()?;
     let event_subscriber = event_service.subscriber_builder().create()?;
@@ -13,7 +13,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Subscribe to zero-copy MidiNoteEvent stream from e_midi
     let node = NodeBuilder::new().create::<iceoryx2::service::ipc::Service>()?;
     let event_service = node
-        .service_builder(&ServiceName::new(e_midi_shared::ipc::EMIDI_EVENTS_SERVICE)?)
+        .service_builder(&ServiceName::new(
+            e_midi_shared::ipc::EMIDI_NOTE_EVENTS_SERVICE,
+        )?)
         .publish_subscribe::<MidiNoteEvent>()
         .open()?;
     let event_sub = event_service.subscriber_builder().create()?;
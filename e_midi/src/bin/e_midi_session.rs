@@ -0,0 +1,343 @@
+//! e_midi_session: record and replay the e_midi IPC services
+//!
+//! `record` captures the serde `Event`s and the zero-copy `MidiNoteEvent`s
+//! on the e_midi event service into a JSON Lines file, one entry per message,
+//! each stamped with the time it was received. `replay` publishes a capture
+//! again with the original spacing, optionally sped up or slowed down:
+//!
+//! ```text
+//! e_midi_session record session.jsonl --app EMidi --app EGrid
+//! e_midi_session replay session.jsonl --speed 2
+//! ```
+//!
+//! `--app` keeps only events published by one of the given apps. Publishers
+//! from before protocol version 2 don't name themselves; their events fall
+//! back to `Event::typical_source`. Notes always come from the player and
+//! count as `EMidi`.
+
+use clap::{Parser, Subcommand};
+use e_midi_shared::ipc::{
+    unix_micros, AppId, Event, EventPublisher, EventSubscriber, EMIDI_NOTE_EVENTS_SERVICE,
+};
+use e_midi_shared::ipc_protocol::MidiNoteEvent;
+use iceoryx2::port::publisher::Publisher;
+use iceoryx2::port::subscriber::Subscriber;
+use iceoryx2::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{hash_map, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Apps that can be given to `--app`
+const APPS: [AppId; 7] = [
+    AppId::EMidi,
+    AppId::EGrid,
+    AppId::StateServer,
+    AppId::Demo05,
+    AppId::EMidiCtl,
+    AppId::EMidiSession,
+    AppId::Unknown,
+];
+
+#[derive(Parser)]
+#[command(name = "e_midi_session")]
+#[command(about = "Record and replay e_midi IPC traffic")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Capture IPC traffic to a file until Ctrl+C
+    Record {
+        /// Capture file (JSON Lines)
+        file: PathBuf,
+        /// Only keep events from these apps (repeatable)
+        #[arg(long = "app", value_parser = parse_app)]
+        apps: Vec<AppId>,
+        /// Stop after this many seconds
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Publish a capture again
+    Replay {
+        /// Capture file (JSON Lines)
+        file: PathBuf,
+        /// Playback speed (2 = twice as fast)
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        /// Only replay events from these apps (repeatable)
+        #[arg(long = "app", value_parser = parse_app)]
+        apps: Vec<AppId>,
+    },
+}
+
+/// One captured message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// Local UNIX microseconds at which the message was received
+    received_us: u64,
+    /// App that published the event, when its publisher said so
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender: Option<AppId>,
+    message: Message,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Message {
+    Event(Event),
+    Note(Note),
+}
+
+/// `MidiNoteEvent` in a form serde can write
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Note {
+    channel: u8,
+    pitch: u8,
+    velocity: u8,
+    kind: u8,
+    timestamp: u64,
+}
+
+impl From<&MidiNoteEvent> for Note {
+    fn from(note: &MidiNoteEvent) -> Self {
+        Note {
+            channel: note.channel,
+            pitch: note.pitch,
+            velocity: note.velocity,
+            kind: note.kind,
+            timestamp: note.timestamp,
+        }
+    }
+}
+
+impl From<Note> for MidiNoteEvent {
+    fn from(note: Note) -> Self {
+        MidiNoteEvent {
+            channel: note.channel,
+            pitch: note.pitch,
+            velocity: note.velocity,
+            kind: note.kind,
+            timestamp: note.timestamp,
+            _reserved: [0; 4],
+        }
+    }
+}
+
+impl Entry {
+    fn source(&self) -> AppId {
+        match &self.message {
+            Message::Event(event) => self.sender.unwrap_or_else(|| event.typical_source()),
+            Message::Note(_) => AppId::EMidi,
+        }
+    }
+
+    fn is_from(&self, apps: &[AppId]) -> bool {
+        apps.is_empty() || apps.contains(&self.source())
+    }
+}
+
+fn parse_app(name: &str) -> Result<AppId, String> {
+    APPS.into_iter()
+        .find(|app| format!("{:?}", app).eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<String> = APPS.iter().map(|app| format!("{:?}", app)).collect();
+            format!(
+                "unknown app '{}' (expected one of {})",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_flag = running.clone();
+    ctrlc::set_handler(move || handler_flag.store(false, Ordering::Relaxed))?;
+    match cli.command {
+        Command::Record {
+            file,
+            apps,
+            duration,
+        } => record(&file, &apps, duration.map(Duration::from_secs), &running),
+        Command::Replay { file, speed, apps } => replay(&file, speed, &apps, &running),
+    }
+}
+
+fn note_subscriber() -> Result<Subscriber<ipc::Service, MidiNoteEvent, ()>, Box<dyn Error>> {
+    let node = NodeBuilder::new().create::<ipc::Service>()?;
+    let service = node
+        .service_builder(&ServiceName::new(EMIDI_NOTE_EVENTS_SERVICE)?)
+        .publish_subscribe::<MidiNoteEvent>()
+        .max_publishers(16)
+        .max_subscribers(16)
+        .open_or_create()?;
+    Ok(service.subscriber_builder().create()?)
+}
+
+fn note_publisher() -> Result<Publisher<ipc::Service, MidiNoteEvent, ()>, Box<dyn Error>> {
+    let node = NodeBuilder::new().create::<ipc::Service>()?;
+    let service = node
+        .service_builder(&ServiceName::new(EMIDI_NOTE_EVENTS_SERVICE)?)
+        .publish_subscribe::<MidiNoteEvent>()
+        .max_publishers(16)
+        .max_subscribers(16)
+        .open_or_create()?;
+    Ok(service.publisher_builder().create()?)
+}
+
+fn record(
+    path: &Path,
+    apps: &[AppId],
+    duration: Option<Duration>,
+    running: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(path)?);
+    // Each stream is optional, so a capture works with whatever is running
+    let notes = match note_subscriber() {
+        Ok(subscriber) => Some(subscriber),
+        Err(e) => {
+            eprintln!("⚠️  Not recording notes: {}", e);
+            None
+        }
+    };
+    let mut events = match EventSubscriber::new(AppId::EMidi, AppId::EMidiSession) {
+        Ok(subscriber) => Some(subscriber),
+        Err(e) => {
+            eprintln!("⚠️  Not recording events: {}", e);
+            None
+        }
+    };
+    if notes.is_none() && events.is_none() {
+        return Err("Nothing to record".into());
+    }
+
+    println!("🔴 Recording to {} (Ctrl+C to stop)", path.display());
+    let started = Instant::now();
+    let mut count = 0usize;
+    while running.load(Ordering::Relaxed) && duration.map_or(true, |d| started.elapsed() < d) {
+        let mut received = Vec::new();
+        if let Some(subscriber) = &mut events {
+            for (sender, event) in subscriber.try_receive_with_sender()? {
+                received.push((sender, Message::Event(event)));
+            }
+        }
+        if let Some(subscriber) = &notes {
+            while let Some(sample) = subscriber.receive()? {
+                received.push((None, Message::Note(Note::from(&*sample))));
+            }
+        }
+        if received.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+        let received_us = unix_micros();
+        let entries = received
+            .into_iter()
+            .map(|(sender, message)| Entry {
+                received_us,
+                sender,
+                message,
+            })
+            .filter(|entry| entry.is_from(apps));
+        for entry in entries {
+            serde_json::to_writer(&mut out, &entry)?;
+            out.write_all(b"\n")?;
+            count += 1;
+        }
+        out.flush()?;
+    }
+    out.flush()?;
+    println!(
+        "⏹️ Recorded {} messages in {:.1}s",
+        count,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+fn replay(
+    path: &Path,
+    speed: f64,
+    apps: &[AppId],
+    running: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    if !(speed > 0.0 && speed.is_finite()) {
+        return Err(format!("Invalid speed: {} (must be greater than 0)", speed).into());
+    }
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line)
+            .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+        if entry.is_from(apps) {
+            entries.push(entry);
+        }
+    }
+    let Some(first_us) = entries.first().map(|e| e.received_us) else {
+        println!("📭 Nothing to replay");
+        return Ok(());
+    };
+
+    // Publishers are created on first use, so a capture without notes
+    // doesn't claim the note service. Events go out under their original
+    // sender, so `--app` filters still match them in a second capture.
+    let mut notes: Option<Publisher<ipc::Service, MidiNoteEvent, ()>> = None;
+    let mut events: HashMap<AppId, EventPublisher> = HashMap::new();
+    println!(
+        "▶️ Replaying {} messages from {} at {}x",
+        entries.len(),
+        path.display(),
+        speed
+    );
+    let started = Instant::now();
+    let mut count = 0usize;
+    for entry in entries {
+        let offset_us = entry.received_us.saturating_sub(first_us) as f64 / speed;
+        let due = started + Duration::from_micros(offset_us as u64);
+        while running.load(Ordering::Relaxed) && Instant::now() < due {
+            std::thread::sleep(
+                due.saturating_duration_since(Instant::now())
+                    .min(Duration::from_millis(10)),
+            );
+        }
+        if !running.load(Ordering::Relaxed) {
+            break;
+        }
+        let sender = entry.sender.unwrap_or(AppId::EMidiSession);
+        match entry.message {
+            Message::Event(event) => {
+                let publisher = match events.entry(sender) {
+                    hash_map::Entry::Occupied(slot) => slot.into_mut(),
+                    hash_map::Entry::Vacant(slot) => slot.insert(EventPublisher::new(sender)?),
+                };
+                publisher.publish(event)?;
+            }
+            Message::Note(note) => {
+                if notes.is_none() {
+                    notes = Some(note_publisher()?);
+                }
+                if let Some(publisher) = &notes {
+                    publisher.send_copy(MidiNoteEvent::from(note))?;
+                }
+            }
+        }
+        count += 1;
+    }
+    println!(
+        "⏹️ Replayed {} messages in {:.1}s",
+        count,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
@@ -9,29 +9,45 @@ use crate::MidiPlayer;
 #[command(about = env!("CARGO_PKG_DESCRIPTION"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(author = env!("CARGO_PKG_AUTHORS"))]
+#[command(
+    after_help = "Without a MIDI output port e_midi plays through its built-in software synth, \
+                  which needs a build with `--features uses_rodio`; other builds play silently."
+)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
 
     /// Loop the entire playlist continuously
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_loop_playlist")]
     pub loop_playlist: bool,
 
+    /// Don't loop the playlist, whatever the config file or environment says
+    #[arg(long, overrides_with = "loop_playlist")]
+    pub no_loop_playlist: bool,
+
     /// Loop individual songs
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_loop_individual_songs")]
     pub loop_individual_songs: bool,
-    /// Delay between songs in seconds
-    #[arg(long, default_value = "0")]
-    pub delay_between_songs: u32,
 
-    /// Scan segment duration in seconds
-    #[arg(long, default_value = "30")]
-    pub scan_duration: u32,
+    /// Don't loop individual songs, whatever the config file or environment says
+    #[arg(long, overrides_with = "loop_individual_songs")]
+    pub no_loop_individual_songs: bool,
+    /// Delay between songs in seconds [default: 0]
+    #[arg(long)]
+    pub delay_between_songs: Option<u32>,
 
-    /// Start scan segments at random positions
+    /// Scan segment duration in seconds [default: 30]
     #[arg(long)]
+    pub scan_duration: Option<u32>,
+
+    /// Start scan segments at random positions
+    #[arg(long, overrides_with = "no_scan_random_start")]
     pub scan_random_start: bool,
 
+    /// Start scan segments at the beginning, whatever the config file or environment says
+    #[arg(long, overrides_with = "scan_random_start")]
+    pub no_scan_random_start: bool,
+
     /// Use TUI mode with split panels (menu + playback info)
     #[arg(short = 't', long)]
     pub tui: bool,
@@ -44,9 +60,73 @@ pub struct Cli {
     #[arg(long = "scan-directory")]
     pub scan_directories: Vec<std::path::PathBuf>,
 
+    /// Load directories and keep the dynamic playlist in sync as files change
+    #[arg(long = "watch-directory")]
+    pub watch_directories: Vec<std::path::PathBuf>,
+
     /// Enable IPC event publishing for playback
-    #[arg(long)]
+    #[arg(long, overrides_with = "no_ipc")]
     pub ipc: bool,
+
+    /// Disable IPC event publishing, whatever the config file or environment says
+    #[arg(long, overrides_with = "ipc")]
+    pub no_ipc: bool,
+
+    /// Keep a persistent library index of added songs, play counts and positions
+    #[arg(long, overrides_with = "no_library")]
+    pub library: bool,
+
+    /// Don't use the library index, whatever the config file or environment says
+    #[arg(long, overrides_with = "library")]
+    pub no_library: bool,
+
+    /// Config file (default: $XDG_CONFIG_HOME/e_midi/config.toml)
+    #[arg(long)]
+    pub config: Option<std::path::PathBuf>,
+
+    /// MIDI output port index or part of its name
+    #[arg(long)]
+    pub output_port: Option<String>,
+
+    /// Multiply every song's default tempo, e.g. 0.8 [default: 1.0]
+    #[arg(long)]
+    pub tempo_scale: Option<f64>,
+
+    /// Play (and render) through this SF2 SoundFont instead of a MIDI port
+    #[arg(long)]
+    pub soundfont: Option<std::path::PathBuf>,
+
+    /// Play through SuperDirt instead of a MIDI port (default 127.0.0.1:57120)
+    #[cfg(feature = "uses_osc")]
+    #[arg(long, num_args = 0..=1, default_missing_value = crate::superdirt::DEFAULT_SUPERDIRT_ADDR)]
+    pub superdirt: Option<String>,
+
+    /// SuperDirt scheduling latency in milliseconds
+    #[cfg(feature = "uses_osc")]
+    #[arg(long, default_value = "200")]
+    pub dirt_latency: u64,
+
+    /// SuperDirt sound for a MIDI channel, as CHANNEL=SOUND[:N] (repeatable)
+    #[cfg(feature = "uses_osc")]
+    #[arg(long = "dirt-sound")]
+    pub dirt_sounds: Vec<String>,
+
+    /// SuperDirt sound for a drum key on channel 10, as KEY=SOUND[:N] (repeatable)
+    #[cfg(feature = "uses_osc")]
+    #[arg(long = "dirt-drum")]
+    pub dirt_drums: Vec<String>,
+
+    /// Emit MIDI Time Code while playing: 24, 25, 29.97 (drop-frame) or 30
+    #[arg(long)]
+    pub mtc: Option<crate::mtc::MtcFrameRate>,
+
+    /// Timecode at which songs start on the MTC timeline (hh:mm:ss:ff)
+    #[arg(long, default_value = "00:00:00:00")]
+    pub mtc_offset: crate::mtc::Timecode,
+
+    /// Start timecode for one song, as INDEX=hh:mm:ss:ff (repeatable)
+    #[arg(long = "mtc-song-offset")]
+    pub mtc_song_offsets: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -91,23 +171,361 @@ pub enum Commands {
 
     /// Run in interactive mode (default)
     Interactive,
+
+    /// Record from a MIDI input port into a new dynamic song
+    Record {
+        /// MIDI input port index (defaults to the first port)
+        #[arg(long)]
+        port: Option<usize>,
+
+        /// Write the recording to a Standard MIDI File
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Play this song as a backing track while recording
+        #[arg(long)]
+        overdub: Option<usize>,
+
+        /// Stop recording after this many seconds
+        #[arg(long)]
+        duration: Option<u32>,
+
+        /// Tempo in BPM written to the recording (ignored when overdubbing)
+        #[arg(long, default_value = "120")]
+        tempo: u32,
+
+        /// List available MIDI input ports and exit
+        #[arg(long)]
+        list_ports: bool,
+    },
+
+    /// Export a song to a Standard MIDI File or MusicXML
+    Export {
+        /// Song index to export
+        song_index: usize,
+
+        /// Output file (defaults to "<song name>.mid" or "<song name>.musicxml")
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Output format: midi or musicxml (inferred from the output extension)
+        #[arg(long)]
+        format: Option<String>,
+
+        /// Track numbers to export (comma-separated, 0 for all tracks)
+        #[arg(long, value_delimiter = ',')]
+        tracks: Option<Vec<usize>>,
+
+        /// Tempo in BPM
+        #[arg(long)]
+        tempo: Option<u32>,
+
+        /// MusicXML quantization grid: 4, 8, 16, 32 or 64
+        #[arg(long, default_value = "16")]
+        grid: u32,
+
+        /// MusicXML time signature, e.g. 3/4 (read from the song if omitted)
+        #[arg(long)]
+        time: Option<String>,
+
+        /// MusicXML key signature in fifths, e.g. -1 for F major (read from the song if omitted)
+        #[arg(long, allow_hyphen_values = true)]
+        key: Option<i8>,
+    },
+
+    /// Render a song to WAV with the built-in synthesizer (no devices needed)
+    Render {
+        /// Song index to render
+        song_index: usize,
+
+        /// Output file (defaults to "<song name>.wav")
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+
+        /// Track numbers to render (comma-separated, 0 for all tracks)
+        #[arg(long, value_delimiter = ',')]
+        tracks: Option<Vec<usize>>,
+
+        /// Tempo in BPM
+        #[arg(long)]
+        tempo: Option<u32>,
+
+        /// Sample rate in Hz
+        #[arg(long, default_value = "44100")]
+        sample_rate: u32,
+    },
+
+    /// Route a MIDI input port through a transformation chain to the output
+    Thru {
+        /// MIDI input port index (defaults to the first port)
+        #[arg(long)]
+        port: Option<usize>,
+
+        /// Transform to apply, in order (e.g. transpose=12, remap=1:3,
+        /// velocity=gamma:0.6, program=*:40, split=60:2:1)
+        #[arg(long = "transform")]
+        transforms: Vec<String>,
+
+        /// Play this song as a backing track while thru is active
+        #[arg(long)]
+        play: Option<usize>,
+    },
+
+    /// Run an OSC control server (play, stop, pause, tempo, seek, mute, ...)
+    #[cfg(feature = "uses_osc")]
+    Osc {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:57130")]
+        bind: String,
+    },
+
+    /// Run a JSON-RPC control endpoint on a Unix domain socket
+    #[cfg(unix)]
+    Rpc {
+        /// Socket path (default: $XDG_RUNTIME_DIR/e_midi.sock)
+        #[arg(long)]
+        socket: Option<std::path::PathBuf>,
+    },
+
+    /// List the persistent library index (play counts, last positions)
+    Library,
+
+    /// Show or locate the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+
+    /// Run headless as a background service (IPC commands, heartbeats, control endpoints)
+    Serve {
+        /// PID lock file (default: $XDG_RUNTIME_DIR/e_midi.pid)
+        #[arg(long)]
+        pid_file: Option<std::path::PathBuf>,
+
+        /// Heartbeat interval in milliseconds
+        #[arg(long, default_value = "1000")]
+        heartbeat_ms: u64,
+
+        /// Also run the OSC control server on this address
+        #[cfg(feature = "uses_osc")]
+        #[arg(long)]
+        osc: Option<String>,
+
+        /// Also run the JSON-RPC control socket (optionally at this path)
+        #[cfg(unix)]
+        #[arg(long, num_args = 0..=1, default_missing_value = "")]
+        rpc: Option<std::path::PathBuf>,
+    },
+
+    /// Play songs in step with other processes on a shared heartbeat clock
+    Sync {
+        #[command(subcommand)]
+        action: SyncCommand,
+    },
+
+    /// Follow incoming MIDI Time Code and keep a song locked to it
+    Chase {
+        /// Song index to play
+        song_index: usize,
+
+        /// MIDI input port index carrying MTC (defaults to the first port)
+        #[arg(long)]
+        port: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration (file, environment and flags merged)
+    Show,
+    /// Print the config file path
+    Path,
+}
+
+#[derive(Subcommand)]
+pub enum SyncCommand {
+    /// Follow the heartbeat clock and play the songs scheduled on it
+    Follow {
+        /// Also run the heartbeat clock (exactly one process should)
+        #[arg(long)]
+        master: bool,
+
+        /// Heartbeat interval in milliseconds (with --master)
+        #[arg(long, default_value = "100")]
+        interval_ms: u64,
+
+        /// Track numbers this process plays (comma-separated, 0 for all tracks)
+        #[arg(long, value_delimiter = ',')]
+        tracks: Option<Vec<usize>>,
+    },
+    /// Start a song on every follower at the same heartbeat
+    Play {
+        /// Song index or name
+        song: String,
+
+        /// Heartbeats from now until the start
+        #[arg(long, default_value = "10")]
+        in_beats: u32,
+
+        /// Stop after this many heartbeats
+        #[arg(long)]
+        beats: Option<u32>,
+
+        /// Stop after this many milliseconds
+        #[arg(long)]
+        for_ms: Option<u32>,
+
+        /// Voice for a track as TRACK=PROGRAM (repeatable, up to 16)
+        #[arg(long = "voice")]
+        voices: Vec<String>,
+    },
+    /// Stop every follower at the same heartbeat
+    Stop {
+        /// Heartbeats from now until the stop (0 stops at once)
+        #[arg(long, default_value = "0")]
+        in_beats: u32,
+    },
+}
+
+/// Value of a `--flag` / `--no-flag` pair, None when neither was given
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Config file and environment with command line flags on top
+pub fn effective_config(
+    cli: &Cli,
+) -> Result<(crate::config::Config, Option<std::path::PathBuf>), Box<dyn Error>> {
+    let (mut config, source) = crate::config::Config::load(cli.config.as_deref())?;
+    let playback = &mut config.playback;
+    if let Some(v) = flag(cli.loop_playlist, cli.no_loop_playlist) {
+        playback.loop_playlist = v;
+    }
+    if let Some(v) = flag(cli.loop_individual_songs, cli.no_loop_individual_songs) {
+        playback.loop_individual_songs = v;
+    }
+    if let Some(v) = flag(cli.scan_random_start, cli.no_scan_random_start) {
+        playback.scan_random_start = v;
+    }
+    if let Some(delay) = cli.delay_between_songs {
+        playback.delay_between_songs = delay;
+    }
+    if let Some(duration) = cli.scan_duration {
+        playback.scan_duration = duration;
+    }
+    if let Some(scale) = cli.tempo_scale {
+        playback.tempo_scale = scale;
+    }
+    if let Some(port) = &cli.output_port {
+        config.output.port = Some(port.clone());
+    }
+    if !cli.scan_directories.is_empty() {
+        config.library.scan_directories = cli.scan_directories.clone();
+    }
+    if !cli.watch_directories.is_empty() {
+        config.library.watch_directories = cli.watch_directories.clone();
+    }
+    if !cli.add_songs.is_empty() {
+        config.library.add_songs = cli.add_songs.clone();
+    }
+    if let Some(v) = flag(cli.library, cli.no_library) {
+        config.library.index = v;
+    }
+    if let Some(v) = flag(cli.ipc, cli.no_ipc) {
+        config.ipc.enabled = v;
+    }
+    config.validate()?;
+    Ok((config, source))
 }
 
 pub fn run_cli() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
+    let (config, config_source) = effective_config(&cli)?;
+
+    if let Some(Commands::Config { action }) = &cli.command {
+        match action {
+            ConfigCommand::Show => {
+                match &config_source {
+                    Some(path) => println!("# Config file: {}", path.display()),
+                    None => println!("# No config file (defaults, environment and flags)"),
+                }
+                print!("{}", config.to_toml()?);
+            }
+            ConfigCommand::Path => match config_source
+                .or_else(|| cli.config.clone())
+                .or_else(crate::config::default_config_path)
+            {
+                Some(path) => println!("{}", path.display()),
+                None => return Err("No config directory on this platform".into()),
+            },
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Library) = &cli.command {
+        let path = config
+            .library_index_path()
+            .or_else(crate::library::default_index_path)
+            .ok_or("No data directory on this platform")?;
+        let index = crate::library::LibraryIndex::open(&path)?;
+        println!("📚 {} ({} songs)", path.display(), index.len());
+        for entry in index.entries() {
+            println!(
+                "  {:<40} plays: {:<4} last position: {:<8} {}",
+                entry.song.name,
+                entry.play_count,
+                crate::format_duration(entry.last_position_ms),
+                entry.path.display()
+            );
+        }
+        return Ok(());
+    }
 
-    let mut player = MidiPlayer::new()?; // Apply CLI configuration
-    {
-        let config = player.get_config_mut();
-        config.loop_playlist = cli.loop_playlist;
-        config.loop_individual_songs = cli.loop_individual_songs;
-        config.delay_between_songs_ms = cli.delay_between_songs * 1000;
-        config.scan_segment_duration_ms = cli.scan_duration * 1000;
-        config.scan_random_start = cli.scan_random_start;
+    // Offline rendering and export must work without any MIDI or audio device
+    let mut player = if matches!(
+        cli.command,
+        Some(Commands::Render { .. }) | Some(Commands::Export { .. })
+    ) {
+        MidiPlayer::new_offline()?
+    } else if let Some(soundfont) = &cli.soundfont {
+        soundfont_player(soundfont)?
+    } else if let Some(player) = superdirt_player(&cli)? {
+        player
+    } else {
+        MidiPlayer::with_output(&config.output)?
+    };
+    // Apply the effective configuration
+    *player.get_config_mut() = config.playback.loop_config();
+    player.set_key_bindings(config.keys.clone());
+    let use_ipc = config.ipc.enabled;
+    if let Some(path) = config.library_index_path() {
+        let index = crate::library::LibraryIndex::open(&path)?;
+        player.attach_library(index)?;
+    }
+    // MTC start offsets apply to both output and chase
+    let mtc_rate = cli.mtc.unwrap_or(crate::mtc::MtcFrameRate::Fps30);
+    let mut mtc_settings = crate::mtc::MtcSettings::new(mtc_rate);
+    cli.mtc_offset.validate(mtc_rate)?;
+    mtc_settings.default_offset = cli.mtc_offset;
+    for entry in &cli.mtc_song_offsets {
+        let (index, timecode) = entry
+            .split_once('=')
+            .ok_or("--mtc-song-offset must look like INDEX=hh:mm:ss:ff")?;
+        mtc_settings.song_offsets.insert(
+            index.trim().parse()?,
+            crate::mtc::Timecode::parse_at(timecode, mtc_rate)?,
+        );
+    }
+    if cli.mtc.is_some() {
+        player.set_mtc_output(Some(mtc_settings.clone()));
     }
     player.init_ipc_publisher()?; // Initialize IPC publisher
-                                  // Process global options to add songs/directories to dynamic playlist
-    for path in &cli.add_songs {
+    player.set_ipc_enabled(use_ipc);
+    // Process global options to add songs/directories to dynamic playlist
+    for path in &config.add_songs() {
         let path_str = path.to_string_lossy();
         // if path_str.starts_with("http://") || path_str.starts_with("https://") {
         //     // Download file from URL
@@ -160,12 +578,19 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
     //         Err(e) => eprintln!("❌ Failed to scan {}: {}", path.display(), e),
     //     }
     // }
-    for path in &cli.scan_directories {
+    for path in &config.scan_directories() {
         match player.scan_directory(path) {
             Ok(count) => println!("✅ Added {} songs from {}", count, path.display()),
             Err(e) => eprintln!("❌ Failed to scan {}: {}", path.display(), e),
         }
     }
+    for path in &config.watch_directories() {
+        match player.watch_directory(path) {
+            Ok(count) => println!("✅ Added {} songs from {}", count, path.display()),
+            Err(e) => eprintln!("❌ Failed to watch {}: {}", path.display(), e),
+        }
+    }
+    player.save_library();
 
     match cli.command {
         Some(Commands::List) => {
@@ -189,7 +614,7 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
             let result: Result<(), Box<dyn Error>> = if loop_individual {
                 // For looping, we need to handle it differently
                 loop {
-                    if cli.ipc {
+                    if use_ipc {
                         player.play_song_with_ipc(song_index)?;
                     } else {
                         let continue_playing =
@@ -201,7 +626,7 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
                 }
                 Ok(())
             } else {
-                if cli.ipc {
+                if use_ipc {
                     player.play_song_with_ipc(song_index)?;
                 } else {
                     player.play_song(song_index, tracks, tempo)?;
@@ -213,7 +638,7 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
         }
 
         Some(Commands::PlayAll) => {
-            if cli.ipc {
+            if use_ipc {
                 // Play all songs with IPC event publishing
                 for i in 0..player.get_total_song_count() {
                     player.play_song_with_ipc(i)?;
@@ -227,7 +652,7 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
             player.play_random_song()?;
         }
         Some(Commands::Scan { mode, duration }) => {
-            let scan_duration = duration.unwrap_or(cli.scan_duration);
+            let scan_duration = duration.unwrap_or(config.playback.scan_duration);
             player.scan_mode_non_interactive(scan_duration, mode)?;
         }
         Some(Commands::ListDynamic) => {
@@ -237,6 +662,169 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
         Some(Commands::ClearDynamic) => {
             player.clear_dynamic_songs();
         }
+        Some(Commands::Record {
+            port,
+            output,
+            overdub,
+            duration,
+            tempo,
+            list_ports,
+        }) => {
+            if list_ports {
+                println!("🎹 Available MIDI input ports:");
+                for (i, name) in crate::record::list_input_ports()?.iter().enumerate() {
+                    println!("  {}: {}", i, name);
+                }
+                return Ok(());
+            }
+            let options = crate::record::RecordOptions {
+                port_index: port,
+                output,
+                overdub_song: overdub,
+                max_duration_secs: duration,
+                tempo_bpm: tempo,
+            };
+            let index = player.record_from_input(&options)?;
+            println!("🎵 Recording available as song {}", index);
+        }
+        Some(Commands::Export {
+            song_index,
+            output,
+            format,
+            tracks,
+            tempo,
+            grid,
+            time,
+            key,
+        }) => {
+            let song_name = player
+                .get_song(song_index)
+                .map(|s| s.name.clone())
+                .ok_or_else(|| format!("Invalid song index {}", song_index))?;
+            let extension = output
+                .as_ref()
+                .and_then(|p| p.extension())
+                .map(|e| e.to_string_lossy().to_ascii_lowercase());
+            let musicxml = match format.as_deref() {
+                Some("musicxml") | Some("xml") => true,
+                Some("midi") | Some("mid") => false,
+                Some(other) => return Err(format!("Unknown export format: {}", other).into()),
+                None => matches!(extension.as_deref(), Some("musicxml") | Some("xml")),
+            };
+            if musicxml {
+                let time_signature = match time {
+                    Some(t) => {
+                        let (beats, beat_type) = t
+                            .split_once('/')
+                            .ok_or("Time signature must look like 3/4")?;
+                        Some((beats.trim().parse()?, beat_type.trim().parse()?))
+                    }
+                    None => None,
+                };
+                let options = crate::musicxml_export::MusicXmlExportOptions {
+                    grid,
+                    tracks,
+                    tempo_bpm: tempo,
+                    time_signature,
+                    key_fifths: key,
+                };
+                let output = output.unwrap_or_else(|| default_output(&song_name, "musicxml"));
+                player.export_song_musicxml(song_index, &output, &options)?;
+            } else {
+                let output = output.unwrap_or_else(|| default_output(&song_name, "mid"));
+                player.export_song(song_index, &output, tracks.as_deref(), tempo)?;
+            }
+        }
+        Some(Commands::Render {
+            song_index,
+            output,
+            tracks,
+            tempo,
+            sample_rate,
+        }) => {
+            let song_name = player
+                .get_song(song_index)
+                .map(|s| s.name.clone())
+                .ok_or_else(|| format!("Invalid song index {}", song_index))?;
+            let options = crate::render::RenderOptions {
+                tracks,
+                tempo_bpm: tempo,
+                sample_rate,
+                soundfont: cli.soundfont.clone(),
+            };
+            let output = output.unwrap_or_else(|| default_output(&song_name, "wav"));
+            player.render_song_to_wav(song_index, &output, &options)?;
+        }
+        Some(Commands::Thru {
+            port,
+            transforms,
+            play,
+        }) => {
+            let transforms = transforms
+                .iter()
+                .map(|t| t.parse::<crate::thru::Transform>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let thru = player.start_thru(port, transforms)?;
+            match play {
+                Some(song_index) => {
+                    player.play_song(song_index, None, None)?;
+                }
+                None => {
+                    println!("⏎ Press Enter to stop thru...");
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                }
+            }
+            thru.stop();
+        }
+        #[cfg(feature = "uses_osc")]
+        Some(Commands::Osc { bind }) => {
+            player.run_osc_server(bind.as_str())?;
+        }
+        #[cfg(unix)]
+        Some(Commands::Rpc { socket }) => {
+            let socket = socket.unwrap_or_else(crate::rpc::default_socket_path);
+            player.run_rpc_server(socket)?;
+        }
+        Some(Commands::Serve {
+            pid_file,
+            heartbeat_ms,
+            #[cfg(feature = "uses_osc")]
+            osc,
+            #[cfg(unix)]
+            rpc,
+        }) => {
+            let options = crate::serve::ServeOptions {
+                pid_file: pid_file.unwrap_or_else(crate::serve::default_pid_file),
+                heartbeat_interval: std::time::Duration::from_millis(heartbeat_ms.max(1)),
+                #[cfg(feature = "uses_osc")]
+                osc_bind: osc,
+                #[cfg(unix)]
+                rpc_socket: rpc.map(|path| {
+                    if path.as_os_str().is_empty() {
+                        crate::rpc::default_socket_path()
+                    } else {
+                        path
+                    }
+                }),
+            };
+            player.serve(options)?;
+        }
+        Some(Commands::Sync { action }) => run_sync_command(&mut player, action)?,
+        Some(Commands::Chase { song_index, port }) => {
+            let start_offset = mtc_settings.song_offset(song_index);
+            let chaser = player.chase_mtc(port, song_index, start_offset)?;
+            println!(
+                "⏎ Song {} starts at {}; press Enter to stop chasing...",
+                song_index, start_offset
+            );
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            chaser.stop();
+        }
+        Some(Commands::Config { .. }) | Some(Commands::Library) => {
+            unreachable!("handled before the player is created")
+        }
         Some(Commands::Interactive) | None => {
             // Choose between TUI and CLI mode
             if cli.tui {
@@ -250,6 +838,149 @@ pub fn run_cli() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn run_sync_command(player: &mut MidiPlayer, action: SyncCommand) -> Result<(), Box<dyn Error>> {
+    use crate::sync::{heartbeat_from_now, play_at_heartbeat, send_sync_message};
+
+    match action {
+        SyncCommand::Follow {
+            master,
+            interval_ms,
+            tracks,
+        } => player.run_sync(crate::sync::SyncOptions {
+            master,
+            interval: std::time::Duration::from_millis(interval_ms.max(1)),
+            tracks: tracks.unwrap_or_default(),
+        }),
+        SyncCommand::Play {
+            song,
+            in_beats,
+            beats,
+            for_ms,
+            voices,
+        } => {
+            let song_index = player
+                .find_song(&song)
+                .ok_or_else(|| format!("No song matches '{}'", song))?;
+            let info = player.get_song(song_index).ok_or("Invalid song index")?;
+            let mut overrides = Vec::new();
+            for voice in &voices {
+                let (track, program) = voice
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid voice '{}' (expected TRACK=PROGRAM)", voice))?;
+                let track: usize = track.trim().parse()?;
+                let program: u8 = program.trim().parse()?;
+                if program > 127 {
+                    return Err(format!("Invalid program {} (must be 0-127)", program).into());
+                }
+                let dense = info
+                    .track_index_map
+                    .get(&track)
+                    .ok_or_else(|| format!("Song {} has no track {}", song_index, track))?;
+                overrides.push((*dense, program));
+            }
+            let start = heartbeat_from_now(in_beats)?;
+            let stop = beats.map(|b| start.saturating_add(b));
+            let play_for = for_ms.map(|ms| std::time::Duration::from_millis(ms as u64));
+            let msg = play_at_heartbeat(song_index, start, stop, play_for, &overrides)?;
+            send_sync_message(&msg)?;
+            println!("🕐 {} starts on heartbeat {}", info.name, start);
+            Ok(())
+        }
+        SyncCommand::Stop { in_beats } => {
+            let stop = if in_beats == 0 {
+                0
+            } else {
+                heartbeat_from_now(in_beats)?
+            };
+            send_sync_message(&play_at_heartbeat(0, 0, Some(stop), None, &[])?)?;
+            match stop {
+                0 => println!("⏹️  Stop sent"),
+                beat => println!("⏹️  Stopping on heartbeat {}", beat),
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Create a player that plays through a SoundFont on the default audio device
+#[cfg(feature = "uses_rodio")]
+fn soundfont_player(path: &std::path::Path) -> Result<MidiPlayer, Box<dyn Error>> {
+    MidiPlayer::with_soundfont(path)
+}
+
+/// Create a player on SuperDirt when `--superdirt` is given
+#[cfg(feature = "uses_osc")]
+fn superdirt_player(cli: &Cli) -> Result<Option<MidiPlayer>, Box<dyn Error>> {
+    use crate::superdirt::{DirtConfig, DirtSound};
+
+    let Some(addr) = &cli.superdirt else {
+        return Ok(None);
+    };
+    let mut config = DirtConfig {
+        latency: std::time::Duration::from_millis(cli.dirt_latency),
+        ..DirtConfig::default()
+    };
+    for entry in &cli.dirt_sounds {
+        let (channel, sound) = entry
+            .split_once('=')
+            .ok_or("--dirt-sound must look like CHANNEL=SOUND[:N]")?;
+        let channel = match channel.trim().parse::<u8>() {
+            Ok(c @ 1..=16) => c - 1,
+            _ => return Err(format!("Invalid MIDI channel '{}' (expected 1-16)", channel).into()),
+        };
+        config
+            .mapping
+            .channels
+            .insert(channel, sound.parse::<DirtSound>()?);
+    }
+    for entry in &cli.dirt_drums {
+        let (key, sound) = entry
+            .split_once('=')
+            .ok_or("--dirt-drum must look like KEY=SOUND[:N]")?;
+        config
+            .mapping
+            .drums
+            .insert(key.trim().parse()?, sound.parse::<DirtSound>()?);
+    }
+    Ok(Some(MidiPlayer::with_superdirt(addr.as_str(), config)?))
+}
+
+#[cfg(not(feature = "uses_osc"))]
+fn superdirt_player(_cli: &Cli) -> Result<Option<MidiPlayer>, Box<dyn Error>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "uses_rodio"))]
+fn soundfont_player(_path: &std::path::Path) -> Result<MidiPlayer, Box<dyn Error>> {
+    Err("Realtime SoundFont playback requires the uses_rodio feature; use `render --soundfont` for offline output".into())
+}
+
+/// Default output file for a song: the name without its file extension and
+/// with path separators replaced, so it lands in the current directory
+fn default_output(song_name: &str, extension: &str) -> std::path::PathBuf {
+    let stem = match song_name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.is_empty()
+                && ["mid", "midi", "xml", "musicxml"]
+                    .contains(&ext.to_ascii_lowercase().as_str()) =>
+        {
+            stem
+        }
+        _ => song_name,
+    };
+    let stem: String = stem
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\') { '_' } else { c })
+        .collect();
+    let stem = stem.trim();
+    format!(
+        "{}.{}",
+        if stem.is_empty() { "song" } else { stem },
+        extension
+    )
+    .into()
+}
+
 pub fn print_help() {
     let _cli = Cli::parse_from(["e_midi", "--help"]);
 }
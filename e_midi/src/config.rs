@@ -0,0 +1,556 @@
+//! TOML configuration file.
+//!
+//! Read from `$XDG_CONFIG_HOME/e_midi/config.toml` (or the platform config
+//! dir), `--config PATH` or `$E_MIDI_CONFIG`. Every key is optional:
+//!
+//! ```toml
+//! [playback]
+//! loop_playlist = true
+//! delay_between_songs = 2     # seconds
+//! scan_duration = 20          # seconds
+//! tempo_scale = 0.9           # multiplies each song's default tempo
+//!
+//! [output]
+//! port = "FluidSynth"         # port index or part of its name
+//! [output.routing]            # MIDI channel (1-16) -> port
+//! "10" = "Drum Machine"
+//!
+//! [library]
+//! scan_directories = ["~/midi"]
+//! watch_directories = ["~/daw/exports"]
+//! index = true                # remember songs, play counts and positions
+//!
+//! [keys]                      # TUI key bindings
+//! play = ["enter", "space"]
+//! next = ["n", "right"]
+//!
+//! [ipc]
+//! enabled = true
+//! ```
+//!
+//! Settings resolve as command line > `E_MIDI_*` environment > file > defaults.
+
+use crate::LoopConfig;
+use crossterm::event::{KeyCode, KeyModifiers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the config file
+pub const CONFIG_ENV: &str = "E_MIDI_CONFIG";
+
+/// `$XDG_CONFIG_HOME/e_midi/config.toml` or the platform equivalent
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("e_midi").join("config.toml"))
+}
+
+/// Expand a leading `~/` to the home directory
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub playback: PlaybackConfig,
+    pub output: OutputConfig,
+    pub library: LibraryConfig,
+    pub keys: KeyBindings,
+    pub ipc: IpcConfig,
+}
+
+/// `LoopConfig` defaults and tempo scaling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    pub loop_playlist: bool,
+    pub loop_individual_songs: bool,
+    /// Seconds between songs
+    pub delay_between_songs: u32,
+    /// Scan segment length in seconds
+    pub scan_duration: u32,
+    pub scan_random_start: bool,
+    /// Multiplier for each song's default tempo
+    pub tempo_scale: f64,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        let defaults = LoopConfig::default();
+        PlaybackConfig {
+            loop_playlist: defaults.loop_playlist,
+            loop_individual_songs: defaults.loop_individual_songs,
+            delay_between_songs: defaults.delay_between_songs_ms / 1000,
+            scan_duration: defaults.scan_segment_duration_ms / 1000,
+            scan_random_start: defaults.scan_random_start,
+            tempo_scale: defaults.tempo_scale,
+        }
+    }
+}
+
+impl PlaybackConfig {
+    pub fn loop_config(&self) -> LoopConfig {
+        LoopConfig {
+            loop_playlist: self.loop_playlist,
+            loop_individual_songs: self.loop_individual_songs,
+            scan_segment_duration_ms: self.scan_duration.saturating_mul(1000),
+            scan_random_start: self.scan_random_start,
+            delay_between_songs_ms: self.delay_between_songs.saturating_mul(1000),
+            tempo_scale: self.tempo_scale,
+        }
+    }
+}
+
+/// MIDI output port selection and per-channel routing
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// Port index or part of its name (first port if unset)
+    pub port: Option<String>,
+    /// MIDI channel ("1"-"16") to port, for channels that go elsewhere
+    pub routing: BTreeMap<String, String>,
+}
+
+impl OutputConfig {
+    /// True if the first available port will do
+    pub fn is_default(&self) -> bool {
+        self.port.is_none() && self.routing.is_empty()
+    }
+
+    /// Routing as zero-based channel to port spec
+    pub fn channel_routes(&self) -> Result<Vec<(u8, String)>, String> {
+        self.routing
+            .iter()
+            .map(|(channel, port)| match channel.trim().parse::<u8>() {
+                Ok(c @ 1..=16) => Ok((c - 1, port.clone())),
+                _ => Err(format!(
+                    "Routing channel '{}' is out of range (1-16)",
+                    channel
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Songs loaded at startup
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    pub scan_directories: Vec<PathBuf>,
+    pub add_songs: Vec<PathBuf>,
+    /// Directories to load and then follow for added, changed and deleted files
+    pub watch_directories: Vec<PathBuf>,
+    /// Keep a persistent library index (see `library`)
+    pub index: bool,
+    /// Index file (default: $XDG_DATA_HOME/e_midi/library.json)
+    pub index_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpcConfig {
+    /// Play through the IPC-publishing paths (same as `--ipc`)
+    pub enabled: bool,
+}
+
+/// TUI actions that can be bound to keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Play,
+    Stop,
+    List,
+    Tempo,
+    Next,
+    Previous,
+    Help,
+    Quit,
+    ClearDynamic,
+}
+
+/// TUI key bindings: key names like "q", "enter", "space", "pageup", "alt+c"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub up: Vec<String>,
+    pub down: Vec<String>,
+    pub page_up: Vec<String>,
+    pub page_down: Vec<String>,
+    pub play: Vec<String>,
+    pub stop: Vec<String>,
+    pub list: Vec<String>,
+    pub tempo: Vec<String>,
+    pub next: Vec<String>,
+    pub previous: Vec<String>,
+    pub help: Vec<String>,
+    pub quit: Vec<String>,
+    pub clear_dynamic: Vec<String>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let keys = |names: &[&str]| names.iter().map(|s| s.to_string()).collect();
+        KeyBindings {
+            up: keys(&["up"]),
+            down: keys(&["down"]),
+            page_up: keys(&["pageup"]),
+            page_down: keys(&["pagedown"]),
+            play: keys(&["enter", "space"]),
+            stop: keys(&["s"]),
+            list: keys(&["l"]),
+            tempo: keys(&["t"]),
+            next: keys(&["n"]),
+            previous: keys(&["p"]),
+            help: keys(&["h"]),
+            quit: keys(&["esc", "q"]),
+            clear_dynamic: keys(&["alt+c"]),
+        }
+    }
+}
+
+/// Parse "q", "enter", "ctrl+x", "alt+c", "f5", ...
+pub fn parse_key(name: &str) -> Result<(KeyCode, KeyModifiers), String> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut parts: Vec<&str> = name.split('+').collect();
+    // "+" itself, or a trailing "+" as in "ctrl++"
+    if parts.len() > 1 && parts.last() == Some(&"") {
+        parts.pop();
+        *parts.last_mut().unwrap() = "+";
+    }
+    let key = parts.pop().unwrap_or_default();
+    for modifier in parts {
+        modifiers |= match modifier.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            other => return Err(format!("Unknown modifier '{}' in key '{}'", other, name)),
+        };
+    }
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key.to_ascii_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "space" => KeyCode::Char(' '),
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            other => match other.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n @ 1..=24) => KeyCode::F(n),
+                _ => return Err(format!("Unknown key '{}'", name)),
+            },
+        },
+    };
+    Ok((code, modifiers))
+}
+
+impl KeyBindings {
+    fn entries(&self) -> [(KeyAction, &Vec<String>); 13] {
+        [
+            (KeyAction::Up, &self.up),
+            (KeyAction::Down, &self.down),
+            (KeyAction::PageUp, &self.page_up),
+            (KeyAction::PageDown, &self.page_down),
+            (KeyAction::Play, &self.play),
+            (KeyAction::Stop, &self.stop),
+            (KeyAction::List, &self.list),
+            (KeyAction::Tempo, &self.tempo),
+            (KeyAction::Next, &self.next),
+            (KeyAction::Previous, &self.previous),
+            (KeyAction::Help, &self.help),
+            (KeyAction::Quit, &self.quit),
+            (KeyAction::ClearDynamic, &self.clear_dynamic),
+        ]
+    }
+
+    /// Check every key name parses
+    pub fn validate(&self) -> Result<(), String> {
+        for (_, names) in self.entries() {
+            for name in names {
+                parse_key(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Action bound to a key press
+    ///
+    /// Ctrl/Alt must match the binding exactly; Shift is ignored unless the
+    /// binding asks for it (typed capitals already arrive as `Char('S')`).
+    pub fn action(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<KeyAction> {
+        self.entries().into_iter().find_map(|(action, names)| {
+            names
+                .iter()
+                .filter_map(|name| parse_key(name).ok())
+                .any(|(key, wanted)| {
+                    let significant = if wanted.contains(KeyModifiers::SHIFT) {
+                        modifiers
+                    } else {
+                        modifiers - KeyModifiers::SHIFT
+                    };
+                    key == code && significant == wanted
+                })
+                .then_some(action)
+        })
+    }
+
+    /// First key bound to an action, for help text
+    pub fn label(&self, action: KeyAction) -> String {
+        self.entries()
+            .into_iter()
+            .find(|(a, _)| *a == action)
+            .and_then(|(_, names)| names.first().cloned())
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Result<Option<T>, Box<dyn Error>> {
+    env_var(name)
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|_| format!("Invalid value '{}' for {}", v, name).into())
+        })
+        .transpose()
+}
+
+fn env_bool(name: &str) -> Result<Option<bool>, Box<dyn Error>> {
+    env_var(name)
+        .map(|v| match v.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(format!("Invalid value '{}' for {} (expected true/false)", v, name).into()),
+        })
+        .transpose()
+}
+
+impl Config {
+    /// Parse a config file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// File and environment layers, plus the file that was read (if any)
+    ///
+    /// An explicit path (`--config` or `$E_MIDI_CONFIG`) must exist; the
+    /// default location is optional.
+    pub fn load(explicit: Option<&Path>) -> Result<(Self, Option<PathBuf>), Box<dyn Error>> {
+        let explicit = explicit
+            .map(Path::to_path_buf)
+            .or_else(|| env_var(CONFIG_ENV).map(PathBuf::from));
+        let (mut config, source) = match explicit {
+            Some(path) => (Config::from_file(&path)?, Some(path)),
+            None => match default_config_path().filter(|p| p.is_file()) {
+                Some(path) => (Config::from_file(&path)?, Some(path)),
+                None => (Config::default(), None),
+            },
+        };
+        config.apply_env()?;
+        Ok((config, source))
+    }
+
+    /// Override settings from `E_MIDI_*` environment variables
+    pub fn apply_env(&mut self) -> Result<(), Box<dyn Error>> {
+        let playback = &mut self.playback;
+        if let Some(v) = env_bool("E_MIDI_LOOP_PLAYLIST")? {
+            playback.loop_playlist = v;
+        }
+        if let Some(v) = env_bool("E_MIDI_LOOP_SONGS")? {
+            playback.loop_individual_songs = v;
+        }
+        if let Some(v) = env_parse("E_MIDI_DELAY")? {
+            playback.delay_between_songs = v;
+        }
+        if let Some(v) = env_parse("E_MIDI_SCAN_DURATION")? {
+            playback.scan_duration = v;
+        }
+        if let Some(v) = env_bool("E_MIDI_SCAN_RANDOM")? {
+            playback.scan_random_start = v;
+        }
+        if let Some(v) = env_parse("E_MIDI_TEMPO_SCALE")? {
+            playback.tempo_scale = v;
+        }
+        if let Some(v) = env_var("E_MIDI_PORT") {
+            self.output.port = Some(v);
+        }
+        if let Some(v) = std::env::var_os("E_MIDI_SCAN_DIRS").filter(|v| !v.is_empty()) {
+            self.library.scan_directories = std::env::split_paths(&v).collect();
+        }
+        if let Some(v) = std::env::var_os("E_MIDI_WATCH_DIRS").filter(|v| !v.is_empty()) {
+            self.library.watch_directories = std::env::split_paths(&v).collect();
+        }
+        if let Some(v) = env_bool("E_MIDI_LIBRARY")? {
+            self.library.index = v;
+        }
+        if let Some(v) = env_bool("E_MIDI_IPC")? {
+            self.ipc.enabled = v;
+        }
+        self.validate()
+    }
+
+    /// Range-check values that serde accepts but playback can't use
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let scale = self.playback.tempo_scale;
+        if !(scale.is_finite() && (0.1..=10.0).contains(&scale)) {
+            return Err(format!("tempo_scale {} is out of range (0.1-10)", scale).into());
+        }
+        self.output.channel_routes()?;
+        self.keys.validate()?;
+        Ok(())
+    }
+
+    /// Directories to scan with `~` expanded
+    pub fn scan_directories(&self) -> Vec<PathBuf> {
+        self.library
+            .scan_directories
+            .iter()
+            .map(|p| expand_home(p))
+            .collect()
+    }
+
+    /// Directories to watch with `~` expanded
+    pub fn watch_directories(&self) -> Vec<PathBuf> {
+        self.library
+            .watch_directories
+            .iter()
+            .map(|p| expand_home(p))
+            .collect()
+    }
+
+    /// Library index file, if the index is enabled
+    pub fn library_index_path(&self) -> Option<PathBuf> {
+        if !self.library.index {
+            return None;
+        }
+        match &self.library.index_path {
+            Some(path) => Some(expand_home(path)),
+            None => crate::library::default_index_path(),
+        }
+    }
+
+    /// Songs to add with `~` expanded
+    pub fn add_songs(&self) -> Vec<PathBuf> {
+        self.library
+            .add_songs
+            .iter()
+            .map(|p| expand_home(p))
+            .collect()
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{effective_config, Cli};
+    use clap::Parser;
+
+    #[test]
+    fn validate_range_checks() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        for scale in [0.0, 0.05, 10.5, f64::NAN, f64::INFINITY] {
+            config.playback.tempo_scale = scale;
+            assert!(config.validate().is_err(), "tempo_scale {} accepted", scale);
+        }
+        config.playback.tempo_scale = 0.8;
+        config
+            .output
+            .routing
+            .insert("10".to_string(), "Drums".to_string());
+        assert!(config.validate().is_ok());
+        config
+            .output
+            .routing
+            .insert("17".to_string(), "Nowhere".to_string());
+        assert!(config.validate().is_err());
+        config.output.routing.clear();
+        config.keys.play = vec!["hyper+x".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn file_rejects_unknown_keys() {
+        assert!(toml::from_str::<Config>("[playback]\nloop_playlists = true\n").is_err());
+    }
+
+    /// Command line over `E_MIDI_*` environment over file over defaults
+    ///
+    /// The only test touching the environment, so it can't race another.
+    #[test]
+    fn command_line_beats_environment_beats_file() {
+        let path =
+            std::env::temp_dir().join(format!("e_midi_config_test_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[playback]\nloop_playlist = true\ndelay_between_songs = 5\nscan_duration = 12\n\n\
+             [library]\nindex = true\n\n[ipc]\nenabled = true\n",
+        )
+        .unwrap();
+        std::env::set_var("E_MIDI_DELAY", "7");
+        std::env::set_var("E_MIDI_IPC", "false");
+        let config_arg = path.to_str().unwrap();
+        let resolve = |args: &[&str]| {
+            let mut argv = vec!["e_midi", "--config", config_arg];
+            argv.extend_from_slice(args);
+            effective_config(&Cli::try_parse_from(argv).unwrap())
+                .unwrap()
+                .0
+        };
+
+        let config = resolve(&[]);
+        assert!(config.playback.loop_playlist); // file
+        assert_eq!(config.playback.scan_duration, 12); // file
+        assert_eq!(config.playback.delay_between_songs, 7); // env over file
+        assert!(!config.ipc.enabled); // env over file
+        assert!(config.library.index); // file
+
+        let config = resolve(&[
+            "--no-loop-playlist",
+            "--delay-between-songs",
+            "9",
+            "--ipc",
+            "--no-library",
+        ]);
+        assert!(!config.playback.loop_playlist);
+        assert_eq!(config.playback.delay_between_songs, 9);
+        assert!(config.ipc.enabled);
+        assert!(!config.library.index);
+
+        // The last of a --flag / --no-flag pair wins
+        let config = resolve(&["--no-loop-playlist", "--loop-playlist"]);
+        assert!(config.playback.loop_playlist);
+
+        std::env::remove_var("E_MIDI_DELAY");
+        std::env::remove_var("E_MIDI_IPC");
+        let _ = std::fs::remove_file(&path);
+    }
+}
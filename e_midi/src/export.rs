@@ -0,0 +1,458 @@
+//! Export library songs (static, MusicXML or dynamic) to Standard MIDI Files.
+//!
+//! Songs are rendered through `get_events_for_song`, so anything the player can
+//! play can be saved, which also makes this the MusicXML → MIDI conversion path.
+
+use crate::{MidiPlayer, Note, SongInfo, SongType};
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::error::Error;
+use std::path::Path;
+
+/// Resolution used for exported Standard MIDI Files
+pub const EXPORT_TICKS_PER_Q: u16 = 480;
+
+/// Build a note-on (vel > 0) or note-off (vel == 0) track event
+pub(crate) fn note_event(chan: u8, pitch: u8, vel: u8) -> TrackEventKind<'static> {
+    let message = if vel > 0 {
+        MidiMessage::NoteOn {
+            key: u7::new(pitch & 0x7F),
+            vel: u7::new(vel & 0x7F),
+        }
+    } else {
+        MidiMessage::NoteOff {
+            key: u7::new(pitch & 0x7F),
+            vel: u7::new(0),
+        }
+    };
+    TrackEventKind::Midi {
+        channel: u4::new(chan & 0x0F),
+        message,
+    }
+}
+
+/// Sort absolute-tick events and convert them to a delta-timed track
+pub(crate) fn to_track<'a>(
+    name: &'a [u8],
+    mut events: Vec<(u64, TrackEventKind<'a>)>,
+) -> Vec<TrackEvent<'a>> {
+    // Stable sort preserves arrival order for events on the same tick
+    events.sort_by_key(|(tick, _)| *tick);
+    let mut track = Vec::with_capacity(events.len() + 2);
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::TrackName(name)),
+    });
+    let mut last_tick = 0u64;
+    for (tick, kind) in events {
+        let delta = (tick - last_tick).min(0x0FFF_FFFF) as u32;
+        last_tick = tick;
+        track.push(TrackEvent {
+            delta: u28::new(delta),
+            kind,
+        });
+    }
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+/// Tempo, time signature and key signature events of SMF data, at ticks
+/// rescaled to `ticks_per_q`
+pub(crate) fn conductor_events(
+    data: &[u8],
+    ticks_per_q: u16,
+) -> Vec<(u64, TrackEventKind<'static>)> {
+    let Ok(smf) = Smf::parse(data) else {
+        return Vec::new();
+    };
+    let Timing::Metrical(source_tpq) = smf.header.timing else {
+        return Vec::new();
+    };
+    let source_tpq = source_tpq.as_int().max(1) as u64;
+    let mut events = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            let meta = match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(t)) => MetaMessage::Tempo(t),
+                TrackEventKind::Meta(MetaMessage::TimeSignature(n, d, c, b)) => {
+                    MetaMessage::TimeSignature(n, d, c, b)
+                }
+                TrackEventKind::Meta(MetaMessage::KeySignature(fifths, minor)) => {
+                    MetaMessage::KeySignature(fifths, minor)
+                }
+                _ => continue,
+            };
+            let event = (
+                tick * ticks_per_q as u64 / source_tpq,
+                TrackEventKind::Meta(meta),
+            );
+            // Type-1 files often repeat the key signature in every track
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+    }
+    events
+}
+
+/// Map a note's `track` field to the dense index of its `TrackInfo`
+///
+/// MIDI songs tag notes with the original SMF track index, MusicXML songs with
+/// the dense part index.
+pub(crate) fn dense_track_index(song: &SongInfo, note_track: u8) -> Option<usize> {
+    let note_track = note_track as usize;
+    if song.song_type == SongType::MusicXml {
+        (note_track < song.tracks.len()).then_some(note_track)
+    } else {
+        song.track_index_map.get(&note_track).copied()
+    }
+}
+
+/// Program change for each channel used by `notes`, from its track's program
+pub(crate) fn program_changes(song: &SongInfo, notes: &[Note]) -> Vec<Vec<u8>> {
+    let mut setup = Vec::new();
+    let mut seen = [false; 16];
+    for note in notes {
+        let chan = note.chan & 0x0F;
+        if seen[chan as usize] {
+            continue;
+        }
+        seen[chan as usize] = true;
+        let program = dense_track_index(song, note.track)
+            .and_then(|dense| song.tracks[dense].program)
+            .unwrap_or(0);
+        setup.push(vec![0xC0 | chan, program & 0x7F]);
+    }
+    setup
+}
+
+/// Display name for an exported track
+pub(crate) fn track_display_name(song: &SongInfo, dense: usize) -> String {
+    let track = &song.tracks[dense];
+    match (track.program, &track.guess) {
+        _ if track.channels.contains(&9) => "Drums".to_string(),
+        (Some(program), _) if song.song_type == SongType::Midi => {
+            e_midi_shared::midi::gm_instrument_name(program).to_string()
+        }
+        (_, Some(guess)) => guess.clone(),
+        (Some(program), None) => e_midi_shared::midi::gm_instrument_name(program).to_string(),
+        (None, None) => format!("Track {}", track.index),
+    }
+}
+
+impl MidiPlayer {
+    /// Render a song to a type-1 Standard MIDI File
+    ///
+    /// `tracks` uses the same user-facing track numbers as `play --tracks`
+    /// (None or 0 for all tracks). Track 0 of the file carries the song's
+    /// tempo map and time and key signatures (a single tempo when `tempo_bpm`
+    /// is given); every selected `TrackInfo` gets its own track with its
+    /// program change.
+    pub fn export_song_to_smf(
+        &self,
+        song_index: usize,
+        tracks: Option<&[usize]>,
+        tempo_bpm: Option<u32>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let song = self.get_song(song_index).ok_or("Invalid song index")?;
+        if !matches!(song.song_type, SongType::Midi | SongType::MusicXml) {
+            return Err(format!("{} is not a MIDI or MusicXML song", song.name).into());
+        }
+        let tempo = tempo_bpm.unwrap_or(song.default_tempo).max(1);
+        let dense_indices = Self::get_dense_indices_for_song(song, tracks);
+        let events = self.get_events_for_song(song_index, &dense_indices, tempo);
+
+        // Notes are timed at one tempo, so this recovers their original ticks
+        let tpq = EXPORT_TICKS_PER_Q as u64;
+        let ms_to_tick = |ms: u32| ms as u64 * tpq * tempo as u64 / 60_000;
+
+        // Group notes per selected TrackInfo, note-offs ahead of note-ons on the same tick
+        let mut per_track: Vec<Vec<(u64, bool, Note)>> = vec![Vec::new(); song.tracks.len()];
+        for note in &events {
+            match dense_track_index(song, note.track) {
+                Some(dense) if dense_indices.contains(&dense) => {
+                    per_track[dense].push((ms_to_tick(note.start_ms), true, note.clone()));
+                    per_track[dense].push((
+                        ms_to_tick(note.start_ms + note.dur_ms),
+                        false,
+                        note.clone(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let names: Vec<String> = (0..song.tracks.len())
+            .map(|dense| track_display_name(song, dense))
+            .collect();
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(EXPORT_TICKS_PER_Q)),
+        ));
+        let single_tempo = (
+            0,
+            TrackEventKind::Meta(MetaMessage::Tempo(u24::new(60_000_000 / tempo))),
+        );
+        let mut conductor = self
+            .song_midi_data(song_index)
+            .map(|data| conductor_events(data, EXPORT_TICKS_PER_Q))
+            .unwrap_or_default();
+        let has_tempo_map = conductor
+            .iter()
+            .any(|(_, kind)| matches!(kind, TrackEventKind::Meta(MetaMessage::Tempo(_))));
+        if tempo_bpm.is_some() || !has_tempo_map {
+            conductor
+                .retain(|(_, kind)| !matches!(kind, TrackEventKind::Meta(MetaMessage::Tempo(_))));
+            conductor.insert(0, single_tempo);
+        }
+        smf.tracks.push(to_track(song.name.as_bytes(), conductor));
+        for &dense in &dense_indices {
+            let Some(mut notes) = per_track.get(dense).cloned() else {
+                continue;
+            };
+            if notes.is_empty() {
+                continue;
+            }
+            notes.sort_by_key(|(tick, on, _)| (*tick, *on));
+            let track = &song.tracks[dense];
+            let mut channels: Vec<u8> = notes.iter().map(|(_, _, n)| n.chan).collect();
+            channels.sort_unstable();
+            channels.dedup();
+            let mut kinds = Vec::with_capacity(notes.len() + channels.len());
+            if let Some(program) = track.program {
+                for &chan in &channels {
+                    kinds.push((
+                        0,
+                        TrackEventKind::Midi {
+                            channel: u4::new(chan & 0x0F),
+                            message: MidiMessage::ProgramChange {
+                                program: u7::new(program & 0x7F),
+                            },
+                        },
+                    ));
+                }
+            }
+            for (tick, on, note) in notes {
+                let vel = if on { note.vel.max(1) } else { 0 };
+                kinds.push((tick, note_event(note.chan, note.pitch, vel)));
+            }
+            smf.tracks.push(to_track(names[dense].as_bytes(), kinds));
+        }
+        if smf.tracks.len() == 1 {
+            return Err("No notes to export! Check track selection.".into());
+        }
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Write a song to a Standard MIDI File on disk
+    pub fn export_song<P: AsRef<Path>>(
+        &self,
+        song_index: usize,
+        path: P,
+        tracks: Option<&[usize]>,
+        tempo_bpm: Option<u32>,
+    ) -> Result<(), Box<dyn Error>> {
+        let bytes = self.export_song_to_smf(song_index, tracks, tempo_bpm)?;
+        std::fs::write(path.as_ref(), bytes)?;
+        println!(
+            "💾 Exported song {} to {}",
+            song_index,
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const TWO_PART_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="4.0">
+  <part-list>
+    <score-part id="P1"><part-name>Piano</part-name></score-part>
+    <score-part id="P2"><part-name>Violin</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>2</divisions><time><beats>3</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>2</duration><voice>1</voice><type>quarter</type></note>
+    </measure>
+  </part>
+  <part id="P2">
+    <measure number="1">
+      <attributes><divisions>2</divisions><time><beats>3</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>6</duration><voice>1</voice><type>half</type><dot/></note>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+
+    /// Tick, key and velocity of every note-on, per track
+    fn note_ons(track: &[TrackEvent]) -> Vec<(u64, u8, u8)> {
+        let mut tick = 0u64;
+        let mut notes = Vec::new();
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi {
+                message: MidiMessage::NoteOn { key, vel },
+                ..
+            } = event.kind
+            {
+                if vel > 0 {
+                    notes.push((tick, key.as_int(), vel.as_int()));
+                }
+            }
+        }
+        notes
+    }
+
+    fn programs(track: &[TrackEvent]) -> Vec<(u8, u8)> {
+        track
+            .iter()
+            .filter_map(|e| match e.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::ProgramChange { program },
+                } => Some((channel.as_int(), program.as_int())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn tempos(track: &[TrackEvent]) -> Vec<u32> {
+        track
+            .iter()
+            .filter_map(|e| match e.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(t)) => Some(t.as_int()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn embedded_midi_songs_round_trip_through_smf() {
+        let player = MidiPlayer::new_offline().unwrap();
+        // The shortest embedded MIDI song keeps the test quick
+        let song_index = (0..player.get_total_song_count())
+            .filter(|&i| player.get_song(i).unwrap().song_type == SongType::Midi)
+            .min_by_key(|&i| player.get_song(i).unwrap().duration_ms.unwrap_or(u32::MAX))
+            .expect("no embedded MIDI song");
+        let song = player.get_song(song_index).unwrap();
+        let dense_indices: Vec<usize> = (0..song.tracks.len()).collect();
+        let notes = player.get_events_for_song(song_index, &dense_indices, song.default_tempo);
+        let mut expected_tracks: Vec<usize> = notes
+            .iter()
+            .filter_map(|n| dense_track_index(song, n.track))
+            .collect();
+        expected_tracks.sort_unstable();
+        expected_tracks.dedup();
+
+        let bytes = player.export_song_to_smf(song_index, None, None).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(
+            smf.header.timing,
+            Timing::Metrical(u15::new(EXPORT_TICKS_PER_Q))
+        );
+        assert_eq!(smf.tracks.len(), expected_tracks.len() + 1);
+
+        // The conductor track keeps the file's tempo map
+        let source = player.song_midi_data(song_index).unwrap();
+        let mut source_tempos: Vec<(u64, u32)> = conductor_events(source, EXPORT_TICKS_PER_Q)
+            .iter()
+            .filter_map(|(tick, kind)| match kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(t)) => Some((*tick, t.as_int())),
+                _ => None,
+            })
+            .collect();
+        source_tempos.sort_by_key(|(tick, _)| *tick);
+        let mut source_tempos: Vec<u32> = source_tempos.into_iter().map(|(_, t)| t).collect();
+        if source_tempos.is_empty() {
+            source_tempos.push(60_000_000 / song.default_tempo);
+        }
+        assert_eq!(tempos(&smf.tracks[0]), source_tempos);
+
+        for (track, &dense) in smf.tracks[1..].iter().zip(&expected_tracks) {
+            let program = song.tracks[dense].program;
+            assert!(programs(track)
+                .iter()
+                .all(|&(_, p)| Some(p) == program.map(|p| p & 0x7F)));
+            if program.is_some() {
+                assert!(!programs(track).is_empty());
+            }
+        }
+
+        let mut exported: Vec<u8> = smf.tracks[1..]
+            .iter()
+            .flat_map(|t| note_ons(t))
+            .map(|(_, key, _)| key)
+            .collect();
+        let mut played: Vec<u8> = notes
+            .iter()
+            .filter(|n| dense_track_index(song, n.track).is_some())
+            .map(|n| n.pitch)
+            .collect();
+        exported.sort_unstable();
+        played.sort_unstable();
+        assert_eq!(exported, played);
+
+        // A tempo override replaces the whole tempo map
+        let bytes = player
+            .export_song_to_smf(song_index, None, Some(90))
+            .unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(tempos(&smf.tracks[0]), vec![60_000_000 / 90]);
+    }
+
+    #[test]
+    fn musicxml_songs_export_one_track_per_part() {
+        let dir = std::env::temp_dir().join(format!("e_midi_export_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("two_parts.xml");
+        fs::write(&path, TWO_PART_XML).unwrap();
+
+        let mut player = MidiPlayer::new_offline().unwrap();
+        player.add_song_from_file(&path).unwrap();
+        let song_index = player.get_total_song_count() - 1;
+        assert_eq!(
+            player.get_song(song_index).unwrap().song_type,
+            SongType::MusicXml
+        );
+
+        let bytes = player.export_song_to_smf(song_index, None, None).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.tracks.len(), 3);
+        // MusicXML songs play at 120 BPM unless told otherwise
+        assert_eq!(tempos(&smf.tracks[0]), vec![500_000]);
+        assert_eq!(programs(&smf.tracks[1]), vec![(0, 0)]);
+        assert_eq!(programs(&smf.tracks[2]), vec![(0, 40)]);
+        assert_eq!(
+            note_ons(&smf.tracks[1]),
+            vec![(0, 60, 64), (480, 64, 64), (960, 67, 64)]
+        );
+        assert_eq!(note_ons(&smf.tracks[2]), vec![(0, 70, 64)]);
+
+        // Exporting at another tempo keeps the notes on the same beats
+        let bytes = player
+            .export_song_to_smf(song_index, Some(&[1]), Some(60))
+            .unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.tracks.len(), 2);
+        assert_eq!(tempos(&smf.tracks[0]), vec![1_000_000]);
+        assert_eq!(note_ons(&smf.tracks[1]), vec![(0, 70, 64)]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -20,7 +20,7 @@ pub use e_midi_shared::types::{Note, SongInfo, SongType, TrackInfo, XmlSongInfo,
 #[cfg(feature = "uses_rodio")]
 use rodio::Decoder;
 #[cfg(feature = "uses_rodio")]
-use rodio::OutputStream;
+use rodio::OutputStreamBuilder;
 #[cfg(feature = "uses_rodio")]
 use rodio::Sink;
 #[cfg(feature = "uses_rodio")]
@@ -35,6 +35,18 @@ pub fn set_shutdown_flag() {
 pub fn should_shutdown() -> bool {
     SHUTDOWN.load(Ordering::Relaxed)
 }
+
+// When set, the signal handler only raises the shutdown flag and leaves the
+// exit to the running loop (used by `serve` to clean up)
+static GRACEFUL_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_graceful_shutdown(enabled: bool) {
+    GRACEFUL_SHUTDOWN.store(enabled, Ordering::Relaxed);
+}
+
+pub fn graceful_shutdown() -> bool {
+    GRACEFUL_SHUTDOWN.load(Ordering::Relaxed)
+}
 /// Format duration in milliseconds to a readable string
 pub fn format_duration(duration_ms: u32) -> String {
     let seconds = duration_ms / 1000;
@@ -69,6 +81,118 @@ pub enum MidiCommand {
         tracks: Option<Vec<usize>>,
         tempo_bpm: Option<u32>,
     },
+    /// Play prepared notes in the background (any song, including dynamic ones)
+    PlayNotes {
+        song_index: usize,
+        notes: Vec<Note>,
+        position_ms: u32,
+        /// Messages sent before the first note, e.g. program changes
+        setup: Vec<Vec<u8>>,
+        /// Beats to announce over IPC while playing
+        beats: Option<beats::BeatGrid>,
+    },
+    /// Emit MIDI Time Code during background playback (None disables it)
+    SetMtc(Option<mtc::MtcSettings>),
+    /// Publish played messages and notes over IPC
+    SetIpcEnabled(bool),
+}
+
+/// (time_ms, note_on, channel, pitch, velocity, track) as scheduled by the MIDI thread
+type TimelineEvent = (u32, bool, u8, u8, u8, u8);
+
+/// Follow the note-ons and note-offs the MIDI thread sends
+fn track_sounding(sounding: &mut HashSet<(u8, u8)>, msg: &[u8]) {
+    if let [status, pitch, velocity, ..] = *msg {
+        let channel = status & 0x0F;
+        match status & 0xF0 {
+            0x90 if velocity > 0 => {
+                sounding.insert((channel, pitch));
+            }
+            0x80 | 0x90 => {
+                sounding.remove(&(channel, pitch));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Playback thread holding the sink, handing it back with the last played ms
+type PlaybackHandle = JoinHandle<(Box<dyn sink::MidiSink>, u32)>;
+
+/// Send whatever live messages (MIDI thru, foreground notes) have arrived for
+/// a playback thread that owns the sink
+fn forward_live(conn: &mut dyn sink::MidiSink, live: &mpsc::Receiver<Vec<u8>>) {
+    while let Ok(msg) = live.try_recv() {
+        let _ = conn.send(&msg);
+    }
+}
+
+/// Note-offs for every tracked note; not every synth honours All Notes Off
+fn release_sounding(
+    conn: &mut dyn sink::MidiSink,
+    sounding: &mut HashSet<(u8, u8)>,
+    ipc_enabled: bool,
+) {
+    for (channel, pitch) in sounding.drain() {
+        let msg = [0x80 | channel, pitch, 0];
+        let _ = conn.send(&msg);
+        if ipc_enabled {
+            ipc::publish_midi_message(&msg, None, None, 0);
+        }
+    }
+}
+
+type NoteEventPublisher = iceoryx2::port::publisher::Publisher<
+    iceoryx2::service::ipc::Service,
+    e_midi_shared::ipc_protocol::MidiNoteEvent,
+    (),
+>;
+
+/// Publisher for the zero-copy `MidiNoteEvent` stream
+fn note_event_publisher() -> Option<NoteEventPublisher> {
+    let node = iceoryx2::node::NodeBuilder::new()
+        .create::<iceoryx2::service::ipc::Service>()
+        .ok()?;
+    let name = iceoryx2::prelude::ServiceName::new(ipc::EMIDI_NOTE_EVENTS_SERVICE).ok()?;
+    let service = node
+        .service_builder(&name)
+        .publish_subscribe::<e_midi_shared::ipc_protocol::MidiNoteEvent>()
+        .max_publishers(16)
+        .max_subscribers(16)
+        .open_or_create()
+        .ok()?;
+    service.publisher_builder().create().ok()
+}
+
+/// Send `MidiNoteEvent`s for a timeline (non-blocking, best-effort)
+///
+/// The publisher is created on first use, so a player with IPC disabled never
+/// opens the service. It is !Send, so this stays on the MIDI thread rather
+/// than the playback thread.
+fn publish_timeline(
+    publisher: &mut Option<Option<NoteEventPublisher>>,
+    timeline: &[TimelineEvent],
+) {
+    let Some(publisher) = publisher.get_or_insert_with(note_event_publisher) else {
+        return;
+    };
+    for (_t, on, chan, pitch, vel, _track) in timeline {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let midi_note_event = e_midi_shared::ipc_protocol::MidiNoteEvent {
+            channel: *chan,
+            pitch: *pitch,
+            velocity: if *on { *vel } else { 0 },
+            kind: if *on { 0 } else { 1 },
+            timestamp: now,
+            _reserved: [0; 4],
+        };
+        if let Err(e) = publisher.send_copy(midi_note_event) {
+            eprintln!("[IPC ERROR] Failed to send MidiNoteEvent: {:?}", e);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -92,8 +216,30 @@ pub fn calculate_song_duration_ms(events: &[Note]) -> u32 {
         .unwrap_or(0)
 }
 
+pub mod beats;
 pub mod cli;
+pub mod config;
+pub mod export;
+pub mod library;
+pub mod mtc;
+pub mod musicxml_export;
+#[cfg(feature = "uses_osc")]
+pub mod osc;
+pub mod record;
+pub mod render;
+#[cfg(unix)]
+pub mod rpc;
+pub mod serve;
+pub mod sink;
+pub mod soundfont;
+#[cfg(feature = "uses_osc")]
+pub mod superdirt;
+pub mod sync;
+pub mod synth;
+pub mod thru;
+pub mod transport;
 mod tui;
+pub mod watch;
 
 #[derive(Clone, Debug)]
 /// Configuration for looping and playback behavior
@@ -108,6 +254,8 @@ pub struct LoopConfig {
     pub scan_random_start: bool,
     /// Delay between songs in milliseconds
     pub delay_between_songs_ms: u32,
+    /// Multiplier applied to each song's default tempo
+    pub tempo_scale: f64,
 }
 
 impl Default for LoopConfig {
@@ -118,6 +266,7 @@ impl Default for LoopConfig {
             scan_segment_duration_ms: 30000, // 30 seconds
             scan_random_start: false,
             delay_between_songs_ms: 0, // No delay between songs by default
+            tempo_scale: 1.0,
         }
     }
 }
@@ -139,10 +288,125 @@ pub struct MidiPlayer {
     elapsed_ms: Option<u32>,
     current_tick: Option<u32>,
     start_instant: Option<Instant>,
+    output_backend: sink::OutputBackend,
+    mtc_settings: Option<mtc::MtcSettings>,
+    key_bindings: config::KeyBindings,
+    library: Option<Mutex<library::LibraryIndex>>,
+    watcher: Option<watch::DirectoryWatcher>,
+    /// Whether the MIDI thread publishes played messages and notes over IPC
+    ipc_enabled: bool,
+    state_providers: ipc::StateProviders<PlayerState>,
+    chunks: ipc::ChunkAssembler,
+}
+
+/// What state providers see: a snapshot taken when a `StateRequest` arrives
+#[derive(Debug, Clone)]
+pub struct PlayerState {
+    pub playback: ipc::MidiPlaybackState,
+    pub songs: Vec<ipc::IpcSongInfo>,
+}
+
+impl PlayerState {
+    fn default_providers() -> ipc::StateProviders<PlayerState> {
+        let mut providers = ipc::StateProviders::new();
+        providers.register_json(ipc::StateType::MidiPlayback, |s: &PlayerState| {
+            s.playback.clone()
+        });
+        providers.register_json(ipc::StateType::MidiSongList, |s: &PlayerState| {
+            s.songs.clone()
+        });
+        providers
+    }
 }
 
 impl MidiPlayer {
+    /// Create a player on the first MIDI output port
+    ///
+    /// Without a usable port this falls back to the built-in software synth on
+    /// the default audio device (`uses_rodio` feature), and failing that to a
+    /// null sink; `output_backend` reports which one was chosen.
     pub fn new() -> Result<Self, Box<dyn Error>> {
+        match Self::connect_first_midi_port() {
+            Ok(sink) => Self::with_sink(Box::new(sink)),
+            Err(e) => {
+                println!("⚠️  No usable MIDI output port ({})", e);
+                Self::with_fallback_sink()
+            }
+        }
+    }
+
+    /// Create a player on the ports named by an `[output]` config section
+    ///
+    /// Unlike `new`, a port that was asked for but can't be opened is an error.
+    pub fn with_output(output: &config::OutputConfig) -> Result<Self, Box<dyn Error>> {
+        if output.is_default() {
+            return Self::new();
+        }
+        let routes = output.channel_routes()?;
+        let main_spec = output.port.as_deref().unwrap_or("0");
+        let mut specs = vec![main_spec.to_string()];
+        let mut channel_ports = [0usize; 16];
+        for (channel, spec) in routes {
+            let index = match specs.iter().position(|s| *s == spec) {
+                Some(i) => i,
+                None => {
+                    specs.push(spec);
+                    specs.len() - 1
+                }
+            };
+            channel_ports[channel as usize] = index;
+        }
+        let mut ports = Vec::with_capacity(specs.len());
+        for spec in &specs {
+            ports.push(Self::connect_midi_port(spec)?);
+        }
+        if ports.len() == 1 {
+            let port = ports.pop().ok_or("missing MIDI output port")?;
+            return Self::with_sink(Box::new(port));
+        }
+        Self::with_sink(Box::new(sink::RoutedSink::new(ports, channel_ports)))
+    }
+
+    /// Open the output port matching `spec`: an index or part of its name
+    fn connect_midi_port(spec: &str) -> Result<sink::MidirSink, Box<dyn Error>> {
+        let midi_out = MidiOutput::new("e_midi")
+            .map_err(|e| format!("Cannot open MIDI output for port '{}': {}", spec, e))?;
+        let ports = midi_out.ports();
+        let names: Vec<String> = ports
+            .iter()
+            .map(|p| {
+                midi_out
+                    .port_name(p)
+                    .unwrap_or_else(|_| "Unknown".to_string())
+            })
+            .collect();
+        let wanted = spec.trim().to_lowercase();
+        let index = match wanted.parse::<usize>() {
+            Ok(i) if i < ports.len() => Some(i),
+            Ok(_) => None,
+            Err(_) => names
+                .iter()
+                .position(|n| n.to_lowercase().contains(&wanted)),
+        };
+        let Some(index) = index else {
+            return Err(format!(
+                "No MIDI output port matching '{}' (available: {})",
+                spec,
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+            .into());
+        };
+        let conn = midi_out.connect(&ports[index], "e_midi")?;
+        println!("🔌 Connected to MIDI port: {}", names[index]);
+        Ok(sink::MidirSink::new(conn, names[index].clone()))
+    }
+
+    /// Open the first MIDI output port, listing what is available
+    fn connect_first_midi_port() -> Result<sink::MidirSink, Box<dyn Error>> {
         let midi_out = MidiOutput::new("e_midi")?;
         let ports = midi_out.ports();
 
@@ -193,6 +457,54 @@ impl MidiPlayer {
         let conn = midi_out.connect(port, "e_midi")?;
         println!("🔌 Connected to MIDI port: {}", port_name);
 
+        Ok(sink::MidirSink::new(conn, port_name))
+    }
+
+    /// Software synth on the default audio device, else a null sink
+    ///
+    /// The software synth needs the `uses_rodio` feature, which is off by
+    /// default; without it playback falls back to silence.
+    fn with_fallback_sink() -> Result<Self, Box<dyn Error>> {
+        #[cfg(feature = "uses_rodio")]
+        {
+            let synth = synth::SoftSynth::new(render::DEFAULT_SAMPLE_RATE);
+            match sink::SynthSink::new(Box::new(synth), sink::OutputBackend::SoftSynth) {
+                Ok(synth_sink) => return Self::with_sink(Box::new(synth_sink)),
+                Err(e) => println!("⚠️  No audio device for the software synth ({})", e),
+            }
+        }
+        #[cfg(not(feature = "uses_rodio"))]
+        eprintln!(
+            "⚠️  This build has no software synth (the uses_rodio feature is off); \
+             rebuild with `--features uses_rodio` or connect a MIDI output port to hear anything"
+        );
+        eprintln!("🔇 Playback is silent: played messages are discarded");
+
+        Self::with_sink(Box::new(sink::NullSink))
+    }
+
+    /// Create a player without any MIDI or audio device
+    ///
+    /// Live playback is discarded; the library, export and offline rendering
+    /// work as usual.
+    pub fn new_offline() -> Result<Self, Box<dyn Error>> {
+        Self::with_sink(Box::new(sink::NullSink))
+    }
+
+    /// Create a player that plays through an SF2 SoundFont on the default audio device
+    #[cfg(feature = "uses_rodio")]
+    pub fn with_soundfont<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let synth =
+            soundfont::SoundFontSynth::from_file(path.as_ref(), render::DEFAULT_SAMPLE_RATE)?;
+        let backend = sink::OutputBackend::SoundFont(path.as_ref().to_path_buf());
+        Self::with_sink(Box::new(sink::SynthSink::new(Box::new(synth), backend)?))
+    }
+
+    /// Create a player whose background MIDI thread writes to `sink`
+    pub fn with_sink(sink: Box<dyn sink::MidiSink>) -> Result<Self, Box<dyn Error>> {
+        let output_backend = sink.backend();
+        println!("🔈 Output backend: {}", output_backend);
+
         // Create the channel for sending MIDI commands to the background thread
         let (sender, receiver) = mpsc::channel::<MidiCommand>();
 
@@ -213,7 +525,7 @@ impl MidiPlayer {
 
         // Spawn the background MIDI thread, move core into it
         let midi_thread = thread::spawn(move || {
-            Self::midi_thread_loop(conn, receiver, core_state);
+            Self::midi_thread_loop(sink, receiver, core_state);
         });
 
         Ok(MidiPlayer {
@@ -230,9 +542,22 @@ impl MidiPlayer {
             elapsed_ms: None,
             current_tick: None,
             start_instant: None,
+            output_backend,
+            mtc_settings: None,
+            key_bindings: config::KeyBindings::default(),
+            state_providers: PlayerState::default_providers(),
+            chunks: ipc::ChunkAssembler::new(),
+            library: None,
+            watcher: None,
+            ipc_enabled: false,
         })
     }
 
+    /// The output the player is connected to
+    pub fn output_backend(&self) -> &sink::OutputBackend {
+        &self.output_backend
+    }
+
     /// Play a song with IPC event publishing for TUI integration
     pub fn play_song_with_ipc(&mut self, song_index: usize) -> Result<(), Box<dyn Error>> {
         if song_index >= self.get_total_song_count() {
@@ -283,6 +608,7 @@ impl MidiPlayer {
         let track_indices: Vec<usize> = selected_song.tracks.iter().map(|t| t.index).collect();
         let tempo = selected_song.default_tempo;
 
+        self.record_play(song_index);
         // Publish playback started event
         self.publish_midi_event(crate::ipc::Event::MidiPlaybackStarted {
             song_index,
@@ -335,11 +661,41 @@ impl MidiPlayer {
             let _ = ipc_manager.publish_event(event); // Silently ignore errors
         }
     }
+
+    /// Keep the IPC service registry up to date from received events
+    ///
+    /// Returns the `ServiceJoined`/`ServiceLeft` events for app instances
+    /// that appeared or went stale.
+    pub fn track_ipc_services(&mut self, events: &[crate::ipc::Event]) -> Vec<crate::ipc::Event> {
+        let Some(manager) = self.ipc_manager.as_mut() else {
+            return Vec::new();
+        };
+        let mut changes: Vec<_> = events
+            .iter()
+            .filter_map(|event| manager.observe_event(event))
+            .collect();
+        changes.extend(manager.expire_services());
+        changes
+    }
+    /// Publish every played message (`MidiMessageEvent`) and note
+    /// (`MidiNoteEvent`) over IPC; off by default, like `config.ipc.enabled`
+    pub fn set_ipc_enabled(&mut self, enabled: bool) {
+        self.ipc_enabled = enabled;
+        let _ = self.send_midi_command(MidiCommand::SetIpcEnabled(enabled));
+    }
+
+    pub fn ipc_enabled(&self) -> bool {
+        self.ipc_enabled
+    }
+
     /// Initialize IPC publisher for event-driven communication
     pub fn init_ipc_publisher(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         if self.ipc_manager.is_none() {
             match ipc::IpcServiceManager::new(ipc::AppId::EMidi) {
-                Ok(manager) => {
+                Ok(mut manager) => {
+                    // Without a publisher every publish_midi_event is dropped
+                    let _ = manager.init_publisher();
+                    manager.set_capabilities(&[ipc::CAP_MIDI_MESSAGES, ipc::CAP_BEATS]);
                     self.ipc_manager = Some(manager);
                     // // Automatically set the global relay if available
                     // if let Some(sender) = self
@@ -423,7 +779,7 @@ impl MidiPlayer {
 
         let service = node
             .service_builder(&iceoryx2::prelude::ServiceName::new(
-                ipc::EMIDI_EVENTS_SERVICE,
+                ipc::EMIDI_NOTE_EVENTS_SERVICE,
             )?)
             .publish_subscribe::<e_midi_shared::ipc_protocol::MidiNoteEvent>()
             .max_publishers(16)
@@ -497,7 +853,7 @@ impl MidiPlayer {
     }
     // Background MIDI thread that handles all MIDI output and playback
     fn midi_thread_loop(
-        conn: midir::MidiOutputConnection,
+        conn: Box<dyn sink::MidiSink>,
         receiver: std::sync::mpsc::Receiver<MidiCommand>,
         core_state: MidiPlayerCore,
     ) {
@@ -505,23 +861,21 @@ impl MidiPlayer {
             atomic::{AtomicBool, Ordering},
             Arc,
         };
-        use std::time::{Duration, Instant};
 
         println!("🎹 MIDI background thread started");
         // Playback state for the background thread
         let playback_stop_flag = Arc::new(AtomicBool::new(false));
-        let mut playback_thread: Option<
-            std::thread::JoinHandle<(midir::MidiOutputConnection, u32)>,
-        > = None;
+        let mut playback_thread: Option<PlaybackHandle> = None;
+        // Live messages for the playback thread while it owns the sink
+        let mut live_sender: Option<mpsc::Sender<Vec<u8>>> = None;
         // Track last stopped position for each song
         use std::collections::HashMap;
         let mut last_positions: HashMap<usize, u32> = HashMap::new();
         let mut current_playing: Option<(usize, u32)> = None; // (song_index, start_ms)
-                                                              // Helper to stop playback
+        let mut mtc_settings: Option<mtc::MtcSettings> = None;
+        // Helper to stop playback
         let stop_playback = |stop_flag: &Arc<AtomicBool>,
-                             playback_thread: &mut Option<
-            std::thread::JoinHandle<(midir::MidiOutputConnection, u32)>,
-        >,
+                             playback_thread: &mut Option<PlaybackHandle>,
                              current_playing: &mut Option<(usize, u32)>,
                              last_positions: &mut HashMap<usize, u32>| {
             stop_flag.store(true, Ordering::Relaxed);
@@ -539,28 +893,65 @@ impl MidiPlayer {
             current_playing.take();
             None
         };
-        let node = iceoryx2::node::NodeBuilder::new()
-            .create::<iceoryx2::service::ipc::Service>()
-            .expect("Failed to create IPC node");
-
-        let service = node
-            .service_builder(
-                &iceoryx2::prelude::ServiceName::new(ipc::EMIDI_EVENTS_SERVICE)
-                    .expect("Failed to create service name"),
-            )
-            .publish_subscribe::<e_midi_shared::ipc_protocol::MidiNoteEvent>()
-            .max_publishers(16)
-            .max_subscribers(16)
-            .open_or_create();
-        let publisher = service
-            .expect("service bad")
-            .publisher_builder()
-            .create()
-            .expect("Failed to create publisher");
+        // Publishing is off until the player turns it on
+        let mut ipc_enabled = false;
+        let mut note_publisher: Option<Option<NoteEventPublisher>> = None;
         // Move conn into the playback thread, get it back after join
         let mut conn_opt = Some(conn);
+        // Notes sent through this thread (foreground playback, MIDI thru) that
+        // are still sounding; stopping releases them with explicit note-offs
+        let mut sounding: HashSet<(u8, u8)> = HashSet::new();
         while let Ok(command) = receiver.recv() {
             trace!("🎹 [MIDI THREAD] Received command: {:?}", command); // DEBUG
+            let live_msg = match &command {
+                MidiCommand::NoteOn {
+                    channel,
+                    pitch,
+                    velocity,
+                } => Some(vec![0x90 | (channel & 0x0F), *pitch, *velocity]),
+                MidiCommand::NoteOff { channel, pitch } => {
+                    Some(vec![0x80 | (channel & 0x0F), *pitch, 0])
+                }
+                MidiCommand::SendMessage(msg) => Some(msg.clone()),
+                _ => None,
+            };
+            if let Some(msg) = live_msg {
+                // A song that played to its end still holds the sink until joined
+                if conn_opt.is_none() && playback_thread.as_ref().is_some_and(|h| h.is_finished()) {
+                    conn_opt = stop_playback(
+                        &playback_stop_flag,
+                        &mut playback_thread,
+                        &mut current_playing,
+                        &mut last_positions,
+                    );
+                }
+                let undelivered = match (conn_opt.as_mut(), live_sender.as_ref()) {
+                    (Some(conn), _) => {
+                        let _ = conn.send(&msg);
+                        None
+                    }
+                    // Background playback owns the sink; it sends live
+                    // messages between timeline events
+                    (None, Some(live)) => live.send(msg.clone()).err().map(|e| e.0),
+                    (None, None) => None,
+                };
+                if let Some(msg) = undelivered {
+                    // The playback thread ended between the check and the send
+                    conn_opt = stop_playback(
+                        &playback_stop_flag,
+                        &mut playback_thread,
+                        &mut current_playing,
+                        &mut last_positions,
+                    );
+                    if let Some(conn) = conn_opt.as_mut() {
+                        let _ = conn.send(&msg);
+                    }
+                }
+                if ipc_enabled {
+                    ipc::publish_midi_message(&msg, None, None, 0);
+                }
+                track_sounding(&mut sounding, &msg);
+            }
             match command {
                 MidiCommand::NoteOn {
                     channel,
@@ -585,27 +976,18 @@ impl MidiPlayer {
                     // if let Err(e) = res {
                     //     eprintln!("[IPC ERROR] Failed to publish MidiNoteOn: {}", e);
                     // }
-                    if let Some(conn) = conn_opt.as_mut() {
-                        let msg = [0x90 | (channel & 0x0F), pitch, velocity];
-                        let _ = conn.send(&msg);
-                    }
-                }
-                MidiCommand::NoteOff { channel, pitch } => {
-                    if let Some(conn) = conn_opt.as_mut() {
-                        let msg = [0x80 | (channel & 0x0F), pitch, 0];
-                        let _ = conn.send(&msg);
-                    }
-                }
-                MidiCommand::SendMessage(msg) => {
-                    if let Some(conn) = conn_opt.as_mut() {
-                        let _ = conn.send(&msg);
-                    }
                 }
+                // Sent above, together with NoteOn
+                MidiCommand::NoteOff { .. } | MidiCommand::SendMessage(_) => {}
                 MidiCommand::AllNotesOff => {
                     if let Some(conn) = conn_opt.as_mut() {
+                        release_sounding(conn.as_mut(), &mut sounding, ipc_enabled);
                         for channel in 0..16 {
                             let msg = [0xB0 | channel, 123, 0];
                             let _ = conn.send(&msg);
+                            if ipc_enabled {
+                                ipc::publish_midi_message(&msg, None, None, 0);
+                            }
                         }
                     }
                 }
@@ -619,6 +1001,12 @@ impl MidiPlayer {
                     );
                     break;
                 }
+                MidiCommand::SetMtc(settings) => {
+                    mtc_settings = settings;
+                }
+                MidiCommand::SetIpcEnabled(enabled) => {
+                    ipc_enabled = enabled;
+                }
                 MidiCommand::Stop => {
                     println!("🎹 [MIDI THREAD] Processing STOP command"); // DEBUG
                     conn_opt = stop_playback(
@@ -628,6 +1016,9 @@ impl MidiPlayer {
                         &mut last_positions,
                     )
                     .or(conn_opt);
+                    if let Some(conn) = conn_opt.as_mut() {
+                        release_sounding(conn.as_mut(), &mut sounding, ipc_enabled);
+                    }
                 }
                 MidiCommand::PlaySongResumeAware {
                     song_index,
@@ -650,7 +1041,7 @@ impl MidiPlayer {
                     // Reset stop flag for new playback
                     playback_stop_flag.store(false, Ordering::Relaxed);
                     let stop_flag = Arc::clone(&playback_stop_flag);
-                    if let (Some(idx), Some(mut conn)) = (song_index, conn_opt.take()) {
+                    if let (Some(idx), Some(conn)) = (song_index, conn_opt.take()) {
                         let static_count = core_state.static_songs.len();
                         // --- PATCH: Always play all tracks if tracks is None ---
                         let (song, _is_static) = if idx < static_count {
@@ -661,7 +1052,8 @@ impl MidiPlayer {
                                 (&core_state.dynamic_songs[dyn_idx], false)
                             } else {
                                 println!("[ERROR] Song index {} out of range", idx);
-                                return;
+                                conn_opt = Some(conn);
+                                continue;
                             }
                         };
                         // Always use user-facing track indices (track.index) for all tracks
@@ -694,93 +1086,71 @@ impl MidiPlayer {
                         };
                         // Update current_playing
                         current_playing = Some((idx, start_ms));
-                        let timeline = {
-                            let mut timeline = Vec::with_capacity(events.len() * 2);
-                            for n in &events {
-                                timeline.push((n.start_ms, true, n.chan, n.pitch, n.vel));
-                                timeline.push((n.start_ms + n.dur_ms, false, n.chan, n.pitch, 0));
-                            }
-                            timeline.sort_by_key(|e| e.0);
-                            timeline
-                        };
+                        let timeline = Self::build_timeline(&events, start_ms);
                         println!("[DEBUG][MIDI THREAD] timeline.len() = {}", timeline.len());
-                        // Move only the MIDI connection into the thread.
-                        // All IPC publisher usage must remain in the parent thread due to !Send/!Sync.
-                        let stop_flag_clone = Arc::clone(&stop_flag);
-                        let timeline_clone = timeline.clone();
-
-                        playback_thread = Some(std::thread::spawn(move || {
-                            let start = Instant::now();
-                            let mut idx_tl = 0;
-                            // Send all events at or before start_ms immediately (fix for short songs)
-                            let mut sent_first = false;
-                            let mut last_played_ms = start_ms;
-                            while idx_tl < timeline_clone.len()
-                                && timeline_clone[idx_tl].0 <= start_ms
-                            {
-                                let (t, on, chan, pitch, vel) = timeline_clone[idx_tl];
-                                let msg = if on {
-                                    [0x90 | (chan & 0x0F), pitch, vel]
-                                } else {
-                                    [0x80 | (chan & 0x0F), pitch, 0]
-                                };
-                                if !sent_first && on {
-                                    println!("[DEBUG][MIDI THREAD] Sending first note: chan={}, pitch={}, vel={}", chan, pitch, vel);
-                                    sent_first = true;
-                                }
-                                let _ = conn.send(&msg);
-                                last_played_ms = t;
-                                idx_tl += 1;
-                            }
-                            // Now continue with timed playback for remaining events
-                            while idx_tl < timeline_clone.len() {
-                                if stop_flag_clone.load(Ordering::Relaxed) {
-                                    println!(
-                                        "🎹 [MIDI THREAD] Stop flag set, breaking playback loop"
-                                    );
-                                    break;
-                                }
-                                let now = start.elapsed().as_millis() as u32 + start_ms;
-                                while idx_tl < timeline_clone.len()
-                                    && timeline_clone[idx_tl].0 <= now
-                                {
-                                    let (t, on, chan, pitch, vel) = timeline_clone[idx_tl];
-                                    let msg = if on {
-                                        [0x90 | (chan & 0x0F), pitch, vel]
-                                    } else {
-                                        [0x80 | (chan & 0x0F), pitch, 0]
-                                    };
-                                    let _ = conn.send(&msg);
-                                    last_played_ms = t;
-                                    idx_tl += 1;
-                                }
-                                std::thread::sleep(Duration::from_millis(1));
-                            }
-                            // All notes off at end
-                            for channel in 0..16 {
-                                let msg = [0xB0 | channel, 123, 0];
-                                let _ = conn.send(&msg);
-                            }
-                            (conn, last_played_ms)
-                        }));
-                        // Send IPC events for the timeline in the parent thread (non-blocking, best-effort)
-                        for (_t, on, chan, pitch, vel) in &timeline {
-                            let now = std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_millis() as u64;
-                            let midi_note_event = e_midi_shared::ipc_protocol::MidiNoteEvent {
-                                channel: *chan,
-                                pitch: *pitch,
-                                velocity: if *on { *vel } else { 0 },
-                                kind: if *on { 0 } else { 1 },
-                                timestamp: now,
-                                _reserved: [0; 4],
-                            };
-                            if let Err(e) = publisher.send_copy(midi_note_event) {
-                                eprintln!("[IPC ERROR] Failed to send MidiNoteEvent: {:?}", e);
+                        let midi_data = match idx.checked_sub(static_count) {
+                            None => get_embedded_midi_bytes(idx),
+                            Some(i) => core_state.dynamic_midi_data.get(i).map(|d| &d[..]),
+                        };
+                        let beats = beats::BeatGrid::for_song(song, midi_data, tempo);
+                        let (live, live_receiver) = mpsc::channel();
+                        live_sender = Some(live);
+                        playback_thread = Some(Self::spawn_timeline_playback(
+                            conn,
+                            timeline.clone(),
+                            idx,
+                            start_ms,
+                            stop_flag,
+                            mtc_settings.clone(),
+                            Some(beats),
+                            live_receiver,
+                            ipc_enabled,
+                        ));
+                        if ipc_enabled {
+                            publish_timeline(&mut note_publisher, &timeline);
+                        }
+                    }
+                }
+                MidiCommand::PlayNotes {
+                    song_index,
+                    notes,
+                    position_ms,
+                    setup,
+                    beats,
+                } => {
+                    conn_opt = stop_playback(
+                        &playback_stop_flag,
+                        &mut playback_thread,
+                        &mut current_playing,
+                        &mut last_positions,
+                    )
+                    .or(conn_opt);
+                    playback_stop_flag.store(false, Ordering::Relaxed);
+                    if let Some(mut conn) = conn_opt.take() {
+                        for msg in &setup {
+                            let _ = conn.send(msg);
+                            if ipc_enabled {
+                                ipc::publish_midi_message(msg, Some(song_index), None, position_ms);
                             }
                         }
+                        current_playing = Some((song_index, position_ms));
+                        let timeline = Self::build_timeline(&notes, position_ms);
+                        let (live, live_receiver) = mpsc::channel();
+                        live_sender = Some(live);
+                        playback_thread = Some(Self::spawn_timeline_playback(
+                            conn,
+                            timeline.clone(),
+                            song_index,
+                            position_ms,
+                            Arc::clone(&playback_stop_flag),
+                            mtc_settings.clone(),
+                            beats,
+                            live_receiver,
+                            ipc_enabled,
+                        ));
+                        if ipc_enabled {
+                            publish_timeline(&mut note_publisher, &timeline);
+                        }
                     }
                 }
             }
@@ -793,7 +1163,134 @@ impl MidiPlayer {
             &mut last_positions,
         );
         println!("🎹 MIDI background thread finished");
-    } // Send a MIDI command to the background thread
+    }
+
+    /// Note-on/off timeline for background playback from `start_ms`
+    fn build_timeline(events: &[Note], start_ms: u32) -> Vec<TimelineEvent> {
+        let mut timeline = Vec::with_capacity(events.len() * 2);
+        // Notes that ended before the start position are skipped
+        for n in events.iter().filter(|n| n.start_ms + n.dur_ms > start_ms) {
+            timeline.push((n.start_ms, true, n.chan, n.pitch, n.vel, n.track));
+            timeline.push((n.start_ms + n.dur_ms, false, n.chan, n.pitch, 0, n.track));
+        }
+        timeline.sort_by_key(|e| e.0);
+        timeline
+    }
+
+    /// Play a timeline on its own thread, handing the sink back when done
+    ///
+    /// Messages arriving on `live` (MIDI thru, foreground notes) are sent
+    /// between timeline events, since this thread owns the sink meanwhile.
+    /// Sent messages and beats are published over IPC only when `ipc_enabled`.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_timeline_playback(
+        mut conn: Box<dyn sink::MidiSink>,
+        timeline: Vec<TimelineEvent>,
+        song_index: usize,
+        start_ms: u32,
+        stop_flag: Arc<AtomicBool>,
+        mtc_settings: Option<mtc::MtcSettings>,
+        beats: Option<beats::BeatGrid>,
+        live: mpsc::Receiver<Vec<u8>>,
+        ipc_enabled: bool,
+    ) -> PlaybackHandle {
+        std::thread::spawn(move || {
+            let publish = |msg: &[u8], track: Option<u8>, song_time_ms: u32| {
+                if ipc_enabled {
+                    ipc::publish_midi_message(msg, Some(song_index), track, song_time_ms);
+                }
+            };
+            let start = Instant::now();
+            let mut mtc_generator = mtc_settings.map(|settings| {
+                let generator = mtc::MtcGenerator::new(
+                    settings.rate,
+                    settings.timeline_ms(song_index, start_ms as u64),
+                    start,
+                );
+                let _ = conn.send(&generator.locate_message());
+                generator
+            });
+            let mut next_beat = beats.as_ref().map(|grid| grid.beat_at_or_after(start_ms));
+            let mut idx_tl = 0;
+            // Send all events at or before start_ms immediately (fix for short songs)
+            let mut sent_first = false;
+            let mut last_played_ms = start_ms;
+            while idx_tl < timeline.len() && timeline[idx_tl].0 <= start_ms {
+                let (t, on, chan, pitch, vel, track) = timeline[idx_tl];
+                let msg = if on {
+                    [0x90 | (chan & 0x0F), pitch, vel]
+                } else {
+                    [0x80 | (chan & 0x0F), pitch, 0]
+                };
+                if !sent_first && on {
+                    println!(
+                        "[DEBUG][MIDI THREAD] Sending first note: chan={}, pitch={}, vel={}",
+                        chan, pitch, vel
+                    );
+                    sent_first = true;
+                }
+                let _ = conn.send(&msg);
+                publish(&msg, Some(track), t);
+                last_played_ms = t;
+                idx_tl += 1;
+            }
+            // Now continue with timed playback for remaining events
+            while idx_tl < timeline.len() {
+                if stop_flag.load(Ordering::Relaxed) {
+                    println!("🎹 [MIDI THREAD] Stop flag set, breaking playback loop");
+                    break;
+                }
+                let now = start.elapsed().as_millis() as u32 + start_ms;
+                while idx_tl < timeline.len() && timeline[idx_tl].0 <= now {
+                    let (t, on, chan, pitch, vel, track) = timeline[idx_tl];
+                    let msg = if on {
+                        [0x90 | (chan & 0x0F), pitch, vel]
+                    } else {
+                        [0x80 | (chan & 0x0F), pitch, 0]
+                    };
+                    let _ = conn.send(&msg);
+                    publish(&msg, Some(track), t);
+                    last_played_ms = t;
+                    idx_tl += 1;
+                }
+                if let (Some(grid), Some(beat)) = (beats.as_ref(), next_beat) {
+                    if beat.position_ms <= now {
+                        if ipc_enabled {
+                            ipc::publish_beat(grid.beat_event(song_index, &beat));
+                        }
+                        next_beat = Some(grid.next(&beat));
+                    }
+                }
+                if let Some(generator) = mtc_generator.as_mut() {
+                    for qf in generator.poll(Instant::now()) {
+                        let _ = conn.send(&qf);
+                        publish(&qf, None, now);
+                    }
+                }
+                // Waiting for live messages doubles as the 1 ms tick
+                match live.recv_timeout(Duration::from_millis(1)) {
+                    Ok(msg) => {
+                        let _ = conn.send(&msg);
+                        forward_live(conn.as_mut(), &live);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+            forward_live(conn.as_mut(), &live);
+            // All notes off at end
+            for channel in 0..16 {
+                let msg = [0xB0 | channel, 123, 0];
+                let _ = conn.send(&msg);
+                publish(&msg, None, last_played_ms);
+            }
+            (conn, last_played_ms)
+        })
+    }
+
+    // Send a MIDI command to the background thread
     fn send_midi_command(&self, command: MidiCommand) -> Result<(), Box<dyn Error>> {
         self.midi_sender
             .send(command)
@@ -805,7 +1302,7 @@ impl MidiPlayer {
         self.is_playing.store(false, Ordering::Relaxed);
 
         // Always record resume state if a song was ever started
-        if let (Some(_idx), Some(start)) = (self.current_song_index, self.start_instant) {
+        if let (Some(idx), Some(start)) = (self.current_song_index, self.start_instant) {
             let elapsed = start.elapsed().as_millis();
             // Clamp to u32::MAX
             let elapsed_ms = if elapsed > u32::MAX as u128 {
@@ -815,6 +1312,7 @@ impl MidiPlayer {
             };
             self.elapsed_ms = Some(elapsed_ms);
             // self.current_song_index is already set
+            self.record_position(idx, elapsed_ms);
         }
 
         // Send all notes off command through the MIDI channel
@@ -883,6 +1381,20 @@ impl MidiPlayer {
         &mut self.config
     }
 
+    /// A song's default tempo with `LoopConfig::tempo_scale` applied
+    pub fn scaled_tempo(&self, default_tempo: u32) -> u32 {
+        ((default_tempo as f64 * self.config.tempo_scale).round() as u32).max(1)
+    }
+
+    /// TUI key bindings
+    pub fn key_bindings(&self) -> &config::KeyBindings {
+        &self.key_bindings
+    }
+
+    pub fn set_key_bindings(&mut self, bindings: config::KeyBindings) {
+        self.key_bindings = bindings;
+    }
+
     /// Helper to format duration_ms as mm:ss or seconds
     fn format_duration(duration_ms: Option<u32>) -> String {
         match duration_ms {
@@ -980,8 +1492,9 @@ impl MidiPlayer {
             return Err("Invalid song index".into());
         }
         self.current_song_index = Some(song_index);
+        self.record_play(song_index);
         let selected_song = self.get_song(song_index).ok_or("Invalid song index")?;
-        let tempo = tempo_bpm.unwrap_or(selected_song.default_tempo);
+        let tempo = tempo_bpm.unwrap_or_else(|| self.scaled_tempo(selected_song.default_tempo));
         // --- Map user-facing track indices to dense indices ---
         let track_indices = Self::get_dense_indices_for_song(selected_song, tracks.as_deref());
         let user_indices: Vec<_> = track_indices
@@ -1072,6 +1585,7 @@ impl MidiPlayer {
         loop {
             for i in 0..songs_count {
                 self.current_song_index = Some(i);
+                self.record_play(i);
                 let song = self.get_song(i).ok_or("Invalid song index")?;
                 println!(
                     "\n🔀 Playing song {} of {}: {}",
@@ -1083,11 +1597,11 @@ impl MidiPlayer {
                     SongType::Midi | SongType::MusicXml => {
                         // Map user-facing indices to dense indices
                         let dense_indices = Self::get_dense_indices_for_song(song, None);
-                        let events =
-                            self.get_events_for_song(i, &dense_indices, song.default_tempo);
+                        let tempo = self.scaled_tempo(song.default_tempo);
+                        let events = self.get_events_for_song(i, &dense_indices, tempo);
                         if !events.is_empty() {
                             let continue_playing =
-                                self.play_events_with_tempo_control(&events, song.default_tempo)?;
+                                self.play_events_with_tempo_control(&events, tempo)?;
                             if !continue_playing {
                                 return Ok(());
                             }
@@ -1123,16 +1637,17 @@ impl MidiPlayer {
         indices.shuffle(&mut rng);
         for &song_index in &indices {
             self.current_song_index = Some(song_index);
+            self.record_play(song_index);
             let song = self.get_song(song_index).ok_or("Invalid song index")?;
             println!("\n🎲 Random song {}: {}", song_index, song.name);
             match song.song_type {
                 SongType::Midi | SongType::MusicXml => {
                     let dense_indices = Self::get_dense_indices_for_song(song, None);
-                    let events =
-                        self.get_events_for_song(song_index, &dense_indices, song.default_tempo);
+                    let tempo = self.scaled_tempo(song.default_tempo);
+                    let events = self.get_events_for_song(song_index, &dense_indices, tempo);
                     if !events.is_empty() {
                         let continue_playing =
-                            self.play_events_with_tempo_control(&events, song.default_tempo)?;
+                            self.play_events_with_tempo_control(&events, tempo)?;
                         if !continue_playing {
                             break;
                         }
@@ -1358,6 +1873,7 @@ impl MidiPlayer {
                 println!("🛑 Shutdown requested, exiting interactive mode");
                 break;
             }
+            self.poll_watched_directories();
             self.show_main_menu()?;
         }
         Ok(())
@@ -1435,14 +1951,15 @@ impl MidiPlayer {
             println!("\n💡 During playback: 'n' = next song, 'q' = quit to menu");
         }
 
-        print!("\nSelect option (1-14, q, x): ");
+        let prompt = "\nSelect option (1-14, q, x): ";
+        print!("{}", prompt);
         stdout().flush()?;
-        let mut input = String::new();
-        let bytes_read = stdin().read_line(&mut input)?;
+        let input = self.read_line_watching(prompt)?;
 
         // If no bytes were read, stdin is closed (EOF), so exit gracefully
-        if bytes_read == 0 {
+        if input.is_empty() {
             println!("👋 Goodbye!");
+            self.save_library();
             std::process::exit(0);
         }
 
@@ -1537,6 +2054,7 @@ impl MidiPlayer {
             }
             "x" => {
                 println!("👋 Goodbye!");
+                self.save_library();
                 std::process::exit(0);
             }
             _ => {
@@ -1707,12 +2225,20 @@ impl MidiPlayer {
             .and_then(|s| s.to_str())
             .unwrap_or("")
             .to_ascii_lowercase();
+        if self.library.is_some() && matches!(ext.as_str(), "mid" | "xml" | "musicxml") {
+            let canonical = fs::canonicalize(path)?;
+            let filename = canonical.to_string_lossy();
+            if self.dynamic_songs.iter().any(|s| s.filename == filename) {
+                println!("ℹ️  Already loaded: {}", canonical.display());
+                return Ok(());
+            }
+        }
         if ext == "mid" {
             let midi_data = fs::read(path)?;
-            let song_info = self.parse_midi_file_from_data(&midi_data, path)?;
+            let song_info = self.midi_song_info(path, &midi_data)?;
             println!("{:?}", song_info);
             println!(
-                "✅ Parsed MIDI file: {} ({} tracks, default tempo: {} BPM)",
+                "✅ Loaded MIDI file: {} ({} tracks, default tempo: {} BPM)",
                 song_info.name,
                 song_info.tracks.len(),
                 song_info.default_tempo
@@ -1731,37 +2257,54 @@ impl MidiPlayer {
             );
             Ok(())
         } else if ext == "xml" || ext == "musicxml" {
-            // Try to parse as MusicXML
-            match musicxml::read_score_partwise(&path.to_string_lossy()) {
-                Ok(_score) => {
-                    // Use the same extraction logic as embed_musicxml.rs
-                    let xml_song = e_midi_shared::embed_musicxml::extract_musicxml_songs(
-                        path.parent().unwrap_or_else(|| std::path::Path::new(".")),
-                    )
-                    .into_iter()
-                    .find(|s| s.filename == path.file_name().unwrap().to_string_lossy());
-                    if let Some(xml) = xml_song {
-                        let song_info = xml_song_to_song_info(&xml);
-                        self.dynamic_songs.push(song_info);
-                        // For MusicXML, push empty Vec to dynamic_midi_data to keep indices aligned
-                        self.dynamic_midi_data.push(Vec::new());
-                        println!(
-                            "✅ Added MusicXML song: {} (index {})",
-                            self.dynamic_songs.last().unwrap().name,
-                            self.get_static_song_count() + self.dynamic_songs.len() - 1
-                        );
-                        Ok(())
-                    } else {
-                        Err("Failed to extract MusicXML song info".into())
-                    }
-                }
-                Err(e) => Err(format!("Failed to parse MusicXML: {}", e).into()),
-            }
+            let song_info = self.musicxml_song_info(path)?;
+            self.dynamic_songs.push(song_info);
+            // For MusicXML, push empty Vec to dynamic_midi_data to keep indices aligned
+            self.dynamic_midi_data.push(Vec::new());
+            println!(
+                "✅ Added MusicXML song: {} (index {})",
+                self.dynamic_songs.last().unwrap().name,
+                self.get_static_song_count() + self.dynamic_songs.len() - 1
+            );
+            Ok(())
         } else {
             Err("Unsupported file type (must be .mid, .xml, or .musicxml)".into())
         }
     }
 
+    /// Parse a MusicXML file into a SongInfo named by its path
+    ///
+    /// Loaded MusicXML songs keep no note data, so playback re-reads the file.
+    fn read_musicxml_song(path: &Path) -> Result<SongInfo, Box<dyn Error>> {
+        let mut song = xml_song_to_song_info(&read_musicxml_file(path)?);
+        song.filename = path.to_string_lossy().to_string();
+        Ok(song)
+    }
+
+    /// Add a song from in-memory Standard MIDI File data, returning its global index
+    pub fn add_song_from_midi_data(
+        &mut self,
+        data: &[u8],
+        name: Option<&str>,
+    ) -> Result<usize, Box<dyn Error>> {
+        let song_info = self.parse_midi_file_from_data(data, name.unwrap_or("Untitled.mid"))?;
+        println!(
+            "✅ Parsed MIDI data: {} ({} tracks, default tempo: {} BPM)",
+            song_info.name,
+            song_info.tracks.len(),
+            song_info.default_tempo
+        );
+        self.dynamic_songs.push(song_info);
+        self.dynamic_midi_data.push(data.to_vec());
+        let index = self.get_static_song_count() + self.dynamic_songs.len() - 1;
+        println!(
+            "✅ Added song: {} (index {})",
+            self.dynamic_songs.last().unwrap().name,
+            index
+        );
+        Ok(index)
+    }
+
     /// Scan a directory and add all MIDI files to the dynamic song list
     pub fn scan_directory<P: AsRef<Path>>(&mut self, dir_path: P) -> Result<usize, Box<dyn Error>> {
         let dir_path = dir_path.as_ref();
@@ -1978,6 +2521,19 @@ impl MidiPlayer {
         }
     }
 
+    /// Standard MIDI File data of a MIDI song: embedded for static songs, as
+    /// loaded for dynamic ones
+    pub fn song_midi_data(&self, song_index: usize) -> Option<&[u8]> {
+        match song_index.checked_sub(self.get_static_song_count()) {
+            None => get_embedded_midi_bytes(song_index),
+            Some(dynamic_index) => self
+                .dynamic_midi_data
+                .get(dynamic_index)
+                .map(|data| &data[..])
+                .filter(|data| !data.is_empty()),
+        }
+    }
+
     /// Get events for dynamic songs
     fn get_events_for_dynamic_song(
         &self,
@@ -1990,6 +2546,14 @@ impl MidiPlayer {
             return Vec::new();
         }
 
+        let song = &self.dynamic_songs[dynamic_song_index];
+        if song.song_type == SongType::MusicXml {
+            return musicxml_events(song, track_indices, fallback_bpm).unwrap_or_else(|e| {
+                println!("❌ Failed to read MusicXML song {}: {}", song.filename, e);
+                Vec::new()
+            });
+        }
+
         let midi_data = &self.dynamic_midi_data[dynamic_song_index];
         let smf = match Smf::parse(midi_data) {
             Ok(smf) => smf,
@@ -2233,28 +2797,10 @@ impl MidiPlayer {
         events: &[Note],
         initial_tempo_bpm: u32,
     ) -> Result<bool, Box<dyn Error>> {
-        // --- PATCH: Send Program Change for MusicXML ---
-        if let Some(song_index) = self.current_song_index {
-            if let Some(song) = self.get_song(song_index) {
-                if song.song_type == SongType::MusicXml {
-                    for track in song.tracks.iter() {
-                        if let Some(program) = track.program {
-                            // Find the first note in events for this track and use its channel
-                            let note_channel = events
-                                .iter()
-                                .map(|n| n.chan)
-                                .next()
-                                .or_else(|| track.channels.first().copied())
-                                .unwrap_or(0);
-                            let msg = vec![0xC0 | note_channel, program];
-                            println!(
-                                "🎶 Sending Program Change for MusicXML: Channel {}, Program {}",
-                                note_channel, program
-                            );
-                            let _ = self.send_midi_command(MidiCommand::SendMessage(msg));
-                        }
-                    }
-                }
+        // Send each channel's program so synths and sinks pick the right instrument
+        if let Some(song) = self.current_song_index.and_then(|i| self.get_song(i)) {
+            for msg in export::program_changes(song, events) {
+                let _ = self.send_midi_command(MidiCommand::SendMessage(msg));
             }
         }
 
@@ -2377,6 +2923,18 @@ impl MidiPlayer {
         });
 
         let start = Instant::now();
+        let mut mtc_generator = match (&self.mtc_settings, self.current_song_index) {
+            (Some(settings), Some(song_index)) => {
+                let generator = mtc::MtcGenerator::new(
+                    settings.rate,
+                    settings.timeline_ms(song_index, 0),
+                    start,
+                );
+                self.send_midi_command(MidiCommand::SendMessage(generator.locate_message()))?;
+                Some(generator)
+            }
+            _ => None,
+        };
         let mut idx = 0;
         let mut last_tempo = initial_tempo_bpm as f32 * 1000.0;
         let mut time_offset = 0.0;
@@ -2440,15 +2998,26 @@ impl MidiPlayer {
                 last_print_time = adjusted_time;
             }
 
+            if let Some(generator) = mtc_generator.as_mut() {
+                for qf in generator.poll(Instant::now()) {
+                    self.send_midi_command(MidiCommand::SendMessage(qf.to_vec()))?;
+                }
+            }
+
             // --- FIX: Use absolute event scheduling ---
             if idx < timeline.len() {
                 let e = &timeline[idx];
                 let target_time_ms = e.t as u64;
                 let elapsed_ms = start.elapsed().as_millis() as u64;
                 if elapsed_ms < target_time_ms {
-                    std::thread::sleep(std::time::Duration::from_millis(
-                        target_time_ms - elapsed_ms,
-                    ));
+                    // Wake often enough to keep quarter frames on time
+                    let wait_ms = target_time_ms - elapsed_ms;
+                    let wait_ms = if mtc_generator.is_some() {
+                        wait_ms.min(2)
+                    } else {
+                        wait_ms
+                    };
+                    std::thread::sleep(std::time::Duration::from_millis(wait_ms));
                     continue;
                 }
                 let msg = match e.kind {
@@ -2880,31 +3449,19 @@ impl MidiPlayer {
                 } else if path.extension().and_then(|s| s.to_str()) == Some("xml")
                     || path.extension().and_then(|s| s.to_str()) == Some("musicxml")
                 {
-                    // Try to parse as MusicXML
-                    match musicxml::read_score_partwise(&path.to_string_lossy()) {
-                        Ok(_score) => {
-                            // Use the same extraction logic as embed_musicxml.rs
-                            let xml_song = e_midi_shared::embed_musicxml::extract_musicxml_songs(
-                                path.parent().unwrap_or_else(|| std::path::Path::new(".")),
-                            )
-                            .into_iter()
-                            .find(|s| s.filename == path.file_name().unwrap().to_string_lossy());
-                            if let Some(xml) = xml_song {
-                                let song_info = xml_song_to_song_info(&xml);
-                                self.dynamic_songs.push(song_info);
-                                // For MusicXML, push empty Vec to dynamic_midi_data to keep indices aligned
-                                self.dynamic_midi_data.push(Vec::new());
-                                println!(
-                                    "✅ Added MusicXML song: {} (index {})",
-                                    self.dynamic_songs.last().unwrap().name,
-                                    self.get_static_song_count() + self.dynamic_songs.len() - 1
-                                );
-                            } else {
-                                println!("❌ Failed to extract MusicXML song info");
-                            }
+                    match Self::read_musicxml_song(path) {
+                        Ok(song_info) => {
+                            self.dynamic_songs.push(song_info);
+                            // For MusicXML, push empty Vec to dynamic_midi_data to keep indices aligned
+                            self.dynamic_midi_data.push(Vec::new());
+                            println!(
+                                "✅ Added MusicXML song: {} (index {})",
+                                self.dynamic_songs.last().unwrap().name,
+                                self.get_static_song_count() + self.dynamic_songs.len() - 1
+                            );
                         }
                         Err(e) => {
-                            println!("❌ Failed to parse MusicXML: {}", e);
+                            println!("❌ {}", e);
                         }
                     }
                 } else {
@@ -2941,73 +3498,265 @@ impl MidiPlayer {
         mut subscriber: crate::ipc::EventSubscriber,
     ) -> Result<(), Box<dyn Error>> {
         println!("🔗 IPC command loop started, listening for TUI commands...");
+        let mut transport = crate::transport::Transport::new();
 
         loop {
             if should_shutdown() {
                 break;
             }
 
-            // Check for commands from TUI
-            match subscriber.try_receive() {
-                Ok(events) => {
-                    for event in events {
-                        self.handle_ipc_command(event)?;
-                    }
-                }
-                Err(_) => {
-                    // No events available - continue
-                }
-            }
+            self.poll_ipc_commands(&mut subscriber, &mut transport);
+            transport.update(self);
 
             // Small delay to prevent busy waiting
             thread::sleep(Duration::from_millis(10));
         }
 
+        transport.stop(self);
         println!("🔗 IPC command loop finished");
         Ok(())
     }
 
-    /// Handle individual IPC commands
-    fn handle_ipc_command(&mut self, event: crate::ipc::Event) -> Result<(), Box<dyn Error>> {
+    /// Handle any IPC commands waiting on the subscriber
+    ///
+    /// A failing command is reported and does not stop the ones after it.
+    fn poll_ipc_commands(
+        &mut self,
+        subscriber: &mut crate::ipc::EventSubscriber,
+        transport: &mut crate::transport::Transport,
+    ) {
+        // No events available is not an error
+        if let Ok(events) = subscriber.try_receive() {
+            for change in self.track_ipc_services(&events) {
+                match change {
+                    crate::ipc::Event::ServiceJoined {
+                        app_id, process_id, ..
+                    } => println!("🔗 {:?} joined (pid {})", app_id, process_id),
+                    crate::ipc::Event::ServiceLeft {
+                        app_id, process_id, ..
+                    } => println!("💤 {:?} left (pid {})", app_id, process_id),
+                    _ => {}
+                }
+            }
+            for event in events {
+                if let Err(e) = self.handle_ipc_command(event, transport) {
+                    eprintln!("⚠️  IPC command failed: {}", e);
+                }
+            }
+        }
+        for transfer_id in self.chunks.expire() {
+            println!("⌛ Dropped unfinished IPC transfer {}", transfer_id);
+        }
+    }
+
+    /// Handle individual IPC commands against the playlist transport
+    fn handle_ipc_command(
+        &mut self,
+        event: crate::ipc::Event,
+        transport: &mut crate::transport::Transport,
+    ) -> Result<(), Box<dyn Error>> {
+        use crate::ipc::Event;
         match event {
-            crate::ipc::Event::MidiCommandPlay { song_index, .. } => {
+            Event::MidiCommandPlay { song_index, .. } => {
                 println!("🎵 Received play command for song {}", song_index);
-                if song_index < self.get_total_song_count() {
-                    // Use the IPC-enabled playback method
-                    self.play_song_with_ipc(song_index)?;
-                } else {
-                    println!("❌ Invalid song index: {}", song_index);
-                }
+                transport.play(self, song_index)?;
             }
-            crate::ipc::Event::MidiCommandStop { .. } => {
+            Event::MidiCommandStop { .. } => {
                 println!("⏹️ Received stop command");
-                // Send all notes off
-                for channel in 0..16 {
-                    self.send_midi_command(MidiCommand::SendMessage(vec![0xB0 | channel, 123, 0]))?;
-                }
-                self.publish_midi_event(crate::ipc::Event::MidiPlaybackStopped {
-                    timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_millis() as u64,
-                });
+                transport.stop(self);
             }
-            crate::ipc::Event::MidiCommandNext { .. } => {
+            Event::MidiCommandPause { .. } => {
+                println!("⏸️ Received pause command");
+                // `Transport::pause` toggles; a pause command only ever pauses
+                if transport.state() == crate::transport::TransportState::Playing {
+                    transport.pause(self)?;
+                }
+            }
+            Event::MidiCommandResume { .. } => {
+                println!("▶️ Received resume command");
+                transport.resume(self)?;
+            }
+            Event::MidiCommandNext { .. } => {
                 println!("⏭️ Received next command");
-                // TODO: Implement next song logic
+                transport.next(self)?;
             }
-            crate::ipc::Event::MidiCommandPrevious { .. } => {
+            Event::MidiCommandPrevious { .. } => {
                 println!("⏮️ Received previous command");
-                // TODO: Implement previous song logic
+                transport.previous(self)?;
+            }
+            Event::MidiCommandSetTempo { new_tempo, .. } => {
+                println!("🎶 Received tempo command: {} BPM", new_tempo);
+                transport.set_tempo(self, new_tempo)?;
+            }
+            Event::MidiCommandSongListRequest { .. } => {
+                println!("📋 Received song list request");
+                self.publish_song_list()?;
+            }
+            Event::StateRequest { .. } => {
+                self.answer_state_request(&event, transport)?;
+            }
+            Event::MessageChunk { .. } => {
+                self.receive_chunk(&event)?;
+            }
+            Event::MidiCommandLoadSong {
+                transfer_id,
+                file_name,
+                ..
+            } => {
+                println!("📥 Received load song command: {}", file_name);
+                self.load_sent_song(transfer_id, &file_name, transport)?;
             }
             _ => {
-                // Ignore other events
+                // Not a command
             }
         }
 
         Ok(())
     }
 
+    /// All songs in the form sent over IPC
+    pub fn ipc_song_list(&self) -> Vec<crate::ipc::IpcSongInfo> {
+        let static_count = self.get_static_song_count();
+        self.get_all_songs()
+            .into_iter()
+            .enumerate()
+            .map(|(index, song)| crate::ipc::IpcSongInfo {
+                index,
+                name: song.name.clone(),
+                filename: song.filename.clone(),
+                track_count: song.tracks.len(),
+                default_tempo: song.default_tempo,
+                duration_ms: song.duration_ms,
+                is_dynamic: index >= static_count,
+            })
+            .collect()
+    }
+
+    /// Answer a song list request
+    ///
+    /// Publishes `MidiSongListUpdated` with the song count, then the list as
+    /// unsolicited `StateResponse { state_type: MidiSongList }` parts whose
+    /// joined data is a JSON array of `IpcSongInfo`.
+    fn publish_song_list(&self) -> Result<(), Box<dyn Error>> {
+        let ipc_manager = self
+            .ipc_manager
+            .as_ref()
+            .ok_or("IPC publisher not initialized")?;
+        let data = serde_json::to_vec(&self.ipc_song_list())?;
+        ipc_manager.publish_event(crate::ipc::Event::MidiSongListUpdated {
+            song_count: self.get_total_song_count(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        })?;
+        for event in crate::ipc::state_responses(0, crate::ipc::StateType::MidiSongList, &data) {
+            ipc_manager.publish_event(event)?;
+        }
+        Ok(())
+    }
+
+    /// Answer a `StateRequest` from the registered state providers
+    fn answer_state_request(
+        &mut self,
+        event: &crate::ipc::Event,
+        transport: &crate::transport::Transport,
+    ) -> Result<(), Box<dyn Error>> {
+        let snapshot = PlayerState {
+            playback: transport.playback_state(self),
+            songs: self.ipc_song_list(),
+        };
+        let responses = self.state_providers.respond(&snapshot, event);
+        if responses.is_empty() {
+            return Ok(());
+        }
+        let ipc_manager = self
+            .ipc_manager
+            .as_ref()
+            .ok_or("IPC publisher not initialized")?;
+        for response in responses {
+            ipc_manager.publish_event(response)?;
+        }
+        Ok(())
+    }
+
+    /// Feed a chunk to the reassembly and acknowledge it when due
+    fn receive_chunk(&mut self, event: &crate::ipc::Event) -> Result<(), Box<dyn Error>> {
+        let Some(ack) = self.chunks.handle(event) else {
+            return Ok(());
+        };
+        if let crate::ipc::Event::MessageChunkAck {
+            transfer_id,
+            error: Some(error),
+            ..
+        } = &ack
+        {
+            eprintln!("⚠️  Rejected IPC transfer {}: {}", transfer_id, error);
+        }
+        let ipc_manager = self
+            .ipc_manager
+            .as_ref()
+            .ok_or("IPC publisher not initialized")?;
+        ipc_manager.publish_event(ack)?;
+        Ok(())
+    }
+
+    /// Add a song file sent over IPC and play it
+    ///
+    /// The file is saved under the runtime directory and added with
+    /// `add_song_from_file`, just like a file given on the command line.
+    fn load_sent_song(
+        &mut self,
+        transfer_id: crate::ipc::EventId,
+        file_name: &str,
+        transport: &mut crate::transport::Transport,
+    ) -> Result<(), Box<dyn Error>> {
+        let message = self
+            .chunks
+            .take(transfer_id)
+            .ok_or_else(|| format!("Song transfer {} has not arrived", transfer_id))?;
+        if message.topic != crate::ipc::TOPIC_SONG {
+            return Err(format!(
+                "Transfer {} is not a song (topic '{}')",
+                transfer_id, message.topic
+            )
+            .into());
+        }
+        // Only the name is kept, so a sender can't write outside the directory
+        let name = Path::new(file_name)
+            .file_name()
+            .ok_or_else(|| format!("Invalid song file name: '{}'", file_name))?;
+        // One directory per transfer, as MusicXML songs are read by directory
+        let dir = serve::runtime_dir()
+            .join("e_midi_songs")
+            .join(transfer_id.to_string());
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(name);
+        fs::write(&path, &message.data)?;
+        let count = self.get_total_song_count();
+        self.add_song_from_file(&path)?;
+        if self.get_total_song_count() == count {
+            return Err(format!("{} was not added", path.display()).into());
+        }
+        self.publish_midi_event(ipc::Event::MidiSongListUpdated {
+            song_count: self.get_total_song_count(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        });
+        transport.play(self, count)?;
+        Ok(())
+    }
+
+    /// State providers answering IPC `StateRequest`s
+    ///
+    /// `MidiPlayback` and `MidiSongList` are registered by default; register
+    /// more to publish your own state from the same player.
+    pub fn state_providers_mut(&mut self) -> &mut crate::ipc::StateProviders<PlayerState> {
+        &mut self.state_providers
+    }
+
     /// Play a song with resume support. All state is managed internally.
     /// If song_index is None, resumes last song. If position_ms is None, resumes last position.
     /// If tracks or tempo_bpm are None, uses defaults.
@@ -3061,8 +3810,9 @@ impl MidiPlayer {
             println!("[DIAG][resume] Invalid song index: {}", idx);
             return Err("Invalid song index".into());
         }
+        self.record_play(idx);
         let selected_song = self.get_song(idx).ok_or("Invalid song index")?;
-        let tempo = tempo_bpm.unwrap_or(selected_song.default_tempo);
+        let tempo = tempo_bpm.unwrap_or_else(|| self.scaled_tempo(selected_song.default_tempo));
         let track_indices = if let Some(ref tracks) = tracks {
             if tracks.contains(&0) {
                 // 0 means all tracks
@@ -3404,8 +4154,8 @@ impl MidiPlayer {
     pub fn play_embedded_audio(_data: &'static [u8]) -> Result<(), Box<dyn std::error::Error>> {
         #[cfg(feature = "uses_rodio")]
         {
-            let (_stream, stream_handle) = OutputStream::try_default()?;
-            let sink = Sink::try_new(&stream_handle)?;
+            let stream = OutputStreamBuilder::open_default_stream()?;
+            let sink = Sink::connect_new(stream.mixer());
             let cursor = Cursor::new(_data);
             let source = Decoder::new(cursor)?;
             sink.append(source);
@@ -3415,6 +4165,57 @@ impl MidiPlayer {
     }
 }
 
+/// Extract a MusicXML file the way songs are embedded at build time
+pub(crate) fn read_musicxml_file(path: &Path) -> Result<XmlSongInfo, Box<dyn Error>> {
+    if let Err(e) = musicxml::read_score_partwise(&path.to_string_lossy()) {
+        return Err(format!("Failed to parse MusicXML: {}", e).into());
+    }
+    // Use the same extraction logic as embed_musicxml.rs
+    e_midi_shared::embed_musicxml::extract_musicxml_songs(
+        path.parent().unwrap_or_else(|| std::path::Path::new(".")),
+    )
+    .into_iter()
+    .find(|s| s.filename == path.file_name().unwrap_or_default().to_string_lossy())
+    .ok_or_else(|| "Failed to extract MusicXML song info".into())
+}
+
+/// Notes of a loaded MusicXML song's parts (all parts when `track_indices` is
+/// empty), timed at `tempo_bpm` like embedded songs
+fn musicxml_events(
+    song: &SongInfo,
+    track_indices: &[usize],
+    tempo_bpm: u32,
+) -> Result<Vec<Note>, Box<dyn Error>> {
+    let xml = read_musicxml_file(Path::new(&song.filename))?;
+    let ticks_to_ms = |ticks: u32| {
+        (ticks as u64 * 60_000 / (tempo_bpm.max(1) as u64 * xml.ticks_per_q.max(1) as u64)) as u32
+    };
+    let mut events = Vec::new();
+    for (part, timeline) in xml.track_notes.iter().enumerate() {
+        if !track_indices.is_empty() && !track_indices.contains(&part) {
+            continue;
+        }
+        let chan = song
+            .tracks
+            .get(part)
+            .and_then(|t| t.channels.first())
+            .copied()
+            .unwrap_or(0);
+        for &(start, dur, _voice, pitch, vel) in timeline {
+            events.push(Note {
+                start_ms: ticks_to_ms(start),
+                dur_ms: ticks_to_ms(dur).max(50),
+                chan,
+                pitch,
+                vel,
+                track: part as u8,
+            });
+        }
+    }
+    events.sort_by_key(|n| n.start_ms);
+    Ok(events)
+}
+
 /// Converts an XmlSongInfo (from MusicXML) into a SongInfo and its notes, for playback.
 pub fn xml_song_to_song_info(xml: &XmlSongInfo) -> SongInfo {
     // Build track index map: user index -> dense index (identity for XML)
@@ -3472,3 +4273,48 @@ pub fn xml_song_to_song_info(xml: &XmlSongInfo) -> SongInfo {
         duration_ms: None, // Add this field, or compute from notes if needed
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thru_reaches_the_sink_while_a_song_is_playing() {
+        let recording = sink::RecordingSink::new();
+        let messages = recording.messages();
+        let player = MidiPlayer::with_sink(Box::new(recording)).unwrap();
+        let sender = player.command_sender();
+        // One long note keeps the playback thread holding the sink
+        sender
+            .send(MidiCommand::PlayNotes {
+                song_index: 0,
+                notes: vec![Note {
+                    start_ms: 0,
+                    dur_ms: 5_000,
+                    chan: 0,
+                    pitch: 60,
+                    vel: 100,
+                    track: 0,
+                }],
+                position_ms: 0,
+                setup: Vec::new(),
+                beats: None,
+            })
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let thru = vec![0x91, 64, 90];
+        sender.send(MidiCommand::SendMessage(thru.clone())).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let sent: Vec<Vec<u8>> = messages
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.bytes.clone())
+            .collect();
+        assert!(sent.contains(&vec![0x90, 60, 100]), "song note not played");
+        assert!(sent.contains(&thru), "thru message dropped during playback");
+        sender.send(MidiCommand::Stop).unwrap();
+    }
+}
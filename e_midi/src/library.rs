@@ -0,0 +1,581 @@
+//! Persistent library index.
+//!
+//! Remembers every MIDI and MusicXML file added to the dynamic playlist:
+//! canonical path, content hash, size and modification time, the parsed
+//! `SongInfo` metadata, when it was added, how often it was played and where
+//! playback last stopped.
+//! The index lives in `$XDG_DATA_HOME/e_midi/library.json` by default.
+//!
+//! On startup unchanged files (same size and mtime) are loaded from the index
+//! without being parsed; touched files are hashed and only re-parsed when their
+//! content actually changed. Files that disappeared are dropped.
+//!
+//! Play counts and positions change on every play, so those updates are
+//! written at most every [`PLAY_STATS_SAVE_INTERVAL`]; the rest is flushed when
+//! the index is dropped.
+
+use crate::{MidiPlayer, SongInfo, SongSource, SongType, TrackInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// On-disk format version
+const INDEX_VERSION: u32 = 1;
+
+/// Minimum time between index writes caused by play statistics
+pub const PLAY_STATS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default index location: `$XDG_DATA_HOME/e_midi/library.json` or the platform equivalent
+pub fn default_index_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("e_midi").join("library.json"))
+}
+
+/// 64-bit FNV-1a hash of a file's content, as hex
+pub fn content_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn modified_ms(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// `TrackInfo` as stored in the index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexedTrack {
+    pub index: usize,
+    pub program: Option<u8>,
+    pub guess: Option<String>,
+    pub channels: Vec<u8>,
+    pub note_count: usize,
+    pub pitch_range: (u8, u8),
+    pub sample_notes: Vec<u8>,
+}
+
+/// Format of an indexed file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexedType {
+    #[default]
+    Midi,
+    MusicXml,
+}
+
+/// `SongInfo` metadata as stored in the index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexedSong {
+    /// Missing in indexes written before MusicXML files were indexed
+    #[serde(default)]
+    pub song_type: IndexedType,
+    pub name: String,
+    pub default_tempo: u32,
+    pub ticks_per_q: Option<u32>,
+    pub duration_ms: Option<u32>,
+    pub tracks: Vec<IndexedTrack>,
+}
+
+impl From<&SongInfo> for IndexedSong {
+    fn from(song: &SongInfo) -> Self {
+        IndexedSong {
+            song_type: if song.song_type == SongType::MusicXml {
+                IndexedType::MusicXml
+            } else {
+                IndexedType::Midi
+            },
+            name: song.name.clone(),
+            default_tempo: song.default_tempo,
+            ticks_per_q: song.ticks_per_q,
+            duration_ms: song.duration_ms,
+            tracks: song
+                .tracks
+                .iter()
+                .map(|t| IndexedTrack {
+                    index: t.index,
+                    program: t.program,
+                    guess: t.guess.clone(),
+                    channels: t.channels.clone(),
+                    note_count: t.note_count,
+                    pitch_range: t.pitch_range,
+                    sample_notes: t.sample_notes.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl IndexedSong {
+    /// Rebuild the `SongInfo` the MIDI or MusicXML parser would have produced
+    pub fn to_song_info(&self, path: &Path) -> SongInfo {
+        let tracks: Vec<TrackInfo> = self
+            .tracks
+            .iter()
+            .map(|t| TrackInfo {
+                index: t.index,
+                program: t.program,
+                guess: t.guess.clone(),
+                channels: t.channels.clone(),
+                note_count: t.note_count,
+                pitch_range: t.pitch_range,
+                sample_notes: t.sample_notes.clone(),
+            })
+            .collect();
+        let track_index_map: HashMap<usize, usize> = match self.song_type {
+            IndexedType::Midi => tracks
+                .iter()
+                .enumerate()
+                .map(|(dense, t)| (t.index, dense))
+                .collect(),
+            // Same identity map as `xml_song_to_song_info`
+            IndexedType::MusicXml => tracks.iter().map(|t| (t.index, t.index)).collect(),
+        };
+        SongInfo {
+            filename: path.to_string_lossy().to_string(),
+            name: self.name.clone(),
+            tracks,
+            default_tempo: self.default_tempo,
+            ticks_per_q: self.ticks_per_q,
+            song_type: match self.song_type {
+                IndexedType::Midi => SongType::Midi,
+                IndexedType::MusicXml => SongType::MusicXml,
+            },
+            source: SongSource::None,
+            track_index_map,
+            duration_ms: self.duration_ms,
+        }
+    }
+}
+
+/// One indexed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub hash: String,
+    pub size: u64,
+    pub modified_ms: u64,
+    /// Unix time the file was first indexed
+    pub added: u64,
+    pub play_count: u32,
+    /// Unix time of the last play
+    pub last_played: Option<u64>,
+    /// Where playback last stopped
+    pub last_position_ms: u32,
+    pub song: IndexedSong,
+}
+
+impl LibraryEntry {
+    fn matches(&self, meta: &fs::Metadata) -> bool {
+        self.size == meta.len() && self.modified_ms == modified_ms(meta)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile {
+    version: u32,
+    songs: Vec<LibraryEntry>,
+}
+
+/// The on-disk index, keyed by canonical path
+#[derive(Debug)]
+pub struct LibraryIndex {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, LibraryEntry>,
+    dirty: bool,
+    last_saved: Option<Instant>,
+}
+
+impl LibraryIndex {
+    /// Load an index file; a missing file gives an empty index
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => {
+                let file: IndexFile = serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid library index {}: {}", path.display(), e))?;
+                if file.version != INDEX_VERSION {
+                    return Err(format!(
+                        "Library index {} has unsupported version {}",
+                        path.display(),
+                        file.version
+                    )
+                    .into());
+                }
+                file.songs
+                    .into_iter()
+                    .map(|e| (e.path.clone(), e))
+                    .collect()
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        Ok(LibraryIndex {
+            path,
+            entries,
+            dirty: false,
+            last_saved: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &LibraryEntry> {
+        self.entries.values()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&LibraryEntry> {
+        self.entries.get(path)
+    }
+
+    /// Write the index if anything changed (atomically, via a temp file)
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = IndexFile {
+            version: INDEX_VERSION,
+            songs: self.entries.values().cloned().collect(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(&file)?)?;
+        fs::rename(&tmp, &self.path)?;
+        self.dirty = false;
+        self.last_saved = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Write the index unless it was written less than `interval` ago
+    pub fn save_if_due(&mut self, interval: Duration) -> Result<(), Box<dyn Error>> {
+        if self.last_saved.is_some_and(|t| t.elapsed() < interval) {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Drop entries whose file no longer exists, returning how many
+    pub fn prune_missing(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| path.is_file());
+        let removed = before - self.entries.len();
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    pub fn remove(&mut self, path: &Path) -> Option<LibraryEntry> {
+        let removed = self.entries.remove(path);
+        self.dirty |= removed.is_some();
+        removed
+    }
+
+    /// Cached metadata for `path`, refreshed against its current content
+    ///
+    /// `parse` runs only when the file is new or its hash changed.
+    pub fn song_info<F>(
+        &mut self,
+        path: &Path,
+        data: &[u8],
+        parse: F,
+    ) -> Result<SongInfo, Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<SongInfo, Box<dyn Error>>,
+    {
+        let meta = fs::metadata(path)?;
+        let mut hash = None;
+        if let Some(entry) = self.entries.get_mut(path) {
+            if entry.matches(&meta) {
+                return Ok(entry.song.to_song_info(path));
+            }
+            let current = content_hash(data);
+            if entry.hash == current {
+                // Touched but not changed
+                entry.size = meta.len();
+                entry.modified_ms = modified_ms(&meta);
+                self.dirty = true;
+                return Ok(entry.song.to_song_info(path));
+            }
+            hash = Some(current);
+        }
+
+        let song = parse()?;
+        let hash = hash.unwrap_or_else(|| content_hash(data));
+        let now = unix_secs();
+        let entry = self
+            .entries
+            .entry(path.to_path_buf())
+            .or_insert_with(|| LibraryEntry {
+                path: path.to_path_buf(),
+                hash: String::new(),
+                size: 0,
+                modified_ms: 0,
+                added: now,
+                play_count: 0,
+                last_played: None,
+                last_position_ms: 0,
+                song: IndexedSong::from(&song),
+            });
+        entry.hash = hash;
+        entry.size = meta.len();
+        entry.modified_ms = modified_ms(&meta);
+        entry.song = IndexedSong::from(&song);
+        self.dirty = true;
+        Ok(song)
+    }
+
+    /// Count a play of `path`
+    pub fn record_play(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.get_mut(path) {
+            entry.play_count += 1;
+            entry.last_played = Some(unix_secs());
+            self.dirty = true;
+        }
+    }
+
+    /// Remember where playback of `path` stopped
+    pub fn record_position(&mut self, path: &Path, position_ms: u32) {
+        if let Some(entry) = self.entries.get_mut(path) {
+            if entry.last_position_ms != position_ms {
+                entry.last_position_ms = position_ms;
+                self.dirty = true;
+            }
+        }
+    }
+}
+
+impl Drop for LibraryIndex {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            eprintln!("⚠️  Failed to save library index: {}", e);
+        }
+    }
+}
+
+impl MidiPlayer {
+    /// Use `index` for MIDI and MusicXML files added from now on and load everything in it
+    ///
+    /// Returns the number of songs loaded from the index.
+    pub fn attach_library(&mut self, mut index: LibraryIndex) -> Result<usize, Box<dyn Error>> {
+        let pruned = index.prune_missing();
+        if pruned > 0 {
+            println!("🧹 Dropped {} missing files from the library", pruned);
+        }
+        let paths: Vec<PathBuf> = index.entries().map(|e| e.path.clone()).collect();
+        println!(
+            "📚 Library index {} ({} songs)",
+            index.path().display(),
+            paths.len()
+        );
+        self.library = Some(std::sync::Mutex::new(index));
+
+        let mut loaded = 0;
+        for path in paths {
+            match self.add_song_from_file(&path) {
+                Ok(()) => loaded += 1,
+                Err(e) => println!("❌ Failed to load {}: {}", path.display(), e),
+            }
+        }
+        self.save_library();
+        Ok(loaded)
+    }
+
+    /// Run `f` on the attached library index, if any
+    pub fn with_library<R>(&self, f: impl FnOnce(&mut LibraryIndex) -> R) -> Option<R> {
+        let library = self.library.as_ref()?;
+        let mut index = library.lock().unwrap_or_else(|e| e.into_inner());
+        Some(f(&mut index))
+    }
+
+    /// Write pending library changes, reporting (not failing on) errors
+    pub fn save_library(&self) {
+        if let Some(Err(e)) = self.with_library(|index| index.save()) {
+            eprintln!("⚠️  Failed to save library index: {}", e);
+        }
+    }
+
+    /// Write pending play statistics unless the index was saved recently
+    fn save_play_stats(&self) {
+        if let Some(Err(e)) = self.with_library(|index| index.save_if_due(PLAY_STATS_SAVE_INTERVAL))
+        {
+            eprintln!("⚠️  Failed to save library index: {}", e);
+        }
+    }
+
+    /// Library key for a song: the indexed file behind a dynamic MIDI or MusicXML song
+    fn library_path(&self, song_index: usize) -> Option<PathBuf> {
+        self.library.as_ref()?;
+        let song = self.get_song(song_index)?;
+        (matches!(song.song_type, SongType::Midi | SongType::MusicXml)
+            && song_index >= self.get_static_song_count())
+        .then(|| PathBuf::from(&song.filename))
+    }
+
+    /// Count a play in the library index
+    pub fn record_play(&self, song_index: usize) {
+        if let Some(path) = self.library_path(song_index) {
+            self.with_library(|index| index.record_play(&path));
+            self.save_play_stats();
+        }
+    }
+
+    /// Remember where playback stopped in the library index
+    pub fn record_position(&self, song_index: usize, position_ms: u32) {
+        if let Some(path) = self.library_path(song_index) {
+            self.with_library(|index| index.record_position(&path, position_ms));
+            self.save_play_stats();
+        }
+    }
+
+    /// `SongInfo` for a MIDI file, from the library index when it is attached
+    pub(crate) fn midi_song_info(
+        &self,
+        path: &Path,
+        data: &[u8],
+    ) -> Result<SongInfo, Box<dyn Error>> {
+        if self.library.is_none() {
+            return self.parse_midi_file_from_data(data, path);
+        }
+        let canonical = fs::canonicalize(path)?;
+        self.with_library(|index| {
+            index.song_info(&canonical, data, || {
+                self.parse_midi_file_from_data(data, &canonical)
+            })
+        })
+        .unwrap_or_else(|| self.parse_midi_file_from_data(data, path))
+    }
+
+    /// `SongInfo` for a MusicXML file, from the library index when it is attached
+    ///
+    /// Indexed songs are named by their canonical path, like MIDI files.
+    pub(crate) fn musicxml_song_info(&self, path: &Path) -> Result<SongInfo, Box<dyn Error>> {
+        if self.library.is_none() {
+            return Self::read_musicxml_song(path);
+        }
+        let canonical = fs::canonicalize(path)?;
+        let data = fs::read(&canonical)?;
+        self.with_library(|index| {
+            index.song_info(&canonical, &data, || {
+                let mut song = Self::read_musicxml_song(&canonical)?;
+                song.filename = canonical.to_string_lossy().to_string();
+                Ok(song)
+            })
+        })
+        .unwrap_or_else(|| Self::read_musicxml_song(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &[u8] = b"<score-partwise/>";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "e_midi_library_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn xml_song(path: &Path) -> SongInfo {
+        SongInfo {
+            filename: path.to_string_lossy().to_string(),
+            name: "Song".to_string(),
+            tracks: vec![TrackInfo {
+                index: 0,
+                program: Some(0),
+                guess: Some("Piano".to_string()),
+                channels: vec![0],
+                note_count: 4,
+                pitch_range: (60, 67),
+                sample_notes: vec![60, 64, 67],
+            }],
+            default_tempo: 100,
+            ticks_per_q: Some(480),
+            song_type: SongType::MusicXml,
+            source: SongSource::None,
+            track_index_map: HashMap::from([(0, 0)]),
+            duration_ms: None,
+        }
+    }
+
+    #[test]
+    fn musicxml_entries_are_loaded_from_the_index() {
+        let dir = temp_dir("xml");
+        let song_path = dir.join("song.musicxml");
+        fs::write(&song_path, XML).unwrap();
+        let index_path = dir.join("library.json");
+        {
+            let mut index = LibraryIndex::open(&index_path).unwrap();
+            index
+                .song_info(&song_path, XML, || Ok(xml_song(&song_path)))
+                .unwrap();
+        }
+        let mut index = LibraryIndex::open(&index_path).unwrap();
+        let song = index
+            .song_info(&song_path, XML, || Err("parsed again".into()))
+            .unwrap();
+        assert_eq!(song.song_type, SongType::MusicXml);
+        assert_eq!(song.tracks[0].guess.as_deref(), Some("Piano"));
+        assert_eq!(song.track_index_map, HashMap::from([(0, 0)]));
+        drop(index);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn play_stats_are_saved_at_most_once_per_interval() {
+        let dir = temp_dir("stats");
+        let song_path = dir.join("song.musicxml");
+        fs::write(&song_path, XML).unwrap();
+        let index_path = dir.join("library.json");
+        let saved_plays = || {
+            LibraryIndex::open(&index_path)
+                .unwrap()
+                .get(&song_path)
+                .unwrap()
+                .play_count
+        };
+
+        let mut index = LibraryIndex::open(&index_path).unwrap();
+        index
+            .song_info(&song_path, XML, || Ok(xml_song(&song_path)))
+            .unwrap();
+        index.save().unwrap();
+        index.record_play(&song_path);
+        index.save_if_due(Duration::from_secs(3600)).unwrap();
+        assert_eq!(saved_plays(), 0);
+        index.save_if_due(Duration::ZERO).unwrap();
+        assert_eq!(saved_plays(), 1);
+
+        index.record_play(&song_path);
+        drop(index);
+        assert_eq!(saved_plays(), 2);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
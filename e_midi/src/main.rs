@@ -1,10 +1,16 @@
 use e_midi::cli::run_cli;
-use e_midi::set_shutdown_flag;
+use e_midi::{graceful_shutdown, set_shutdown_flag, should_shutdown};
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Set up Ctrl+C handler
+    // Set up Ctrl+C (and SIGTERM) handler
     ctrlc::set_handler(move || {
+        // `serve` cleans up and exits on its own; a second signal forces it
+        if graceful_shutdown() && !should_shutdown() {
+            println!("\n🛑 Received shutdown signal, stopping...");
+            set_shutdown_flag();
+            return;
+        }
         println!("\n🛑 Received Ctrl+C, shutting down gracefully...");
         set_shutdown_flag();
         std::process::exit(0);
@@ -0,0 +1,706 @@
+//! MIDI Time Code (MTC) output and chase.
+//!
+//! `MtcGenerator` produces quarter-frame messages for a running song position
+//! and full-frame messages on locate; `MtcDecoder` turns incoming MTC back into
+//! positions, which `MtcChaser` uses to drive playback from an external master.
+
+use crate::record::select_input_port;
+use crate::{MidiCommand, MidiPlayer};
+use midir::MidiInputConnection;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Quarter frames emitted per poll at most; anything older is skipped
+const MAX_QUARTER_FRAMES_PER_POLL: u64 = 8;
+/// Incoming MTC silence after which the chased transport is considered stopped
+const CHASE_TIMEOUT: Duration = Duration::from_millis(250);
+/// Drift between playback and incoming MTC that triggers a relocate
+const CHASE_TOLERANCE_MS: u64 = 100;
+
+/// SMPTE frame rates carried by MTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtcFrameRate {
+    Fps24,
+    Fps25,
+    /// 29.97 fps drop-frame
+    Fps2997Drop,
+    Fps30,
+}
+
+impl MtcFrameRate {
+    /// Frames per second in real time
+    pub fn fps(self) -> f64 {
+        match self {
+            MtcFrameRate::Fps24 => 24.0,
+            MtcFrameRate::Fps25 => 25.0,
+            MtcFrameRate::Fps2997Drop => 30_000.0 / 1001.0,
+            MtcFrameRate::Fps30 => 30.0,
+        }
+    }
+
+    /// Frame numbers per second as counted in the timecode
+    pub fn nominal_fps(self) -> u64 {
+        match self {
+            MtcFrameRate::Fps24 => 24,
+            MtcFrameRate::Fps25 => 25,
+            MtcFrameRate::Fps2997Drop | MtcFrameRate::Fps30 => 30,
+        }
+    }
+
+    /// Two-bit rate code used in quarter-frame piece 7 and full-frame hours
+    fn code(self) -> u8 {
+        match self {
+            MtcFrameRate::Fps24 => 0,
+            MtcFrameRate::Fps25 => 1,
+            MtcFrameRate::Fps2997Drop => 2,
+            MtcFrameRate::Fps30 => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code & 3 {
+            0 => MtcFrameRate::Fps24,
+            1 => MtcFrameRate::Fps25,
+            2 => MtcFrameRate::Fps2997Drop,
+            _ => MtcFrameRate::Fps30,
+        }
+    }
+}
+
+impl FromStr for MtcFrameRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "24" => Ok(MtcFrameRate::Fps24),
+            "25" => Ok(MtcFrameRate::Fps25),
+            "29.97" | "29.97df" | "2997" => Ok(MtcFrameRate::Fps2997Drop),
+            "30" => Ok(MtcFrameRate::Fps30),
+            other => Err(format!(
+                "Unknown MTC frame rate '{}' (use 24, 25, 29.97 or 30)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MtcFrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MtcFrameRate::Fps24 => write!(f, "24 fps"),
+            MtcFrameRate::Fps25 => write!(f, "25 fps"),
+            MtcFrameRate::Fps2997Drop => write!(f, "29.97 fps drop-frame"),
+            MtcFrameRate::Fps30 => write!(f, "30 fps"),
+        }
+    }
+}
+
+/// SMPTE timecode hh:mm:ss:ff
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+}
+
+impl Timecode {
+    pub fn new(hours: u8, minutes: u8, seconds: u8, frames: u8) -> Self {
+        Timecode {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        }
+    }
+
+    /// Timecode of a frame count since 00:00:00:00 (wraps at 24 hours)
+    pub fn from_frame_count(frames: u64, rate: MtcFrameRate) -> Self {
+        let mut frames = frames;
+        if rate == MtcFrameRate::Fps2997Drop {
+            // Frame numbers 0 and 1 are skipped every minute except every tenth
+            let ten_minutes = frames / 17_982;
+            let remainder = frames % 17_982;
+            let dropped = if remainder < 2 {
+                0
+            } else {
+                2 * ((remainder - 2) / 1_798)
+            };
+            frames += 18 * ten_minutes + dropped;
+        }
+        let fps = rate.nominal_fps();
+        let total_seconds = frames / fps;
+        Timecode {
+            hours: ((total_seconds / 3600) % 24) as u8,
+            minutes: ((total_seconds / 60) % 60) as u8,
+            seconds: (total_seconds % 60) as u8,
+            frames: (frames % fps) as u8,
+        }
+    }
+
+    /// Frame count since 00:00:00:00
+    pub fn to_frame_count(self, rate: MtcFrameRate) -> u64 {
+        let fps = rate.nominal_fps();
+        let total_seconds =
+            self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64;
+        let frames = total_seconds * fps + self.frames as u64;
+        if rate == MtcFrameRate::Fps2997Drop {
+            let total_minutes = self.hours as u64 * 60 + self.minutes as u64;
+            frames.saturating_sub(2 * (total_minutes - total_minutes / 10))
+        } else {
+            frames
+        }
+    }
+
+    /// Timecode at a real-time position
+    pub fn from_ms(ms: u64, rate: MtcFrameRate) -> Self {
+        Self::from_frame_count((ms as f64 * rate.fps() / 1000.0) as u64, rate)
+    }
+
+    /// Real-time position of this timecode
+    pub fn to_ms(self, rate: MtcFrameRate) -> u64 {
+        (self.to_frame_count(rate) as f64 * 1000.0 / rate.fps()).round() as u64
+    }
+
+    /// Advance by whole frames
+    pub fn add_frames(self, frames: u64, rate: MtcFrameRate) -> Self {
+        Self::from_frame_count(self.to_frame_count(rate) + frames, rate)
+    }
+
+    /// Check that this timecode exists at `rate`
+    ///
+    /// Frame numbers must be below the rate's frame count, and drop-frame
+    /// skips frames 0 and 1 at the start of every minute except every tenth.
+    pub fn validate(self, rate: MtcFrameRate) -> Result<(), String> {
+        if self.hours > 23 || self.minutes > 59 || self.seconds > 59 {
+            return Err(format!("Invalid timecode {}", self));
+        }
+        if self.frames as u64 >= rate.nominal_fps() {
+            return Err(format!(
+                "Timecode {} has no frame {} at {}",
+                self, self.frames, rate
+            ));
+        }
+        if rate == MtcFrameRate::Fps2997Drop
+            && self.seconds == 0
+            && self.frames < 2
+            && self.minutes % 10 != 0
+        {
+            return Err(format!("Timecode {} is dropped at {}", self, rate));
+        }
+        Ok(())
+    }
+
+    /// Parse a timecode and check it against `rate`
+    pub fn parse_at(s: &str, rate: MtcFrameRate) -> Result<Self, String> {
+        let tc: Timecode = s.parse()?;
+        tc.validate(rate)?;
+        Ok(tc)
+    }
+}
+
+impl FromStr for Timecode {
+    type Err = String;
+
+    /// Parse "hh:mm:ss:ff", or "hh:mm:ss;ff" for drop-frame
+    ///
+    /// Without a rate, frames up to 29 are accepted; the drop-frame form is
+    /// checked against 29.97 fps drop-frame. Use `parse_at` or `validate` once
+    /// the rate is known.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let drop_frame = trimmed.contains(';');
+        let parts: Vec<&str> = trimmed.split([':', ';', '.']).collect();
+        let [h, m, sec, f] = parts.as_slice() else {
+            return Err(format!("Timecode must look like hh:mm:ss:ff, got '{}'", s));
+        };
+        let field = |v: &str, max: u8, name: &str| -> Result<u8, String> {
+            v.parse::<u8>()
+                .ok()
+                .filter(|n| *n <= max)
+                .ok_or_else(|| format!("Invalid {} '{}' in timecode '{}'", name, v, s))
+        };
+        let tc = Timecode {
+            hours: field(h, 23, "hours")?,
+            minutes: field(m, 59, "minutes")?,
+            seconds: field(sec, 59, "seconds")?,
+            frames: field(f, 29, "frames")?,
+        };
+        if drop_frame {
+            tc.validate(MtcFrameRate::Fps2997Drop)?;
+        }
+        Ok(tc)
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+/// Full-frame SysEx locating receivers to `tc`
+pub fn full_frame_message(tc: Timecode, rate: MtcFrameRate) -> Vec<u8> {
+    vec![
+        0xF0,
+        0x7F,
+        0x7F,
+        0x01,
+        0x01,
+        (rate.code() << 5) | (tc.hours & 0x1F),
+        tc.minutes & 0x3F,
+        tc.seconds & 0x3F,
+        tc.frames & 0x1F,
+        0xF7,
+    ]
+}
+
+/// Quarter-frame message carrying `piece` (0-7) of `tc`
+pub fn quarter_frame_message(tc: Timecode, rate: MtcFrameRate, piece: u8) -> [u8; 2] {
+    let nibble = match piece & 7 {
+        0 => tc.frames & 0x0F,
+        1 => (tc.frames >> 4) & 0x01,
+        2 => tc.seconds & 0x0F,
+        3 => (tc.seconds >> 4) & 0x03,
+        4 => tc.minutes & 0x0F,
+        5 => (tc.minutes >> 4) & 0x03,
+        6 => tc.hours & 0x0F,
+        _ => ((tc.hours >> 4) & 0x01) | (rate.code() << 1),
+    };
+    [0xF1, ((piece & 7) << 4) | nibble]
+}
+
+/// MTC output settings: frame rate and where each song starts on the timeline
+#[derive(Debug, Clone)]
+pub struct MtcSettings {
+    pub rate: MtcFrameRate,
+    /// Start offset for songs without their own entry
+    pub default_offset: Timecode,
+    /// Per-song start offsets by song index
+    pub song_offsets: HashMap<usize, Timecode>,
+}
+
+impl MtcSettings {
+    pub fn new(rate: MtcFrameRate) -> Self {
+        MtcSettings {
+            rate,
+            default_offset: Timecode::default(),
+            song_offsets: HashMap::new(),
+        }
+    }
+
+    /// Timecode at which `song_index` starts
+    pub fn song_offset(&self, song_index: usize) -> Timecode {
+        self.song_offsets
+            .get(&song_index)
+            .copied()
+            .unwrap_or(self.default_offset)
+    }
+
+    /// Timeline position in ms of a song position
+    pub fn timeline_ms(&self, song_index: usize, position_ms: u64) -> u64 {
+        self.song_offset(song_index).to_ms(self.rate) + position_ms
+    }
+}
+
+/// Generates quarter frames for a running transport
+#[derive(Debug, Clone)]
+pub struct MtcGenerator {
+    rate: MtcFrameRate,
+    /// Instant at which `origin_frame` begins
+    origin: Instant,
+    origin_frame: u64,
+    next_quarter_frame: u64,
+}
+
+impl MtcGenerator {
+    /// Start generating at timeline position `timeline_ms`, as of `now`
+    pub fn new(rate: MtcFrameRate, timeline_ms: u64, now: Instant) -> Self {
+        let exact_frame = timeline_ms as f64 * rate.fps() / 1000.0;
+        // Quarter-frame sequences start on an even frame
+        let mut origin_frame = exact_frame.ceil() as u64;
+        origin_frame += origin_frame & 1;
+        let lead = (origin_frame as f64 - exact_frame) / rate.fps();
+        MtcGenerator {
+            rate,
+            origin: now + Duration::from_secs_f64(lead),
+            origin_frame,
+            next_quarter_frame: 0,
+        }
+    }
+
+    /// Full-frame message for the position the generator started at
+    pub fn locate_message(&self) -> Vec<u8> {
+        full_frame_message(
+            Timecode::from_frame_count(self.origin_frame, self.rate),
+            self.rate,
+        )
+    }
+
+    fn quarter_frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / (self.rate.fps() * 4.0))
+    }
+
+    /// Quarter-frame messages due at `now`
+    pub fn poll(&mut self, now: Instant) -> Vec<[u8; 2]> {
+        if now < self.origin {
+            return Vec::new();
+        }
+        let qf = self.quarter_frame_duration();
+        let due = ((now - self.origin).as_secs_f64() / qf.as_secs_f64()) as u64 + 1;
+        if due > self.next_quarter_frame + MAX_QUARTER_FRAMES_PER_POLL {
+            // Fell behind (e.g. a stalled thread): resume at the current sequence
+            self.next_quarter_frame = (due - 1) / 8 * 8;
+        }
+        let mut messages = Vec::new();
+        while self.next_quarter_frame < due {
+            let piece = (self.next_quarter_frame % 8) as u8;
+            let frame = self.origin_frame + self.next_quarter_frame / 8 * 2;
+            let tc = Timecode::from_frame_count(frame, self.rate);
+            messages.push(quarter_frame_message(tc, self.rate, piece));
+            self.next_quarter_frame += 1;
+        }
+        messages
+    }
+}
+
+/// Position reported by incoming MTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtcEvent {
+    /// Full-frame locate (transport usually stopped)
+    Locate(Timecode, MtcFrameRate),
+    /// Running position from a complete quarter-frame sequence
+    Running(Timecode, MtcFrameRate),
+}
+
+/// Decodes quarter-frame and full-frame MTC messages
+#[derive(Debug, Clone, Default)]
+pub struct MtcDecoder {
+    nibbles: [u8; 8],
+    /// Bitmask of pieces received since the last piece 0
+    received: u8,
+}
+
+impl MtcDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed one MIDI message, returning a position when one is complete
+    pub fn feed(&mut self, msg: &[u8]) -> Option<MtcEvent> {
+        match msg {
+            [0xF1, data, ..] => {
+                let piece = (data >> 4) & 7;
+                if piece == 0 {
+                    self.received = 0;
+                }
+                self.nibbles[piece as usize] = data & 0x0F;
+                self.received |= 1 << piece;
+                if piece != 7 || self.received != 0xFF {
+                    return None;
+                }
+                let n = &self.nibbles;
+                let rate = MtcFrameRate::from_code(n[7] >> 1);
+                let tc = Timecode {
+                    hours: n[6] | ((n[7] & 1) << 4),
+                    minutes: n[4] | ((n[5] & 3) << 4),
+                    seconds: n[2] | ((n[3] & 3) << 4),
+                    frames: n[0] | ((n[1] & 1) << 4),
+                };
+                // A full sequence spans two frames, so the source is now two frames on
+                Some(MtcEvent::Running(tc.add_frames(2, rate), rate))
+            }
+            [0xF0, 0x7F, _, 0x01, 0x01, hr, mn, sc, fr, 0xF7] => {
+                self.received = 0;
+                let rate = MtcFrameRate::from_code(hr >> 5);
+                Some(MtcEvent::Locate(
+                    Timecode::new(hr & 0x1F, mn & 0x3F, sc & 0x3F, fr & 0x1F),
+                    rate,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Follows incoming MTC and keeps a song locked to it
+pub struct MtcChaser {
+    connection: MidiInputConnection<()>,
+    chase_thread: Option<JoinHandle<()>>,
+    stop_flag: Arc<AtomicBool>,
+    port_name: String,
+}
+
+impl MtcChaser {
+    /// Listen on `port_index` and drive `song_index`, which starts at `start_offset`
+    pub fn start(
+        port_index: Option<usize>,
+        song_index: usize,
+        song_duration_ms: u64,
+        start_offset: Timecode,
+        midi_sender: mpsc::Sender<MidiCommand>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (midi_in, port, port_name) = select_input_port("e_midi_mtc", port_index)?;
+        let (event_sender, event_receiver) = mpsc::channel::<(Instant, MtcEvent)>();
+        let mut decoder = MtcDecoder::new();
+        let connection = midi_in
+            .connect(
+                &port,
+                "e_midi_mtc_in",
+                move |_stamp, message, _| {
+                    if let Some(event) = decoder.feed(message) {
+                        let _ = event_sender.send((Instant::now(), event));
+                    }
+                },
+                (),
+            )
+            .map_err(|e| format!("Failed to connect to {}: {}", port_name, e))?;
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop_flag);
+        let chase_thread = thread::spawn(move || {
+            // (instant, song position in ms) of the last relocate while playing
+            let mut anchor: Option<(Instant, u64)> = None;
+            let mut last_running = Instant::now();
+            let stop = |anchor: &mut Option<(Instant, u64)>| {
+                if anchor.take().is_some() {
+                    let _ = midi_sender.send(MidiCommand::Stop);
+                    println!("⏹️  MTC stopped");
+                }
+            };
+            while !thread_stop.load(Ordering::Relaxed) && !crate::should_shutdown() {
+                match event_receiver.recv_timeout(Duration::from_millis(20)) {
+                    Ok((at, MtcEvent::Running(tc, rate))) => {
+                        last_running = at;
+                        let offset_ms = start_offset.to_ms(rate);
+                        let timeline_ms = tc.to_ms(rate);
+                        if timeline_ms < offset_ms || timeline_ms - offset_ms >= song_duration_ms {
+                            stop(&mut anchor);
+                            continue;
+                        }
+                        let position_ms = timeline_ms - offset_ms;
+                        let drift = anchor.map(|(since, anchored_ms)| {
+                            let expected = anchored_ms + (at - since).as_millis() as u64;
+                            expected.abs_diff(position_ms)
+                        });
+                        if drift.map_or(true, |d| d > CHASE_TOLERANCE_MS) {
+                            println!("🔒 MTC {} → song position {}ms", tc, position_ms);
+                            let _ = midi_sender.send(MidiCommand::PlaySongResumeAware {
+                                song_index: Some(song_index),
+                                position_ms: Some(position_ms as u32),
+                                tracks: None,
+                                tempo_bpm: None,
+                            });
+                            anchor = Some((at, position_ms));
+                        }
+                    }
+                    Ok((_, MtcEvent::Locate(tc, _))) => {
+                        println!("📍 MTC locate {}", tc);
+                        stop(&mut anchor);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if anchor.is_some() && last_running.elapsed() > CHASE_TIMEOUT {
+                            stop(&mut anchor);
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            stop(&mut anchor);
+        });
+
+        println!("⏱️  Chasing MTC on {}", port_name);
+        Ok(MtcChaser {
+            connection,
+            chase_thread: Some(chase_thread),
+            stop_flag,
+            port_name,
+        })
+    }
+
+    /// Name of the input port being followed
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    /// Stop chasing and playback
+    pub fn stop(mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.chase_thread.take() {
+            let _ = handle.join();
+        }
+        self.connection.close();
+    }
+}
+
+impl MidiPlayer {
+    /// Emit MTC while playing (None disables it)
+    pub fn set_mtc_output(&mut self, settings: Option<MtcSettings>) {
+        if let Some(settings) = &settings {
+            println!("⏱️  MTC output at {}", settings.rate);
+        }
+        self.mtc_settings = settings.clone();
+        let _ = self.command_sender().send(MidiCommand::SetMtc(settings));
+    }
+
+    /// Current MTC output settings
+    pub fn mtc_settings(&self) -> Option<&MtcSettings> {
+        self.mtc_settings.as_ref()
+    }
+
+    /// Lock `song_index` to MTC arriving on an input port
+    pub fn chase_mtc(
+        &self,
+        port_index: Option<usize>,
+        song_index: usize,
+        start_offset: Timecode,
+    ) -> Result<MtcChaser, Box<dyn Error>> {
+        let song = self.get_song(song_index).ok_or("Invalid song index")?;
+        let dense_indices = Self::get_dense_indices_for_song(song, None);
+        let events = self.get_events_for_song(song_index, &dense_indices, song.default_tempo);
+        let duration_ms = crate::calculate_song_duration_ms(&events) as u64;
+        if duration_ms == 0 {
+            return Err("No events to play! Check track selection.".into());
+        }
+        MtcChaser::start(
+            port_index,
+            song_index,
+            duration_ms,
+            start_offset,
+            self.command_sender(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: [MtcFrameRate; 4] = [
+        MtcFrameRate::Fps24,
+        MtcFrameRate::Fps25,
+        MtcFrameRate::Fps2997Drop,
+        MtcFrameRate::Fps30,
+    ];
+
+    #[test]
+    fn drop_frame_skips_two_frames_a_minute_except_every_tenth() {
+        let rate = MtcFrameRate::Fps2997Drop;
+        // 00:01:00;02 is the first frame of minute 1; ;00 and ;01 don't exist
+        let minute_one = Timecode::new(0, 1, 0, 2);
+        assert_eq!(minute_one.to_frame_count(rate), 1_800);
+        assert_eq!(Timecode::from_frame_count(1_800, rate), minute_one);
+        assert_eq!(
+            Timecode::from_frame_count(1_799, rate),
+            Timecode::new(0, 0, 59, 29)
+        );
+
+        // Every tenth minute keeps its frames 0 and 1
+        let minute_ten = Timecode::new(0, 10, 0, 0);
+        assert_eq!(minute_ten.to_frame_count(rate), 17_982);
+        assert_eq!(Timecode::from_frame_count(17_982, rate), minute_ten);
+        assert_eq!(
+            Timecode::from_frame_count(17_983, rate),
+            Timecode::new(0, 10, 0, 1)
+        );
+
+        for tc in [minute_one, minute_ten, Timecode::new(1, 23, 45, 12)] {
+            assert_eq!(
+                Timecode::from_frame_count(tc.to_frame_count(rate), rate),
+                tc
+            );
+        }
+    }
+
+    #[test]
+    fn frame_counts_round_trip_at_every_rate() {
+        for rate in RATES {
+            for frames in [0, 1, 29, 1_799, 1_800, 17_981, 17_982, 107_892] {
+                let tc = Timecode::from_frame_count(frames, rate);
+                assert!(tc.validate(rate).is_ok(), "{} at {}", tc, rate);
+                assert_eq!(tc.to_frame_count(rate), frames, "{} at {}", tc, rate);
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_frames_decode_to_the_same_timecode() {
+        for rate in RATES {
+            let tc = Timecode::new(13, 59, 58, 20);
+            let mut decoder = MtcDecoder::new();
+            let mut events = Vec::new();
+            for piece in 0..8 {
+                events.extend(decoder.feed(&quarter_frame_message(tc, rate, piece)));
+            }
+            // The decoder reports where the source is after the two-frame sequence
+            assert_eq!(
+                events,
+                vec![MtcEvent::Running(tc.add_frames(2, rate), rate)],
+                "{}",
+                rate
+            );
+        }
+    }
+
+    #[test]
+    fn full_frames_carry_the_rate_code() {
+        let tc = Timecode::new(1, 2, 3, 4);
+        for (code, rate) in RATES.into_iter().enumerate() {
+            let message = full_frame_message(tc, rate);
+            assert_eq!(message[5] >> 5, code as u8);
+            assert_eq!(
+                quarter_frame_message(tc, rate, 7)[1] & 0x0F,
+                (code as u8) << 1
+            );
+            assert_eq!(
+                MtcDecoder::new().feed(&message),
+                Some(MtcEvent::Locate(tc, rate))
+            );
+        }
+    }
+
+    #[test]
+    fn parsing_checks_frames_against_the_rate() {
+        assert_eq!(
+            "01:02:03:04".parse::<Timecode>(),
+            Ok(Timecode::new(1, 2, 3, 4))
+        );
+        assert!("24:00:00:00".parse::<Timecode>().is_err());
+        assert!("00:00:00:30".parse::<Timecode>().is_err());
+        assert!("00:00:00".parse::<Timecode>().is_err());
+
+        // Drop-frame notation rejects the dropped frame numbers
+        assert!("00:01:00;00".parse::<Timecode>().is_err());
+        assert!("00:01:00;01".parse::<Timecode>().is_err());
+        assert_eq!(
+            "00:01:00;02".parse::<Timecode>(),
+            Ok(Timecode::new(0, 1, 0, 2))
+        );
+        assert!("00:10:00;00".parse::<Timecode>().is_ok());
+
+        assert!(Timecode::parse_at("00:00:00:29", MtcFrameRate::Fps24).is_err());
+        assert!(Timecode::parse_at("00:00:00:24", MtcFrameRate::Fps25).is_ok());
+        assert!(Timecode::parse_at("00:00:00:25", MtcFrameRate::Fps25).is_err());
+        assert!(Timecode::parse_at("00:00:00:29", MtcFrameRate::Fps30).is_ok());
+        assert!(Timecode::parse_at("00:02:00:01", MtcFrameRate::Fps2997Drop).is_err());
+        assert!(Timecode::parse_at("00:02:00:01", MtcFrameRate::Fps30).is_ok());
+    }
+
+    #[test]
+    fn frame_rates_parse_from_their_cli_names() {
+        assert_eq!("29.97".parse(), Ok(MtcFrameRate::Fps2997Drop));
+        assert_eq!("29.97DF".parse(), Ok(MtcFrameRate::Fps2997Drop));
+        assert_eq!("24".parse(), Ok(MtcFrameRate::Fps24));
+        assert!("60".parse::<MtcFrameRate>().is_err());
+    }
+}
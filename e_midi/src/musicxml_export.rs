@@ -0,0 +1,637 @@
+//! Export library songs to score-partwise MusicXML (the reverse of
+//! `e_midi_shared::embed_musicxml`).
+//!
+//! Notes are quantized to a grid, split into one part per track and into
+//! non-overlapping voices, cut at barlines with ties, and spelled as note
+//! types with dots so the result opens cleanly in notation software.
+
+use crate::export::{dense_track_index, track_display_name};
+use crate::{MidiPlayer, SongType};
+use midly::{MetaMessage, Smf, TrackEventKind};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Options for MusicXML export
+#[derive(Debug, Clone)]
+pub struct MusicXmlExportOptions {
+    /// Quantization grid as a note value: 4 = quarter, 8 = eighth, 16 = sixteenth, ...
+    pub grid: u32,
+    /// User-facing track numbers to export (None or 0 for all tracks)
+    pub tracks: Option<Vec<usize>>,
+    /// Tempo override in BPM
+    pub tempo_bpm: Option<u32>,
+    /// Time signature override (beats, beat type); read from the song when None
+    pub time_signature: Option<(u8, u8)>,
+    /// Key signature override in circle-of-fifths steps; read from the song when None
+    pub key_fifths: Option<i8>,
+}
+
+impl Default for MusicXmlExportOptions {
+    fn default() -> Self {
+        MusicXmlExportOptions {
+            grid: 16,
+            tracks: None,
+            tempo_bpm: None,
+            time_signature: None,
+            key_fifths: None,
+        }
+    }
+}
+
+/// Note types from longest to shortest with their length in 1/64 quarter notes
+const NOTE_TYPES: [(&str, u64); 7] = [
+    ("whole", 256),
+    ("half", 128),
+    ("quarter", 64),
+    ("eighth", 32),
+    ("16th", 16),
+    ("32nd", 8),
+    ("64th", 4),
+];
+
+/// A group of pitches sharing start and end (in divisions)
+#[derive(Debug, Clone)]
+struct Chord {
+    start: u64,
+    end: u64,
+    pitches: Vec<u8>,
+}
+
+/// One notated piece of a voice within a measure
+#[derive(Debug, Clone)]
+struct Segment {
+    duration: u64,
+    /// Empty for rests
+    pitches: Vec<u8>,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+/// Split notes into voices in which chords never overlap
+fn assign_voices(mut notes: Vec<(u64, u64, u8)>) -> Vec<Vec<Chord>> {
+    notes.sort_unstable();
+    let mut voices: Vec<Vec<Chord>> = Vec::new();
+    for (start, end, pitch) in notes {
+        if let Some(chord) = voices
+            .iter_mut()
+            .find_map(|v| v.last_mut().filter(|c| c.start == start && c.end == end))
+        {
+            if !chord.pitches.contains(&pitch) {
+                chord.pitches.push(pitch);
+            }
+            continue;
+        }
+        let chord = Chord {
+            start,
+            end,
+            pitches: vec![pitch],
+        };
+        match voices
+            .iter_mut()
+            .find(|v| v.last().map_or(true, |c| c.end <= start))
+        {
+            Some(voice) => voice.push(chord),
+            None => voices.push(vec![chord]),
+        }
+    }
+    voices
+}
+
+/// Cut a voice into rests and (tied) chord segments for one measure
+fn measure_segments(voice: &[Chord], measure_start: u64, measure_end: u64) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut cursor = measure_start;
+    for chord in voice
+        .iter()
+        .filter(|c| c.start < measure_end && c.end > measure_start)
+    {
+        let start = chord.start.max(measure_start);
+        let end = chord.end.min(measure_end);
+        if start > cursor {
+            segments.push(Segment {
+                duration: start - cursor,
+                pitches: Vec::new(),
+                tie_stop: false,
+                tie_start: false,
+            });
+        }
+        segments.push(Segment {
+            duration: end - start,
+            pitches: chord.pitches.clone(),
+            tie_stop: chord.start < measure_start,
+            tie_start: chord.end > measure_end,
+        });
+        cursor = end;
+    }
+    if cursor < measure_end && !segments.is_empty() {
+        segments.push(Segment {
+            duration: measure_end - cursor,
+            pitches: Vec::new(),
+            tie_stop: false,
+            tie_start: false,
+        });
+    }
+    segments
+}
+
+/// Break a duration into note types (with optional dot), longest first
+fn split_duration(duration: u64, divisions: u64) -> Vec<(u64, &'static str, bool)> {
+    let mut candidates = Vec::new();
+    for (name, len64) in NOTE_TYPES {
+        let plain = len64 * divisions;
+        if plain % 64 == 0 {
+            candidates.push((plain / 64, name, false));
+        }
+        let dotted = plain * 3;
+        if dotted % 128 == 0 {
+            candidates.push((dotted / 128, name, true));
+        }
+    }
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.0));
+
+    let mut pieces = Vec::new();
+    let mut remaining = duration;
+    while remaining > 0 {
+        match candidates.iter().find(|(len, ..)| *len <= remaining) {
+            Some(&(len, name, dotted)) => {
+                pieces.push((len, name, dotted));
+                remaining -= len;
+            }
+            None => {
+                // Shorter than a 64th: fold into the previous piece's duration
+                if let Some(last) = pieces.last_mut() {
+                    last.0 += remaining;
+                } else {
+                    pieces.push((remaining, "64th", false));
+                }
+                break;
+            }
+        }
+    }
+    pieces
+}
+
+/// Spell a MIDI pitch as (step, alter, octave), using flats in flat keys
+fn spell_pitch(pitch: u8, key_fifths: i8) -> (&'static str, i8, i32) {
+    const SHARPS: [(&str, i8); 12] = [
+        ("C", 0),
+        ("C", 1),
+        ("D", 0),
+        ("D", 1),
+        ("E", 0),
+        ("F", 0),
+        ("F", 1),
+        ("G", 0),
+        ("G", 1),
+        ("A", 0),
+        ("A", 1),
+        ("B", 0),
+    ];
+    const FLATS: [(&str, i8); 12] = [
+        ("C", 0),
+        ("D", -1),
+        ("D", 0),
+        ("E", -1),
+        ("E", 0),
+        ("F", 0),
+        ("G", -1),
+        ("G", 0),
+        ("A", -1),
+        ("A", 0),
+        ("B", -1),
+        ("B", 0),
+    ];
+    let table = if key_fifths < 0 { &FLATS } else { &SHARPS };
+    let (step, alter) = table[(pitch % 12) as usize];
+    (step, alter, pitch as i32 / 12 - 1)
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// First time signature and key signature found in SMF data
+fn read_signatures(data: &[u8]) -> (Option<(u8, u8)>, Option<i8>) {
+    let Ok(smf) = Smf::parse(data) else {
+        return (None, None);
+    };
+    let mut time = None;
+    let mut key = None;
+    for event in smf.tracks.iter().flatten() {
+        match event.kind {
+            TrackEventKind::Meta(MetaMessage::TimeSignature(beats, denom_pow, ..))
+                if time.is_none() =>
+            {
+                time = Some((beats, 1u8.checked_shl(denom_pow as u32).unwrap_or(4)));
+            }
+            TrackEventKind::Meta(MetaMessage::KeySignature(fifths, _)) if key.is_none() => {
+                key = Some(fifths);
+            }
+            _ => {}
+        }
+    }
+    (time, key)
+}
+
+fn write_segment_notes(
+    xml: &mut String,
+    segment: &Segment,
+    voice: usize,
+    divisions: u64,
+    key_fifths: i8,
+) {
+    let pieces = split_duration(segment.duration, divisions);
+    let last = pieces.len().saturating_sub(1);
+    for (i, (duration, note_type, dotted)) in pieces.into_iter().enumerate() {
+        if segment.pitches.is_empty() {
+            let _ = writeln!(
+                xml,
+                "      <note><rest/><duration>{}</duration><voice>{}</voice><type>{}</type>{}</note>",
+                duration,
+                voice,
+                note_type,
+                if dotted { "<dot/>" } else { "" }
+            );
+            continue;
+        }
+        let tie_stop = i > 0 || segment.tie_stop;
+        let tie_start = i < last || segment.tie_start;
+        for (n, &pitch) in segment.pitches.iter().enumerate() {
+            let (step, alter, octave) = spell_pitch(pitch, key_fifths);
+            xml.push_str("      <note>");
+            if n > 0 {
+                xml.push_str("<chord/>");
+            }
+            let _ = write!(xml, "<pitch><step>{}</step>", step);
+            if alter != 0 {
+                let _ = write!(xml, "<alter>{}</alter>", alter);
+            }
+            let _ = write!(
+                xml,
+                "<octave>{}</octave></pitch><duration>{}</duration>",
+                octave, duration
+            );
+            if tie_stop {
+                xml.push_str("<tie type=\"stop\"/>");
+            }
+            if tie_start {
+                xml.push_str("<tie type=\"start\"/>");
+            }
+            let _ = write!(xml, "<voice>{}</voice><type>{}</type>", voice, note_type);
+            if dotted {
+                xml.push_str("<dot/>");
+            }
+            if tie_stop || tie_start {
+                xml.push_str("<notations>");
+                if tie_stop {
+                    xml.push_str("<tied type=\"stop\"/>");
+                }
+                if tie_start {
+                    xml.push_str("<tied type=\"start\"/>");
+                }
+                xml.push_str("</notations>");
+            }
+            xml.push_str("</note>\n");
+        }
+    }
+}
+
+impl MidiPlayer {
+    /// Render a song as a score-partwise MusicXML document
+    pub fn export_song_to_musicxml(
+        &self,
+        song_index: usize,
+        options: &MusicXmlExportOptions,
+    ) -> Result<String, Box<dyn Error>> {
+        let song = self.get_song(song_index).ok_or("Invalid song index")?;
+        if !matches!(song.song_type, SongType::Midi | SongType::MusicXml) {
+            return Err(format!("{} is not a MIDI or MusicXML song", song.name).into());
+        }
+        if !matches!(options.grid, 4 | 8 | 16 | 32 | 64) {
+            return Err("Grid must be one of 4, 8, 16, 32 or 64".into());
+        }
+        let tempo = options.tempo_bpm.unwrap_or(song.default_tempo).max(1);
+
+        // Signatures: explicit options, then the song's own file, then 4/4 in C
+        let (file_time, file_key) = match self.song_midi_data(song_index) {
+            Some(data) => read_signatures(data),
+            // MusicXML songs keep no signatures, so read them from the source
+            None if song.song_type == SongType::MusicXml => {
+                e_midi_shared::embed_musicxml::read_signatures(Path::new(&song.filename))
+            }
+            None => (None, None),
+        };
+        let (beats, beat_type) = options
+            .time_signature
+            .or(file_time)
+            .filter(|(b, t)| *b > 0 && t.is_power_of_two())
+            .unwrap_or((4, 4));
+        let key_fifths = options.key_fifths.or(file_key).unwrap_or(0).clamp(-7, 7);
+
+        // Divisions per quarter: fine enough for the grid and the measure length
+        let grid_per_q = options.grid as u64 / 4;
+        let mut divisions = grid_per_q;
+        while (4 * divisions * beats as u64) % beat_type as u64 != 0 {
+            divisions *= 2;
+        }
+        let step = divisions / grid_per_q;
+        let measure_len = 4 * divisions * beats as u64 / beat_type as u64;
+        let quantize = |ms: u32| {
+            let grid_units = (ms as f64 * tempo as f64 / 60_000.0 * grid_per_q as f64).round();
+            grid_units as u64 * step
+        };
+
+        let dense_indices = Self::get_dense_indices_for_song(song, options.tracks.as_deref());
+        let events = self.get_events_for_song(song_index, &dense_indices, tempo);
+        let mut per_track: Vec<Vec<(u64, u64, u8)>> = vec![Vec::new(); song.tracks.len()];
+        let mut channels: Vec<Option<u8>> = vec![None; song.tracks.len()];
+        for note in &events {
+            if let Some(dense) = dense_track_index(song, note.track) {
+                if dense_indices.contains(&dense) {
+                    let start = quantize(note.start_ms);
+                    let end = quantize(note.start_ms + note.dur_ms).max(start + step);
+                    per_track[dense].push((start, end, note.pitch));
+                    channels[dense].get_or_insert(note.chan);
+                }
+            }
+        }
+        let parts: Vec<usize> = dense_indices
+            .iter()
+            .copied()
+            .filter(|&d| per_track.get(d).is_some_and(|n| !n.is_empty()))
+            .collect();
+        if parts.is_empty() {
+            return Err("No notes to export! Check track selection.".into());
+        }
+        let song_end = per_track.iter().flatten().map(|n| n.1).max().unwrap_or(0);
+        let measure_count = song_end.div_ceil(measure_len).max(1);
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+        xml.push_str("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">\n");
+        xml.push_str("<score-partwise version=\"4.0\">\n");
+        let _ = writeln!(
+            xml,
+            "  <work><work-title>{}</work-title></work>",
+            escape_xml(&song.name)
+        );
+        let _ = writeln!(
+            xml,
+            "  <identification><encoding><software>e_midi {}</software></encoding></identification>",
+            env!("CARGO_PKG_VERSION")
+        );
+        xml.push_str("  <part-list>\n");
+        for (p, &dense) in parts.iter().enumerate() {
+            let id = format!("P{}", p + 1);
+            let name = escape_xml(&track_display_name(song, dense));
+            let channel = channels[dense].unwrap_or(0);
+            let program = song.tracks[dense].program.unwrap_or(0);
+            let _ = writeln!(xml, "    <score-part id=\"{}\">", id);
+            let _ = writeln!(xml, "      <part-name>{}</part-name>", name);
+            let _ = writeln!(
+                xml,
+                "      <score-instrument id=\"{}-I1\"><instrument-name>{}</instrument-name></score-instrument>",
+                id, name
+            );
+            let _ = writeln!(
+                xml,
+                "      <midi-instrument id=\"{}-I1\"><midi-channel>{}</midi-channel><midi-program>{}</midi-program></midi-instrument>",
+                id,
+                channel + 1,
+                program as u32 + 1
+            );
+            xml.push_str("    </score-part>\n");
+        }
+        xml.push_str("  </part-list>\n");
+
+        for (p, &dense) in parts.iter().enumerate() {
+            let notes = std::mem::take(&mut per_track[dense]);
+            let average_pitch = notes.iter().map(|n| n.2 as u64).sum::<u64>() / notes.len() as u64;
+            let voices = assign_voices(notes);
+            let _ = writeln!(xml, "  <part id=\"P{}\">", p + 1);
+            for m in 0..measure_count {
+                let measure_start = m * measure_len;
+                let measure_end = measure_start + measure_len;
+                let _ = writeln!(xml, "    <measure number=\"{}\">", m + 1);
+                if m == 0 {
+                    let (sign, line) = if average_pitch < 60 {
+                        ("F", 4)
+                    } else {
+                        ("G", 2)
+                    };
+                    let _ = writeln!(
+                        xml,
+                        "      <attributes><divisions>{}</divisions><key><fifths>{}</fifths></key><time><beats>{}</beats><beat-type>{}</beat-type></time><clef><sign>{}</sign><line>{}</line></clef></attributes>",
+                        divisions, key_fifths, beats, beat_type, sign, line
+                    );
+                    if p == 0 {
+                        let _ = writeln!(
+                            xml,
+                            "      <direction placement=\"above\"><direction-type><metronome><beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome></direction-type><sound tempo=\"{}\"/></direction>",
+                            tempo, tempo
+                        );
+                    }
+                }
+                let mut wrote_voice = false;
+                for (v, voice) in voices.iter().enumerate() {
+                    let segments = measure_segments(voice, measure_start, measure_end);
+                    if segments.is_empty() {
+                        continue;
+                    }
+                    if wrote_voice {
+                        let _ = writeln!(
+                            xml,
+                            "      <backup><duration>{}</duration></backup>",
+                            measure_len
+                        );
+                    }
+                    for segment in &segments {
+                        write_segment_notes(&mut xml, segment, v + 1, divisions, key_fifths);
+                    }
+                    wrote_voice = true;
+                }
+                if !wrote_voice {
+                    let _ = writeln!(
+                        xml,
+                        "      <note><rest measure=\"yes\"/><duration>{}</duration><voice>1</voice></note>",
+                        measure_len
+                    );
+                }
+                xml.push_str("    </measure>\n");
+            }
+            xml.push_str("  </part>\n");
+        }
+        xml.push_str("</score-partwise>\n");
+        Ok(xml)
+    }
+
+    /// Write a song to a MusicXML file on disk
+    pub fn export_song_musicxml<P: AsRef<Path>>(
+        &self,
+        song_index: usize,
+        path: P,
+        options: &MusicXmlExportOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let xml = self.export_song_to_musicxml(song_index, options)?;
+        std::fs::write(path.as_ref(), xml)?;
+        println!(
+            "💾 Exported song {} to MusicXML: {}",
+            song_index,
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn chord(start: u64, end: u64, pitches: &[u8]) -> Chord {
+        Chord {
+            start,
+            end,
+            pitches: pitches.to_vec(),
+        }
+    }
+
+    fn spans(voice: &[Chord]) -> Vec<(u64, u64, Vec<u8>)> {
+        voice
+            .iter()
+            .map(|c| (c.start, c.end, c.pitches.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_notes_go_to_separate_voices() {
+        let voices = assign_voices(vec![(4, 8, 62), (2, 6, 67), (0, 4, 64), (0, 4, 60)]);
+        assert_eq!(voices.len(), 2);
+        // Same start and end makes a chord; the next note reuses the first free voice
+        assert_eq!(
+            spans(&voices[0]),
+            vec![(0, 4, vec![60, 64]), (4, 8, vec![62])]
+        );
+        assert_eq!(spans(&voices[1]), vec![(2, 6, vec![67])]);
+    }
+
+    #[test]
+    fn notes_across_a_barline_are_tied() {
+        let voice = vec![chord(12, 20, &[60])];
+        let first = measure_segments(&voice, 0, 16);
+        assert_eq!(first.len(), 2);
+        assert!(first[0].pitches.is_empty());
+        assert_eq!(first[0].duration, 12);
+        assert_eq!(first[1].duration, 4);
+        assert!(first[1].tie_start && !first[1].tie_stop);
+
+        let second = measure_segments(&voice, 16, 32);
+        assert_eq!(second.len(), 2);
+        assert_eq!(second[0].duration, 4);
+        assert!(second[0].tie_stop && !second[0].tie_start);
+        assert!(second[1].pitches.is_empty());
+
+        let mut xml = String::new();
+        write_segment_notes(&mut xml, &first[1], 1, 4, 0);
+        assert!(xml.contains("<tie type=\"start\"/>"));
+        assert!(xml.contains("<tied type=\"start\"/>"));
+        assert!(!xml.contains("type=\"stop\""));
+    }
+
+    #[test]
+    fn durations_use_dots_where_they_fit() {
+        // Two divisions per quarter
+        assert_eq!(split_duration(3, 2), vec![(3, "quarter", true)]);
+        assert_eq!(split_duration(6, 2), vec![(6, "half", true)]);
+        assert_eq!(
+            split_duration(5, 2),
+            vec![(4, "half", false), (1, "eighth", false)]
+        );
+        assert_eq!(split_duration(8, 2), vec![(8, "whole", false)]);
+
+        // A note split into pieces is tied between them
+        let mut xml = String::new();
+        let segment = Segment {
+            duration: 5,
+            pitches: vec![60],
+            tie_stop: false,
+            tie_start: false,
+        };
+        write_segment_notes(&mut xml, &segment, 1, 2, 0);
+        assert_eq!(xml.lines().count(), 2);
+        assert!(xml.contains("<type>half</type>"));
+        assert!(xml.contains("<tie type=\"start\"/>"));
+        assert!(xml.contains("<tie type=\"stop\"/>"));
+    }
+
+    #[test]
+    fn flat_keys_spell_black_keys_as_flats() {
+        assert_eq!(spell_pitch(70, -1), ("B", -1, 4));
+        assert_eq!(spell_pitch(61, -3), ("D", -1, 4));
+        assert_eq!(spell_pitch(70, 0), ("A", 1, 4));
+        assert_eq!(spell_pitch(66, 2), ("F", 1, 4));
+        // White keys are the same either way
+        assert_eq!(spell_pitch(60, -2), ("C", 0, 4));
+        assert_eq!(spell_pitch(21, -2), ("A", 0, 0));
+    }
+
+    #[test]
+    fn musicxml_songs_keep_their_time_and_key() {
+        let dir = std::env::temp_dir().join(format!(
+            "e_midi_musicxml_export_test_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("waltz.xml");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="4.0">
+  <part-list>
+    <score-part id="P1"><part-name>Piano</part-name></score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>2</divisions><key><fifths>-2</fifths></key><time><beats>3</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch><duration>6</duration><voice>1</voice><type>half</type><dot/></note>
+    </measure>
+  </part>
+</score-partwise>
+"#,
+        )
+        .unwrap();
+
+        let mut player = MidiPlayer::new_offline().unwrap();
+        player.add_song_from_file(&path).unwrap();
+        let song_index = player.get_total_song_count() - 1;
+        let xml = player
+            .export_song_to_musicxml(song_index, &MusicXmlExportOptions::default())
+            .unwrap();
+        assert!(xml.contains("<key><fifths>-2</fifths></key>"));
+        assert!(xml.contains("<time><beats>3</beats><beat-type>4</beat-type></time>"));
+        // The dotted half fills the 3/4 bar without a tie
+        assert!(xml.contains("<step>B</step><alter>-1</alter>"));
+        assert!(xml.contains("<type>half</type><dot/>"));
+        assert!(!xml.contains("<tie "));
+        assert_eq!(xml.matches("<measure ").count(), 1);
+
+        // Options still win over the file
+        let options = MusicXmlExportOptions {
+            time_signature: Some((6, 8)),
+            key_fifths: Some(3),
+            ..MusicXmlExportOptions::default()
+        };
+        let xml = player
+            .export_song_to_musicxml(song_index, &options)
+            .unwrap();
+        assert!(xml.contains("<key><fifths>3</fifths></key>"));
+        assert!(xml.contains("<time><beats>6</beats><beat-type>8</beat-type></time>"));
+        assert!(xml.contains("<step>A</step><alter>1</alter>"));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,355 @@
+//! UDP OSC control server.
+//!
+//! Lets TouchOSC, SuperCollider, Tidal or anything else that speaks OSC drive
+//! the player over the network. Commands live under `/e_midi/`; every command
+//! gets a `/e_midi/reply` to its sender, and registered listeners receive
+//! `/e_midi/status` whenever the transport changes (and once a second while
+//! playing).
+//!
+//! | Address                      | Arguments                     |
+//! |------------------------------|-------------------------------|
+//! | `/e_midi/play`               | song index or name (optional) |
+//! | `/e_midi/stop`               |                               |
+//! | `/e_midi/pause`              | (toggles)                     |
+//! | `/e_midi/next`               |                               |
+//! | `/e_midi/previous`           |                               |
+//! | `/e_midi/tempo`              | BPM                           |
+//! | `/e_midi/seek`               | position in seconds           |
+//! | `/e_midi/mute`               | track number, 1/0 (default 1) |
+//! | `/e_midi/transpose`          | semitones                     |
+//! | `/e_midi/cue`                | song index or name            |
+//! | `/e_midi/status`             |                               |
+//! | `/e_midi/list`               |                               |
+//! | `/e_midi/listen`             | reply port (optional)         |
+//! | `/e_midi/unlisten`           | reply port (optional)         |
+
+use crate::transport::{Transport, TransportStatus};
+use crate::MidiPlayer;
+use rosc::{OscMessage, OscPacket, OscType};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Default UDP port for the control server
+pub const DEFAULT_OSC_PORT: u16 = 57130;
+
+/// Address prefix for all e_midi messages
+pub const OSC_PREFIX: &str = "/e_midi";
+
+/// How often listeners get a status update while playing
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long `poll` waits for the first packet
+const POLL_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Largest OSC packet accepted
+const MAX_PACKET_SIZE: usize = 65_536;
+
+fn arg_i64(arg: &OscType) -> Option<i64> {
+    match arg {
+        OscType::Int(v) => Some(*v as i64),
+        OscType::Long(v) => Some(*v),
+        OscType::Float(v) => Some(v.round() as i64),
+        OscType::Double(v) => Some(v.round() as i64),
+        OscType::Bool(v) => Some(*v as i64),
+        OscType::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn arg_f64(arg: &OscType) -> Option<f64> {
+    match arg {
+        OscType::Float(v) => Some(*v as f64),
+        OscType::Double(v) => Some(*v),
+        OscType::String(s) => s.trim().parse().ok(),
+        other => arg_i64(other).map(|v| v as f64),
+    }
+}
+
+fn arg_song(player: &MidiPlayer, arg: &OscType) -> Result<usize, String> {
+    let query = match arg {
+        OscType::String(s) => s.clone(),
+        other => arg_i64(other)
+            .ok_or("Expected a song index or name")?
+            .to_string(),
+    };
+    player
+        .find_song(&query)
+        .ok_or_else(|| format!("No song matching '{}'", query))
+}
+
+fn required<'a>(args: &'a [OscType], what: &str) -> Result<&'a OscType, String> {
+    args.first().ok_or_else(|| format!("Missing {}", what))
+}
+
+fn message(addr: &str, args: Vec<OscType>) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: format!("{}/{}", OSC_PREFIX, addr),
+        args,
+    })
+}
+
+/// `/e_midi/status state index name position_ms duration_ms tempo transpose`
+pub fn status_message(status: &TransportStatus) -> OscPacket {
+    message(
+        "status",
+        vec![
+            OscType::String(status.state.to_string()),
+            OscType::Int(status.song_index.map_or(-1, |i| i as i32)),
+            OscType::String(status.song_name.clone().unwrap_or_default()),
+            OscType::Int(status.position_ms as i32),
+            OscType::Int(status.duration_ms as i32),
+            OscType::Int(status.tempo_bpm.map_or(0, |t| t as i32)),
+            OscType::Int(status.transpose as i32),
+        ],
+    )
+}
+
+/// UDP OSC endpoint controlling a `Transport`
+pub struct OscServer {
+    socket: UdpSocket,
+    listeners: Vec<SocketAddr>,
+    last_status: Instant,
+}
+
+impl OscServer {
+    /// Bind the control socket, e.g. to "127.0.0.1:57130"
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, Box<dyn Error>> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(POLL_TIMEOUT))?;
+        println!(
+            "📡 OSC control server listening on {}",
+            socket.local_addr()?
+        );
+        Ok(OscServer {
+            socket,
+            listeners: Vec::new(),
+            last_status: Instant::now(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Addresses receiving status messages
+    pub fn listeners(&self) -> &[SocketAddr] {
+        &self.listeners
+    }
+
+    /// Handle pending packets and push status updates
+    ///
+    /// Waits up to 20ms for the first packet, so this can drive a loop directly.
+    /// Receive errors are logged and skipped; only failing to configure the
+    /// socket is returned as an error.
+    pub fn poll(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let mut changed = false;
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) => match rosc::decoder::decode_udp(&buf[..len]) {
+                    Ok((_, packet)) => {
+                        changed |= self.handle_packet(player, transport, packet, from)
+                    }
+                    Err(e) => eprintln!("⚠️  Bad OSC packet from {}: {}", from, e),
+                },
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                    ) =>
+                {
+                    break
+                }
+                // An ICMP port-unreachable from a vanished listener surfaces
+                // here (ConnectionReset on Windows); the socket itself is fine
+                Err(e) => {
+                    eprintln!("⚠️  OSC receive failed: {}", e);
+                    break;
+                }
+            }
+            // Drain whatever else is queued without waiting
+            self.socket.set_nonblocking(true)?;
+        }
+        self.socket.set_nonblocking(false)?;
+
+        changed |= transport.update(player);
+        let playing = transport.state() == crate::transport::TransportState::Playing;
+        if changed || (playing && self.last_status.elapsed() >= STATUS_INTERVAL) {
+            self.broadcast(&status_message(&transport.status(player)));
+        }
+        Ok(())
+    }
+
+    /// Send a packet to every registered listener
+    pub fn broadcast(&mut self, packet: &OscPacket) {
+        self.last_status = Instant::now();
+        if self.listeners.is_empty() {
+            return;
+        }
+        match rosc::encoder::encode(packet) {
+            Ok(bytes) => {
+                for listener in &self.listeners {
+                    let _ = self.socket.send_to(&bytes, listener);
+                }
+            }
+            Err(e) => eprintln!("⚠️  Failed to encode OSC status: {}", e),
+        }
+    }
+
+    fn send(&self, packet: &OscPacket, to: SocketAddr) {
+        if let Ok(bytes) = rosc::encoder::encode(packet) {
+            let _ = self.socket.send_to(&bytes, to);
+        }
+    }
+
+    /// Returns true if the transport may have changed
+    fn handle_packet(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+        packet: OscPacket,
+        from: SocketAddr,
+    ) -> bool {
+        match packet {
+            OscPacket::Message(msg) => {
+                let Some(command) = msg.addr.strip_prefix(OSC_PREFIX) else {
+                    return false;
+                };
+                let command = command.trim_start_matches('/').to_string();
+                let result = self.handle_command(player, transport, &command, &msg.args, from);
+                let reply = match &result {
+                    Ok(_) => vec![
+                        OscType::String(command.clone()),
+                        OscType::String("ok".into()),
+                    ],
+                    Err(e) => {
+                        eprintln!("⚠️  OSC {}: {}", msg.addr, e);
+                        vec![
+                            OscType::String(command.clone()),
+                            OscType::String("error".into()),
+                            OscType::String(e.clone()),
+                        ]
+                    }
+                };
+                self.send(&message("reply", reply), from);
+                result.unwrap_or(false)
+            }
+            OscPacket::Bundle(bundle) => bundle.content.into_iter().fold(false, |changed, p| {
+                self.handle_packet(player, transport, p, from) | changed
+            }),
+        }
+    }
+
+    fn handle_command(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+        command: &str,
+        args: &[OscType],
+        from: SocketAddr,
+    ) -> Result<bool, String> {
+        let e = |e: Box<dyn Error>| e.to_string();
+        match command {
+            "play" => match args.first() {
+                Some(arg) => transport.play(player, arg_song(player, arg)?).map_err(e)?,
+                None => transport.resume(player).map_err(e)?,
+            },
+            "stop" => transport.stop(player),
+            "pause" => transport.pause(player).map_err(e)?,
+            "next" => transport.next(player).map_err(e)?,
+            "previous" | "prev" => transport.previous(player).map_err(e)?,
+            "tempo" => {
+                let bpm = arg_i64(required(args, "tempo")?).ok_or("Tempo must be a number")?;
+                transport.set_tempo(player, bpm.max(0) as u32).map_err(e)?;
+            }
+            "seek" => {
+                let seconds =
+                    arg_f64(required(args, "position")?).ok_or("Position must be a number")?;
+                transport
+                    .seek(player, (seconds.max(0.0) * 1000.0) as u32)
+                    .map_err(e)?;
+            }
+            "mute" => {
+                let track = arg_i64(required(args, "track")?).ok_or("Track must be a number")?;
+                let muted = args.get(1).and_then(arg_i64) != Some(0);
+                transport
+                    .set_track_muted(player, track.max(0) as usize, muted)
+                    .map_err(e)?;
+            }
+            "transpose" => {
+                let semitones =
+                    arg_i64(required(args, "semitones")?).ok_or("Transpose must be a number")?;
+                transport
+                    .set_transpose(
+                        player,
+                        semitones.clamp(i8::MIN as i64, i8::MAX as i64) as i8,
+                    )
+                    .map_err(e)?;
+            }
+            "cue" => {
+                let index = arg_song(player, required(args, "song")?)?;
+                transport.cue(player, index).map_err(e)?;
+            }
+            "status" => {
+                self.send(&status_message(&transport.status(player)), from);
+                return Ok(false);
+            }
+            "list" => {
+                for (index, song) in player.get_songs().iter().enumerate() {
+                    let packet = message(
+                        "song",
+                        vec![
+                            OscType::Int(index as i32),
+                            OscType::String(song.name.clone()),
+                            OscType::Int(song.tracks.len() as i32),
+                            OscType::Int(song.default_tempo as i32),
+                        ],
+                    );
+                    self.send(&packet, from);
+                }
+                return Ok(false);
+            }
+            "listen" | "unlisten" => {
+                let mut target = from;
+                if let Some(port) = args.first().and_then(arg_i64) {
+                    target.set_port(u16::try_from(port).map_err(|_| "Invalid port")?);
+                }
+                self.listeners.retain(|l| *l != target);
+                if command == "listen" {
+                    println!("📡 OSC listener registered: {}", target);
+                    self.listeners.push(target);
+                    self.send(&status_message(&transport.status(player)), target);
+                }
+                return Ok(false);
+            }
+            other => return Err(format!("Unknown command '{}'", other)),
+        }
+        Ok(true)
+    }
+}
+
+impl MidiPlayer {
+    /// Run an OSC control server until shutdown (Ctrl+C)
+    pub fn run_osc_server<A: ToSocketAddrs>(&mut self, addr: A) -> Result<(), Box<dyn Error>> {
+        let mut server = OscServer::bind(addr)?;
+        let mut transport = Transport::new();
+        let result = loop {
+            if crate::should_shutdown() {
+                break Ok(());
+            }
+            if let Err(e) = server.poll(self, &mut transport) {
+                break Err(e);
+            }
+            self.poll_watched_directories_for(&mut transport);
+        };
+        transport.stop(self);
+        println!("📡 OSC control server stopped");
+        result
+    }
+}
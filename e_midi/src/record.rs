@@ -0,0 +1,485 @@
+//! Record mode: capture a live performance from a MIDI input port.
+//!
+//! Incoming messages are timestamped relative to the start of the take. When
+//! recording stops the take is rendered to a type-1 Standard MIDI File (one
+//! track per MIDI channel) and added to the player's dynamic song list, where
+//! it is analysed exactly like a `.mid` file loaded from disk.
+
+use crate::export::{note_event, to_track};
+use crate::{MidiPlayer, Note};
+use midir::{MidiInput, MidiInputConnection, MidiInputPort};
+use midly::num::{u15, u24};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Resolution used for recorded Standard MIDI Files
+pub const RECORD_TICKS_PER_Q: u16 = 480;
+
+/// A single MIDI message captured from the input port
+#[derive(Debug, Clone)]
+pub struct RecordedMessage {
+    /// Microseconds since the recording started
+    pub time_us: u64,
+    /// Raw MIDI bytes as delivered by the input port
+    pub bytes: Vec<u8>,
+}
+
+/// Options for a recording session
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    /// Input port index (first available port if None)
+    pub port_index: Option<usize>,
+    /// Optional path to write the take as a Standard MIDI File
+    pub output: Option<PathBuf>,
+    /// Song to play as a backing track while recording (overdub)
+    pub overdub_song: Option<usize>,
+    /// Stop automatically after this many seconds
+    pub max_duration_secs: Option<u32>,
+    /// Tempo written to the file when not overdubbing
+    pub tempo_bpm: u32,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        RecordOptions {
+            port_index: None,
+            output: None,
+            overdub_song: None,
+            max_duration_secs: None,
+            tempo_bpm: 120,
+        }
+    }
+}
+
+/// List the names of all available MIDI input ports
+pub fn list_input_ports() -> Result<Vec<String>, Box<dyn Error>> {
+    let midi_in = MidiInput::new("e_midi_input")?;
+    Ok(midi_in
+        .ports()
+        .iter()
+        .map(|p| {
+            midi_in
+                .port_name(p)
+                .unwrap_or_else(|_| "<Unknown>".to_string())
+        })
+        .collect())
+}
+
+/// Open a MIDI input client and pick a port by index (first port if None)
+pub(crate) fn select_input_port(
+    client_name: &str,
+    port_index: Option<usize>,
+) -> Result<(MidiInput, MidiInputPort, String), Box<dyn Error>> {
+    let midi_in = MidiInput::new(client_name)?;
+    let ports = midi_in.ports();
+    if ports.is_empty() {
+        return Err("No MIDI input ports found".into());
+    }
+    let index = port_index.unwrap_or(0);
+    let port = ports.get(index).cloned().ok_or_else(|| {
+        format!(
+            "Invalid MIDI input port {} ({} available)",
+            index,
+            ports.len()
+        )
+    })?;
+    let port_name = midi_in
+        .port_name(&port)
+        .unwrap_or_else(|_| "Unknown".to_string());
+    Ok((midi_in, port, port_name))
+}
+
+/// Captures timestamped messages from a MIDI input port until stopped
+pub struct MidiRecorder {
+    connection: MidiInputConnection<()>,
+    messages: Arc<Mutex<Vec<RecordedMessage>>>,
+    port_name: String,
+    started: Instant,
+}
+
+impl MidiRecorder {
+    /// Connect to an input port and start recording immediately
+    pub fn start(port_index: Option<usize>) -> Result<Self, Box<dyn Error>> {
+        let (midi_in, port, port_name) = select_input_port("e_midi_record", port_index)?;
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let started = Instant::now();
+        let sink = Arc::clone(&messages);
+        let connection = midi_in.connect(
+            &port,
+            "e_midi_record",
+            move |_stamp, bytes, _| {
+                let time_us = started.elapsed().as_micros() as u64;
+                if let Ok(mut messages) = sink.lock() {
+                    messages.push(RecordedMessage {
+                        time_us,
+                        bytes: bytes.to_vec(),
+                    });
+                }
+            },
+            (),
+        )?;
+        println!("🔴 Recording from MIDI input: {}", port_name);
+        Ok(MidiRecorder {
+            connection,
+            messages,
+            port_name,
+            started,
+        })
+    }
+
+    /// Name of the input port being recorded
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    /// Number of messages captured so far
+    pub fn message_count(&self) -> usize {
+        self.messages.lock().map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// Close the input port and return the captured take
+    pub fn stop(self) -> Recording {
+        let length_us = self.started.elapsed().as_micros() as u64;
+        self.connection.close();
+        let messages = self
+            .messages
+            .lock()
+            .map(|mut m| std::mem::take(&mut *m))
+            .unwrap_or_default();
+        println!("⏹️ Recording stopped: {} messages captured", messages.len());
+        Recording {
+            messages,
+            length_us,
+        }
+    }
+}
+
+/// A finished take
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub messages: Vec<RecordedMessage>,
+    /// Total length of the take in microseconds
+    pub length_us: u64,
+}
+
+impl Recording {
+    /// True if no channel messages were captured
+    pub fn is_empty(&self) -> bool {
+        !self
+            .messages
+            .iter()
+            .any(|m| m.bytes.first().is_some_and(|s| (0x80..0xF0).contains(s)))
+    }
+
+    /// Render the take (plus optional backing notes) to a type-1 Standard MIDI File
+    ///
+    /// Track 0 carries tempo and time signature, backing notes get one track per
+    /// source track, and recorded messages get one track per MIDI channel.
+    pub fn to_smf_bytes(
+        &self,
+        tempo_bpm: u32,
+        backing: &[Note],
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let tempo_bpm = tempo_bpm.max(1) as u64;
+        let tpq = RECORD_TICKS_PER_Q as u64;
+        let us_to_tick = |us: u64| us * tpq * tempo_bpm / 60_000_000;
+
+        // Backing notes grouped by their source track
+        let mut backing_tracks: BTreeMap<u8, Vec<(u64, TrackEventKind<'static>)>> = BTreeMap::new();
+        for note in backing {
+            let events = backing_tracks.entry(note.track).or_default();
+            let start = us_to_tick(note.start_ms as u64 * 1000);
+            let end = us_to_tick((note.start_ms + note.dur_ms) as u64 * 1000);
+            events.push((start, note_event(note.chan, note.pitch, note.vel.max(1))));
+            events.push((end, note_event(note.chan, note.pitch, 0)));
+        }
+
+        // Recorded messages grouped by channel, closing any notes still held at the end
+        let mut recorded_tracks: BTreeMap<u8, Vec<(u64, TrackEventKind<'static>)>> =
+            BTreeMap::new();
+        let mut held: HashSet<(u8, u8)> = HashSet::new();
+        for message in &self.messages {
+            if let Ok(midly::live::LiveEvent::Midi {
+                channel,
+                message: msg,
+            }) = midly::live::LiveEvent::parse(&message.bytes)
+            {
+                match msg {
+                    MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                        held.insert((channel.as_int(), key.as_int()));
+                    }
+                    MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                        held.remove(&(channel.as_int(), key.as_int()));
+                    }
+                    _ => {}
+                }
+                recorded_tracks.entry(channel.as_int()).or_default().push((
+                    us_to_tick(message.time_us),
+                    TrackEventKind::Midi {
+                        channel,
+                        message: msg,
+                    },
+                ));
+            }
+        }
+        let end_tick = us_to_tick(self.length_us);
+        for (chan, pitch) in held {
+            if let Some(events) = recorded_tracks.get_mut(&chan) {
+                events.push((end_tick, note_event(chan, pitch, 0)));
+            }
+        }
+
+        let backing_names: Vec<String> = backing_tracks
+            .keys()
+            .map(|t| format!("Backing {}", t))
+            .collect();
+        let recorded_names: Vec<String> = recorded_tracks
+            .keys()
+            .map(|c| format!("Recorded Ch {}", c + 1))
+            .collect();
+
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(RECORD_TICKS_PER_Q)),
+        ));
+        smf.tracks.push(to_track(
+            b"e_midi recording",
+            vec![
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::Tempo(u24::new(
+                        (60_000_000 / tempo_bpm) as u32,
+                    ))),
+                ),
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8)),
+                ),
+            ],
+        ));
+        for (name, (_, events)) in backing_names.iter().zip(backing_tracks) {
+            smf.tracks.push(to_track(name.as_bytes(), events));
+        }
+        for (name, (_, events)) in recorded_names.iter().zip(recorded_tracks) {
+            smf.tracks.push(to_track(name.as_bytes(), events));
+        }
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl MidiPlayer {
+    /// Record from a MIDI input port into a new dynamic song
+    ///
+    /// Recording stops on Enter, after `max_duration_secs`, or when an overdub
+    /// backing track finishes. Returns the global index of the new song.
+    pub fn record_from_input(&mut self, options: &RecordOptions) -> Result<usize, Box<dyn Error>> {
+        // Prepare the backing track before opening the port so the take starts in sync
+        let (backing, tempo_bpm) = match options.overdub_song {
+            Some(song_index) => {
+                let song = self.get_song(song_index).ok_or("Invalid song index")?;
+                if song.tracks.is_empty() {
+                    return Err("Overdub song has no MIDI tracks".into());
+                }
+                let tempo = song.default_tempo;
+                let track_indices: Vec<usize> = (0..song.tracks.len()).collect();
+                println!("🎵 Overdubbing over: {}", song.name);
+                (
+                    self.get_events_for_song(song_index, &track_indices, tempo),
+                    tempo,
+                )
+            }
+            None => (Vec::new(), options.tempo_bpm),
+        };
+
+        let recorder = MidiRecorder::start(options.port_index)?;
+        let backing_thread = if backing.is_empty() {
+            None
+        } else {
+            self.reset_stop_flag();
+            self.is_playing.store(true, Ordering::Relaxed);
+            let events = backing.clone();
+            let midi_sender = self.midi_sender.clone();
+            let stop_flag = Arc::clone(&self.playback_stop_flag);
+            let playing_state = Arc::clone(&self.is_playing);
+            Some(thread::spawn(move || {
+                if let Err(e) = Self::play_events_in_background(
+                    events,
+                    tempo_bpm,
+                    midi_sender,
+                    stop_flag,
+                    playing_state,
+                ) {
+                    eprintln!("Backing playback error: {}", e);
+                }
+            }))
+        };
+
+        println!("⏎ Press Enter to stop recording...");
+        let (enter_tx, enter_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut line = String::new();
+            let _ = std::io::stdin().read_line(&mut line);
+            let _ = enter_tx.send(());
+        });
+        let started = Instant::now();
+        let limit = options
+            .max_duration_secs
+            .map(|s| Duration::from_secs(s as u64));
+        loop {
+            if enter_rx.try_recv().is_ok() || crate::should_shutdown() {
+                break;
+            }
+            if limit.is_some_and(|l| started.elapsed() >= l) {
+                println!("⏱️ Maximum recording duration reached");
+                break;
+            }
+            if backing_thread.is_some() && !self.is_playing.load(Ordering::Relaxed) {
+                println!("🎵 Backing track finished");
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        let recording = recorder.stop();
+        if let Some(handle) = backing_thread {
+            self.playback_stop_flag.store(true, Ordering::Relaxed);
+            let _ = handle.join();
+            self.is_playing.store(false, Ordering::Relaxed);
+            let _ = self.midi_sender.send(crate::MidiCommand::AllNotesOff);
+        }
+        if recording.is_empty() {
+            return Err("No MIDI messages were recorded".into());
+        }
+
+        let smf_bytes = recording.to_smf_bytes(tempo_bpm, &backing)?;
+        if let Some(path) = &options.output {
+            std::fs::write(path, &smf_bytes)?;
+            println!("💾 Saved recording to {}", path.display());
+        }
+        let name = match &options.output {
+            Some(path) => path.to_string_lossy().to_string(),
+            None => format!(
+                "recording_{}.mid",
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            ),
+        };
+        self.add_song_from_midi_data(&smf_bytes, Some(&name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::u7;
+
+    fn message(time_us: u64, bytes: &[u8]) -> RecordedMessage {
+        RecordedMessage {
+            time_us,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    /// Delta time, channel and message of every channel event in a track
+    fn midi_events(track: &[midly::TrackEvent]) -> Vec<(u32, u8, MidiMessage)> {
+        track
+            .iter()
+            .filter_map(|e| match e.kind {
+                TrackEventKind::Midi { channel, message } => {
+                    Some((e.delta.as_int(), channel.as_int(), message))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn track_name<'a>(track: &[midly::TrackEvent<'a>]) -> Option<&'a [u8]> {
+        track.iter().find_map(|e| match e.kind {
+            TrackEventKind::Meta(MetaMessage::TrackName(name)) => Some(name),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn recorded_messages_round_trip_through_smf() {
+        let on = |key: u8, vel: u8| MidiMessage::NoteOn {
+            key: u7::new(key),
+            vel: u7::new(vel),
+        };
+        let off = |key: u8| MidiMessage::NoteOff {
+            key: u7::new(key),
+            vel: u7::new(0),
+        };
+        // 120 BPM at 480 ticks per quarter: 1000us is 0.96 ticks
+        let recording = Recording {
+            messages: vec![
+                message(0, &[0x90, 60, 100]),
+                message(100_000, &[0xF8]), // clock, not a channel message
+                message(250_000, &[0x91, 64, 90]),
+                message(500_000, &[0x80, 60, 0]),
+                message(750_000, &[0xB0, 7, 100]),
+                message(1_000_000, &[0x90, 62, 80]),
+            ],
+            length_us: 1_500_000,
+        };
+        assert!(!recording.is_empty());
+
+        let bytes = recording.to_smf_bytes(120, &[]).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(
+            smf.header.timing,
+            Timing::Metrical(u15::new(RECORD_TICKS_PER_Q))
+        );
+        assert_eq!(smf.tracks.len(), 3);
+
+        assert!(smf.tracks[0].iter().any(|e| matches!(
+            e.kind,
+            TrackEventKind::Meta(MetaMessage::Tempo(t)) if t.as_int() == 500_000
+        )));
+        assert_eq!(track_name(&smf.tracks[1]), Some(&b"Recorded Ch 1"[..]));
+        assert_eq!(
+            midi_events(&smf.tracks[1]),
+            vec![
+                (0, 0, on(60, 100)),
+                (480, 0, off(60)),
+                (
+                    240,
+                    0,
+                    MidiMessage::Controller {
+                        controller: u7::new(7),
+                        value: u7::new(100),
+                    }
+                ),
+                (240, 0, on(62, 80)),
+                // Still held when the take stopped
+                (480, 0, off(62)),
+            ]
+        );
+        assert_eq!(track_name(&smf.tracks[2]), Some(&b"Recorded Ch 2"[..]));
+        assert_eq!(
+            midi_events(&smf.tracks[2]),
+            vec![(240, 1, on(64, 90)), (1200, 1, off(64))]
+        );
+    }
+
+    #[test]
+    fn a_take_without_channel_messages_is_empty() {
+        let recording = Recording {
+            messages: vec![message(0, &[0xF8]), message(10, &[0xFE])],
+            length_us: 20,
+        };
+        assert!(recording.is_empty());
+    }
+}
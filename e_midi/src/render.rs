@@ -0,0 +1,239 @@
+//! Offline rendering of songs to WAV through a `SynthEngine`.
+//!
+//! Rendering runs faster than realtime without touching any MIDI or audio
+//! device, and the built-in `SoftSynth` makes the output bit-for-bit repeatable.
+
+use crate::export::program_changes;
+use crate::soundfont::SoundFontSynth;
+use crate::synth::{SoftSynth, SynthEngine};
+use crate::{MidiPlayer, Note, SongType};
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Default sample rate for rendered audio
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// Seconds rendered after the last note-off so releases can ring out
+const RENDER_TAIL_SECS: f32 = 1.5;
+
+/// Frames rendered per engine call
+const RENDER_BLOCK_FRAMES: usize = 512;
+
+/// Options for `MidiPlayer::render_song_to_wav`
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// User-facing track numbers, as for `play --tracks` (None for all)
+    pub tracks: Option<Vec<usize>>,
+    pub tempo_bpm: Option<u32>,
+    pub sample_rate: u32,
+    /// Render through this SF2 SoundFont instead of the built-in synthesizer
+    pub soundfont: Option<PathBuf>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            tracks: None,
+            tempo_bpm: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            soundfont: None,
+        }
+    }
+}
+
+/// Drive `engine` through `notes` and return interleaved stereo samples
+///
+/// `setup` messages (program changes etc.) are sent before the first sample.
+pub fn render_notes(engine: &mut dyn SynthEngine, notes: &[Note], setup: &[Vec<u8>]) -> Vec<f32> {
+    let sample_rate = engine.sample_rate() as u64;
+    let ms_to_frame = |ms: u32| ms as u64 * sample_rate / 1000;
+
+    // Note-offs sort ahead of note-ons on the same frame so retriggers work
+    let mut timeline: Vec<(u64, bool, [u8; 3])> = Vec::with_capacity(notes.len() * 2);
+    for note in notes {
+        let chan = note.chan & 0x0F;
+        timeline.push((
+            ms_to_frame(note.start_ms),
+            true,
+            [0x90 | chan, note.pitch & 0x7F, note.vel.clamp(1, 127)],
+        ));
+        timeline.push((
+            ms_to_frame(note.start_ms + note.dur_ms),
+            false,
+            [0x80 | chan, note.pitch & 0x7F, 0],
+        ));
+    }
+    timeline.sort_by_key(|(frame, on, _)| (*frame, *on));
+
+    for msg in setup {
+        engine.handle_message(msg);
+    }
+
+    let last_frame = timeline.last().map_or(0, |(frame, _, _)| *frame);
+    let total_frames = last_frame + (RENDER_TAIL_SECS * sample_rate as f32) as u64;
+    let mut samples = vec![0.0f32; total_frames as usize * 2];
+    let mut cursor = 0u64;
+    let mut events = timeline.into_iter().peekable();
+    while cursor < total_frames {
+        while let Some((_, _, msg)) = events.next_if(|(frame, _, _)| *frame <= cursor) {
+            engine.handle_message(&msg);
+        }
+        let next_event = events.peek().map_or(total_frames, |(frame, _, _)| *frame);
+        let end = next_event
+            .min(cursor + RENDER_BLOCK_FRAMES as u64)
+            .min(total_frames);
+        engine.render(&mut samples[cursor as usize * 2..end as usize * 2]);
+        cursor = end;
+    }
+    samples
+}
+
+/// Write interleaved `f32` samples as a 16-bit PCM WAV stream
+pub fn write_wav<W: Write>(
+    mut writer: W,
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+) -> std::io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    let mut pcm = Vec::with_capacity(samples.len() * 2);
+    for &s in samples {
+        let value = (s.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        pcm.extend_from_slice(&value.to_le_bytes());
+    }
+    writer.write_all(&pcm)
+}
+
+/// Notes to render plus the setup messages sent before them
+pub(crate) struct RenderPlan {
+    pub notes: Vec<Note>,
+    pub setup: Vec<Vec<u8>>,
+}
+
+impl MidiPlayer {
+    /// Collect the notes and program changes needed to render a song
+    pub(crate) fn render_plan(
+        &self,
+        song_index: usize,
+        tracks: Option<&[usize]>,
+        tempo_bpm: Option<u32>,
+    ) -> Result<RenderPlan, Box<dyn Error>> {
+        let song = self.get_song(song_index).ok_or("Invalid song index")?;
+        if !matches!(song.song_type, SongType::Midi | SongType::MusicXml) {
+            return Err(format!("{} is not a MIDI or MusicXML song", song.name).into());
+        }
+        let tempo = tempo_bpm.unwrap_or(song.default_tempo).max(1);
+        let dense_indices = Self::get_dense_indices_for_song(song, tracks);
+        let notes = self.get_events_for_song(song_index, &dense_indices, tempo);
+        if notes.is_empty() {
+            return Err("No notes to render! Check track selection.".into());
+        }
+
+        let setup = program_changes(song, &notes);
+        Ok(RenderPlan { notes, setup })
+    }
+
+    /// Render a song to WAV bytes with the built-in synthesizer or a SoundFont
+    pub fn render_song_to_wav_bytes(
+        &self,
+        song_index: usize,
+        options: &RenderOptions,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let plan = self.render_plan(song_index, options.tracks.as_deref(), options.tempo_bpm)?;
+        let mut synth: Box<dyn SynthEngine> = match &options.soundfont {
+            Some(path) => Box::new(SoundFontSynth::from_file(path, options.sample_rate)?),
+            None => Box::new(SoftSynth::new(options.sample_rate)),
+        };
+        let samples = render_notes(synth.as_mut(), &plan.notes, &plan.setup);
+        let mut bytes = Vec::with_capacity(samples.len() * 2 + 44);
+        write_wav(&mut bytes, &samples, synth.sample_rate(), 2)?;
+        Ok(bytes)
+    }
+
+    /// Render a song to a WAV file on disk
+    pub fn render_song_to_wav<P: AsRef<Path>>(
+        &self,
+        song_index: usize,
+        path: P,
+        options: &RenderOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        let bytes = self.render_song_to_wav_bytes(song_index, options)?;
+        std::fs::write(path.as_ref(), bytes)?;
+        println!(
+            "🔊 Rendered song {} to {}",
+            song_index,
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    #[test]
+    fn rendering_is_deterministic_and_the_header_matches() {
+        let player = MidiPlayer::new_offline().unwrap();
+        // The shortest embedded MIDI song keeps the test quick
+        let song_index = (0..player.get_total_song_count())
+            .filter(|&i| player.get_song(i).unwrap().song_type == SongType::Midi)
+            .min_by_key(|&i| player.get_song(i).unwrap().duration_ms.unwrap_or(u32::MAX))
+            .expect("no embedded MIDI song");
+        let options = RenderOptions {
+            sample_rate: 8_000,
+            ..RenderOptions::default()
+        };
+
+        let first = player
+            .render_song_to_wav_bytes(song_index, &options)
+            .unwrap();
+        let second = player
+            .render_song_to_wav_bytes(song_index, &options)
+            .unwrap();
+        assert!(first == second, "two renders of the same song differ");
+
+        assert_eq!(&first[0..4], b"RIFF");
+        assert_eq!(u32_at(&first, 4) as usize, first.len() - 8);
+        assert_eq!(&first[8..12], b"WAVE");
+        assert_eq!(&first[12..16], b"fmt ");
+        assert_eq!(u16_at(&first, 20), 1);
+        assert_eq!(u16_at(&first, 22), 2);
+        assert_eq!(u32_at(&first, 24), 8_000);
+        assert_eq!(&first[36..40], b"data");
+
+        let plan = player.render_plan(song_index, None, None).unwrap();
+        let last_ms = plan
+            .notes
+            .iter()
+            .map(|n| n.start_ms + n.dur_ms)
+            .max()
+            .unwrap();
+        let frames = last_ms as u64 * 8_000 / 1000 + (RENDER_TAIL_SECS * 8_000.0) as u64;
+        assert_eq!(u32_at(&first, 40) as u64, frames * 4);
+        assert_eq!(first.len() as u64, 44 + frames * 4);
+    }
+}
@@ -0,0 +1,506 @@
+//! Newline-delimited JSON-RPC 2.0 control endpoint on a Unix domain socket.
+//!
+//! For tools that cannot link iceoryx2: each line on the socket is one request
+//! and gets one response line. `subscribe` additionally streams every IPC
+//! `Event` the player publishes as `{"method": "event", "params": ...}`
+//! notifications.
+//!
+//! ```text
+//! $ echo '{"jsonrpc":"2.0","id":1,"method":"play","params":{"song":3}}' | nc -U e_midi.sock
+//! {"id":1,"jsonrpc":"2.0","result":{"state":"playing","song_index":3,...}}
+//! ```
+//!
+//! Methods: `songs`, `status`, `play`, `stop`, `pause`, `next`, `previous`,
+//! `seek`, `tempo`, `mixer`, `queue`, `subscribe`, `unsubscribe`.
+
+use crate::ipc::{AppId, EventSubscriber};
+use crate::transport::Transport;
+use crate::{MidiPlayer, SongInfo, SongType};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long `poll` waits for the first request
+const POLL_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// How long a write may block before the client is dropped
+///
+/// A subscriber that stops reading fills its socket buffer; without a limit
+/// the next event would stall playback control for every other client.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Socket path used when none is given: `$XDG_RUNTIME_DIR/e_midi.sock`, or the
+/// temp dir if that is unset
+pub fn default_socket_path() -> PathBuf {
+    crate::serve::runtime_dir().join("e_midi.sock")
+}
+
+/// JSON-RPC error: code plus message
+struct RpcError(i64, String);
+
+impl RpcError {
+    fn params(message: impl Into<String>) -> Self {
+        RpcError(INVALID_PARAMS, message.into())
+    }
+}
+
+impl From<Box<dyn Error>> for RpcError {
+    fn from(e: Box<dyn Error>) -> Self {
+        RpcError(SERVER_ERROR, e.to_string())
+    }
+}
+
+/// Named (`{"song": 3}`) or positional (`[3]`) parameter
+fn param<'a>(params: &'a Value, name: &str, position: usize) -> Option<&'a Value> {
+    match params {
+        Value::Object(map) => map.get(name),
+        Value::Array(items) => items.get(position),
+        _ => None,
+    }
+    .filter(|v| !v.is_null())
+}
+
+fn u64_param(params: &Value, name: &str, position: usize) -> Result<Option<u64>, RpcError> {
+    match param(params, name, position) {
+        None => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .or_else(|| v.as_f64().filter(|f| *f >= 0.0).map(|f| f.round() as u64))
+            .map(Some)
+            .ok_or_else(|| RpcError::params(format!("'{}' must be a non-negative number", name))),
+    }
+}
+
+fn required_u64(params: &Value, name: &str, position: usize) -> Result<u64, RpcError> {
+    u64_param(params, name, position)?
+        .ok_or_else(|| RpcError::params(format!("Missing parameter '{}'", name)))
+}
+
+/// Song by index or (partial) name
+fn song_param(
+    player: &MidiPlayer,
+    params: &Value,
+    position: usize,
+) -> Result<Option<usize>, RpcError> {
+    let Some(value) = param(params, "song", position) else {
+        return Ok(None);
+    };
+    let query = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    player
+        .find_song(&query)
+        .map(Some)
+        .ok_or_else(|| RpcError::params(format!("No song matching '{}'", query)))
+}
+
+/// Song list entry with track details
+pub fn song_metadata(player: &MidiPlayer, index: usize, song: &SongInfo) -> Value {
+    let song_type = match song.song_type {
+        SongType::Midi => "midi",
+        SongType::MusicXml => "musicxml",
+        SongType::TidalCycles => "tidal",
+        _ => "audio",
+    };
+    let tracks: Vec<Value> = song
+        .tracks
+        .iter()
+        .map(|t| {
+            json!({
+                "track": t.index,
+                "program": t.program,
+                "instrument": t.guess,
+                "channels": t.channels,
+                "note_count": t.note_count,
+                "pitch_range": [t.pitch_range.0, t.pitch_range.1],
+            })
+        })
+        .collect();
+    json!({
+        "index": index,
+        "name": song.name,
+        "filename": song.filename,
+        "type": song_type,
+        "default_tempo": song.default_tempo,
+        "duration_ms": song.duration_ms,
+        "is_dynamic": index >= player.get_static_song_count(),
+        "tracks": tracks,
+    })
+}
+
+enum ClientMessage {
+    Line(u64, String),
+    Closed(u64),
+}
+
+struct RpcClient {
+    stream: UnixStream,
+    subscribed: bool,
+}
+
+/// Unix socket JSON-RPC endpoint controlling a `Transport`
+pub struct RpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+    clients: HashMap<u64, RpcClient>,
+    next_client: u64,
+    sender: mpsc::Sender<ClientMessage>,
+    receiver: mpsc::Receiver<ClientMessage>,
+    events: Option<EventSubscriber>,
+}
+
+impl RpcServer {
+    /// Listen on `path`, replacing a stale socket left by a previous run
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(format!("{} is already in use", path.display()).into());
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        println!("🔌 JSON-RPC control socket listening on {}", path.display());
+        let (sender, receiver) = mpsc::channel();
+        Ok(RpcServer {
+            listener,
+            path,
+            clients: HashMap::new(),
+            next_client: 0,
+            sender,
+            receiver,
+            events: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept clients, answer pending requests and stream events
+    ///
+    /// Waits up to 20ms for a request, so this can drive a loop directly.
+    pub fn poll(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+    ) -> Result<(), Box<dyn Error>> {
+        self.accept()?;
+
+        let mut message = self.receiver.recv_timeout(POLL_TIMEOUT).ok();
+        while let Some(msg) = message {
+            match msg {
+                ClientMessage::Line(client, line) => {
+                    if let Some(response) = self.handle_line(player, transport, client, &line) {
+                        self.write(client, &response);
+                    }
+                }
+                ClientMessage::Closed(client) => self.drop_client(client),
+            }
+            message = self.receiver.try_recv().ok();
+        }
+
+        transport.update(player);
+        self.forward_events();
+        Ok(())
+    }
+
+    fn accept(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let id = self.next_client;
+                    self.next_client += 1;
+                    // Not non-blocking: the reader thread shares the socket
+                    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                    let reader = stream.try_clone()?;
+                    let sender = self.sender.clone();
+                    thread::spawn(move || {
+                        for line in BufReader::new(reader).lines() {
+                            let Ok(line) = line else { break };
+                            if sender.send(ClientMessage::Line(id, line)).is_err() {
+                                return;
+                            }
+                        }
+                        let _ = sender.send(ClientMessage::Closed(id));
+                    });
+                    self.clients.insert(
+                        id,
+                        RpcClient {
+                            stream,
+                            subscribed: false,
+                        },
+                    );
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn write(&mut self, client: u64, value: &Value) {
+        let Some(c) = self.clients.get_mut(&client) else {
+            return;
+        };
+        // A timed out write may have sent part of the line, so the client can't
+        // be written to again either way
+        if let Err(e) = writeln!(c.stream, "{}", value) {
+            if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+                eprintln!("⚠️  Dropping JSON-RPC client {}: not reading", client);
+            }
+            self.drop_client(client);
+        }
+    }
+
+    /// Forget a client and end its reader thread
+    fn drop_client(&mut self, client: u64) {
+        if let Some(c) = self.clients.remove(&client) {
+            let _ = c.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn forward_events(&mut self) {
+        // Drain even without subscribers so a later subscriber doesn't get a
+        // backlog of stale events
+        let Some(events) = self.events.as_mut().and_then(|s| s.try_receive().ok()) else {
+            return;
+        };
+        if !self.clients.values().any(|c| c.subscribed) {
+            return;
+        }
+        for event in events {
+            let notification = json!({"jsonrpc": "2.0", "method": "event", "params": event});
+            let subscribers: Vec<u64> = self
+                .clients
+                .iter()
+                .filter(|(_, c)| c.subscribed)
+                .map(|(id, _)| *id)
+                .collect();
+            for client in subscribers {
+                self.write(client, &notification);
+            }
+        }
+    }
+
+    /// Response for one request line (None for notifications)
+    fn handle_line(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+        client: u64,
+        line: &str,
+    ) -> Option<Value> {
+        if line.trim().is_empty() {
+            return None;
+        }
+        let request: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(
+                id.unwrap_or(Value::Null),
+                INVALID_REQUEST,
+                "Missing method",
+            ));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        let result = self.call(player, transport, client, method, &params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(RpcError(code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    fn call(
+        &mut self,
+        player: &MidiPlayer,
+        transport: &mut Transport,
+        client: u64,
+        method: &str,
+        params: &Value,
+    ) -> Result<Value, RpcError> {
+        match method {
+            "songs" => {
+                let songs: Vec<Value> = player
+                    .get_songs()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, song)| song_metadata(player, i, song))
+                    .collect();
+                return Ok(Value::Array(songs));
+            }
+            "status" => {}
+            "play" => match song_param(player, params, 0)? {
+                Some(index) => transport.play(player, index)?,
+                None => transport.resume(player)?,
+            },
+            "stop" => transport.stop(player),
+            "pause" => transport.pause(player)?,
+            "next" => transport.next(player)?,
+            "previous" => transport.previous(player)?,
+            "seek" => {
+                let position = required_u64(params, "position_ms", 0)?;
+                transport.seek(player, position.min(u32::MAX as u64) as u32)?;
+            }
+            "tempo" => {
+                let bpm = required_u64(params, "bpm", 0)?;
+                transport.set_tempo(player, bpm.min(u32::MAX as u64) as u32)?;
+            }
+            "mixer" => {
+                if let Some(track) = u64_param(params, "track", 0)? {
+                    let muted = param(params, "mute", 1)
+                        .map(|v| {
+                            v.as_bool()
+                                .ok_or_else(|| RpcError::params("'mute' must be true or false"))
+                        })
+                        .transpose()?
+                        .unwrap_or(true);
+                    transport.set_track_muted(player, track as usize, muted)?;
+                }
+                if let Some(value) = param(params, "transpose", 2) {
+                    let semitones = value
+                        .as_i64()
+                        .ok_or_else(|| RpcError::params("'transpose' must be an integer"))?;
+                    transport.set_transpose(player, semitones.clamp(-128, 127) as i8)?;
+                }
+                return Ok(mixer_state(player, transport));
+            }
+            "queue" => {
+                if param(params, "clear", 1).and_then(Value::as_bool) == Some(true) {
+                    transport.clear_queue();
+                }
+                if let Some(index) = song_param(player, params, 0)? {
+                    transport.cue(player, index)?;
+                }
+                return Ok(json!(transport.status(player).queue));
+            }
+            "subscribe" => {
+                if self.events.is_none() {
+                    let subscriber =
+                        EventSubscriber::new(AppId::EMidi, AppId::EMidi).map_err(|e| {
+                            RpcError(SERVER_ERROR, format!("IPC event stream unavailable: {}", e))
+                        })?;
+                    self.events = Some(subscriber);
+                }
+                if let Some(c) = self.clients.get_mut(&client) {
+                    c.subscribed = true;
+                }
+                return Ok(json!(true));
+            }
+            "unsubscribe" => {
+                if let Some(c) = self.clients.get_mut(&client) {
+                    c.subscribed = false;
+                }
+                return Ok(json!(true));
+            }
+            other => {
+                return Err(RpcError(
+                    METHOD_NOT_FOUND,
+                    format!("Unknown method '{}'", other),
+                ))
+            }
+        }
+        // Transport commands answer with the resulting status
+        Ok(json!(transport.status(player)))
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+/// Per-track mute state plus transpose for the current song
+fn mixer_state(player: &MidiPlayer, transport: &Transport) -> Value {
+    let status = transport.status(player);
+    let tracks: Vec<Value> = status
+        .song_index
+        .and_then(|i| player.get_song(i))
+        .map(|song| {
+            song.tracks
+                .iter()
+                .map(|t| {
+                    json!({
+                        "track": t.index,
+                        "instrument": t.guess,
+                        "muted": status.muted_tracks.contains(&t.index),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    json!({"tracks": tracks, "transpose": status.transpose})
+}
+
+impl MidiPlayer {
+    /// Run the JSON-RPC control socket until shutdown (Ctrl+C)
+    pub fn run_rpc_server<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Box<dyn Error>> {
+        let mut server = RpcServer::bind(path)?;
+        // `subscribe` streams whatever this publisher sends
+        if let Err(e) = self.init_ipc_publisher() {
+            eprintln!("⚠️  IPC events unavailable, subscribe will fail: {}", e);
+        }
+        let mut transport = Transport::new();
+        let result = loop {
+            if crate::should_shutdown() {
+                break Ok(());
+            }
+            if let Err(e) = server.poll(self, &mut transport) {
+                break Err(e);
+            }
+            self.poll_watched_directories_for(&mut transport);
+        };
+        transport.stop(self);
+        println!("🔌 JSON-RPC control socket closed");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn a_client_that_stops_reading_is_dropped() {
+        let path =
+            std::env::temp_dir().join(format!("e_midi_rpc_test_{}.sock", std::process::id()));
+        let mut server = RpcServer::bind(&path).unwrap();
+        let _client = UnixStream::connect(&path).unwrap();
+        server.accept().unwrap();
+        let id = *server.clients.keys().next().unwrap();
+
+        let line = json!("x".repeat(64 * 1024));
+        let started = Instant::now();
+        while server.clients.contains_key(&id) {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "a stalled client blocked the server"
+            );
+            server.write(id, &line);
+        }
+    }
+}
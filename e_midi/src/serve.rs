@@ -0,0 +1,257 @@
+//! Headless service mode.
+//!
+//! `e_midi serve` keeps a `MidiPlayer` running in the background with no TUI
+//! or stdin menu: it answers IPC commands, publishes `SystemHeartbeat` events,
+//! optionally runs the OSC and JSON-RPC control endpoints on a shared
+//! `Transport`, and holds a PID lock file so only one instance runs at a time.
+//! SIGINT/SIGTERM stop playback, publish `SystemShutdown` and remove the lock.
+
+use crate::ipc::{self, AppId, Event, EventSubscriber};
+use crate::transport::Transport;
+use crate::MidiPlayer;
+use std::error::Error;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Default interval between `SystemHeartbeat` events
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the loop sleeps when no control endpoint is waiting on a socket
+const IDLE_SLEEP: Duration = Duration::from_millis(10);
+
+/// Directory for sockets and lock files: `$XDG_RUNTIME_DIR`, else the temp dir
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+pub fn default_pid_file() -> PathBuf {
+    runtime_dir().join("e_midi.pid")
+}
+
+/// Whether a process with this id is still running
+fn process_alive(pid: u32) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        Path::new("/proc").join(pid.to_string()).exists()
+    }
+    #[cfg(all(unix, not(target_os = "linux")))]
+    {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(true)
+    }
+    #[cfg(windows)]
+    {
+        std::process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+            .unwrap_or(true)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+/// Exclusive PID lock file, removed on drop
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Create the lock file, taking over a stale one left by a dead process
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref().to_path_buf();
+        let pid = std::process::id();
+        for _ in 0..2 {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    writeln!(file, "{}", pid)?;
+                    return Ok(PidFile { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u32>().ok());
+                    if let Some(owner) = owner.filter(|p| *p != pid && process_alive(*p)) {
+                        return Err(format!(
+                            "e_midi is already running (pid {}, lock file {})",
+                            owner,
+                            path.display()
+                        )
+                        .into());
+                    }
+                    println!("🧹 Removing stale lock file {}", path.display());
+                    fs::remove_file(&path)?;
+                }
+                Err(e) => return Err(format!("Cannot create {}: {}", path.display(), e).into()),
+            }
+        }
+        Err(format!("Cannot acquire lock file {}", path.display()).into())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Only remove the file if it is still ours
+        let ours = fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            == Some(std::process::id());
+        if ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// What `MidiPlayer::serve` runs besides the IPC command loop
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub pid_file: PathBuf,
+    pub heartbeat_interval: Duration,
+    /// OSC control server address
+    #[cfg(feature = "uses_osc")]
+    pub osc_bind: Option<String>,
+    /// JSON-RPC socket path
+    #[cfg(unix)]
+    pub rpc_socket: Option<PathBuf>,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            pid_file: default_pid_file(),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            #[cfg(feature = "uses_osc")]
+            osc_bind: None,
+            #[cfg(unix)]
+            rpc_socket: None,
+        }
+    }
+}
+
+impl MidiPlayer {
+    /// Run headless until SIGINT/SIGTERM
+    pub fn serve(&mut self, options: ServeOptions) -> Result<(), Box<dyn Error>> {
+        let pid_file = PidFile::acquire(&options.pid_file)?;
+        crate::set_graceful_shutdown(true);
+        println!(
+            "🛰️  e_midi service running (pid {}, lock {})",
+            std::process::id(),
+            pid_file.path().display()
+        );
+
+        self.init_ipc_publisher()?;
+        // A service announces CAP_MIDI_MESSAGES, so it publishes what it plays
+        self.set_ipc_enabled(true);
+        let mut commands = match EventSubscriber::new(AppId::EMidi, AppId::EMidi) {
+            Ok(subscriber) => {
+                if let Some(manager) = self.ipc_manager.as_mut() {
+                    manager.set_capabilities(&[
+                        ipc::CAP_MIDI_COMMANDS,
+                        ipc::CAP_STATE,
+                        ipc::CAP_LOAD_SONG,
+                        ipc::CAP_MIDI_MESSAGES,
+                        ipc::CAP_BEATS,
+                    ]);
+                    let _ = manager.say_hello();
+                }
+                Some(subscriber)
+            }
+            Err(e) => {
+                eprintln!("⚠️  IPC commands unavailable: {}", e);
+                None
+            }
+        };
+
+        let mut transport = Transport::new();
+        // Errors end up here too, so every exit runs the cleanup below
+        let result = self.serve_loop(&options, &mut transport, commands.as_mut());
+
+        println!("🛑 Stopping e_midi service...");
+        transport.stop(self);
+        self.stop_playback();
+        let _ = self.send_midi_command(crate::MidiCommand::AllNotesOff);
+        self.publish_midi_event(Event::SystemShutdown {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        });
+        drop(pid_file);
+        println!("👋 e_midi service stopped");
+        result
+    }
+
+    /// Bind the control endpoints and run until shutdown or a socket failure
+    ///
+    /// Playlist problems (a song that can't be played or advanced to) are
+    /// logged by the transport and don't end the loop.
+    fn serve_loop(
+        &mut self,
+        options: &ServeOptions,
+        transport: &mut Transport,
+        mut commands: Option<&mut EventSubscriber>,
+    ) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "uses_osc")]
+        let mut osc = match &options.osc_bind {
+            Some(addr) => Some(crate::osc::OscServer::bind(addr.as_str())?),
+            None => None,
+        };
+        #[cfg(unix)]
+        let mut rpc = match &options.rpc_socket {
+            Some(path) => Some(crate::rpc::RpcServer::bind(path)?),
+            None => None,
+        };
+
+        let mut last_heartbeat: Option<Instant> = None;
+        loop {
+            if crate::should_shutdown() {
+                return Ok(());
+            }
+            if last_heartbeat.map_or(true, |t| t.elapsed() >= options.heartbeat_interval) {
+                self.publish_midi_event(Event::system_heartbeat(AppId::EMidi));
+                last_heartbeat = Some(Instant::now());
+            }
+            self.poll_watched_directories_for(transport);
+            if let Some(subscriber) = commands.as_deref_mut() {
+                self.poll_ipc_commands(subscriber, transport);
+            }
+
+            // The endpoints block briefly on their sockets; otherwise sleep
+            let mut waited = false;
+            #[cfg(feature = "uses_osc")]
+            if let Some(server) = osc.as_mut() {
+                server.poll(self, transport)?;
+                waited = true;
+            }
+            #[cfg(unix)]
+            if let Some(server) = rpc.as_mut() {
+                server.poll(self, transport)?;
+                waited = true;
+            }
+            if !waited {
+                transport.update(self);
+                std::thread::sleep(IDLE_SLEEP);
+            }
+        }
+    }
+}
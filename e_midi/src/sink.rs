@@ -0,0 +1,346 @@
+//! MIDI output sinks driven by the background MIDI thread.
+//!
+//! Playback only ever sends raw MIDI bytes, so anything implementing `MidiSink`
+//! can stand in for a hardware port: a midir connection, a software synth, or
+//! nothing at all for offline work.
+
+use crate::record::{RecordedMessage, Recording};
+#[cfg(feature = "uses_rodio")]
+use crate::synth::SynthEngine;
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+#[cfg(feature = "uses_rodio")]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Messages kept by a `RecordingSink` before it stops capturing
+const RECORDING_SINK_LIMIT: usize = 1 << 20;
+
+/// Engine shared between the MIDI thread and the audio callback
+#[cfg(feature = "uses_rodio")]
+type SharedEngine = Arc<Mutex<Box<dyn SynthEngine>>>;
+
+/// Kind of output a player is connected to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputBackend {
+    /// Hardware or virtual MIDI port, by name
+    MidiPort(String),
+    /// Built-in `SoftSynth` on the default audio device
+    SoftSynth,
+    /// SF2 SoundFont on the default audio device
+    SoundFont(PathBuf),
+    /// SuperDirt `/dirt/play` messages over OSC
+    SuperDirt(SocketAddr),
+    /// No device; played messages are captured in memory
+    Recording,
+    /// No device; played messages are discarded
+    Null,
+}
+
+impl OutputBackend {
+    /// True if playback can actually be heard
+    pub fn is_audible(&self) -> bool {
+        !matches!(self, OutputBackend::Recording | OutputBackend::Null)
+    }
+}
+
+impl fmt::Display for OutputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputBackend::MidiPort(name) => write!(f, "MIDI port '{}'", name),
+            OutputBackend::SoftSynth => write!(f, "built-in software synth"),
+            OutputBackend::SoundFont(path) => write!(f, "SoundFont {}", path.display()),
+            OutputBackend::SuperDirt(addr) => write!(f, "SuperDirt at {}", addr),
+            OutputBackend::Recording => write!(f, "recording sink (output kept in memory)"),
+            OutputBackend::Null => write!(f, "null sink (output discarded)"),
+        }
+    }
+}
+
+/// Destination for raw MIDI messages produced by playback
+pub trait MidiSink: Send {
+    /// Send one complete MIDI message
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    /// Which kind of output this is
+    fn backend(&self) -> OutputBackend;
+}
+
+/// Sink writing to a midir output port
+pub struct MidirSink {
+    connection: midir::MidiOutputConnection,
+    port_name: String,
+}
+
+impl MidirSink {
+    pub fn new(connection: midir::MidiOutputConnection, port_name: String) -> Self {
+        MidirSink {
+            connection,
+            port_name,
+        }
+    }
+}
+
+impl MidiSink for MidirSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.connection.send(msg)?;
+        Ok(())
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::MidiPort(self.port_name.clone())
+    }
+}
+
+/// Several midir ports with each MIDI channel routed to one of them
+///
+/// Channel messages go to the port their channel is routed to; system
+/// messages (clock, MTC, SysEx) go to every port.
+pub struct RoutedSink {
+    ports: Vec<MidirSink>,
+    channel_ports: [usize; 16],
+}
+
+impl RoutedSink {
+    /// `channel_ports[channel]` indexes into `ports`
+    pub fn new(ports: Vec<MidirSink>, channel_ports: [usize; 16]) -> Self {
+        RoutedSink {
+            ports,
+            channel_ports,
+        }
+    }
+}
+
+impl MidiSink for RoutedSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        match msg.first() {
+            Some(status) if *status < 0xF0 => {
+                let port = self.channel_ports[(status & 0x0F) as usize];
+                match self.ports.get_mut(port) {
+                    Some(port) => port.send(msg),
+                    None => Ok(()),
+                }
+            }
+            _ => {
+                for port in &mut self.ports {
+                    port.send(msg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn backend(&self) -> OutputBackend {
+        let names: Vec<&str> = self.ports.iter().map(|p| p.port_name.as_str()).collect();
+        OutputBackend::MidiPort(names.join(" + "))
+    }
+}
+
+/// Sink that discards every message (offline rendering, export, tests)
+#[derive(Debug, Default)]
+pub struct NullSink;
+
+impl MidiSink for NullSink {
+    fn send(&mut self, _msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Null
+    }
+}
+
+/// Sink that timestamps and keeps every message, for inspection or export
+pub struct RecordingSink {
+    messages: Arc<Mutex<Vec<RecordedMessage>>>,
+    started: Instant,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        RecordingSink {
+            messages: Arc::new(Mutex::new(Vec::new())),
+            started: Instant::now(),
+        }
+    }
+
+    /// Shared handle to the captured messages
+    pub fn messages(&self) -> Arc<Mutex<Vec<RecordedMessage>>> {
+        Arc::clone(&self.messages)
+    }
+}
+
+impl Default for RecordingSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiSink for RecordingSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut messages = self
+            .messages
+            .lock()
+            .map_err(|_| "Recording sink lock poisoned")?;
+        if messages.len() < RECORDING_SINK_LIMIT {
+            messages.push(RecordedMessage {
+                time_us: self.started.elapsed().as_micros() as u64,
+                bytes: msg.to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::Recording
+    }
+}
+
+/// Drain messages captured by a `RecordingSink` into a `Recording`
+pub fn take_recording(messages: &Mutex<Vec<RecordedMessage>>) -> Recording {
+    let messages = messages
+        .lock()
+        .map(|mut m| std::mem::take(&mut *m))
+        .unwrap_or_default();
+    let length_us = messages.last().map_or(0, |m| m.time_us);
+    Recording {
+        messages,
+        length_us,
+    }
+}
+
+/// Sink feeding a `SynthEngine` that plays through the default audio device
+///
+/// The audio stream lives on its own thread (cpal streams are not `Send` on
+/// every platform) and pulls samples from the shared engine.
+#[cfg(feature = "uses_rodio")]
+pub struct SynthSink {
+    engine: SharedEngine,
+    backend: OutputBackend,
+    stop_sender: mpsc::Sender<()>,
+}
+
+#[cfg(feature = "uses_rodio")]
+impl SynthSink {
+    /// Open the default audio device and start pulling audio from `engine`
+    pub fn new(
+        engine: Box<dyn SynthEngine>,
+        backend: OutputBackend,
+    ) -> Result<Self, Box<dyn Error>> {
+        let engine = Arc::new(Mutex::new(engine));
+        let source = EngineSource::new(Arc::clone(&engine));
+        let (ready_sender, ready_receiver) = mpsc::channel::<Result<(), String>>();
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        std::thread::Builder::new()
+            .name("e_midi-audio".to_string())
+            .spawn(move || {
+                let stream = match rodio::OutputStreamBuilder::open_default_stream() {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = ready_sender.send(Err(e.to_string()));
+                        return;
+                    }
+                };
+                stream.mixer().add(source);
+                let _ = ready_sender.send(Ok(()));
+                // Keep the stream alive until the sink is dropped
+                let _ = stop_receiver.recv();
+            })?;
+        ready_receiver
+            .recv()
+            .map_err(|_| "Audio thread exited unexpectedly")??;
+        println!("🔊 {} playing on the default audio device", backend);
+        Ok(SynthSink {
+            engine,
+            backend,
+            stop_sender,
+        })
+    }
+}
+
+#[cfg(feature = "uses_rodio")]
+impl MidiSink for SynthSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.engine
+            .lock()
+            .map_err(|_| "Synth engine lock poisoned")?
+            .handle_message(msg);
+        Ok(())
+    }
+
+    fn backend(&self) -> OutputBackend {
+        self.backend.clone()
+    }
+}
+
+#[cfg(feature = "uses_rodio")]
+impl Drop for SynthSink {
+    fn drop(&mut self) {
+        let _ = self.stop_sender.send(());
+    }
+}
+
+/// Endless rodio source rendering blocks from a shared engine
+#[cfg(feature = "uses_rodio")]
+struct EngineSource {
+    engine: SharedEngine,
+    sample_rate: u32,
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+#[cfg(feature = "uses_rodio")]
+impl EngineSource {
+    /// Frames rendered per engine lock
+    const BLOCK_FRAMES: usize = 256;
+
+    fn new(engine: SharedEngine) -> Self {
+        let sample_rate = engine.lock().map(|e| e.sample_rate()).unwrap_or(44_100);
+        EngineSource {
+            engine,
+            sample_rate,
+            buffer: vec![0.0; Self::BLOCK_FRAMES * 2],
+            position: Self::BLOCK_FRAMES * 2,
+        }
+    }
+}
+
+#[cfg(feature = "uses_rodio")]
+impl Iterator for EngineSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position >= self.buffer.len() {
+            match self.engine.lock() {
+                Ok(mut engine) => engine.render(&mut self.buffer),
+                Err(_) => return None,
+            }
+            self.position = 0;
+        }
+        let sample = self.buffer[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+#[cfg(feature = "uses_rodio")]
+impl rodio::Source for EngineSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> rodio::ChannelCount {
+        2
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
+    }
+}
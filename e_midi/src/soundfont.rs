@@ -0,0 +1,932 @@
+//! SoundFont 2 (SF2) sample-playback synthesizer.
+//!
+//! `SoundFont::load` parses the RIFF structure and flattens every preset into
+//! ready-to-play regions; `SoundFontSynth` implements `SynthEngine`, so it can be
+//! used both for offline rendering and behind a realtime `SynthSink`.
+
+use crate::synth::SynthEngine;
+use std::error::Error;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAX_VOICES: usize = 128;
+const DRUM_CHANNEL: u8 = 9;
+const DRUM_BANK: u16 = 128;
+/// Envelope attenuation (centibels) treated as silence
+const SILENT_CB: f32 = 960.0;
+
+// Generator operators used by the synth (SF2.04 section 8.1.2)
+const GEN_START_OFFSET: usize = 0;
+const GEN_END_OFFSET: usize = 1;
+const GEN_LOOP_START_OFFSET: usize = 2;
+const GEN_LOOP_END_OFFSET: usize = 3;
+const GEN_START_COARSE: usize = 4;
+const GEN_FILTER_FC: usize = 8;
+const GEN_END_COARSE: usize = 12;
+const GEN_PAN: usize = 17;
+const GEN_DELAY_VOL_ENV: usize = 33;
+const GEN_ATTACK_VOL_ENV: usize = 34;
+const GEN_HOLD_VOL_ENV: usize = 35;
+const GEN_DECAY_VOL_ENV: usize = 36;
+const GEN_SUSTAIN_VOL_ENV: usize = 37;
+const GEN_RELEASE_VOL_ENV: usize = 38;
+const GEN_KEY_TO_HOLD: usize = 39;
+const GEN_KEY_TO_DECAY: usize = 40;
+const GEN_INSTRUMENT: usize = 41;
+const GEN_KEY_RANGE: usize = 43;
+const GEN_VEL_RANGE: usize = 44;
+const GEN_LOOP_START_COARSE: usize = 45;
+const GEN_KEYNUM: usize = 46;
+const GEN_VELOCITY: usize = 47;
+const GEN_ATTENUATION: usize = 48;
+const GEN_LOOP_END_COARSE: usize = 50;
+const GEN_COARSE_TUNE: usize = 51;
+const GEN_FINE_TUNE: usize = 52;
+const GEN_SAMPLE_ID: usize = 53;
+const GEN_SAMPLE_MODES: usize = 54;
+const GEN_SCALE_TUNING: usize = 56;
+const GEN_EXCLUSIVE_CLASS: usize = 57;
+const GEN_ROOT_KEY: usize = 58;
+const GEN_COUNT: usize = 61;
+
+/// Generators that may not appear at preset level (spec 8.1.3)
+const INSTRUMENT_ONLY: [usize; 12] = [
+    GEN_START_OFFSET,
+    GEN_END_OFFSET,
+    GEN_LOOP_START_OFFSET,
+    GEN_LOOP_END_OFFSET,
+    GEN_START_COARSE,
+    GEN_END_COARSE,
+    GEN_LOOP_START_COARSE,
+    GEN_KEYNUM,
+    GEN_VELOCITY,
+    GEN_LOOP_END_COARSE,
+    GEN_SAMPLE_MODES,
+    GEN_EXCLUSIVE_CLASS,
+];
+
+fn default_generators() -> [i32; GEN_COUNT] {
+    let mut gens = [0i32; GEN_COUNT];
+    gens[GEN_FILTER_FC] = 13_500;
+    for op in [
+        GEN_DELAY_VOL_ENV,
+        GEN_ATTACK_VOL_ENV,
+        GEN_HOLD_VOL_ENV,
+        GEN_DECAY_VOL_ENV,
+        GEN_RELEASE_VOL_ENV,
+    ] {
+        gens[op] = -12_000;
+    }
+    gens[GEN_KEYNUM] = -1;
+    gens[GEN_VELOCITY] = -1;
+    gens[GEN_SCALE_TUNING] = 100;
+    gens[GEN_ROOT_KEY] = -1;
+    gens
+}
+
+/// Sample header from the `shdr` chunk
+#[derive(Debug, Clone)]
+struct SampleHeader {
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    pitch_correction: i8,
+}
+
+/// A fully resolved preset zone × instrument zone
+#[derive(Debug, Clone)]
+struct Region {
+    key_range: (u8, u8),
+    vel_range: (u8, u8),
+    sample: usize,
+    gens: [i32; GEN_COUNT],
+}
+
+/// One playable preset (bank/program)
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub bank: u16,
+    pub program: u8,
+    regions: Vec<Region>,
+}
+
+/// Parsed SoundFont: sample data plus flattened presets
+#[derive(Debug)]
+pub struct SoundFont {
+    pub name: String,
+    samples: Vec<i16>,
+    headers: Vec<SampleHeader>,
+    presets: Vec<Preset>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Zone {
+    gen_start: usize,
+    gen_end: usize,
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn read_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+/// RIFF chunk id and body
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Split `data` into its RIFF sub-chunks
+fn chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let id = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        let len = read_u32(data, pos + 4) as usize;
+        let body_start = pos + 8;
+        let body_end = body_start
+            .checked_add(len)
+            .filter(|&end| end <= data.len())
+            .ok_or("Truncated SoundFont chunk")?;
+        out.push((id, &data[body_start..body_end]));
+        // Chunks are padded to an even length
+        pos = body_end + (len & 1);
+    }
+    Ok(out)
+}
+
+/// Split a `pbag`/`ibag` table into zones referencing generator ranges
+fn zones(bags: &[u8], first: usize, last: usize, gen_count: usize) -> Vec<Zone> {
+    (first..last)
+        .filter(|&i| (i + 1) * 4 + 2 <= bags.len())
+        .map(|i| Zone {
+            gen_start: (read_u16(bags, i * 4) as usize).min(gen_count),
+            gen_end: (read_u16(bags, (i + 1) * 4) as usize).min(gen_count),
+        })
+        .collect()
+}
+
+/// Generators of a zone as `(operator, amount)` pairs
+fn zone_generators(gens: &[u8], zone: Zone) -> Vec<(usize, u16)> {
+    (zone.gen_start..zone.gen_end.max(zone.gen_start))
+        .map(|i| (read_u16(gens, i * 4) as usize, read_u16(gens, i * 4 + 2)))
+        .collect()
+}
+
+fn range_of(amount: u16) -> (u8, u8) {
+    ((amount & 0xFF) as u8, (amount >> 8) as u8)
+}
+
+fn intersect(a: (u8, u8), b: (u8, u8)) -> Option<(u8, u8)> {
+    let lo = a.0.max(b.0);
+    let hi = a.1.min(b.1);
+    (lo <= hi).then_some((lo, hi))
+}
+
+impl SoundFont {
+    /// Load and parse an `.sf2` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read(path.as_ref())
+            .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+        Self::from_bytes(&data)
+    }
+
+    /// Parse SoundFont data already in memory
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"sfbk" {
+            return Err("Not a SoundFont 2 file (missing RIFF/sfbk header)".into());
+        }
+        let riff_len = (read_u32(data, 4) as usize + 8).clamp(12, data.len());
+
+        let mut name = String::new();
+        let mut smpl: &[u8] = &[];
+        let mut pdta: Vec<Chunk> = Vec::new();
+        for (id, body) in chunks(&data[12..riff_len])? {
+            if &id != b"LIST" || body.len() < 4 {
+                continue;
+            }
+            let list_type = &body[0..4];
+            let sub = chunks(&body[4..])?;
+            match list_type {
+                b"INFO" => {
+                    if let Some((_, inam)) = sub.iter().find(|(id, _)| id == b"INAM") {
+                        name = read_name(inam);
+                    }
+                }
+                b"sdta" => {
+                    if let Some((_, body)) = sub.iter().find(|(id, _)| id == b"smpl") {
+                        smpl = body;
+                    }
+                }
+                b"pdta" => pdta = sub,
+                _ => {}
+            }
+        }
+        let table = |tag: &[u8; 4]| -> Result<&[u8], Box<dyn Error>> {
+            pdta.iter()
+                .find(|(id, _)| id == tag)
+                .map(|(_, body)| *body)
+                .ok_or_else(|| {
+                    format!(
+                        "SoundFont is missing the {} chunk",
+                        String::from_utf8_lossy(tag)
+                    )
+                    .into()
+                })
+        };
+        let (phdr, pbag, pgen) = (table(b"phdr")?, table(b"pbag")?, table(b"pgen")?);
+        let (inst, ibag, igen, shdr) = (
+            table(b"inst")?,
+            table(b"ibag")?,
+            table(b"igen")?,
+            table(b"shdr")?,
+        );
+        if smpl.is_empty() {
+            return Err("SoundFont has no sample data".into());
+        }
+
+        let samples: Vec<i16> = smpl
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        let headers: Vec<SampleHeader> = shdr
+            .chunks_exact(46)
+            .map(|h| SampleHeader {
+                start: read_u32(h, 20),
+                end: read_u32(h, 24),
+                loop_start: read_u32(h, 28),
+                loop_end: read_u32(h, 32),
+                sample_rate: read_u32(h, 36).max(1),
+                original_pitch: h[40],
+                pitch_correction: h[41] as i8,
+            })
+            .collect();
+
+        let pgen_count = pgen.len() / 4;
+        let igen_count = igen.len() / 4;
+        let inst_count = inst.len() / 22;
+
+        // Resolve instrument zones once: (key range, vel range, sample, generators)
+        let mut instruments: Vec<Vec<Region>> = Vec::with_capacity(inst_count);
+        for i in 0..inst_count.saturating_sub(1) {
+            let first = read_u16(inst, i * 22 + 20) as usize;
+            let last = read_u16(inst, (i + 1) * 22 + 20) as usize;
+            let mut global = default_generators();
+            let mut global_keys = (0u8, 127u8);
+            let mut global_vels = (0u8, 127u8);
+            let mut regions = Vec::new();
+            for (z, zone) in zones(ibag, first, last, igen_count).into_iter().enumerate() {
+                let gens = zone_generators(igen, zone);
+                let sample = gens
+                    .iter()
+                    .find(|(op, _)| *op == GEN_SAMPLE_ID)
+                    .map(|(_, amount)| *amount as usize);
+                let (mut values, mut keys, mut vels) = (global, global_keys, global_vels);
+                for &(op, amount) in &gens {
+                    match op {
+                        GEN_KEY_RANGE => keys = range_of(amount),
+                        GEN_VEL_RANGE => vels = range_of(amount),
+                        op if op < GEN_COUNT => values[op] = amount as i16 as i32,
+                        _ => {}
+                    }
+                }
+                match sample {
+                    Some(sample) if sample < headers.len() => regions.push(Region {
+                        key_range: keys,
+                        vel_range: vels,
+                        sample,
+                        gens: values,
+                    }),
+                    // A first zone without a sample is the global zone
+                    None if z == 0 => {
+                        global = values;
+                        global_keys = keys;
+                        global_vels = vels;
+                    }
+                    _ => {}
+                }
+            }
+            instruments.push(regions);
+        }
+
+        let mut presets = Vec::new();
+        let preset_count = phdr.len() / 38;
+        for p in 0..preset_count.saturating_sub(1) {
+            let h = &phdr[p * 38..(p + 1) * 38];
+            let first = read_u16(h, 24) as usize;
+            let last = read_u16(phdr, (p + 1) * 38 + 24) as usize;
+            let mut global: Vec<(usize, u16)> = Vec::new();
+            let mut regions = Vec::new();
+            for (z, zone) in zones(pbag, first, last, pgen_count).into_iter().enumerate() {
+                let gens = zone_generators(pgen, zone);
+                let Some(instrument) = gens
+                    .iter()
+                    .find(|(op, _)| *op == GEN_INSTRUMENT)
+                    .map(|(_, amount)| *amount as usize)
+                else {
+                    if z == 0 {
+                        global = gens;
+                    }
+                    continue;
+                };
+                // Local preset generators override the global zone, then add to the instrument
+                let mut keys = (0u8, 127u8);
+                let mut vels = (0u8, 127u8);
+                let mut offsets = [0i32; GEN_COUNT];
+                for &(op, amount) in global.iter().chain(gens.iter()) {
+                    match op {
+                        GEN_KEY_RANGE => keys = range_of(amount),
+                        GEN_VEL_RANGE => vels = range_of(amount),
+                        op if op < GEN_COUNT && !INSTRUMENT_ONLY.contains(&op) => {
+                            offsets[op] = amount as i16 as i32
+                        }
+                        _ => {}
+                    }
+                }
+                let Some(inst_regions) = instruments.get(instrument) else {
+                    continue;
+                };
+                for region in inst_regions {
+                    let (Some(key_range), Some(vel_range)) = (
+                        intersect(keys, region.key_range),
+                        intersect(vels, region.vel_range),
+                    ) else {
+                        continue;
+                    };
+                    let mut gens = region.gens;
+                    for (op, offset) in offsets.iter().enumerate() {
+                        if !matches!(op, GEN_INSTRUMENT | GEN_SAMPLE_ID) {
+                            gens[op] += offset;
+                        }
+                    }
+                    regions.push(Region {
+                        key_range,
+                        vel_range,
+                        sample: region.sample,
+                        gens,
+                    });
+                }
+            }
+            presets.push(Preset {
+                name: read_name(&h[0..20]),
+                program: read_u16(h, 20).min(127) as u8,
+                bank: read_u16(h, 22),
+                regions,
+            });
+        }
+        if presets.is_empty() {
+            return Err("SoundFont contains no presets".into());
+        }
+
+        Ok(SoundFont {
+            name,
+            samples,
+            headers,
+            presets,
+        })
+    }
+
+    /// All presets in file order
+    pub fn presets(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    /// Find the preset for a bank/program, falling back to bank 0 and then the first preset
+    fn find_preset(&self, bank: u16, program: u8) -> Option<&Preset> {
+        let exact = |bank: u16| {
+            self.presets
+                .iter()
+                .find(|p| p.bank == bank && p.program == program)
+        };
+        exact(bank)
+            .or_else(|| {
+                if bank == DRUM_BANK {
+                    self.presets
+                        .iter()
+                        .find(|p| p.bank == DRUM_BANK && p.program == 0)
+                } else {
+                    exact(0)
+                }
+            })
+            .or_else(|| self.presets.first())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sf2Channel {
+    program: u8,
+    bank: u16,
+    volume: u8,
+    expression: u8,
+    pan: u8,
+    sustain: bool,
+    bend: f32,
+    bend_range: f32,
+    rpn: (u8, u8),
+}
+
+impl Default for Sf2Channel {
+    fn default() -> Self {
+        Sf2Channel {
+            program: 0,
+            bank: 0,
+            volume: 100,
+            expression: 127,
+            pan: 64,
+            sustain: false,
+            bend: 0.0,
+            bend_range: 2.0,
+            rpn: (127, 127),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EnvStage {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+fn timecents_to_secs(tc: i32) -> f32 {
+    2f32.powf(tc.max(-12_000) as f32 / 1200.0)
+}
+
+#[derive(Debug, Clone)]
+struct SfVoice {
+    channel: u8,
+    key: u8,
+    exclusive_class: i32,
+    held: bool,
+    sustained: bool,
+    age: u64,
+    // Sample playback
+    sample_rate: f32,
+    position: f64,
+    /// Pitch ratio before pitch bend
+    base_semitones: f32,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    looping: bool,
+    loop_until_release: bool,
+    // Level
+    gain: f32,
+    pan: f32,
+    cutoff_alpha: Option<f32>,
+    lowpass: f32,
+    // Volume envelope
+    stage: EnvStage,
+    stage_time: f32,
+    delay: f32,
+    attack: f32,
+    hold: f32,
+    decay: f32,
+    sustain_cb: f32,
+    release: f32,
+    env_cb: f32,
+    env_amp: f32,
+}
+
+impl SfVoice {
+    fn release(&mut self) {
+        if self.env_amp < 1.0 && matches!(self.stage, EnvStage::Delay | EnvStage::Attack) {
+            self.env_cb = -200.0 * self.env_amp.max(1e-5).log10();
+        }
+        self.stage = EnvStage::Release;
+        if self.loop_until_release {
+            self.looping = false;
+        }
+    }
+
+    fn advance_envelope(&mut self, dt: f32) {
+        self.stage_time += dt;
+        match self.stage {
+            EnvStage::Delay => {
+                self.env_amp = 0.0;
+                if self.stage_time >= self.delay {
+                    self.stage = EnvStage::Attack;
+                    self.stage_time = 0.0;
+                }
+            }
+            EnvStage::Attack => {
+                self.env_amp = (self.stage_time / self.attack).min(1.0);
+                self.env_cb = 0.0;
+                if self.stage_time >= self.attack {
+                    self.stage = EnvStage::Hold;
+                    self.stage_time = 0.0;
+                }
+            }
+            EnvStage::Hold => {
+                self.env_amp = 1.0;
+                if self.stage_time >= self.hold {
+                    self.stage = EnvStage::Decay;
+                    self.stage_time = 0.0;
+                }
+            }
+            EnvStage::Decay => {
+                // Decay time is the time for a full 96 dB change
+                self.env_cb += SILENT_CB * dt / self.decay;
+                if self.env_cb >= self.sustain_cb {
+                    self.env_cb = self.sustain_cb;
+                    self.stage = EnvStage::Sustain;
+                }
+                self.env_amp = 10f32.powf(-self.env_cb / 200.0);
+            }
+            EnvStage::Sustain => {
+                if self.env_cb >= SILENT_CB {
+                    self.stage = EnvStage::Done;
+                }
+            }
+            EnvStage::Release => {
+                self.env_cb += SILENT_CB * dt / self.release;
+                self.env_amp = 10f32.powf(-self.env_cb / 200.0);
+                if self.env_cb >= SILENT_CB {
+                    self.stage = EnvStage::Done;
+                }
+            }
+            EnvStage::Done => self.env_amp = 0.0,
+        }
+    }
+}
+
+/// Realtime-capable SF2 synthesizer
+pub struct SoundFontSynth {
+    font: Arc<SoundFont>,
+    sample_rate: u32,
+    channels: [Sf2Channel; 16],
+    voices: Vec<SfVoice>,
+    voice_counter: u64,
+    master_gain: f32,
+}
+
+impl SoundFontSynth {
+    pub fn new(font: Arc<SoundFont>, sample_rate: u32) -> Self {
+        SoundFontSynth {
+            font,
+            sample_rate: sample_rate.max(8000),
+            channels: [Sf2Channel::default(); 16],
+            voices: Vec::with_capacity(MAX_VOICES),
+            voice_counter: 0,
+            master_gain: 0.5,
+        }
+    }
+
+    /// Load `path` and create a synth for it
+    pub fn from_file<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::new(Arc::new(SoundFont::load(path)?), sample_rate))
+    }
+
+    /// Number of voices currently sounding
+    pub fn active_voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        self.note_off(channel, key);
+        let state = self.channels[channel as usize];
+        let bank = if channel == DRUM_CHANNEL {
+            DRUM_BANK
+        } else {
+            state.bank
+        };
+        let font = Arc::clone(&self.font);
+        let Some(preset) = font.find_preset(bank, state.program) else {
+            return;
+        };
+        for region in preset.regions.iter().filter(|r| {
+            (r.key_range.0..=r.key_range.1).contains(&key)
+                && (r.vel_range.0..=r.vel_range.1).contains(&velocity)
+        }) {
+            let gens = &region.gens;
+            let header = &font.headers[region.sample];
+            let exclusive_class = gens[GEN_EXCLUSIVE_CLASS];
+            if exclusive_class != 0 {
+                for v in self
+                    .voices
+                    .iter_mut()
+                    .filter(|v| v.channel == channel && v.exclusive_class == exclusive_class)
+                {
+                    v.stage = EnvStage::Done;
+                }
+            }
+            if self.voices.len() >= MAX_VOICES {
+                if let Some(oldest) = self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, v)| v.age)
+                    .map(|(i, _)| i)
+                {
+                    self.voices.swap_remove(oldest);
+                }
+            }
+
+            let total = font.samples.len() as i64;
+            let addr = |base: u32, fine: usize, coarse: usize| -> usize {
+                (base as i64 + gens[fine] as i64 + gens[coarse] as i64 * 32_768).clamp(0, total)
+                    as usize
+            };
+            let start = addr(header.start, GEN_START_OFFSET, GEN_START_COARSE);
+            let end = addr(header.end, GEN_END_OFFSET, GEN_END_COARSE).max(start);
+            let loop_start = addr(
+                header.loop_start,
+                GEN_LOOP_START_OFFSET,
+                GEN_LOOP_START_COARSE,
+            )
+            .clamp(start, end);
+            let loop_end =
+                addr(header.loop_end, GEN_LOOP_END_OFFSET, GEN_LOOP_END_COARSE).clamp(start, end);
+            let sample_modes = gens[GEN_SAMPLE_MODES] & 3;
+
+            let pitch_key = if gens[GEN_KEYNUM] >= 0 {
+                gens[GEN_KEYNUM]
+            } else {
+                key as i32
+            };
+            let vel = if gens[GEN_VELOCITY] >= 0 {
+                gens[GEN_VELOCITY] as f32
+            } else {
+                velocity as f32
+            };
+            let root = if gens[GEN_ROOT_KEY] >= 0 {
+                gens[GEN_ROOT_KEY]
+            } else if header.original_pitch <= 127 {
+                header.original_pitch as i32
+            } else {
+                60
+            };
+            let base_semitones = (pitch_key - root) as f32 * gens[GEN_SCALE_TUNING] as f32 / 100.0
+                + gens[GEN_COARSE_TUNE] as f32
+                + (gens[GEN_FINE_TUNE] + header.pitch_correction as i32) as f32 / 100.0;
+
+            // Initial attenuation in centibels plus a concave velocity curve
+            let attenuation = 10f32.powf(-(gens[GEN_ATTENUATION].max(0) as f32) / 200.0);
+            let vel_gain = (vel / 127.0).powi(2);
+            let cutoff_alpha = (gens[GEN_FILTER_FC] < 13_500).then(|| {
+                let hz = 8.176 * 2f32.powf(gens[GEN_FILTER_FC] as f32 / 1200.0);
+                let hz = hz.min(self.sample_rate as f32 * 0.45);
+                1.0 - (-2.0 * PI * hz / self.sample_rate as f32).exp()
+            });
+            let key_offset = 60 - key as i32;
+
+            self.voice_counter += 1;
+            self.voices.push(SfVoice {
+                channel,
+                key,
+                exclusive_class,
+                held: true,
+                sustained: false,
+                age: self.voice_counter,
+                sample_rate: header.sample_rate as f32,
+                position: start as f64,
+                base_semitones,
+                end,
+                loop_start,
+                loop_end,
+                looping: (sample_modes == 1 || sample_modes == 3) && loop_end > loop_start + 1,
+                loop_until_release: sample_modes == 3,
+                gain: attenuation * vel_gain,
+                pan: gens[GEN_PAN].clamp(-500, 500) as f32 / 1000.0,
+                cutoff_alpha,
+                lowpass: 0.0,
+                stage: EnvStage::Delay,
+                stage_time: 0.0,
+                delay: timecents_to_secs(gens[GEN_DELAY_VOL_ENV]),
+                attack: timecents_to_secs(gens[GEN_ATTACK_VOL_ENV]),
+                hold: timecents_to_secs(
+                    gens[GEN_HOLD_VOL_ENV] + gens[GEN_KEY_TO_HOLD] * key_offset,
+                ),
+                decay: timecents_to_secs(
+                    gens[GEN_DECAY_VOL_ENV] + gens[GEN_KEY_TO_DECAY] * key_offset,
+                ),
+                sustain_cb: gens[GEN_SUSTAIN_VOL_ENV].clamp(0, 1440) as f32,
+                release: timecents_to_secs(gens[GEN_RELEASE_VOL_ENV]),
+                env_cb: 0.0,
+                env_amp: 0.0,
+            });
+        }
+    }
+
+    fn note_off(&mut self, channel: u8, key: u8) {
+        let sustain = self.channels[channel as usize].sustain;
+        for v in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.channel == channel && v.key == key && v.held)
+        {
+            v.held = false;
+            if sustain {
+                v.sustained = true;
+            } else {
+                v.release();
+            }
+        }
+    }
+
+    fn release_sustained(&mut self, channel: u8) {
+        for v in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.channel == channel && v.sustained)
+        {
+            v.sustained = false;
+            v.release();
+        }
+    }
+
+    fn control_change(&mut self, channel: u8, controller: u8, value: u8) {
+        let state = &mut self.channels[channel as usize];
+        match controller {
+            0 => state.bank = value as u16,
+            6 if state.rpn == (0, 0) => state.bend_range = value as f32,
+            7 => state.volume = value,
+            10 => state.pan = value,
+            11 => state.expression = value,
+            64 => {
+                state.sustain = value >= 64;
+                if value < 64 {
+                    self.release_sustained(channel);
+                }
+            }
+            100 => state.rpn.1 = value,
+            101 => state.rpn.0 = value,
+            120 => self.voices.retain(|v| v.channel != channel),
+            121 => {
+                *state = Sf2Channel {
+                    program: state.program,
+                    bank: state.bank,
+                    ..Sf2Channel::default()
+                };
+                self.release_sustained(channel);
+            }
+            123 => {
+                state.sustain = false;
+                for v in self
+                    .voices
+                    .iter_mut()
+                    .filter(|v| v.channel == channel && (v.held || v.sustained))
+                {
+                    v.held = false;
+                    v.sustained = false;
+                    v.release();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SynthEngine for SoundFontSynth {
+    fn handle_message(&mut self, msg: &[u8]) {
+        let Some(&status) = msg.first() else {
+            return;
+        };
+        let channel = status & 0x0F;
+        match (status & 0xF0, msg.get(1).copied(), msg.get(2).copied()) {
+            (0x90, Some(key), Some(vel)) if vel > 0 => {
+                self.note_on(channel, key & 0x7F, vel & 0x7F)
+            }
+            (0x80, Some(key), _) | (0x90, Some(key), Some(_)) => self.note_off(channel, key & 0x7F),
+            (0xB0, Some(controller), Some(value)) => {
+                self.control_change(channel, controller & 0x7F, value & 0x7F)
+            }
+            (0xC0, Some(program), _) => self.channels[channel as usize].program = program & 0x7F,
+            (0xE0, Some(lsb), Some(msb)) => {
+                let bend = (((msb as i32 & 0x7F) << 7) | (lsb as i32 & 0x7F)) - 8192;
+                let state = &mut self.channels[channel as usize];
+                state.bend = bend as f32 / 8192.0 * state.bend_range;
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let out_rate = self.sample_rate as f32;
+        let dt = 1.0 / out_rate;
+        let samples = &self.font.samples;
+        let channels = self.channels;
+        out.iter_mut().for_each(|s| *s = 0.0);
+        for v in self.voices.iter_mut() {
+            let state = channels[v.channel as usize];
+            let step = 2f64.powf(((v.base_semitones + state.bend) / 12.0) as f64)
+                * (v.sample_rate / out_rate) as f64;
+            // CC7 and CC11 follow the default SF2 concave curves (approximated as squares)
+            let channel_gain =
+                (state.volume as f32 / 127.0).powi(2) * (state.expression as f32 / 127.0).powi(2);
+            let pan = (v.pan + (state.pan as f32 - 64.0) / 127.0).clamp(-0.5, 0.5) + 0.5;
+            let (left_gain, right_gain) = ((pan * PI / 2.0).cos(), (pan * PI / 2.0).sin());
+            for frame in out.chunks_mut(2) {
+                v.advance_envelope(dt);
+                if v.stage == EnvStage::Done {
+                    break;
+                }
+                if v.looping && v.position >= v.loop_end as f64 {
+                    // A high note can step over the whole loop in one frame
+                    let loop_len = (v.loop_end - v.loop_start) as f64;
+                    v.position =
+                        v.loop_start as f64 + (v.position - v.loop_start as f64) % loop_len;
+                }
+                let index = v.position as usize;
+                if !v.looping && index + 1 >= v.end {
+                    v.stage = EnvStage::Done;
+                    break;
+                }
+                let frac = (v.position - index as f64) as f32;
+                let next_index = if v.looping && index + 1 >= v.loop_end {
+                    v.loop_start
+                } else {
+                    index + 1
+                };
+                let (Some(&current), Some(&next)) = (samples.get(index), samples.get(next_index))
+                else {
+                    v.stage = EnvStage::Done;
+                    break;
+                };
+                let mut sample = (current as f32 * (1.0 - frac) + next as f32 * frac) / 32_768.0;
+                if let Some(alpha) = v.cutoff_alpha {
+                    v.lowpass += alpha * (sample - v.lowpass);
+                    sample = v.lowpass;
+                }
+                let sample = sample * v.gain * v.env_amp * channel_gain;
+                frame[0] += sample * left_gain;
+                if let Some(r) = frame.get_mut(1) {
+                    *r += sample * right_gain;
+                }
+                v.position += step;
+            }
+        }
+        let gain = self.master_gain;
+        for s in out.iter_mut() {
+            *s = (*s * gain).tanh();
+        }
+        self.voices.retain(|v| v.stage != EnvStage::Done);
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One-region font around a looped sample of `len` frames
+    fn looped_font(len: usize, loop_start: u32) -> SoundFont {
+        let mut gens = default_generators();
+        gens[GEN_SAMPLE_MODES] = 1;
+        SoundFont {
+            name: "test".to_string(),
+            samples: (0..len).map(|i| (i as i16 - 4) * 1_000).collect(),
+            headers: vec![SampleHeader {
+                start: 0,
+                end: len as u32,
+                loop_start,
+                loop_end: len as u32,
+                sample_rate: 44_100,
+                original_pitch: 0,
+                pitch_correction: 0,
+            }],
+            presets: vec![Preset {
+                name: "looped".to_string(),
+                bank: 0,
+                program: 0,
+                regions: vec![Region {
+                    key_range: (0, 127),
+                    vel_range: (0, 127),
+                    sample: 0,
+                    gens,
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn short_loop_at_high_pitch_stays_in_bounds() {
+        // Key 127 over root 0 steps thousands of frames past a 4-frame loop
+        let mut synth = SoundFontSynth::new(Arc::new(looped_font(8, 4)), 44_100);
+        synth.handle_message(&[0x90, 127, 127]);
+        synth.handle_message(&[0xE0, 0x7F, 0x7F]);
+        let mut out = vec![0.0f32; 2 * 1024];
+        for _ in 0..4 {
+            synth.render(&mut out);
+            assert!(out.iter().all(|s| s.is_finite()));
+        }
+        assert_eq!(synth.active_voices(), 1);
+    }
+}
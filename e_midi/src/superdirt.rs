@@ -0,0 +1,487 @@
+//! SuperDirt output: play songs through SuperDirt samples and synths.
+//!
+//! `DirtSink` turns each note into a `/dirt/play` OSC message (`s`, `n`, `note`,
+//! `gain`, `sustain`, `orbit`, `cps`) wrapped in a bundle timestamped `latency` ahead,
+//! the same way Tidal schedules events. A note is sent when its note-off
+//! arrives, so `sustain` is exact for notes shorter than the latency; longer
+//! notes are sent just before their deadline with a sustain estimate.
+
+use crate::sink::{MidiSink, OutputBackend};
+use crate::MidiPlayer;
+use rosc::{OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Where SuperDirt listens by default
+pub const DEFAULT_SUPERDIRT_ADDR: &str = "127.0.0.1:57120";
+
+/// Default scheduling latency, enough to absorb jitter on localhost
+pub const DEFAULT_LATENCY: Duration = Duration::from_millis(200);
+
+/// Tidal's default tempo in cycles per second
+pub const DEFAULT_CPS: f32 = 0.5625;
+
+/// Pending notes are sent this long before their deadline
+const SEND_MARGIN: Duration = Duration::from_millis(20);
+
+/// How often pending notes are checked against their deadline
+const FLUSH_INTERVAL: Duration = Duration::from_millis(5);
+
+/// SuperDirt synth per General MIDI instrument family (program / 8)
+const FAMILY_SOUNDS: [&str; 16] = [
+    "superpiano",    // piano
+    "supervibe",     // chromatic percussion
+    "superhammond",  // organ
+    "supermandolin", // guitar
+    "superfm",       // bass
+    "supersaw",      // strings
+    "supersaw",      // ensemble
+    "superpwm",      // brass
+    "superpwm",      // reed
+    "superfork",     // pipe
+    "supersquare",   // synth lead
+    "superhoover",   // synth pad
+    "superstatic",   // synth effects
+    "supermandolin", // ethnic
+    "supergong",     // percussive
+    "supernoise",    // sound effects
+];
+
+/// Dirt-Samples bank per General MIDI drum key
+const DRUM_SOUNDS: [(u8, &str, i32); 24] = [
+    (35, "bd", 1),
+    (36, "bd", 0),
+    (37, "rm", 0),
+    (38, "sn", 0),
+    (39, "cp", 0),
+    (40, "sn", 1),
+    (41, "lt", 0),
+    (42, "hh", 0),
+    (43, "lt", 1),
+    (44, "hh", 1),
+    (45, "mt", 0),
+    (46, "oh", 0),
+    (47, "mt", 1),
+    (48, "ht", 0),
+    (49, "cr", 0),
+    (50, "ht", 1),
+    (51, "cr", 2),
+    (52, "cr", 1),
+    (53, "cr", 3),
+    (54, "perc", 0),
+    (55, "cr", 4),
+    (56, "cb", 0),
+    (57, "cr", 5),
+    (59, "cr", 6),
+];
+
+/// A SuperDirt sound: `s` plus sample/variation number `n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtSound {
+    pub s: String,
+    pub n: i32,
+}
+
+impl DirtSound {
+    pub fn new(s: &str, n: i32) -> Self {
+        DirtSound {
+            s: s.to_string(),
+            n,
+        }
+    }
+}
+
+impl FromStr for DirtSound {
+    type Err = String;
+
+    /// Parse "superpiano" or "drum:3"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, n) = match s.trim().split_once(':') {
+            Some((name, n)) => (
+                name,
+                n.trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid sample number in '{}'", s))?,
+            ),
+            None => (s.trim(), 0),
+        };
+        if name.is_empty() {
+            return Err("Sound name cannot be empty".to_string());
+        }
+        Ok(DirtSound::new(name, n))
+    }
+}
+
+impl fmt::Display for DirtSound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.s, self.n)
+    }
+}
+
+/// Which SuperDirt sound and orbit each note goes to
+///
+/// Channels are zero-based. Lookup order for pitched notes is channel, then
+/// program, then the General MIDI family default; notes on the drum channel
+/// use the per-key drum map.
+#[derive(Debug, Clone)]
+pub struct DirtMapping {
+    pub channels: HashMap<u8, DirtSound>,
+    pub programs: HashMap<u8, DirtSound>,
+    pub drums: HashMap<u8, DirtSound>,
+    /// Channel treated as General MIDI drums (None to play it pitched)
+    pub drum_channel: Option<u8>,
+    /// Drum keys missing from `drums`
+    pub default_drum: DirtSound,
+    pub orbits: HashMap<u8, i32>,
+}
+
+impl Default for DirtMapping {
+    fn default() -> Self {
+        DirtMapping {
+            channels: HashMap::new(),
+            programs: HashMap::new(),
+            drums: DRUM_SOUNDS
+                .iter()
+                .map(|(key, s, n)| (*key, DirtSound::new(s, *n)))
+                .collect(),
+            drum_channel: Some(9),
+            default_drum: DirtSound::new("perc", 0),
+            orbits: HashMap::new(),
+        }
+    }
+}
+
+impl DirtMapping {
+    /// Sound for a note, and whether it is a drum hit (unpitched)
+    pub fn sound_for(&self, channel: u8, program: u8, pitch: u8) -> (DirtSound, bool) {
+        if self.drum_channel == Some(channel) && !self.channels.contains_key(&channel) {
+            let sound = self.drums.get(&pitch).unwrap_or(&self.default_drum);
+            return (sound.clone(), true);
+        }
+        let sound = self
+            .channels
+            .get(&channel)
+            .or_else(|| self.programs.get(&program))
+            .cloned()
+            .unwrap_or_else(|| DirtSound::new(FAMILY_SOUNDS[(program as usize / 8) % 16], 0));
+        (sound, false)
+    }
+
+    pub fn orbit_for(&self, channel: u8) -> i32 {
+        self.orbits.get(&channel).copied().unwrap_or(0)
+    }
+}
+
+/// Settings for `DirtSink`
+#[derive(Debug, Clone)]
+pub struct DirtConfig {
+    /// How far ahead bundles are timestamped
+    pub latency: Duration,
+    /// Sustain in seconds for notes still held at their send deadline
+    pub default_sustain: f32,
+    /// Cycles per second reported to SuperDirt (tempo-relative effects use it)
+    pub cps: f32,
+    pub mapping: DirtMapping,
+}
+
+impl Default for DirtConfig {
+    fn default() -> Self {
+        DirtConfig {
+            latency: DEFAULT_LATENCY,
+            default_sustain: 1.0,
+            cps: DEFAULT_CPS,
+            mapping: DirtMapping::default(),
+        }
+    }
+}
+
+/// `/dirt/play` message for one note
+///
+/// Drum hits carry no `sustain`, so samples are not cut at the note-off.
+/// Velocity maps to gain as `(velocity / 127) ^ 0.25`, which undoes SuperDirt's
+/// `gain ^ 4` amplitude curve so loudness follows velocity linearly.
+pub fn dirt_message(
+    mapping: &DirtMapping,
+    channel: u8,
+    program: u8,
+    pitch: u8,
+    velocity: u8,
+    sustain_secs: f32,
+    cps: f32,
+) -> OscMessage {
+    let (sound, drum) = mapping.sound_for(channel, program, pitch);
+    // SuperDirt notes are semitones relative to middle C
+    let note = if drum { 0.0 } else { pitch as f32 - 60.0 };
+    let mut args = vec![
+        OscType::String("s".into()),
+        OscType::String(sound.s),
+        OscType::String("n".into()),
+        OscType::Float(sound.n as f32),
+        OscType::String("note".into()),
+        OscType::Float(note),
+        OscType::String("gain".into()),
+        OscType::Float((velocity.min(127) as f32 / 127.0).powf(0.25)),
+        OscType::String("orbit".into()),
+        OscType::Int(mapping.orbit_for(channel)),
+        OscType::String("cps".into()),
+        OscType::Float(cps),
+    ];
+    // Drum samples play out in full; a note-off length would cut them short
+    if !drum {
+        args.push(OscType::String("sustain".into()));
+        args.push(OscType::Float(sustain_secs));
+    }
+    OscMessage {
+        addr: "/dirt/play".to_string(),
+        args,
+    }
+}
+
+struct PendingNote {
+    channel: u8,
+    pitch: u8,
+    velocity: u8,
+    on_at: Instant,
+    on_time: SystemTime,
+}
+
+struct DirtState {
+    socket: UdpSocket,
+    target: SocketAddr,
+    config: DirtConfig,
+    programs: [u8; 16],
+    pending: Vec<PendingNote>,
+}
+
+impl DirtState {
+    fn send_note(&self, note: &PendingNote, sustain_secs: f32) {
+        let message = dirt_message(
+            &self.config.mapping,
+            note.channel,
+            self.programs[note.channel as usize],
+            note.pitch,
+            note.velocity,
+            sustain_secs,
+            self.config.cps,
+        );
+        let Ok(timetag) = OscTime::try_from(note.on_time + self.config.latency) else {
+            return;
+        };
+        let packet = OscPacket::Bundle(OscBundle {
+            timetag,
+            content: vec![OscPacket::Message(message)],
+        });
+        match rosc::encoder::encode(&packet) {
+            Ok(bytes) => {
+                let _ = self.socket.send_to(&bytes, self.target);
+            }
+            Err(e) => eprintln!("⚠️  Failed to encode /dirt/play: {}", e),
+        }
+    }
+
+    /// Send and forget the pending note for (channel, pitch), if any
+    fn release(&mut self, channel: u8, pitch: u8) {
+        if let Some(i) = self
+            .pending
+            .iter()
+            .position(|n| n.channel == channel && n.pitch == pitch)
+        {
+            let note = self.pending.remove(i);
+            self.send_note(&note, note.on_at.elapsed().as_secs_f32());
+        }
+    }
+
+    /// Send notes whose bundle would otherwise arrive late
+    fn flush_due(&mut self, all: bool) {
+        let deadline = self.config.latency.saturating_sub(SEND_MARGIN);
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|n| all || n.on_at.elapsed() >= deadline);
+        self.pending = waiting;
+        for note in due {
+            let held = note.on_at.elapsed().as_secs_f32();
+            self.send_note(&note, held.max(self.config.default_sustain));
+        }
+    }
+}
+
+/// MIDI sink playing notes through SuperDirt
+pub struct DirtSink {
+    state: Arc<Mutex<DirtState>>,
+    target: SocketAddr,
+    stop: Arc<AtomicBool>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+impl DirtSink {
+    /// Send to SuperDirt at `addr` (usually `DEFAULT_SUPERDIRT_ADDR`)
+    pub fn new<A: ToSocketAddrs>(addr: A, config: DirtConfig) -> Result<Self, Box<dyn Error>> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or("SuperDirt address did not resolve")?;
+        let bind = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind)?;
+        let state = Arc::new(Mutex::new(DirtState {
+            socket,
+            target,
+            config,
+            programs: [0; 16],
+            pending: Vec::new(),
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let flusher = {
+            let state = Arc::clone(&state);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Ok(mut state) = state.lock() {
+                        state.flush_due(false);
+                    }
+                    thread::sleep(FLUSH_INTERVAL);
+                }
+            })
+        };
+        println!("🥁 Sending /dirt/play to SuperDirt at {}", target);
+        Ok(DirtSink {
+            state,
+            target,
+            stop,
+            flusher: Some(flusher),
+        })
+    }
+}
+
+impl MidiSink for DirtSink {
+    fn send(&mut self, msg: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "SuperDirt sink lock poisoned")?;
+        match *msg {
+            [status, pitch, velocity] if status & 0xF0 == 0x90 && velocity > 0 => {
+                let channel = status & 0x0F;
+                // A retrigger without note-off ends the previous note here
+                state.release(channel, pitch);
+                state.pending.push(PendingNote {
+                    channel,
+                    pitch,
+                    velocity,
+                    on_at: Instant::now(),
+                    on_time: SystemTime::now(),
+                });
+            }
+            [status, pitch, _] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+                state.release(status & 0x0F, pitch);
+            }
+            [status, program] if status & 0xF0 == 0xC0 => {
+                state.programs[(status & 0x0F) as usize] = program & 0x7F;
+            }
+            [status, 120 | 123, _] if status & 0xF0 == 0xB0 => {
+                let channel = status & 0x0F;
+                let notes: Vec<u8> = state
+                    .pending
+                    .iter()
+                    .filter(|n| n.channel == channel)
+                    .map(|n| n.pitch)
+                    .collect();
+                for pitch in notes {
+                    state.release(channel, pitch);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> OutputBackend {
+        OutputBackend::SuperDirt(self.target)
+    }
+}
+
+impl Drop for DirtSink {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.flusher.take() {
+            let _ = handle.join();
+        }
+        if let Ok(mut state) = self.state.lock() {
+            state.flush_due(true);
+        }
+    }
+}
+
+impl MidiPlayer {
+    /// Create a player whose output goes to SuperDirt at `addr`
+    pub fn with_superdirt<A: ToSocketAddrs>(
+        addr: A,
+        config: DirtConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::with_sink(Box::new(DirtSink::new(addr, config)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Named arguments of a `/dirt/play` message
+    fn arg<'a>(message: &'a OscMessage, name: &str) -> Option<&'a OscType> {
+        message
+            .args
+            .chunks(2)
+            .find(|pair| pair[0] == OscType::String(name.to_string()))
+            .map(|pair| &pair[1])
+    }
+
+    #[test]
+    fn sends_dirt_play_bundles_to_a_udp_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let mut config = DirtConfig {
+            latency: Duration::from_millis(50),
+            cps: 0.75,
+            ..DirtConfig::default()
+        };
+        config.mapping.orbits.insert(1, 2);
+        let mut sink = DirtSink::new(listener.local_addr().unwrap(), config).unwrap();
+
+        // Program 0 on channel 2 is a piano; the note-off sends the note
+        sink.send(&[0xC1, 0]).unwrap();
+        sink.send(&[0x91, 64, 127]).unwrap();
+        sink.send(&[0x81, 64, 0]).unwrap();
+
+        let mut buf = [0u8; 4096];
+        let (len, _) = listener.recv_from(&mut buf).unwrap();
+        let (_, packet) = rosc::decoder::decode_udp(&buf[..len]).unwrap();
+        let OscPacket::Bundle(bundle) = packet else {
+            panic!("expected a bundle, got {:?}", packet);
+        };
+        let [OscPacket::Message(message)] = &bundle.content[..] else {
+            panic!("expected one message, got {:?}", bundle.content);
+        };
+        assert_eq!(message.addr, "/dirt/play");
+        assert_eq!(
+            arg(message, "s"),
+            Some(&OscType::String("superpiano".into()))
+        );
+        assert_eq!(arg(message, "n"), Some(&OscType::Float(0.0)));
+        assert_eq!(arg(message, "note"), Some(&OscType::Float(4.0)));
+        assert_eq!(arg(message, "orbit"), Some(&OscType::Int(2)));
+        assert_eq!(arg(message, "cps"), Some(&OscType::Float(0.75)));
+        assert!(matches!(arg(message, "sustain"), Some(OscType::Float(_))));
+    }
+}
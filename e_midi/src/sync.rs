@@ -0,0 +1,377 @@
+//! Heartbeat-synchronized playback across processes.
+//!
+//! Every participating process follows the shared heartbeat clock (one of
+//! them also runs the clock master). A `PlaySongAtHeartbeat` message on the
+//! music sync service tells all followers which song to start on which
+//! heartbeat, when to stop and which voice each track uses. Start and stop
+//! happen on the heartbeat boundary as estimated from the local clock, and a
+//! follower that joins late starts mid-song at the matching position.
+
+use crate::export::{dense_track_index, program_changes};
+use crate::ipc::{
+    self, ClockFollower, HeartbeatClock, HeartbeatClockSubscriber, MusicSyncPublisher,
+    MusicSyncSubscriber,
+};
+use crate::{MidiCommand, MidiPlayer, Note, SongInfo};
+use e_midi_shared::ipc_protocol::{PlaySongAtHeartbeat, TrackVoiceOverride};
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Voice overrides one message can carry
+pub const MAX_TRACK_OVERRIDES: usize = 16;
+/// How long to wait for a heartbeat before giving up on the clock
+const CLOCK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest sleep between polls when nothing is due
+const IDLE_SLEEP: Duration = Duration::from_millis(5);
+/// Sleeping overshoots; spin for the last stretch before a deadline
+const SPIN_WINDOW: Duration = Duration::from_millis(2);
+
+fn now_ms() -> u64 {
+    ipc::unix_micros() / 1000
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    /// Also run the heartbeat clock master
+    pub master: bool,
+    /// Heartbeat interval of the master
+    pub interval: Duration,
+    /// User-facing track numbers this process plays (empty or 0: all)
+    pub tracks: Vec<usize>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            master: false,
+            interval: ipc::DEFAULT_CLOCK_INTERVAL,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+/// Build a message that plays a song from `start_heartbeat`
+///
+/// Overrides are (0-based track position, program) pairs.
+pub fn play_at_heartbeat(
+    song_index: usize,
+    start_heartbeat: u32,
+    stop_heartbeat: Option<u32>,
+    play_for: Option<Duration>,
+    overrides: &[(usize, u8)],
+) -> Result<PlaySongAtHeartbeat, Box<dyn Error>> {
+    if overrides.len() > MAX_TRACK_OVERRIDES {
+        return Err(format!(
+            "At most {} voice overrides are allowed",
+            MAX_TRACK_OVERRIDES
+        )
+        .into());
+    }
+    let mut msg = PlaySongAtHeartbeat {
+        song_index: u16::try_from(song_index)
+            .map_err(|_| format!("Song index {} is out of range", song_index))?,
+        start_heartbeat,
+        stop_heartbeat: stop_heartbeat.unwrap_or(0),
+        play_for_duration_ms: play_for.map_or(0, |d| d.as_millis().min(u32::MAX as u128) as u32),
+        num_track_overrides: overrides.len() as u8,
+        ..PlaySongAtHeartbeat::default()
+    };
+    for (slot, &(track, voice)) in msg.track_overrides.iter_mut().zip(overrides) {
+        *slot = TrackVoiceOverride {
+            track_index: u8::try_from(track)
+                .map_err(|_| format!("Track {} is out of range", track))?,
+            voice: voice & 0x7F,
+            _reserved: [0; 2],
+        };
+    }
+    Ok(msg)
+}
+
+/// Program changes for the notes, with the message's voice overrides applied
+///
+/// An override retunes every channel its track plays on, so tracks sharing a
+/// channel share the voice.
+pub fn voice_setup(song: &SongInfo, notes: &[Note], msg: &PlaySongAtHeartbeat) -> Vec<Vec<u8>> {
+    let mut setup = program_changes(song, notes);
+    let count = (msg.num_track_overrides as usize).min(MAX_TRACK_OVERRIDES);
+    for voice in &msg.track_overrides[..count] {
+        let mut channels = [false; 16];
+        for note in notes {
+            if dense_track_index(song, note.track) == Some(voice.track_index as usize) {
+                channels[(note.chan & 0x0F) as usize] = true;
+            }
+        }
+        for change in setup.iter_mut() {
+            if channels[(change[0] & 0x0F) as usize] {
+                change[1] = voice.voice & 0x7F;
+            }
+        }
+    }
+    setup
+}
+
+/// A song that has been started on a heartbeat
+#[derive(Debug)]
+struct SyncedSong {
+    song_index: usize,
+    stop_heartbeat: Option<u32>,
+    /// Stop from `play_for_duration_ms`, fixed when the song started
+    stop_at: Option<Instant>,
+    ends: Instant,
+}
+
+/// Follows the heartbeat clock and plays the songs scheduled on it
+#[derive(Debug)]
+pub struct SyncPlayer {
+    clock: Option<HeartbeatClock>,
+    heartbeats: HeartbeatClockSubscriber,
+    messages: MusicSyncSubscriber,
+    tracks: Vec<usize>,
+    /// Waiting for its start heartbeat
+    pending: Option<PlaySongAtHeartbeat>,
+    playing: Option<SyncedSong>,
+}
+
+impl SyncPlayer {
+    pub fn new(options: &SyncOptions) -> Result<Self, Box<dyn Error>> {
+        let clock = if options.master {
+            Some(HeartbeatClock::new(options.interval)?)
+        } else {
+            None
+        };
+        Ok(SyncPlayer {
+            clock,
+            heartbeats: HeartbeatClockSubscriber::new()?,
+            messages: MusicSyncSubscriber::new()?,
+            tracks: options.tracks.clone(),
+            pending: None,
+            playing: None,
+        })
+    }
+
+    pub fn clock(&self) -> &ClockFollower {
+        self.heartbeats.follower()
+    }
+
+    /// Take in heartbeats and messages and start or stop what is due
+    ///
+    /// Returns the next moment something is scheduled to happen.
+    pub fn poll(&mut self, player: &MidiPlayer) -> Result<Option<Instant>, Box<dyn Error>> {
+        if let Some(clock) = self.clock.as_mut() {
+            clock.tick()?;
+        }
+        self.heartbeats.poll()?;
+        for msg in self.messages.try_receive()? {
+            self.schedule(player, msg);
+        }
+
+        let now = Instant::now();
+        let mut next = self.clock.as_ref().map(|c| now + c.until_next());
+        if let Some(msg) = self.pending {
+            match self.clock().instant_of(msg.start_heartbeat) {
+                Some(at) if at <= now => {
+                    self.pending = None;
+                    let late = now.duration_since(at).as_millis() as u32;
+                    if let Err(e) = self.start(player, &msg, at, late) {
+                        println!("❌ Synchronized start failed: {}", e);
+                    }
+                }
+                Some(at) => next = Some(next.map_or(at, |n| n.min(at))),
+                // No heartbeat yet; it is checked again on the next poll
+                None => {}
+            }
+        }
+        if let Some(song) = &self.playing {
+            let stop_beat = song
+                .stop_heartbeat
+                .and_then(|beat| self.clock().instant_of(beat));
+            let stop = [stop_beat, song.stop_at]
+                .into_iter()
+                .flatten()
+                .min()
+                .map_or(song.ends, |at| at.min(song.ends));
+            if stop <= now {
+                self.stop(player);
+            } else {
+                next = Some(next.map_or(stop, |n| n.min(stop)));
+            }
+        }
+        Ok(next)
+    }
+
+    /// Stop playback now
+    pub fn stop(&mut self, player: &MidiPlayer) {
+        if let Some(song) = self.playing.take() {
+            let _ = player.command_sender().send(MidiCommand::Stop);
+            println!("⏹️  Stopped song {}", song.song_index);
+            player.publish_midi_event(ipc::Event::MidiPlaybackStopped {
+                timestamp: now_ms(),
+            });
+        }
+    }
+
+    fn schedule(&mut self, player: &MidiPlayer, msg: PlaySongAtHeartbeat) {
+        if msg.start_heartbeat == 0 {
+            let stop = (msg.stop_heartbeat != 0).then_some(msg.stop_heartbeat);
+            if let Some(pending) = self.pending.as_mut() {
+                pending.stop_heartbeat = msg.stop_heartbeat;
+            }
+            match (stop, self.playing.as_mut()) {
+                (Some(beat), Some(song)) => {
+                    song.stop_heartbeat = Some(beat);
+                    println!("🕐 Stop rescheduled for heartbeat {}", beat);
+                }
+                (Some(_), None) => {}
+                (None, _) => {
+                    self.pending = None;
+                    self.stop(player);
+                }
+            }
+            return;
+        }
+        if player.get_song(msg.song_index as usize).is_none() {
+            println!(
+                "⚠️  Ignoring sync message for unknown song {}",
+                msg.song_index
+            );
+            return;
+        }
+        let song_index = msg.song_index;
+        let beat = msg.start_heartbeat;
+        self.pending = Some(msg);
+        match self.clock().current_beat() {
+            Some(now) if beat > now => println!(
+                "🕐 Song {} scheduled for heartbeat {} ({} beats from now)",
+                song_index,
+                beat,
+                beat - now
+            ),
+            _ => println!("🕐 Song {} scheduled for heartbeat {}", song_index, beat),
+        }
+    }
+
+    fn start(
+        &mut self,
+        player: &MidiPlayer,
+        msg: &PlaySongAtHeartbeat,
+        started: Instant,
+        position_ms: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let song_index = msg.song_index as usize;
+        let song = player
+            .get_song(song_index)
+            .ok_or_else(|| format!("Invalid song index {}", song_index))?;
+        let tempo = player.scaled_tempo(song.default_tempo);
+        let dense = MidiPlayer::get_dense_indices_for_song(
+            song,
+            (!self.tracks.is_empty()).then_some(&self.tracks[..]),
+        );
+        let notes = player.get_events_for_song(song_index, &dense, tempo);
+        let duration_ms = crate::calculate_song_duration_ms(&notes);
+        if position_ms >= duration_ms {
+            return Err(format!("Joined too late, song {} is already over", song_index).into());
+        }
+        let setup = voice_setup(song, &notes, msg);
+        let song_name = song.name.clone();
+        // Replacing a song that is still playing
+        self.stop(player);
+        player.command_sender().send(MidiCommand::PlayNotes {
+            song_index,
+            notes,
+            position_ms,
+            setup,
+            beats: player.beat_grid(song_index, tempo),
+        })?;
+        self.playing = Some(SyncedSong {
+            song_index,
+            stop_heartbeat: (msg.stop_heartbeat != 0).then_some(msg.stop_heartbeat),
+            stop_at: (msg.play_for_duration_ms != 0)
+                .then(|| started + Duration::from_millis(msg.play_for_duration_ms as u64)),
+            ends: started + Duration::from_millis(duration_ms as u64),
+        });
+        if position_ms > 0 {
+            println!(
+                "▶️  Playing {} from heartbeat {} (joined {} ms late)",
+                song_name, msg.start_heartbeat, position_ms
+            );
+        } else {
+            println!(
+                "▶️  Playing {} on heartbeat {}",
+                song_name, msg.start_heartbeat
+            );
+        }
+        player.record_play(song_index);
+        player.publish_midi_event(ipc::Event::MidiPlaybackStarted {
+            song_index,
+            song_name,
+            timestamp: now_ms(),
+        });
+        Ok(())
+    }
+}
+
+/// Heartbeat that is `beats` from now on the running clock
+pub fn heartbeat_from_now(beats: u32) -> Result<u32, Box<dyn Error>> {
+    let mut heartbeats = HeartbeatClockSubscriber::new()?;
+    if !heartbeats.wait_for_heartbeat(CLOCK_TIMEOUT)? {
+        return Err(
+            "No heartbeat clock is running (start one with `e_midi sync follow --master`)".into(),
+        );
+    }
+    let now = heartbeats
+        .follower()
+        .current_beat()
+        .ok_or("Heartbeat clock has no current beat")?;
+    Ok(now.saturating_add(beats.max(1)))
+}
+
+/// Publish a message to every follower
+pub fn send_sync_message(msg: &PlaySongAtHeartbeat) -> Result<(), Box<dyn Error>> {
+    MusicSyncPublisher::new()?.publish(msg)?;
+    Ok(())
+}
+
+impl MidiPlayer {
+    /// Play songs scheduled on the heartbeat clock until SIGINT/SIGTERM
+    pub fn run_sync(&mut self, options: SyncOptions) -> Result<(), Box<dyn Error>> {
+        crate::set_graceful_shutdown(true);
+        self.init_ipc_publisher()?;
+        let mut sync = SyncPlayer::new(&options)?;
+        if sync.clock.is_some() {
+            println!(
+                "🫀 Heartbeat clock master running ({} ms interval)",
+                options.interval.as_millis()
+            );
+        }
+        println!("🔗 Following the heartbeat clock; press Ctrl+C to stop");
+
+        let mut synced = false;
+        let result = loop {
+            if crate::should_shutdown() {
+                break Ok(());
+            }
+            let next = match sync.poll(self) {
+                Ok(next) => next,
+                Err(e) => break Err(e),
+            };
+            if !synced && sync.clock().is_synced() {
+                synced = true;
+                println!(
+                    "🫀 Clock locked (offset {} µs)",
+                    sync.clock().offset_us().unwrap_or(0)
+                );
+            }
+            let wait = next.map_or(IDLE_SLEEP, |at| {
+                at.saturating_duration_since(Instant::now()).min(IDLE_SLEEP)
+            });
+            if wait > SPIN_WINDOW {
+                std::thread::sleep(wait - SPIN_WINDOW);
+            } else {
+                std::thread::yield_now();
+            }
+        };
+
+        sync.stop(self);
+        result
+    }
+}
@@ -0,0 +1,475 @@
+//! Built-in lightweight software synthesizer.
+//!
+//! `SoftSynth` turns raw MIDI messages into stereo samples with band-limited
+//! (PolyBLEP) oscillators chosen per GM instrument family, ADSR envelopes and
+//! a small synthesized drum kit on channel 10. It is fully deterministic (no
+//! clocks, fixed noise seeds), so the same input always renders the same audio.
+
+use std::f32::consts::PI;
+
+/// Anything that consumes MIDI messages and produces interleaved stereo audio
+pub trait SynthEngine: Send {
+    /// Apply one raw MIDI message
+    fn handle_message(&mut self, msg: &[u8]);
+    /// Fill `out` with interleaved stereo samples (L, R, L, R, ...)
+    fn render(&mut self, out: &mut [f32]);
+    /// Output sample rate in Hz
+    fn sample_rate(&self) -> u32;
+}
+
+const MAX_VOICES: usize = 64;
+const DRUM_CHANNEL: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Waveform {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    Noise,
+}
+
+/// Envelope times in seconds, sustain as a level
+#[derive(Debug, Clone, Copy)]
+struct Adsr {
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+}
+
+/// Sound design for one GM instrument family
+#[derive(Debug, Clone, Copy)]
+struct Patch {
+    wave: Waveform,
+    /// Second oscillator mixed in one octave up (0.0 = off)
+    octave_mix: f32,
+    env: Adsr,
+    /// One-pole lowpass cutoff as a multiple of the note frequency
+    brightness: f32,
+    gain: f32,
+}
+
+const fn patch(
+    wave: Waveform,
+    octave_mix: f32,
+    env: (f32, f32, f32, f32),
+    brightness: f32,
+    gain: f32,
+) -> Patch {
+    Patch {
+        wave,
+        octave_mix,
+        env: Adsr {
+            attack: env.0,
+            decay: env.1,
+            sustain: env.2,
+            release: env.3,
+        },
+        brightness,
+        gain,
+    }
+}
+
+/// One patch per GM family (program / 8)
+const FAMILY_PATCHES: [Patch; 16] = [
+    patch(Waveform::Triangle, 0.3, (0.002, 1.5, 0.0, 0.3), 8.0, 1.0), // Piano
+    patch(Waveform::Sine, 0.5, (0.001, 0.6, 0.0, 0.3), 12.0, 0.9),    // Chromatic Percussion
+    patch(Waveform::Square, 0.4, (0.01, 0.05, 1.0, 0.05), 6.0, 0.6),  // Organ
+    patch(Waveform::Saw, 0.0, (0.002, 1.0, 0.05, 0.2), 5.0, 0.7),     // Guitar
+    patch(Waveform::Triangle, 0.2, (0.005, 0.4, 0.6, 0.1), 6.0, 1.1), // Bass
+    patch(Waveform::Saw, 0.0, (0.12, 0.3, 0.8, 0.4), 4.0, 0.6),       // Strings
+    patch(Waveform::Saw, 0.3, (0.15, 0.3, 0.8, 0.5), 4.0, 0.5),       // Ensemble
+    patch(Waveform::Saw, 0.0, (0.04, 0.2, 0.8, 0.15), 7.0, 0.6),      // Brass
+    patch(Waveform::Square, 0.0, (0.03, 0.2, 0.8, 0.1), 5.0, 0.5),    // Reed
+    patch(Waveform::Sine, 0.2, (0.05, 0.2, 0.9, 0.15), 10.0, 0.9),    // Pipe
+    patch(Waveform::Square, 0.3, (0.005, 0.2, 0.8, 0.1), 10.0, 0.5),  // Synth Lead
+    patch(Waveform::Triangle, 0.5, (0.5, 0.5, 0.8, 1.0), 4.0, 0.8),   // Synth Pad
+    patch(Waveform::Saw, 0.5, (0.1, 0.8, 0.5, 0.8), 3.0, 0.5),        // Synth Effects
+    patch(Waveform::Triangle, 0.3, (0.002, 0.8, 0.0, 0.2), 8.0, 0.9), // Ethnic
+    patch(Waveform::Sine, 0.0, (0.001, 0.3, 0.0, 0.1), 12.0, 1.0),    // Percussive
+    patch(Waveform::Noise, 0.0, (0.05, 0.5, 0.5, 0.5), 1.0, 0.4),     // Sound Effects
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+/// Drum sound parameters: pitched body plus filtered noise
+#[derive(Debug, Clone, Copy)]
+struct DrumSound {
+    /// Start and end body frequency in Hz (0 = no body)
+    body: (f32, f32),
+    body_gain: f32,
+    noise_gain: f32,
+    /// Highpass the noise (hi-hats, cymbals)
+    bright: bool,
+    decay: f32,
+}
+
+fn drum_sound(key: u8) -> DrumSound {
+    let (body, body_gain, noise_gain, bright, decay) = match key {
+        35 | 36 => ((150.0, 45.0), 1.0, 0.05, false, 0.35), // kick
+        38 | 40 => ((190.0, 160.0), 0.5, 0.6, false, 0.18), // snare
+        37 | 39 => ((0.0, 0.0), 0.0, 0.7, false, 0.12),     // side stick / clap
+        42 | 44 => ((0.0, 0.0), 0.0, 0.4, true, 0.05),      // closed / pedal hat
+        46 => ((0.0, 0.0), 0.0, 0.4, true, 0.3),            // open hat
+        41 | 43 | 45 | 47 | 48 | 50 => {
+            let f = 80.0 + (key as f32 - 41.0) * 15.0;
+            ((f * 1.5, f), 0.9, 0.1, false, 0.3) // toms
+        }
+        49 | 52 | 55 | 57 => ((0.0, 0.0), 0.0, 0.35, true, 1.2), // crash / china / splash
+        51 | 53 | 59 => ((0.0, 0.0), 0.0, 0.25, true, 0.8),      // ride
+        _ => ((800.0, 600.0), 0.3, 0.3, false, 0.08),            // percussion click
+    };
+    DrumSound {
+        body,
+        body_gain,
+        noise_gain,
+        bright,
+        decay,
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Voice {
+    channel: u8,
+    key: u8,
+    velocity: f32,
+    freq: f32,
+    phase: f32,
+    phase2: f32,
+    patch: Patch,
+    drum: Option<DrumSound>,
+    stage: Stage,
+    level: f32,
+    release_from: f32,
+    held: bool,
+    sustained: bool,
+    age: u64,
+    elapsed: f32,
+    noise: u32,
+    lowpass: f32,
+    highpass_prev_in: f32,
+    highpass: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelState {
+    program: u8,
+    volume: f32,
+    expression: f32,
+    pan: f32,
+    sustain: bool,
+    /// Pitch bend in semitones
+    bend: f32,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState {
+            program: 0,
+            volume: 100.0 / 127.0,
+            expression: 1.0,
+            pan: 0.5,
+            sustain: false,
+            bend: 0.0,
+        }
+    }
+}
+
+/// PolyBLEP residual for band-limiting saw and square discontinuities
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+fn oscillator(wave: Waveform, phase: f32, dt: f32, noise: &mut u32) -> f32 {
+    match wave {
+        Waveform::Sine => (2.0 * PI * phase).sin(),
+        Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        Waveform::Saw => 2.0 * phase - 1.0 - poly_blep(phase, dt),
+        Waveform::Square => {
+            let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+            naive + poly_blep(phase, dt) - poly_blep((phase + 0.5) % 1.0, dt)
+        }
+        Waveform::Noise => next_noise(noise),
+    }
+}
+
+/// Deterministic white noise in -1.0..1.0
+fn next_noise(state: &mut u32) -> f32 {
+    *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+    (*state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+}
+
+/// Deterministic multi-voice software synthesizer
+pub struct SoftSynth {
+    sample_rate: u32,
+    channels: [ChannelState; 16],
+    voices: Vec<Voice>,
+    voice_counter: u64,
+    master_gain: f32,
+}
+
+impl SoftSynth {
+    pub fn new(sample_rate: u32) -> Self {
+        SoftSynth {
+            sample_rate: sample_rate.max(8000),
+            channels: [ChannelState::default(); 16],
+            voices: Vec::with_capacity(MAX_VOICES),
+            voice_counter: 0,
+            master_gain: 0.25,
+        }
+    }
+
+    /// Number of voices currently sounding
+    pub fn active_voices(&self) -> usize {
+        self.voices.len()
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        // Retrigger: release the same key on the same channel first
+        self.note_off(channel, key);
+        if self.voices.len() >= MAX_VOICES {
+            // Steal the oldest voice
+            if let Some(oldest) = self
+                .voices
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, v)| v.age)
+                .map(|(i, _)| i)
+            {
+                self.voices.swap_remove(oldest);
+            }
+        }
+        let state = self.channels[channel as usize];
+        let drum = (channel == DRUM_CHANNEL).then(|| drum_sound(key));
+        self.voice_counter += 1;
+        self.voices.push(Voice {
+            channel,
+            key,
+            velocity: velocity as f32 / 127.0,
+            freq: 440.0 * 2f32.powf((key as f32 - 69.0) / 12.0),
+            phase: 0.0,
+            phase2: 0.0,
+            patch: FAMILY_PATCHES[(state.program / 8) as usize],
+            drum,
+            stage: Stage::Attack,
+            level: 0.0,
+            release_from: 0.0,
+            held: true,
+            sustained: false,
+            age: self.voice_counter,
+            elapsed: 0.0,
+            noise: 0x9E37_79B9 ^ ((channel as u32) << 8 | key as u32),
+            lowpass: 0.0,
+            highpass_prev_in: 0.0,
+            highpass: 0.0,
+        });
+    }
+
+    fn note_off(&mut self, channel: u8, key: u8) {
+        let sustain = self.channels[channel as usize].sustain;
+        for v in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.channel == channel && v.key == key && v.held)
+        {
+            v.held = false;
+            if sustain {
+                v.sustained = true;
+            } else if v.drum.is_none() {
+                v.release_from = v.level;
+                v.stage = Stage::Release;
+            }
+        }
+    }
+
+    fn release_sustained(&mut self, channel: u8) {
+        for v in self
+            .voices
+            .iter_mut()
+            .filter(|v| v.channel == channel && v.sustained)
+        {
+            v.sustained = false;
+            if v.drum.is_none() {
+                v.release_from = v.level;
+                v.stage = Stage::Release;
+            }
+        }
+    }
+
+    fn next_voice_sample(v: &mut Voice, state: &ChannelState, sample_rate: f32) -> f32 {
+        let dt_sec = 1.0 / sample_rate;
+        v.elapsed += dt_sec;
+        if let Some(drum) = v.drum {
+            let env = (-v.elapsed / drum.decay * 4.0).exp();
+            if env < 0.0005 {
+                v.stage = Stage::Done;
+                return 0.0;
+            }
+            let mut sample = 0.0;
+            if drum.body_gain > 0.0 {
+                let sweep = (-v.elapsed * 25.0).exp();
+                let freq = drum.body.1 + (drum.body.0 - drum.body.1) * sweep;
+                v.phase = (v.phase + freq / sample_rate) % 1.0;
+                sample += (2.0 * PI * v.phase).sin() * drum.body_gain;
+            }
+            if drum.noise_gain > 0.0 {
+                let n = next_noise(&mut v.noise);
+                let filtered = if drum.bright {
+                    // One-pole highpass
+                    v.highpass = 0.6 * (v.highpass + n - v.highpass_prev_in);
+                    v.highpass_prev_in = n;
+                    v.highpass
+                } else {
+                    v.lowpass += 0.5 * (n - v.lowpass);
+                    v.lowpass
+                };
+                sample += filtered * drum.noise_gain;
+            }
+            return sample * env * v.velocity;
+        }
+
+        let env = v.patch.env;
+        match v.stage {
+            Stage::Attack => {
+                v.level += dt_sec / env.attack.max(0.0005);
+                if v.level >= 1.0 {
+                    v.level = 1.0;
+                    v.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                v.level -= dt_sec / env.decay.max(0.0005) * (1.0 - env.sustain).max(0.05);
+                if v.level <= env.sustain {
+                    v.level = env.sustain;
+                    v.stage = Stage::Sustain;
+                }
+                if v.level <= 0.0005 {
+                    v.stage = Stage::Done;
+                }
+            }
+            Stage::Sustain => {
+                if v.level <= 0.0005 {
+                    v.stage = Stage::Done;
+                }
+            }
+            Stage::Release => {
+                v.level -= dt_sec / env.release.max(0.005) * v.release_from.max(0.001);
+                if v.level <= 0.0 {
+                    v.level = 0.0;
+                    v.stage = Stage::Done;
+                }
+            }
+            Stage::Done => return 0.0,
+        }
+
+        let freq = v.freq * 2f32.powf(state.bend / 12.0);
+        let dt = (freq / sample_rate).min(0.5);
+        v.phase = (v.phase + dt) % 1.0;
+        let mut sample = oscillator(v.patch.wave, v.phase, dt, &mut v.noise);
+        if v.patch.octave_mix > 0.0 {
+            let dt2 = (dt * 2.0).min(0.5);
+            v.phase2 = (v.phase2 + dt2) % 1.0;
+            sample += v.patch.octave_mix * oscillator(v.patch.wave, v.phase2, dt2, &mut v.noise);
+        }
+        // Gentle lowpass tracking the note frequency, opened up by velocity
+        let cutoff = (freq * v.patch.brightness * (0.5 + v.velocity)).min(sample_rate * 0.45);
+        let alpha = 1.0 - (-2.0 * PI * cutoff / sample_rate).exp();
+        v.lowpass += alpha * (sample - v.lowpass);
+        v.lowpass * v.level * v.velocity * v.patch.gain
+    }
+}
+
+impl SynthEngine for SoftSynth {
+    fn handle_message(&mut self, msg: &[u8]) {
+        let Some(&status) = msg.first() else {
+            return;
+        };
+        let channel = status & 0x0F;
+        let ch = channel as usize;
+        match (status & 0xF0, msg.get(1).copied(), msg.get(2).copied()) {
+            (0x90, Some(key), Some(vel)) if vel > 0 => {
+                self.note_on(channel, key & 0x7F, vel & 0x7F)
+            }
+            (0x80, Some(key), _) | (0x90, Some(key), Some(_)) => self.note_off(channel, key & 0x7F),
+            (0xB0, Some(controller), Some(value)) => {
+                let value = value & 0x7F;
+                match controller {
+                    7 => self.channels[ch].volume = value as f32 / 127.0,
+                    10 => self.channels[ch].pan = value as f32 / 127.0,
+                    11 => self.channels[ch].expression = value as f32 / 127.0,
+                    64 => {
+                        self.channels[ch].sustain = value >= 64;
+                        if value < 64 {
+                            self.release_sustained(channel);
+                        }
+                    }
+                    120 => self.voices.retain(|v| v.channel != channel),
+                    121 => self.channels[ch] = ChannelState::default(),
+                    123 => {
+                        let held: Vec<u8> = self
+                            .voices
+                            .iter()
+                            .filter(|v| v.channel == channel)
+                            .map(|v| v.key)
+                            .collect();
+                        self.channels[ch].sustain = false;
+                        for key in held {
+                            self.note_off(channel, key);
+                        }
+                        self.release_sustained(channel);
+                    }
+                    _ => {}
+                }
+            }
+            (0xC0, Some(program), _) => self.channels[ch].program = program & 0x7F,
+            (0xE0, Some(lsb), Some(msb)) => {
+                let bend = (((msb as i32 & 0x7F) << 7) | (lsb as i32 & 0x7F)) - 8192;
+                self.channels[ch].bend = bend as f32 / 8192.0 * 2.0;
+            }
+            _ => {}
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let sample_rate = self.sample_rate as f32;
+        for frame in out.chunks_mut(2) {
+            let (mut left, mut right) = (0.0f32, 0.0f32);
+            for v in self.voices.iter_mut() {
+                let state = self.channels[v.channel as usize];
+                let sample = Self::next_voice_sample(v, &state, sample_rate)
+                    * state.volume
+                    * state.expression;
+                // Equal-power pan
+                let angle = state.pan * PI / 2.0;
+                left += sample * angle.cos();
+                right += sample * angle.sin();
+            }
+            let gain = self.master_gain;
+            frame[0] = (left * gain).tanh();
+            if let Some(r) = frame.get_mut(1) {
+                *r = (right * gain).tanh();
+            }
+        }
+        self.voices.retain(|v| v.stage != Stage::Done);
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
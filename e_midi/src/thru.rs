@@ -0,0 +1,612 @@
+//! MIDI thru: route a live input port through a transformation chain to the
+//! player's output.
+//!
+//! Transformed messages are sent to the background MIDI thread with the same
+//! `MidiCommand::SendMessage` path used by file playback, and note events are
+//! published over IPC (serde `MidiNoteOn`/`MidiNoteOff` plus the zero-copy
+//! `MidiNoteEvent`), so thru can run on top of a backing track. The MIDI
+//! thread tracks the notes it sends, so stopping the player releases notes
+//! held through thru as well.
+
+use crate::record::select_input_port;
+use crate::{ipc, MidiCommand, MidiPlayer};
+use midir::MidiInputConnection;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Controller number of the sustain pedal
+const SUSTAIN_PEDAL: u8 = 64;
+
+/// How incoming note velocities are mapped to outgoing velocities
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+    /// Pass velocities through unchanged
+    Linear,
+    /// Always use the same velocity
+    Fixed(u8),
+    /// Multiply by a factor (clamped to 1..=127)
+    Scale(f32),
+    /// Apply `127 * (v / 127) ^ gamma`; gamma < 1 is softer, > 1 is harder
+    Gamma(f32),
+}
+
+impl VelocityCurve {
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let out = match *self {
+            VelocityCurve::Linear => velocity as f32,
+            VelocityCurve::Fixed(v) => v as f32,
+            VelocityCurve::Scale(factor) => velocity as f32 * factor,
+            VelocityCurve::Gamma(gamma) => 127.0 * (velocity as f32 / 127.0).powf(gamma),
+        };
+        out.round().clamp(1.0, 127.0) as u8
+    }
+}
+
+/// A single step in the thru transformation chain
+///
+/// Channels are zero-based (0-15) internally; the string form uses 1-16.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    /// Move messages from one channel (or all channels if None) to another
+    ChannelRemap { from: Option<u8>, to: u8 },
+    /// Shift note pitches by a number of semitones
+    Transpose(i8),
+    /// Reshape note-on velocities
+    Velocity(VelocityCurve),
+    /// Replace program changes (and set the program when thru starts)
+    ProgramOverride { channel: Option<u8>, program: u8 },
+    /// Send notes below `split_point` to one channel and the rest to another
+    KeySplit {
+        split_point: u8,
+        lower_channel: u8,
+        upper_channel: u8,
+    },
+}
+
+fn parse_channel(s: &str) -> Result<u8, String> {
+    match s.trim().parse::<u8>() {
+        Ok(c @ 1..=16) => Ok(c - 1),
+        _ => Err(format!("Invalid MIDI channel '{}' (expected 1-16)", s)),
+    }
+}
+
+fn parse_u7(s: &str, what: &str) -> Result<u8, String> {
+    match s.trim().parse::<u8>() {
+        Ok(v @ 0..=127) => Ok(v),
+        _ => Err(format!("Invalid {} '{}' (expected 0-127)", what, s)),
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    /// Parse `transpose=12`, `remap=1:3`, `remap=*:3`, `velocity=fixed:100`,
+    /// `velocity=scale:0.8`, `velocity=gamma:0.6`, `program=1:40`, `program=*:40`
+    /// or `split=60:2:1` (split point, lower channel, upper channel)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid transform '{}' (expected name=value)", s))?;
+        let parts: Vec<&str> = value.split(':').collect();
+        let any_channel = |p: &str| {
+            if p.trim() == "*" {
+                Ok(None)
+            } else {
+                parse_channel(p).map(Some)
+            }
+        };
+        match (name.trim().to_ascii_lowercase().as_str(), parts.as_slice()) {
+            ("transpose", [semitones]) => semitones
+                .trim()
+                .parse::<i8>()
+                .map(Transform::Transpose)
+                .map_err(|_| format!("Invalid transpose '{}'", semitones)),
+            ("remap", [from, to]) => Ok(Transform::ChannelRemap {
+                from: any_channel(from)?,
+                to: parse_channel(to)?,
+            }),
+            ("velocity", [curve, rest @ ..]) => {
+                let arg = rest.first().copied().unwrap_or("");
+                let number = || {
+                    arg.trim()
+                        .parse::<f32>()
+                        .map_err(|_| format!("Invalid velocity value '{}'", arg))
+                };
+                let curve = match curve.trim() {
+                    "linear" => VelocityCurve::Linear,
+                    "fixed" => VelocityCurve::Fixed(parse_u7(arg, "velocity")?.max(1)),
+                    "scale" => VelocityCurve::Scale(number()?),
+                    "gamma" => VelocityCurve::Gamma(number()?.max(0.01)),
+                    other => return Err(format!("Unknown velocity curve '{}'", other)),
+                };
+                Ok(Transform::Velocity(curve))
+            }
+            ("program", [channel, program]) => Ok(Transform::ProgramOverride {
+                channel: any_channel(channel)?,
+                program: parse_u7(program, "program")?,
+            }),
+            ("split", [point, lower, upper]) => Ok(Transform::KeySplit {
+                split_point: parse_u7(point, "split point")?,
+                lower_channel: parse_channel(lower)?,
+                upper_channel: parse_channel(upper)?,
+            }),
+            _ => Err(format!("Unknown transform '{}'", s)),
+        }
+    }
+}
+
+/// An ordered list of transforms plus the note-tracking state they need
+///
+/// Note-offs and polyphonic aftertouch are routed to wherever the matching
+/// note-on went, so changing a transpose or split while keys are held never
+/// leaves notes hanging. The sustain pedal is sent to both sides of a split.
+#[derive(Debug, Clone, Default)]
+pub struct TransformChain {
+    transforms: Vec<Transform>,
+    /// (input channel, input pitch) -> (output channel, output pitch)
+    active_notes: HashMap<(u8, u8), (u8, u8)>,
+}
+
+impl TransformChain {
+    pub fn new(transforms: Vec<Transform>) -> Self {
+        TransformChain {
+            transforms,
+            active_notes: HashMap::new(),
+        }
+    }
+
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+
+    /// Program changes to send before any input arrives
+    pub fn initial_messages(&self) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        for t in &self.transforms {
+            if let Transform::ProgramOverride { channel, program } = t {
+                match channel {
+                    Some(c) => messages.push(vec![0xC0 | c, *program]),
+                    None => messages.extend((0..16).map(|c| vec![0xC0 | c, *program])),
+                }
+            }
+        }
+        messages
+    }
+
+    /// Transform one incoming message; returns the messages to send (possibly none)
+    pub fn apply(&mut self, message: &[u8]) -> Vec<Vec<u8>> {
+        let Some(&status) = message.first() else {
+            return Vec::new();
+        };
+        if !(0x80..0xF0).contains(&status) {
+            // System messages pass through untouched
+            return vec![message.to_vec()];
+        }
+        let kind = status & 0xF0;
+        let in_channel = status & 0x0F;
+        let expected_len = if kind == 0xC0 || kind == 0xD0 { 2 } else { 3 };
+        if message.len() < expected_len {
+            return Vec::new();
+        }
+        let is_note_on = kind == 0x90 && message[2] > 0;
+        let is_note_off = kind == 0x80 || (kind == 0x90 && !is_note_on);
+
+        if is_note_off {
+            let pitch = message[1];
+            // Keep the release velocity of a real note-off
+            let release = if kind == 0x80 { message[2] } else { 0 };
+            return match self.active_notes.remove(&(in_channel, pitch)) {
+                Some((channel, pitch)) => vec![vec![0x80 | channel, pitch, release]],
+                None => Vec::new(),
+            };
+        }
+        if kind == 0xA0 {
+            // Pressure on a key that isn't sounding through thru goes nowhere
+            return match self.active_notes.get(&(in_channel, message[1])) {
+                Some(&(channel, pitch)) => vec![vec![0xA0 | channel, pitch, message[2]]],
+                None => Vec::new(),
+            };
+        }
+
+        let is_sustain = kind == 0xB0 && message[1] == SUSTAIN_PEDAL;
+        // Output channels; only the sustain pedal ever goes to more than one
+        let mut channels = vec![in_channel];
+        let mut data: Vec<u8> = message[1..expected_len].to_vec();
+        for t in &self.transforms {
+            match t {
+                Transform::ChannelRemap { from, to } => {
+                    for channel in channels.iter_mut() {
+                        if from.map_or(true, |f| f == *channel) {
+                            *channel = *to;
+                        }
+                    }
+                    channels.dedup();
+                }
+                Transform::Transpose(semitones) if is_note_on => {
+                    let pitch = data[0] as i16 + *semitones as i16;
+                    if !(0..=127).contains(&pitch) {
+                        return Vec::new();
+                    }
+                    data[0] = pitch as u8;
+                }
+                Transform::Velocity(curve) if is_note_on => {
+                    data[1] = curve.apply(data[1]);
+                }
+                Transform::ProgramOverride {
+                    channel: target,
+                    program,
+                } if kind == 0xC0 => {
+                    if target.map_or(true, |c| channels.contains(&c)) {
+                        data[0] = *program;
+                    }
+                }
+                Transform::KeySplit {
+                    split_point,
+                    lower_channel,
+                    upper_channel,
+                } if is_note_on => {
+                    channels = vec![if data[0] < *split_point {
+                        *lower_channel
+                    } else {
+                        *upper_channel
+                    }];
+                }
+                Transform::KeySplit {
+                    lower_channel,
+                    upper_channel,
+                    ..
+                } if is_sustain => {
+                    channels = vec![*lower_channel, *upper_channel];
+                    channels.dedup();
+                }
+                _ => {}
+            }
+        }
+
+        if is_note_on {
+            let channel = channels[0];
+            let in_pitch = message[1];
+            // Retriggered key: release the previous output note first
+            let mut out = Vec::new();
+            if let Some((c, p)) = self
+                .active_notes
+                .insert((in_channel, in_pitch), (channel, data[0]))
+            {
+                out.push(vec![0x80 | c, p, 0]);
+            }
+            out.push(vec![0x90 | channel, data[0], data[1]]);
+            return out;
+        }
+
+        channels
+            .into_iter()
+            .map(|channel| {
+                let mut out = vec![kind | channel];
+                out.extend_from_slice(&data);
+                out
+            })
+            .collect()
+    }
+
+    /// Note-offs for every note still sounding, clearing the tracking state
+    pub fn release_all(&mut self) -> Vec<Vec<u8>> {
+        self.active_notes
+            .drain()
+            .map(|(_, (channel, pitch))| vec![0x80 | channel, pitch, 0])
+            .collect()
+    }
+}
+
+/// Forward a message to the MIDI thread and publish note events over IPC
+fn forward(
+    msg: &[u8],
+    midi_sender: &mpsc::Sender<MidiCommand>,
+    publisher: Option<
+        &iceoryx2::port::publisher::Publisher<
+            iceoryx2::service::ipc::Service,
+            e_midi_shared::ipc_protocol::MidiNoteEvent,
+            (),
+        >,
+    >,
+) -> bool {
+    if msg.len() == 3 && (msg[0] & 0xE0) == 0x80 {
+        let on = (msg[0] & 0xF0) == 0x90 && msg[2] > 0;
+        let channel = msg[0] & 0x0F;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let event = if on {
+            ipc::Event::MidiNoteOn {
+                channel,
+                pitch: msg[1],
+                velocity: msg[2],
+                timestamp: now,
+            }
+        } else {
+            ipc::Event::MidiNoteOff {
+                channel,
+                pitch: msg[1],
+                timestamp: now,
+            }
+        };
+        ipc::IpcServiceManager::publish_ipc_event(event);
+        if let Some(publisher) = publisher {
+            let midi_note_event = e_midi_shared::ipc_protocol::MidiNoteEvent {
+                channel,
+                pitch: msg[1],
+                velocity: if on { msg[2] } else { 0 },
+                kind: if on { 0 } else { 1 },
+                timestamp: now,
+                _reserved: [0; 4],
+            };
+            if let Err(e) = publisher.send_copy(midi_note_event) {
+                eprintln!("[IPC ERROR] Failed to send MidiNoteEvent: {:?}", e);
+            }
+        }
+    }
+    midi_sender
+        .send(MidiCommand::SendMessage(msg.to_vec()))
+        .is_ok()
+}
+
+/// A running thru connection; stop it to release held notes
+pub struct MidiThru {
+    connection: MidiInputConnection<()>,
+    chain: Arc<Mutex<TransformChain>>,
+    forward_sender: mpsc::Sender<Vec<Vec<u8>>>,
+    forward_thread: JoinHandle<()>,
+    port_name: String,
+}
+
+impl MidiThru {
+    /// Connect an input port to the player's output through `chain`
+    pub fn start(
+        port_index: Option<usize>,
+        chain: TransformChain,
+        midi_sender: mpsc::Sender<MidiCommand>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (midi_in, port, port_name) = select_input_port("e_midi_thru", port_index)?;
+
+        // The IPC publisher is !Send, so it lives on a dedicated forwarding thread
+        let (forward_sender, forward_receiver) = mpsc::channel::<Vec<Vec<u8>>>();
+        let forward_thread = thread::spawn(move || {
+            let publisher = iceoryx2::node::NodeBuilder::new()
+                .create::<iceoryx2::service::ipc::Service>()
+                .ok()
+                .and_then(|node| {
+                    node.service_builder(
+                        &iceoryx2::prelude::ServiceName::new(ipc::EMIDI_NOTE_EVENTS_SERVICE)
+                            .ok()?,
+                    )
+                    .publish_subscribe::<e_midi_shared::ipc_protocol::MidiNoteEvent>()
+                    .max_publishers(16)
+                    .max_subscribers(16)
+                    .open_or_create()
+                    .ok()?
+                    .publisher_builder()
+                    .create()
+                    .ok()
+                });
+            while let Ok(messages) = forward_receiver.recv() {
+                for msg in &messages {
+                    if !forward(msg, &midi_sender, publisher.as_ref()) {
+                        println!("[ERROR] MIDI thread unavailable, stopping thru");
+                        return;
+                    }
+                }
+            }
+        });
+
+        let initial = chain.initial_messages();
+        if !initial.is_empty() {
+            let _ = forward_sender.send(initial);
+        }
+
+        let chain = Arc::new(Mutex::new(chain));
+        let callback_chain = Arc::clone(&chain);
+        let callback_sender = forward_sender.clone();
+        let connection = midi_in.connect(
+            &port,
+            "e_midi_thru",
+            move |_stamp, message, _| {
+                let out = match callback_chain.lock() {
+                    Ok(mut chain) => chain.apply(message),
+                    Err(_) => return,
+                };
+                if !out.is_empty() {
+                    let _ = callback_sender.send(out);
+                }
+            },
+            (),
+        )?;
+        println!("🔀 MIDI thru active: {} → e_midi output", port_name);
+
+        Ok(MidiThru {
+            connection,
+            chain,
+            forward_sender,
+            forward_thread,
+            port_name,
+        })
+    }
+
+    /// Name of the input port feeding the thru
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    /// Replace the transformation chain while running; held notes are still released correctly
+    pub fn set_transforms(&self, transforms: Vec<Transform>) {
+        if let Ok(mut chain) = self.chain.lock() {
+            chain.transforms = transforms;
+        }
+    }
+
+    /// Disconnect the input port and release any notes it left sounding
+    pub fn stop(self) {
+        self.connection.close();
+        if let Ok(mut chain) = self.chain.lock() {
+            let released = chain.release_all();
+            if !released.is_empty() {
+                let _ = self.forward_sender.send(released);
+            }
+        }
+        drop(self.forward_sender);
+        let _ = self.forward_thread.join();
+        println!("🔀 MIDI thru stopped");
+    }
+}
+
+impl MidiPlayer {
+    /// Start MIDI thru from an input port into this player's output
+    pub fn start_thru(
+        &self,
+        port_index: Option<usize>,
+        transforms: Vec<Transform>,
+    ) -> Result<MidiThru, Box<dyn Error>> {
+        MidiThru::start(
+            port_index,
+            TransformChain::new(transforms),
+            self.midi_sender.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_of(specs: &[&str]) -> TransformChain {
+        TransformChain::new(specs.iter().map(|s| s.parse().unwrap()).collect())
+    }
+
+    #[test]
+    fn parses_every_transform() {
+        assert_eq!("transpose=-12".parse(), Ok(Transform::Transpose(-12)));
+        assert_eq!(
+            "remap=1:3".parse(),
+            Ok(Transform::ChannelRemap {
+                from: Some(0),
+                to: 2
+            })
+        );
+        assert_eq!(
+            "remap=*:16".parse(),
+            Ok(Transform::ChannelRemap { from: None, to: 15 })
+        );
+        assert_eq!(
+            "velocity=fixed:100".parse(),
+            Ok(Transform::Velocity(VelocityCurve::Fixed(100)))
+        );
+        assert_eq!(
+            "velocity=scale:0.5".parse(),
+            Ok(Transform::Velocity(VelocityCurve::Scale(0.5)))
+        );
+        assert_eq!(
+            "Velocity=linear".parse(),
+            Ok(Transform::Velocity(VelocityCurve::Linear))
+        );
+        assert_eq!(
+            "program=*:40".parse(),
+            Ok(Transform::ProgramOverride {
+                channel: None,
+                program: 40
+            })
+        );
+        assert_eq!(
+            "split=60:2:1".parse(),
+            Ok(Transform::KeySplit {
+                split_point: 60,
+                lower_channel: 1,
+                upper_channel: 0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_transforms() {
+        for spec in [
+            "transpose",
+            "transpose=200",
+            "remap=0:3",
+            "remap=1:17",
+            "velocity=loud:1",
+            "velocity=scale:x",
+            "program=1:128",
+            "split=60:2",
+            "octave=1",
+        ] {
+            assert!(spec.parse::<Transform>().is_err(), "{} parsed", spec);
+        }
+    }
+
+    #[test]
+    fn note_off_follows_a_transposed_note_on() {
+        let mut chain = chain_of(&["transpose=12"]);
+        assert_eq!(chain.apply(&[0x90, 60, 100]), vec![vec![0x90, 72, 100]]);
+        // Changing the transpose while the key is held still releases 72
+        chain.transforms = vec![Transform::Transpose(-5)];
+        assert_eq!(chain.apply(&[0x80, 60, 64]), vec![vec![0x80, 72, 64]]);
+        // A second note-off for the same key has nothing left to release
+        assert!(chain.apply(&[0x80, 60, 64]).is_empty());
+    }
+
+    #[test]
+    fn note_off_follows_a_split_note_on() {
+        let mut chain = chain_of(&["split=60:2:3"]);
+        assert_eq!(chain.apply(&[0x90, 48, 90]), vec![vec![0x91, 48, 90]]);
+        assert_eq!(chain.apply(&[0x90, 72, 90]), vec![vec![0x92, 72, 90]]);
+        // Note-on with velocity 0 counts as a note-off
+        assert_eq!(chain.apply(&[0x90, 48, 0]), vec![vec![0x81, 48, 0]]);
+        assert_eq!(chain.apply(&[0x80, 72, 30]), vec![vec![0x82, 72, 30]]);
+    }
+
+    #[test]
+    fn retriggered_key_releases_the_previous_note_first() {
+        let mut chain = chain_of(&["transpose=2"]);
+        chain.apply(&[0x90, 60, 100]);
+        chain.transforms = vec![Transform::Transpose(4)];
+        assert_eq!(
+            chain.apply(&[0x90, 60, 80]),
+            vec![vec![0x80, 62, 0], vec![0x90, 64, 80]]
+        );
+        assert_eq!(chain.release_all(), vec![vec![0x80, 64, 0]]);
+    }
+
+    #[test]
+    fn poly_aftertouch_follows_the_sounding_note() {
+        let mut chain = chain_of(&["transpose=-3", "split=60:2:3"]);
+        chain.apply(&[0x90, 64, 100]);
+        assert_eq!(chain.apply(&[0xA0, 64, 50]), vec![vec![0xA2, 61, 50]]);
+        // No note, no pressure
+        assert!(chain.apply(&[0xA0, 65, 50]).is_empty());
+    }
+
+    #[test]
+    fn sustain_reaches_both_sides_of_a_split() {
+        let mut chain = chain_of(&["split=60:2:3"]);
+        assert_eq!(
+            chain.apply(&[0xB0, 64, 127]),
+            vec![vec![0xB1, 64, 127], vec![0xB2, 64, 127]]
+        );
+        // Other controllers stay on their channel
+        assert_eq!(chain.apply(&[0xB0, 1, 10]), vec![vec![0xB0, 1, 10]]);
+
+        let mut merged = chain_of(&["split=60:2:2"]);
+        assert_eq!(merged.apply(&[0xB0, 64, 0]), vec![vec![0xB1, 64, 0]]);
+    }
+
+    #[test]
+    fn velocity_and_program_transforms() {
+        let mut chain = chain_of(&["velocity=fixed:90", "program=2:40", "remap=1:2"]);
+        assert_eq!(chain.apply(&[0x90, 60, 10]), vec![vec![0x91, 60, 90]]);
+        // The remap runs after the override, so channel 1 keeps its program
+        assert_eq!(chain.apply(&[0xC0, 5]), vec![vec![0xC1, 5]]);
+        assert_eq!(chain.apply(&[0xC1, 5]), vec![vec![0xC1, 40]]);
+        assert_eq!(chain.initial_messages(), vec![vec![0xC1, 40]]);
+    }
+}
@@ -0,0 +1,593 @@
+//! Remote-controlled transport for a `MidiPlayer`.
+//!
+//! Control servers (OSC and friends) translate requests into calls on a
+//! `Transport`, which keeps playlist position, tempo, mutes, transpose and the
+//! cue queue, and plays through the background MIDI thread so no call blocks.
+
+use crate::export::{dense_track_index, program_changes};
+use crate::{ipc, MidiCommand, MidiPlayer};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::time::Instant;
+
+/// Transpose range accepted by `Transport::set_transpose`
+const MAX_TRANSPOSE: i8 = 48;
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl fmt::Display for TransportState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportState::Stopped => write!(f, "stopped"),
+            TransportState::Playing => write!(f, "playing"),
+            TransportState::Paused => write!(f, "paused"),
+        }
+    }
+}
+
+/// Snapshot of the transport for status replies
+#[derive(Debug, Clone, Serialize)]
+pub struct TransportStatus {
+    pub state: TransportState,
+    pub song_index: Option<usize>,
+    pub song_name: Option<String>,
+    pub position_ms: u32,
+    pub duration_ms: u32,
+    pub tempo_bpm: Option<u32>,
+    pub transpose: i8,
+    /// Muted tracks by user-facing track number
+    pub muted_tracks: Vec<usize>,
+    /// Songs cued to play next, in order
+    pub queue: Vec<usize>,
+}
+
+/// Playlist transport driven by remote commands
+#[derive(Debug)]
+pub struct Transport {
+    state: TransportState,
+    song_index: Option<usize>,
+    /// Song position at `started` (or the paused position)
+    position_ms: u32,
+    started: Option<Instant>,
+    /// Length of the current song at the current tempo
+    duration_ms: u32,
+    tempo_bpm: Option<u32>,
+    transpose: i8,
+    muted_tracks: BTreeSet<usize>,
+    queue: VecDeque<usize>,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Transport {
+            state: TransportState::Stopped,
+            song_index: None,
+            position_ms: 0,
+            started: None,
+            duration_ms: 0,
+            tempo_bpm: None,
+            transpose: 0,
+            muted_tracks: BTreeSet::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> TransportState {
+        self.state
+    }
+
+    pub fn song_index(&self) -> Option<usize> {
+        self.song_index
+    }
+
+    /// Current song position in ms
+    pub fn position_ms(&self) -> u32 {
+        match self.started {
+            Some(started) => {
+                (self.position_ms + started.elapsed().as_millis() as u32).min(self.duration_ms)
+            }
+            None => self.position_ms,
+        }
+    }
+
+    pub fn status(&self, player: &MidiPlayer) -> TransportStatus {
+        TransportStatus {
+            state: self.state,
+            song_index: self.song_index,
+            song_name: self
+                .song_index
+                .and_then(|i| player.get_song(i))
+                .map(|s| s.name.clone()),
+            position_ms: self.position_ms(),
+            duration_ms: self.duration_ms,
+            tempo_bpm: self.tempo_bpm,
+            transpose: self.transpose,
+            muted_tracks: self.muted_tracks.iter().copied().collect(),
+            queue: self.queue.iter().copied().collect(),
+        }
+    }
+
+    /// Playback state in the form sent over IPC
+    pub fn playback_state(&self, player: &MidiPlayer) -> ipc::MidiPlaybackState {
+        let song = self.song_index.and_then(|i| player.get_song(i));
+        ipc::MidiPlaybackState {
+            is_playing: self.state == TransportState::Playing,
+            current_song_index: self.song_index,
+            current_song_name: song.map(|s| s.name.clone()).unwrap_or_default(),
+            progress_ms: self.position_ms(),
+            total_duration_ms: self.duration_ms,
+            tempo_bpm: self
+                .tempo_bpm
+                .or_else(|| song.map(|s| player.scaled_tempo(s.default_tempo)))
+                .unwrap_or(120),
+            volume: 1.0,
+            timestamp: now_ms(),
+        }
+    }
+
+    /// Play a song from the start
+    pub fn play(&mut self, player: &MidiPlayer, song_index: usize) -> Result<(), Box<dyn Error>> {
+        let song = player
+            .get_song(song_index)
+            .ok_or_else(|| format!("Invalid song index {}", song_index))?;
+        let song_name = song.name.clone();
+        if self.song_index != Some(song_index) {
+            self.tempo_bpm = None;
+            self.muted_tracks.clear();
+        }
+        self.song_index = Some(song_index);
+        self.position_ms = 0;
+        self.start(player)?;
+        player.record_play(song_index);
+        player.publish_midi_event(ipc::Event::MidiPlaybackStarted {
+            song_index,
+            song_name,
+            timestamp: now_ms(),
+        });
+        Ok(())
+    }
+
+    /// Resume after pause, or start the current, cued or first song
+    pub fn resume(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        match (self.state, self.song_index) {
+            (TransportState::Playing, _) => Ok(()),
+            (TransportState::Paused, Some(_)) => {
+                self.start(player)?;
+                player.publish_midi_event(ipc::Event::MidiPlaybackResumed {
+                    timestamp: now_ms(),
+                });
+                Ok(())
+            }
+            (_, Some(index)) if self.queue.is_empty() => self.play(player, index),
+            _ => {
+                let index = self.queue.pop_front().unwrap_or(0);
+                self.play(player, index)
+            }
+        }
+    }
+
+    /// Pause, keeping the position; pausing again resumes
+    pub fn pause(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        match self.state {
+            TransportState::Playing => {
+                self.position_ms = self.position_ms();
+                self.halt(player);
+                self.state = TransportState::Paused;
+                if let Some(index) = self.song_index {
+                    player.record_position(index, self.position_ms);
+                }
+                player.publish_midi_event(ipc::Event::MidiPlaybackPaused {
+                    timestamp: now_ms(),
+                });
+                Ok(())
+            }
+            TransportState::Paused => self.resume(player),
+            TransportState::Stopped => Ok(()),
+        }
+    }
+
+    /// Stop and rewind to the start of the song
+    pub fn stop(&mut self, player: &MidiPlayer) {
+        if self.state != TransportState::Stopped {
+            if let Some(index) = self.song_index {
+                player.record_position(index, self.position_ms());
+            }
+            self.halt(player);
+            player.publish_midi_event(ipc::Event::MidiPlaybackStopped {
+                timestamp: now_ms(),
+            });
+        }
+        self.state = TransportState::Stopped;
+        self.position_ms = 0;
+    }
+
+    /// Play the next cued song, or the next one in the playlist
+    pub fn next(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        let count = player.get_total_song_count();
+        if count == 0 {
+            return Err("No songs available".into());
+        }
+        let index = match self.queue.pop_front() {
+            Some(index) => index,
+            None => self.song_index.map_or(0, |i| (i + 1) % count),
+        };
+        self.play(player, index)
+    }
+
+    /// Play the previous song in the playlist
+    pub fn previous(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        let count = player.get_total_song_count();
+        if count == 0 {
+            return Err("No songs available".into());
+        }
+        let index = self.song_index.map_or(0, |i| (i + count - 1) % count);
+        self.play(player, index)
+    }
+
+    /// Change tempo, keeping the musical position
+    pub fn set_tempo(&mut self, player: &MidiPlayer, bpm: u32) -> Result<(), Box<dyn Error>> {
+        if !(1..=500).contains(&bpm) {
+            return Err(format!("Invalid tempo: {} (must be 1-500 BPM)", bpm).into());
+        }
+        let index = self.song_index.ok_or("No song selected")?;
+        let song = player.get_song(index).ok_or("Invalid song index")?;
+        let old_bpm = self
+            .tempo_bpm
+            .unwrap_or_else(|| player.scaled_tempo(song.default_tempo))
+            .max(1);
+        let position = self.position_ms() as u64 * old_bpm as u64 / bpm as u64;
+        self.tempo_bpm = Some(bpm);
+        self.position_ms = position as u32;
+        self.refresh(player)?;
+        player.publish_midi_event(ipc::Event::MidiTempoChanged {
+            new_tempo: bpm,
+            timestamp: now_ms(),
+        });
+        Ok(())
+    }
+
+    /// Jump to a song position in ms
+    pub fn seek(&mut self, player: &MidiPlayer, position_ms: u32) -> Result<(), Box<dyn Error>> {
+        if self.song_index.is_none() {
+            return Err("No song selected".into());
+        }
+        if self.state == TransportState::Stopped {
+            self.state = TransportState::Paused;
+        }
+        self.position_ms = position_ms;
+        self.refresh(player)
+    }
+
+    /// Mute or unmute a track by user-facing track number
+    pub fn set_track_muted(
+        &mut self,
+        player: &MidiPlayer,
+        track: usize,
+        muted: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let index = self.song_index.ok_or("No song selected")?;
+        let song = player.get_song(index).ok_or("Invalid song index")?;
+        if !song.track_index_map.contains_key(&track) {
+            return Err(format!("Song {} has no track {}", index, track).into());
+        }
+        if muted {
+            self.muted_tracks.insert(track);
+        } else {
+            self.muted_tracks.remove(&track);
+        }
+        self.refresh(player)
+    }
+
+    /// Transpose all pitched channels by semitones
+    pub fn set_transpose(
+        &mut self,
+        player: &MidiPlayer,
+        semitones: i8,
+    ) -> Result<(), Box<dyn Error>> {
+        if !(-MAX_TRANSPOSE..=MAX_TRANSPOSE).contains(&semitones) {
+            return Err(format!(
+                "Invalid transpose: {} (must be within ±{})",
+                semitones, MAX_TRANSPOSE
+            )
+            .into());
+        }
+        self.transpose = semitones;
+        self.refresh(player)
+    }
+
+    /// Queue a song to play when the current one ends
+    pub fn cue(&mut self, player: &MidiPlayer, song_index: usize) -> Result<(), Box<dyn Error>> {
+        if player.get_song(song_index).is_none() {
+            return Err(format!("Invalid song index {}", song_index).into());
+        }
+        self.queue.push_back(song_index);
+        Ok(())
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Follow the removal of song `index` from the playlist
+    ///
+    /// Stops the current song if it was the one removed, shifts the current
+    /// and cued indices above it down by one and drops cues of the removed song.
+    pub fn song_removed(&mut self, player: &MidiPlayer, index: usize) {
+        match self.song_index {
+            Some(current) if current == index => {
+                // The song is gone, so there is no position to record
+                if self.state != TransportState::Stopped {
+                    self.halt(player);
+                    player.publish_midi_event(ipc::Event::MidiPlaybackStopped {
+                        timestamp: now_ms(),
+                    });
+                }
+                self.state = TransportState::Stopped;
+                self.song_index = None;
+                self.position_ms = 0;
+                self.duration_ms = 0;
+                self.tempo_bpm = None;
+                self.muted_tracks.clear();
+            }
+            Some(current) if current > index => self.song_index = Some(current - 1),
+            _ => {}
+        }
+        self.queue.retain(|&cued| cued != index);
+        for cued in self.queue.iter_mut().filter(|cued| **cued > index) {
+            *cued -= 1;
+        }
+    }
+
+    /// Follow watched directory changes reported by `poll_watched_directories`
+    ///
+    /// A reparsed current song restarts at its position with the new notes.
+    pub fn playlist_changed(
+        &mut self,
+        player: &MidiPlayer,
+        changes: &crate::watch::PlaylistChanges,
+    ) -> Result<(), Box<dyn Error>> {
+        for &index in &changes.removed {
+            self.song_removed(player, index);
+        }
+        match self.song_index {
+            Some(current) if changes.reloaded.contains(&current) => self.refresh(player),
+            _ => Ok(()),
+        }
+    }
+
+    /// Advance when the current song has finished; true if the state changed
+    ///
+    /// A song that can't be advanced to (say, one removed from a watched
+    /// directory) is reported and stops the transport, so a service loop
+    /// calling this keeps running.
+    pub fn update(&mut self, player: &MidiPlayer) -> bool {
+        if self.state != TransportState::Playing || self.position_ms() < self.duration_ms {
+            return false;
+        }
+        let result = if !self.queue.is_empty() {
+            self.next(player)
+        } else if player.get_config().loop_individual_songs {
+            self.position_ms = 0;
+            self.start(player)
+        } else if player.get_config().loop_playlist {
+            self.next(player)
+        } else {
+            self.stop(player);
+            Ok(())
+        };
+        if let Err(e) = result {
+            eprintln!("⚠️  Failed to advance the playlist: {}", e);
+            self.stop(player);
+        }
+        true
+    }
+
+    /// Restart playback at the current position if playing, to apply changes
+    fn refresh(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        if self.state == TransportState::Playing {
+            self.position_ms = self.position_ms();
+            self.start(player)
+        } else {
+            self.duration_ms = self.prepare_notes(player)?.1;
+            self.position_ms = self.position_ms.min(self.duration_ms);
+            Ok(())
+        }
+    }
+
+    fn start(&mut self, player: &MidiPlayer) -> Result<(), Box<dyn Error>> {
+        let index = self.song_index.ok_or("No song selected")?;
+        let (notes, duration_ms) = self.prepare_notes(player)?;
+        self.duration_ms = duration_ms;
+        self.position_ms = self.position_ms.min(duration_ms);
+        let song = player.get_song(index).ok_or("Invalid song index")?;
+        let setup = program_changes(song, &notes);
+        let tempo = self
+            .tempo_bpm
+            .unwrap_or_else(|| player.scaled_tempo(song.default_tempo));
+        player.command_sender().send(MidiCommand::PlayNotes {
+            song_index: index,
+            notes,
+            position_ms: self.position_ms,
+            setup,
+            beats: player.beat_grid(index, tempo),
+        })?;
+        self.started = Some(Instant::now());
+        self.state = TransportState::Playing;
+        Ok(())
+    }
+
+    fn halt(&mut self, player: &MidiPlayer) {
+        let _ = player.command_sender().send(MidiCommand::Stop);
+        self.started = None;
+    }
+
+    /// Notes for the current song with mutes and transpose applied, plus its length
+    fn prepare_notes(
+        &self,
+        player: &MidiPlayer,
+    ) -> Result<(Vec<crate::Note>, u32), Box<dyn Error>> {
+        let index = self.song_index.ok_or("No song selected")?;
+        let song = player.get_song(index).ok_or("Invalid song index")?;
+        let tempo = self
+            .tempo_bpm
+            .unwrap_or_else(|| player.scaled_tempo(song.default_tempo));
+        let dense_indices: Vec<usize> = (0..song.tracks.len()).collect();
+        let mut notes = player.get_events_for_song(index, &dense_indices, tempo);
+        if notes.is_empty() {
+            return Err(format!("{} has no notes to play", song.name).into());
+        }
+        let duration_ms = crate::calculate_song_duration_ms(&notes);
+        let muted: BTreeSet<usize> = self
+            .muted_tracks
+            .iter()
+            .filter_map(|t| song.track_index_map.get(t).copied())
+            .collect();
+        notes.retain(|n| dense_track_index(song, n.track).map_or(true, |d| !muted.contains(&d)));
+        if self.transpose != 0 {
+            for note in notes.iter_mut().filter(|n| n.chan != 9) {
+                note.pitch = (note.pitch as i16 + self.transpose as i16).clamp(0, 127) as u8;
+            }
+        }
+        Ok((notes, duration_ms))
+    }
+}
+
+impl MidiPlayer {
+    /// `poll_watched_directories` for a loop driving `transport`
+    ///
+    /// Keeps the transport's current and cued songs pointing at the same
+    /// files; problems are logged so a service keeps running.
+    pub fn poll_watched_directories_for(&mut self, transport: &mut Transport) {
+        let changes = self.poll_watched_directories();
+        if let Err(e) = transport.playlist_changed(self, &changes) {
+            eprintln!("⚠️  Failed to follow playlist changes: {}", e);
+        }
+    }
+
+    /// Find a song by index or by (partial, case-insensitive) name
+    pub fn find_song(&self, query: &str) -> Option<usize> {
+        let query = query.trim();
+        if let Ok(index) = query.parse::<usize>() {
+            return (index < self.get_total_song_count()).then_some(index);
+        }
+        let query = query.to_lowercase();
+        let songs = self.get_songs();
+        songs
+            .iter()
+            .position(|s| s.name.to_lowercase() == query)
+            .or_else(|| {
+                songs
+                    .iter()
+                    .position(|s| s.name.to_lowercase().contains(&query))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SongType;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Poll the watched directories until a removal shows up
+    fn poll_until_removed(player: &mut MidiPlayer, transport: &mut Transport, count: usize) {
+        let started = Instant::now();
+        while player.get_total_song_count() != count {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "deletion not noticed"
+            );
+            player.poll_watched_directories_for(transport);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn deleting_watched_songs_keeps_the_transport_on_the_same_songs() {
+        let mut player = MidiPlayer::new_offline().unwrap();
+        let midi = (0..player.get_static_song_count())
+            .filter(|&i| player.get_song(i).unwrap().song_type == SongType::Midi)
+            .find_map(|i| player.song_midi_data(i).map(<[u8]>::to_vec))
+            .expect("no embedded MIDI song");
+        let dir =
+            std::env::temp_dir().join(format!("e_midi_transport_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["a.mid", "b.mid", "c.mid"] {
+            std::fs::write(dir.join(name), &midi).unwrap();
+        }
+        assert_eq!(player.watch_directory(&dir).unwrap(), 3);
+        let index_of = |player: &MidiPlayer, name: &str| {
+            (0..player.get_total_song_count())
+                .find(|&i| Path::new(&player.get_song(i).unwrap().filename).ends_with(name))
+                .unwrap()
+        };
+        let (a, b, c) = (
+            index_of(&player, "a.mid"),
+            index_of(&player, "b.mid"),
+            index_of(&player, "c.mid"),
+        );
+
+        let mut transport = Transport::new();
+        transport.play(&player, b).unwrap();
+        transport.cue(&player, a).unwrap();
+        transport.cue(&player, c).unwrap();
+
+        // Removing another song keeps the current and cued songs
+        let count = player.get_total_song_count();
+        std::fs::remove_file(dir.join("a.mid")).unwrap();
+        poll_until_removed(&mut player, &mut transport, count - 1);
+        assert_eq!(transport.state(), TransportState::Playing);
+        let b = index_of(&player, "b.mid");
+        assert_eq!(transport.song_index(), Some(b));
+        assert_eq!(
+            transport.status(&player).queue,
+            vec![index_of(&player, "c.mid")]
+        );
+
+        // Removing the playing song stops the transport
+        std::fs::remove_file(dir.join("b.mid")).unwrap();
+        poll_until_removed(&mut player, &mut transport, count - 2);
+        assert_eq!(transport.state(), TransportState::Stopped);
+        assert_eq!(transport.song_index(), None);
+        assert_eq!(
+            transport.status(&player).queue,
+            vec![index_of(&player, "c.mid")]
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn a_failed_advance_stops_instead_of_failing() {
+        let player = MidiPlayer::new_offline().unwrap();
+        let mut transport = Transport::new();
+        // A finished song with a cue that no longer exists
+        transport.state = TransportState::Playing;
+        transport.song_index = Some(0);
+        transport.queue.push_back(player.get_total_song_count());
+        assert!(transport.update(&player));
+        assert_eq!(transport.state(), TransportState::Stopped);
+        assert!(!transport.update(&player));
+    }
+}
@@ -22,6 +22,7 @@ use ratatui::{
     Frame, Terminal,
 };
 
+use crate::config::KeyAction;
 use crate::ipc::{AppId, Event as IpcEvent, EventPublisher, EventSubscriber};
 use crate::{set_shutdown_flag, should_shutdown, MidiPlayer};
 
@@ -136,19 +137,26 @@ impl TuiApp {
         }
     }
 
-    pub fn process_ipc_events(&mut self) {
+    pub fn process_ipc_events(&mut self, midi_player: &mut MidiPlayer) {
+        let mut events = Vec::new();
         if let Some(ref mut subscriber) = self.event_subscriber {
             match subscriber.try_receive() {
-                Ok(events) => {
-                    for event in events {
-                        self.handle_ipc_event(event);
-                    }
-                }
+                Ok(received) => events = received,
                 Err(_) => {
                     // No events available or error - continue silently
                 }
             }
         }
+        // Announce ourselves and keep track of the other e_* apps
+        if let Some(manager) = midi_player.ipc_manager.as_mut() {
+            let _ = manager.heartbeat();
+        }
+        for change in midi_player.track_ipc_services(&events) {
+            self.handle_ipc_event(change);
+        }
+        for event in events {
+            self.handle_ipc_event(event);
+        }
     }
     fn handle_ipc_event(&mut self, event: IpcEvent) {
         match event {
@@ -187,9 +195,22 @@ impl TuiApp {
                     }
                 }
             }
+            IpcEvent::MidiSongListUpdated { song_count, .. } => {
+                self.add_log(format!("📂 Song list updated: {} songs", song_count));
+            }
             IpcEvent::SystemHeartbeat { .. } => {
                 // Ignore heartbeat events in TUI
             }
+            IpcEvent::ServiceJoined {
+                app_id, process_id, ..
+            } => {
+                self.add_log(format!("🔗 {:?} joined (pid {})", app_id, process_id));
+            }
+            IpcEvent::ServiceLeft {
+                app_id, process_id, ..
+            } => {
+                self.add_log(format!("💤 {:?} left (pid {})", app_id, process_id));
+            }
             _ => {
                 // Handle other event types as needed
             }
@@ -229,6 +250,9 @@ pub fn run_tui_mode(midi_player: &mut MidiPlayer) -> Result<(), Box<dyn Error>>
         );
     } else {
         app.add_log("✅ IPC subscriber initialized for real-time updates".to_string());
+        if let Some(manager) = midi_player.ipc_manager.as_ref() {
+            let _ = manager.say_hello();
+        }
     }
 
     // Initialize command publisher for sending commands
@@ -286,7 +310,8 @@ fn run_tui_app<B: ratatui::backend::Backend>(
 ) -> Result<(), Box<dyn Error>> {
     loop {
         // Process IPC events for real-time updates
-        app.process_ipc_events();
+        app.process_ipc_events(midi_player);
+        midi_player.poll_watched_directories();
         // Ensure list state is synchronized before each render
         let song_count = midi_player.get_total_song_count();
         if song_count > 0 {
@@ -338,22 +363,26 @@ fn handle_key_event(
     app: &mut TuiApp,
     midi_player: &mut MidiPlayer,
 ) -> Result<bool, Box<dyn Error>> {
-    match (key.code, key.modifiers) {
-        // Ctrl+C - Exit immediately
-        (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-            app.add_log("🛑 Ctrl+C pressed, exiting".to_string());
-            app.stop_playback();
-            set_shutdown_flag();
-            return Ok(true);
-        }
+    // Ctrl+C - Exit immediately (not rebindable)
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        app.add_log("🛑 Ctrl+C pressed, exiting".to_string());
+        app.stop_playback();
+        set_shutdown_flag();
+        return Ok(true);
+    }
 
-        // Escape or 'q' - Exit
-        (KeyCode::Esc, _) | (KeyCode::Char('q'), _) => {
+    let keys = midi_player.key_bindings().clone();
+    let Some(action) = keys.action(key.code, key.modifiers) else {
+        return Ok(false);
+    };
+    match action {
+        // Exit
+        KeyAction::Quit => {
             app.stop_playback();
             app.should_quit = true;
             return Ok(true);
-        } // Arrow keys - Navigate song list
-        (KeyCode::Up, _) => {
+        } // Navigate song list
+        KeyAction::Up => {
             let song_count = midi_player.get_total_song_count();
             if song_count > 0 {
                 let old_selection = app.selected_song;
@@ -369,7 +398,7 @@ fn handle_key_event(
             }
         }
 
-        (KeyCode::Down, _) => {
+        KeyAction::Down => {
             let song_count = midi_player.get_total_song_count();
             if song_count > 0 {
                 let old_selection = app.selected_song;
@@ -382,26 +411,29 @@ fn handle_key_event(
         }
 
         // Page Up/Page Down for log scrolling
-        (KeyCode::PageUp, _) => {
+        KeyAction::PageUp => {
             for _ in 0..5 {
                 app.scroll_log_up();
             }
         }
 
-        (KeyCode::PageDown, _) => {
+        KeyAction::PageDown => {
             for _ in 0..5 {
                 app.scroll_log_down();
             }
-        } // Enter or Space - Play selected song
-        (KeyCode::Enter, _) | (KeyCode::Char(' '), _) => {
+        } // Play selected song
+        KeyAction::Play => {
             if !app.is_playing.load(Ordering::Relaxed) && midi_player.get_total_song_count() > 0 {
                 app.add_log(format!("▶️ Playing song {}", app.selected_song));
                 start_playback(app, midi_player)?;
             } else if app.is_playing.load(Ordering::Relaxed) {
-                app.add_log("⚠️ Already playing - press 'S' to stop first".to_string());
+                app.add_log(format!(
+                    "⚠️ Already playing - press '{}' to stop first",
+                    keys.label(KeyAction::Stop)
+                ));
             }
-        } // 's' - Stop playback
-        (KeyCode::Char('s'), _) => {
+        } // Stop playback
+        KeyAction::Stop => {
             if app.is_playing.load(Ordering::Relaxed) {
                 app.add_log("⏹️ Sending stop command via IPC...".to_string());
 
@@ -420,8 +452,8 @@ fn handle_key_event(
             }
         }
 
-        // 'l' - List songs (refresh)
-        (KeyCode::Char('l'), _) => {
+        // List songs (refresh)
+        KeyAction::List => {
             app.add_log(format!(
                 "📀 {} total songs available ({} static + {} dynamic)",
                 midi_player.get_total_song_count(),
@@ -430,8 +462,8 @@ fn handle_key_event(
             ));
         }
 
-        // 't' - Tempo adjustment (during playback)
-        (KeyCode::Char('t'), _) => {
+        // Tempo adjustment (during playback)
+        KeyAction::Tempo => {
             if app.is_playing.load(Ordering::Relaxed) {
                 if let Some(ref info) = app.playback_info {
                     let current_tempo = info.tempo.load(Ordering::Relaxed);
@@ -449,8 +481,8 @@ fn handle_key_event(
             } else {
                 app.add_log("⚠️ Tempo can only be changed during playback".to_string());
             }
-        } // 'n' - Next song (during playback)
-        (KeyCode::Char('n'), _) => {
+        } // Next song (during playback)
+        KeyAction::Next => {
             if app.is_playing.load(Ordering::Relaxed) {
                 let song_count = midi_player.get_total_song_count();
                 if song_count > 0 {
@@ -475,8 +507,8 @@ fn handle_key_event(
             }
         }
 
-        // 'p' - Previous song
-        (KeyCode::Char('p'), _) => {
+        // Previous song
+        KeyAction::Previous => {
             if app.is_playing.load(Ordering::Relaxed) {
                 let song_count = midi_player.get_total_song_count();
                 if song_count > 0 {
@@ -508,18 +540,33 @@ fn handle_key_event(
             }
         }
 
-        // 'h' - Help
-        (KeyCode::Char('h'), _) => {
-            app.add_log(
-                "🆘 Navigation: ↑↓=select, Enter/Space=play, S=stop, L=refresh".to_string(),
-            );
-            app.add_log(
-                "🆘 Playback: T=tempo, N=next, P=prev, Q/Esc=quit, Ctrl+C=force".to_string(),
-            );
-            app.add_log("🆘 Scrolling: PgUp/PgDn=scroll logs, Alt+C=clear dynamic".to_string());
+        // Help
+        KeyAction::Help => {
+            let k = |action| keys.label(action);
+            app.add_log(format!(
+                "🆘 Navigation: {}/{}=select, {}=play, {}=stop, {}=refresh",
+                k(KeyAction::Up),
+                k(KeyAction::Down),
+                k(KeyAction::Play),
+                k(KeyAction::Stop),
+                k(KeyAction::List)
+            ));
+            app.add_log(format!(
+                "🆘 Playback: {}=tempo, {}=next, {}=prev, {}=quit, Ctrl+C=force",
+                k(KeyAction::Tempo),
+                k(KeyAction::Next),
+                k(KeyAction::Previous),
+                k(KeyAction::Quit)
+            ));
+            app.add_log(format!(
+                "🆘 Scrolling: {}/{}=scroll logs, {}=clear dynamic",
+                k(KeyAction::PageUp),
+                k(KeyAction::PageDown),
+                k(KeyAction::ClearDynamic)
+            ));
         }
-        // Alt+C - Clear dynamic songs
-        (KeyCode::Char('c'), KeyModifiers::ALT) => {
+        // Clear dynamic songs
+        KeyAction::ClearDynamic => {
             midi_player.clear_dynamic_songs();
             app.add_log("🗑️ Dynamic songs cleared".to_string());
 
@@ -528,8 +575,6 @@ fn handle_key_event(
                 app.selected_song = midi_player.get_total_song_count().saturating_sub(1);
             }
         }
-
-        _ => {}
     }
 
     Ok(false)
@@ -680,7 +725,7 @@ fn ui(f: &mut Frame, app: &mut TuiApp, midi_player: &MidiPlayer) {
     let right_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(10), // Playback info (fixed)
+            Constraint::Length(11), // Playback info (fixed)
             Constraint::Min(5),     // Logs (remaining space)
         ])
         .split(main_chunks[1]);
@@ -689,7 +734,7 @@ fn ui(f: &mut Frame, app: &mut TuiApp, midi_player: &MidiPlayer) {
     if left_chunks.len() >= 3 && right_chunks.len() >= 2 {
         render_header(f, left_chunks[0], midi_player);
         render_song_list(f, left_chunks[1], app, midi_player);
-        render_controls(f, left_chunks[2], app);
+        render_controls(f, left_chunks[2], app, midi_player);
         render_playback_info(f, right_chunks[0], app, midi_player);
         render_log_messages(f, right_chunks[1], app);
     } else {
@@ -797,13 +842,32 @@ fn render_song_list(f: &mut Frame, area: Rect, app: &mut TuiApp, midi_player: &M
     f.render_stateful_widget(list, area, &mut fresh_list_state);
 }
 
-fn render_controls(f: &mut Frame, area: Rect, _app: &TuiApp) {
+fn render_controls(f: &mut Frame, area: Rect, _app: &TuiApp, midi_player: &MidiPlayer) {
+    let keys = midi_player.key_bindings();
+    let k = |action| keys.label(action);
     let controls_text = vec![
         Line::from("CONTROLS:"),
-        Line::from("Up/Down: Navigate  Enter: Play  S: Stop"),
-        Line::from("T: Tempo  N: Next  P: Previous"),
-        Line::from("L: Refresh  H: Help  PgUp/PgDn: Scroll"),
-        Line::from("Q/Esc: Quit  Ctrl+C: Force exit"),
+        Line::from(format!(
+            "{}/{}: Navigate  {}: Play  {}: Stop",
+            k(KeyAction::Up),
+            k(KeyAction::Down),
+            k(KeyAction::Play),
+            k(KeyAction::Stop)
+        )),
+        Line::from(format!(
+            "{}: Tempo  {}: Next  {}: Previous",
+            k(KeyAction::Tempo),
+            k(KeyAction::Next),
+            k(KeyAction::Previous)
+        )),
+        Line::from(format!(
+            "{}: Refresh  {}: Help  {}/{}: Scroll",
+            k(KeyAction::List),
+            k(KeyAction::Help),
+            k(KeyAction::PageUp),
+            k(KeyAction::PageDown)
+        )),
+        Line::from(format!("{}: Quit  Ctrl+C: Force exit", k(KeyAction::Quit))),
         Line::from(""),
         Line::from("Legend: [S]=Static [D]=Dynamic >=Playing"),
     ];
@@ -816,7 +880,7 @@ fn render_controls(f: &mut Frame, area: Rect, _app: &TuiApp) {
 }
 
 fn render_playback_info(f: &mut Frame, area: Rect, app: &TuiApp, midi_player: &MidiPlayer) {
-    let info_text = if let Some(ref info) = app.playback_info {
+    let mut info_text = if let Some(ref info) = app.playback_info {
         let current_time = info.current_time.load(Ordering::Relaxed);
         let current_tempo = info.tempo.load(Ordering::Relaxed);
         let is_playing = midi_player.is_playing();
@@ -855,6 +919,8 @@ fn render_playback_info(f: &mut Frame, area: Rect, app: &TuiApp, midi_player: &M
         ]
     };
 
+    info_text.push(Line::from(connected_apps(midi_player)));
+
     let playback = Paragraph::new(info_text)
         .block(Block::default().borders(Borders::ALL).title("Playback"))
         .wrap(Wrap { trim: true });
@@ -862,6 +928,31 @@ fn render_playback_info(f: &mut Frame, area: Rect, app: &TuiApp, midi_player: &M
     f.render_widget(playback, area);
 }
 
+/// The e_* apps heard from over IPC, this process marked with `*`
+fn connected_apps(midi_player: &MidiPlayer) -> String {
+    let Some(manager) = midi_player.ipc_manager.as_ref() else {
+        return "Apps: IPC off".to_string();
+    };
+    let this = std::process::id();
+    let apps: Vec<String> = manager
+        .services()
+        .iter()
+        .map(|service| {
+            format!(
+                "{:?} {}{}",
+                service.app_id,
+                service.process_id,
+                if service.process_id == this { "*" } else { "" }
+            )
+        })
+        .collect();
+    if apps.is_empty() {
+        "Apps: none seen yet".to_string()
+    } else {
+        format!("Apps: {}", apps.join(", "))
+    }
+}
+
 fn render_log_messages(f: &mut Frame, area: Rect, app: &TuiApp) {
     let visible_height = area.height.saturating_sub(2) as usize; // Account for borders
 
@@ -0,0 +1,355 @@
+//! Directory watch mode.
+//!
+//! Watched directories are scanned once and then followed through filesystem
+//! notifications: new MIDI/MusicXML files are added to the dynamic playlist,
+//! changed ones are reparsed in place and deleted ones are removed. Events are
+//! debounced so a DAW writing a file in several steps triggers one reload, and
+//! every batch of changes publishes `MidiSongListUpdated` over IPC.
+
+use crate::ipc::Event;
+use crate::{MidiPlayer, SongType};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a path must stay quiet before it is reloaded
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Whether a path has one of the song extensions the player loads
+pub fn is_song_file(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    ext == "mid" || ext == "xml" || ext == "musicxml"
+}
+
+/// Recursive filesystem watcher with per-path debouncing
+pub struct DirectoryWatcher {
+    watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    roots: Vec<PathBuf>,
+    /// Changed paths and when they last changed
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl std::fmt::Debug for DirectoryWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectoryWatcher")
+            .field("roots", &self.roots)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl DirectoryWatcher {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)?;
+        Ok(DirectoryWatcher {
+            watcher,
+            events,
+            roots: Vec::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Start watching a directory recursively, returning its canonical path
+    pub fn watch<P: AsRef<Path>>(&mut self, dir: P) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            return Err(format!("Path is not a directory: {}", dir.display()).into());
+        }
+        let root = fs::canonicalize(dir)?;
+        if !self.roots.contains(&root) {
+            self.watcher
+                .watch(&root, RecursiveMode::Recursive)
+                .map_err(|e| format!("Cannot watch {}: {}", root.display(), e))?;
+            self.roots.push(root.clone());
+        }
+        Ok(root)
+    }
+
+    /// Directories being watched
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Drain notifications and return the paths that have settled
+    pub fn take_settled(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        while let Ok(result) = self.events.try_recv() {
+            match result {
+                // Reading a file to parse it generates access events; skip them
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(event) => {
+                    for path in event.paths {
+                        self.pending.insert(path, now);
+                    }
+                }
+                Err(e) => eprintln!("⚠️  Watch error: {}", e),
+            }
+        }
+        let settled: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in &settled {
+            self.pending.remove(path);
+        }
+        settled
+    }
+}
+
+/// How one `poll_watched_directories` call changed the playlist
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlaylistChanges {
+    /// Global indices of removed songs, in removal order
+    ///
+    /// Each index refers to the playlist as it was right before that removal,
+    /// so apply them one after the other.
+    pub removed: Vec<usize>,
+    /// Global indices of songs reparsed in place, in the final playlist
+    pub reloaded: Vec<usize>,
+    /// Number of songs appended to the playlist
+    pub added: usize,
+}
+
+impl PlaylistChanges {
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty() && self.reloaded.is_empty() && self.added == 0
+    }
+}
+
+/// Stdin reader for prompts that keep following watched directories
+///
+/// Stdin is only read while such a prompt asks for a line, so other prompts
+/// can keep reading it directly.
+struct PromptReader {
+    requests: mpsc::Sender<()>,
+    lines: mpsc::Receiver<io::Result<String>>,
+}
+
+fn prompt_reader() -> &'static Mutex<PromptReader> {
+    static READER: OnceLock<Mutex<PromptReader>> = OnceLock::new();
+    READER.get_or_init(|| {
+        let (requests, pending) = mpsc::channel::<()>();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for () in pending {
+                let mut line = String::new();
+                let result = io::stdin().read_line(&mut line).map(|_| line);
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(PromptReader { requests, lines })
+    })
+}
+
+impl MidiPlayer {
+    /// Read a line from stdin, applying watched directory changes meanwhile
+    ///
+    /// `prompt` is printed again after changes were reported. Returns an empty
+    /// string at end of input, like `read_line`.
+    pub fn read_line_watching(&mut self, prompt: &str) -> io::Result<String> {
+        if self.watcher.is_none() {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            return Ok(line);
+        }
+        let stopped = || io::Error::other("stdin reader stopped");
+        let reader = prompt_reader().lock().unwrap_or_else(|e| e.into_inner());
+        reader.requests.send(()).map_err(|_| stopped())?;
+        loop {
+            match reader.lines.recv_timeout(DEBOUNCE) {
+                Ok(line) => return line,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    if !self.poll_watched_directories().is_empty() {
+                        print!("{}", prompt);
+                        io::stdout().flush()?;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Err(stopped()),
+            }
+        }
+    }
+
+    /// Load a directory's songs and keep the dynamic playlist in sync with it
+    ///
+    /// Returns how many songs were added by the initial scan.
+    pub fn watch_directory<P: AsRef<Path>>(&mut self, dir: P) -> Result<usize, Box<dyn Error>> {
+        if self.watcher.is_none() {
+            self.watcher = Some(DirectoryWatcher::new()?);
+        }
+        let root = self.watcher.as_mut().unwrap().watch(dir)?;
+        println!("👀 Watching directory: {}", root.display());
+        let mut changes = PlaylistChanges::default();
+        self.sync_watched_path(&root, &mut changes);
+        self.save_library();
+        Ok(changes.added)
+    }
+
+    /// Directories being watched
+    pub fn watched_directories(&self) -> &[PathBuf] {
+        self.watcher.as_ref().map_or(&[], |w| w.roots())
+    }
+
+    /// Apply settled filesystem changes to the dynamic playlist
+    ///
+    /// Call this regularly from a UI or service loop. Returns what changed;
+    /// unless that is nothing, `MidiSongListUpdated` has been published.
+    /// Loops driving a `Transport` pass the result to
+    /// `Transport::playlist_changed` so it keeps pointing at the right songs.
+    pub fn poll_watched_directories(&mut self) -> PlaylistChanges {
+        let mut changes = PlaylistChanges::default();
+        let settled = match self.watcher.as_mut() {
+            Some(watcher) => watcher.take_settled(),
+            None => return changes,
+        };
+        for path in settled {
+            self.sync_watched_path(&path, &mut changes);
+        }
+        if !changes.is_empty() {
+            self.save_library();
+            self.publish_midi_event(Event::MidiSongListUpdated {
+                song_count: self.get_total_song_count(),
+                timestamp: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64,
+            });
+        }
+        changes
+    }
+
+    /// Remove a dynamic song, returning it
+    ///
+    /// Playback of the removed song is stopped; the current song index of a
+    /// later song is shifted down to follow it.
+    pub fn remove_dynamic_song(&mut self, dynamic_index: usize) -> Option<crate::SongInfo> {
+        if dynamic_index >= self.dynamic_songs.len() {
+            return None;
+        }
+        let index = self.get_static_song_count() + dynamic_index;
+        match self.current_song_index {
+            Some(current) if current == index => self.stop_playback(),
+            Some(current) if current > index => self.current_song_index = Some(current - 1),
+            _ => {}
+        }
+        self.dynamic_midi_data.remove(dynamic_index);
+        let song = self.dynamic_songs.remove(dynamic_index);
+        println!("➖ Removed song: {} (was index {})", song.name, index);
+        Some(song)
+    }
+
+    /// Dynamic index of the song loaded from this file
+    fn dynamic_index_of(&self, path: &Path) -> Option<usize> {
+        self.dynamic_songs
+            .iter()
+            .position(|s| Path::new(&s.filename) == path)
+    }
+
+    /// Bring the playlist in line with a changed file or directory
+    fn sync_watched_path(&mut self, path: &Path, changes: &mut PlaylistChanges) {
+        if path.is_dir() {
+            if let Ok(entries) = fs::read_dir(path) {
+                for entry in entries.flatten() {
+                    // Don't follow symlinked directories, they may loop
+                    let is_link = entry.file_type().is_ok_and(|t| t.is_symlink());
+                    let entry_path = entry.path();
+                    if !(is_link && entry_path.is_dir()) {
+                        self.sync_watched_path(&entry_path, changes);
+                    }
+                }
+            }
+        } else if path.is_file() {
+            if !is_song_file(path) {
+                return;
+            }
+            let result = match self.dynamic_index_of(path) {
+                Some(i) => self.reload_dynamic_song(i, path).map(|reloaded| {
+                    if reloaded {
+                        let index = self.get_static_song_count() + i;
+                        if !changes.reloaded.contains(&index) {
+                            changes.reloaded.push(index);
+                        }
+                    }
+                }),
+                None => self
+                    .add_watched_song(path)
+                    .map(|added| changes.added += added as usize),
+            };
+            if let Err(e) = result {
+                println!("❌ Failed to load {}: {}", path.display(), e);
+            }
+        } else {
+            // Deleted: the file itself or everything under a deleted directory
+            while let Some(i) = self
+                .dynamic_songs
+                .iter()
+                .position(|s| Path::new(&s.filename).starts_with(path))
+            {
+                let index = self.get_static_song_count() + i;
+                if let Some(song) = self.remove_dynamic_song(i) {
+                    self.with_library(|index| index.remove(Path::new(&song.filename)));
+                }
+                changes.removed.push(index);
+                // Reloads of this or later songs now sit one index lower
+                changes.reloaded.retain(|&r| r != index);
+                for reloaded in changes.reloaded.iter_mut().filter(|r| **r > index) {
+                    *reloaded -= 1;
+                }
+            }
+        }
+    }
+
+    /// Add a new file, keyed by its full path so later changes can find it
+    fn add_watched_song(&mut self, path: &Path) -> Result<bool, Box<dyn Error>> {
+        let count = self.dynamic_songs.len();
+        self.add_song_from_file(path)?;
+        if self.dynamic_songs.len() == count {
+            return Ok(false);
+        }
+        // Library entries are named by canonical path
+        self.dynamic_songs[count].filename = path.to_string_lossy().to_string();
+        Ok(true)
+    }
+
+    /// Reparse a changed file in place, returning whether its content changed
+    fn reload_dynamic_song(
+        &mut self,
+        dynamic_index: usize,
+        path: &Path,
+    ) -> Result<bool, Box<dyn Error>> {
+        let song_type = self.dynamic_songs[dynamic_index].song_type.clone();
+        let (mut song_info, data) = if song_type == SongType::MusicXml {
+            (self.musicxml_song_info(path)?, Vec::new())
+        } else {
+            let data = fs::read(path)?;
+            if data == self.dynamic_midi_data[dynamic_index] {
+                return Ok(false);
+            }
+            (self.midi_song_info(path, &data)?, data)
+        };
+        song_info.filename = path.to_string_lossy().to_string();
+        println!(
+            "🔄 Reloaded song: {} (index {})",
+            song_info.name,
+            self.get_static_song_count() + dynamic_index
+        );
+        self.dynamic_songs[dynamic_index] = song_info;
+        self.dynamic_midi_data[dynamic_index] = data;
+        Ok(true)
+    }
+}
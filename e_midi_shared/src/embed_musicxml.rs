@@ -397,6 +397,44 @@ pub fn extract_part_list_mapping(xml_path: &Path) -> HashMap<String, (String, u8
     mapping
 }
 
+/// Reads the first time signature (beats, beat type) and key signature (fifths) of a MusicXML file.
+pub fn read_signatures(xml_path: &Path) -> (Option<(u8, u8)>, Option<i8>) {
+    let file = match std::fs::File::open(xml_path) {
+        Ok(f) => f,
+        Err(_) => return (None, None),
+    };
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    let mut buf = Vec::new();
+    let mut beats: Option<u8> = None;
+    let mut beat_type: Option<u8> = None;
+    let mut fifths: Option<i8> = None;
+    while beats.is_none() || beat_type.is_none() || fifths.is_none() {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                let name = e.name().as_ref().to_vec();
+                if !matches!(name.as_slice(), b"beats" | b"beat-type" | b"fifths") {
+                    buf.clear();
+                    continue;
+                }
+                if let Ok(Event::Text(t)) = reader.read_event_into(&mut buf) {
+                    let text = t.escape_ascii().to_string();
+                    let text = text.trim();
+                    match name.as_slice() {
+                        b"beats" => beats = beats.or(text.parse().ok()),
+                        b"beat-type" => beat_type = beat_type.or(text.parse().ok()),
+                        b"fifths" => fifths = fifths.or(text.parse().ok()),
+                        _ => {}
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    (beats.zip(beat_type), fifths)
+}
+
 // --- Conversion helper: XmlSongInfo -> SongInfo/Note (for main player integration) ---
 // Types are available in the crate root; no import needed.
 
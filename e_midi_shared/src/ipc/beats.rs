@@ -0,0 +1,140 @@
+//! Beat and bar clock over IPC
+//!
+//! The player announces every beat of the playing song twice: as a serde
+//! `Event::MidiBeat` on the event service, and as a fixed-layout
+//! `MidiBeatEvent` on `E_MIDI_BEATS_SERVICE` for subscribers that want
+//! zero-copy. Both are published from one relay thread, so the playback
+//! thread only hands a beat over a channel.
+
+use iceoryx2::port::publisher::Publisher;
+use iceoryx2::port::subscriber::Subscriber;
+use iceoryx2::prelude::*;
+use once_cell::sync::OnceCell;
+use std::sync::mpsc;
+
+use super::{unix_micros, AppId, Event, EventPublisher};
+use crate::ipc_protocol::MidiBeatEvent;
+
+pub const E_MIDI_BEATS_SERVICE: &str = "e_midi_beats";
+
+static BEAT_RELAY: OnceCell<mpsc::Sender<MidiBeatEvent>> = OnceCell::new();
+
+impl From<&MidiBeatEvent> for Event {
+    fn from(beat: &MidiBeatEvent) -> Self {
+        Event::MidiBeat {
+            song_index: beat.song_index as usize,
+            bar: beat.bar,
+            beat: beat.beat,
+            beats_per_bar: beat.beats_per_bar,
+            beat_unit: beat.beat_unit,
+            tempo_bpm: beat.tempo_bpm as u32,
+            position_ms: beat.position_ms,
+            timestamp: beat.timestamp_us / 1000,
+        }
+    }
+}
+
+/// Publish a beat in both forms (best-effort, from any thread)
+///
+/// The timestamp is set here if the caller left it at 0.
+pub fn publish_beat(mut beat: MidiBeatEvent) {
+    if beat.timestamp_us == 0 {
+        beat.timestamp_us = unix_micros();
+    }
+    let relay = BEAT_RELAY.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<MidiBeatEvent>();
+        std::thread::spawn(move || {
+            // Publishers are !Send; they live on this thread for the process lifetime
+            let zero_copy = BeatPublisher::new().ok();
+            let events = EventPublisher::new(AppId::EMidi).ok();
+            for beat in receiver {
+                if let Some(publisher) = &zero_copy {
+                    let _ = publisher.publish(&beat);
+                }
+                if let Some(publisher) = &events {
+                    let _ = publisher.publish(Event::from(&beat));
+                }
+            }
+        });
+        sender
+    });
+    let _ = relay.send(beat);
+}
+
+#[derive(Debug)]
+pub struct BeatPublisher {
+    publisher: Publisher<ipc::Service, MidiBeatEvent, ()>,
+}
+
+impl BeatPublisher {
+    pub fn new() -> Result<Self, String> {
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_BEATS_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<MidiBeatEvent>()
+            .max_publishers(16)
+            .max_subscribers(16)
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let publisher = service
+            .publisher_builder()
+            .create()
+            .map_err(|e| format!("Failed to create publisher: {e:?}"))?;
+        Ok(Self { publisher })
+    }
+
+    pub fn publish(&self, beat: &MidiBeatEvent) -> Result<(), String> {
+        self.publisher
+            .send_copy(*beat)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to publish: {e:?}"))
+    }
+}
+
+#[derive(Debug)]
+pub struct BeatSubscriber {
+    subscriber: Subscriber<ipc::Service, MidiBeatEvent, ()>,
+}
+
+impl BeatSubscriber {
+    pub fn new() -> Result<Self, String> {
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_BEATS_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<MidiBeatEvent>()
+            .max_publishers(16)
+            .max_subscribers(16)
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let subscriber = service
+            .subscriber_builder()
+            .create()
+            .map_err(|e| format!("Failed to create subscriber: {e:?}"))?;
+        Ok(Self { subscriber })
+    }
+
+    /// Receive pending beats (non-blocking)
+    pub fn try_receive(&mut self) -> Result<Vec<MidiBeatEvent>, String> {
+        let mut beats = Vec::new();
+        while let Some(sample) = self
+            .subscriber
+            .receive()
+            .map_err(|e| format!("Receive error: {e:?}"))?
+        {
+            beats.push(*sample);
+        }
+        Ok(beats)
+    }
+}
+
+unsafe impl Send for BeatSubscriber {}
@@ -0,0 +1,377 @@
+//! Chunked transfer of messages larger than one IPC payload
+//!
+//! The sender splits a message into numbered `MessageChunk` events sharing a
+//! `transfer_id`; every chunk also carries the total length and a CRC-32 of
+//! the whole message. Receivers feed chunks to a `ChunkAssembler`, which puts
+//! the parts back in order whatever order they arrive in, ignores duplicates
+//! and checks length and checksum once all parts are in.
+//!
+//! Chunks can be lost when a subscriber's buffer overflows, so receivers
+//! answer with `MessageChunkAck`: once the message is complete, and whenever
+//! the last part arrives while others are still missing. `send_chunked`
+//! resends the missing parts until the message is acknowledged.
+//!
+//! What a message is for is up to its `topic`; `TOPIC_SONG` carries a song
+//! file for `MidiCommandLoadSong`.
+
+use super::{
+    generate_event_id, Event, EventId, EventPublisher, EventSubscriber, IpcError, IpcResult,
+    MAX_BODY_SIZE,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Message bytes per chunk: JSON spells each byte with up to 4 characters,
+/// and the rest of the event needs some room
+pub const CHUNK_SIZE: usize = (MAX_BODY_SIZE - 256) / 4;
+
+/// Largest message a receiver accepts
+pub const MAX_CHUNKED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest topic that leaves room for a full chunk
+pub const MAX_TOPIC_LEN: usize = 64;
+
+/// How long a transfer may take by default
+pub const DEFAULT_CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between chunks, so a subscriber polling every few ms keeps up
+pub const CHUNK_INTERVAL: Duration = Duration::from_millis(2);
+
+/// How long the sender waits for an ack before asking again
+pub const CHUNK_ACK_WAIT: Duration = Duration::from_millis(250);
+
+/// A song file: `MidiCommandLoadSong` names the transfer and the file
+pub const TOPIC_SONG: &str = "midi.song";
+
+/// CRC-32 (IEEE) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Split a message into the `MessageChunk` events that carry it
+pub fn message_chunks(transfer_id: EventId, topic: &str, data: &[u8]) -> Vec<Event> {
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(CHUNK_SIZE).collect()
+    };
+    let parts = chunks.len() as u32;
+    let checksum = crc32(data);
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(part, chunk)| Event::MessageChunk {
+            transfer_id,
+            topic: topic.to_string(),
+            part: part as u32,
+            parts,
+            total_len: data.len() as u64,
+            checksum,
+            data: chunk.to_vec(),
+            timestamp: generate_event_id(),
+        })
+        .collect()
+}
+
+/// A reassembled and verified message
+#[derive(Debug, Clone)]
+pub struct ChunkedMessage {
+    pub transfer_id: EventId,
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct PendingMessage {
+    topic: String,
+    total_len: u64,
+    checksum: u32,
+    parts: Vec<Option<Vec<u8>>>,
+    updated: Instant,
+}
+
+impl PendingMessage {
+    fn missing(&self) -> Vec<u32> {
+        self.parts
+            .iter()
+            .enumerate()
+            .filter(|(_, part)| part.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+}
+
+/// Reassembles the chunked messages arriving at one app
+///
+/// Complete messages are kept until taken or until they time out, so the
+/// command that uses one may arrive after the last chunk.
+#[derive(Debug)]
+pub struct ChunkAssembler {
+    timeout: Duration,
+    pending: HashMap<EventId, PendingMessage>,
+    complete: HashMap<EventId, (ChunkedMessage, Instant)>,
+}
+
+impl Default for ChunkAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkAssembler {
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_CHUNK_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+            complete: HashMap::new(),
+        }
+    }
+
+    /// Feed a received event; returns the `MessageChunkAck` to publish, if any
+    ///
+    /// A message failing its checks is dropped and acknowledged with an error.
+    pub fn handle(&mut self, event: &Event) -> Option<Event> {
+        let Event::MessageChunk {
+            transfer_id,
+            topic,
+            part,
+            parts,
+            total_len,
+            checksum,
+            data,
+            ..
+        } = event
+        else {
+            return None;
+        };
+        let transfer_id = *transfer_id;
+        if self.complete.contains_key(&transfer_id) {
+            // The sender missed our ack
+            return Some(Event::message_chunk_ack(transfer_id, Vec::new(), None));
+        }
+        if let Err(e) = Self::check(*part, *parts, *total_len, data) {
+            self.pending.remove(&transfer_id);
+            return Some(Event::message_chunk_ack(transfer_id, Vec::new(), Some(e)));
+        }
+        let pending = self
+            .pending
+            .entry(transfer_id)
+            .or_insert_with(|| PendingMessage {
+                topic: topic.clone(),
+                total_len: *total_len,
+                checksum: *checksum,
+                parts: vec![None; *parts as usize],
+                updated: Instant::now(),
+            });
+        if pending.parts.len() != *parts as usize
+            || pending.total_len != *total_len
+            || pending.checksum != *checksum
+            || pending.topic != *topic
+        {
+            self.pending.remove(&transfer_id);
+            return Some(Event::message_chunk_ack(
+                transfer_id,
+                Vec::new(),
+                Some("Chunks of one transfer disagree about the message".to_string()),
+            ));
+        }
+        pending.parts[*part as usize] = Some(data.clone());
+        pending.updated = Instant::now();
+
+        let missing = pending.missing();
+        if !missing.is_empty() {
+            // Report gaps once the sender has sent everything
+            return (*part + 1 == *parts)
+                .then(|| Event::message_chunk_ack(transfer_id, missing, None));
+        }
+        let pending = self.pending.remove(&transfer_id)?;
+        let data: Vec<u8> = pending.parts.into_iter().flatten().flatten().collect();
+        if data.len() as u64 != pending.total_len {
+            return Some(Event::message_chunk_ack(
+                transfer_id,
+                Vec::new(),
+                Some(format!(
+                    "Message is {} bytes, expected {}",
+                    data.len(),
+                    pending.total_len
+                )),
+            ));
+        }
+        if crc32(&data) != pending.checksum {
+            return Some(Event::message_chunk_ack(
+                transfer_id,
+                Vec::new(),
+                Some("Checksum mismatch".to_string()),
+            ));
+        }
+        self.complete.insert(
+            transfer_id,
+            (
+                ChunkedMessage {
+                    transfer_id,
+                    topic: pending.topic,
+                    data,
+                },
+                Instant::now(),
+            ),
+        );
+        Some(Event::message_chunk_ack(transfer_id, Vec::new(), None))
+    }
+
+    /// Reject chunks that can't belong to a well-formed message
+    fn check(part: u32, parts: u32, total_len: u64, data: &[u8]) -> Result<(), String> {
+        if total_len > MAX_CHUNKED_SIZE as u64 {
+            return Err(format!(
+                "Message of {} bytes exceeds maximum {}",
+                total_len, MAX_CHUNKED_SIZE
+            ));
+        }
+        // Senders split into full chunks; an empty message still takes one part
+        let expected = (total_len as usize).div_ceil(CHUNK_SIZE).max(1);
+        if parts as usize != expected || part >= parts {
+            return Err(format!(
+                "Invalid chunk {} of {} for a {} byte message",
+                part, parts, total_len
+            ));
+        }
+        if data.len() > CHUNK_SIZE || data.len() as u64 > total_len {
+            return Err(format!(
+                "Chunk of {} bytes exceeds the message ({} bytes)",
+                data.len(),
+                total_len
+            ));
+        }
+        Ok(())
+    }
+
+    /// Take a complete message out of the assembler
+    pub fn take(&mut self, transfer_id: EventId) -> Option<ChunkedMessage> {
+        self.complete
+            .remove(&transfer_id)
+            .map(|(message, _)| message)
+    }
+
+    /// Whether some chunks of this transfer arrived but not all
+    pub fn is_pending(&self, transfer_id: EventId) -> bool {
+        self.pending.contains_key(&transfer_id)
+    }
+
+    /// Drop transfers idle for longer than the timeout and complete messages
+    /// nobody took, returning their ids
+    pub fn expire(&mut self) -> Vec<EventId> {
+        let timeout = self.timeout;
+        let mut expired: Vec<EventId> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.updated.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        expired.extend(
+            self.complete
+                .iter()
+                .filter(|(_, (_, done))| done.elapsed() >= timeout)
+                .map(|(id, _)| *id),
+        );
+        for id in &expired {
+            self.pending.remove(id);
+            self.complete.remove(id);
+        }
+        expired
+    }
+}
+
+/// Send a message in chunks and wait until a receiver has all of it
+///
+/// Parts a receiver reports missing are sent again. Other events received
+/// while waiting are discarded. Returns the transfer id to refer to the
+/// message by, or an error if no receiver completed it within the timeout.
+pub fn send_chunked(
+    publisher: &EventPublisher,
+    subscriber: &mut EventSubscriber,
+    topic: &str,
+    data: &[u8],
+    timeout: Duration,
+) -> IpcResult<EventId> {
+    if topic.len() > MAX_TOPIC_LEN {
+        return Err(IpcError::PayloadTooLarge(format!(
+            "Topic of {} bytes exceeds maximum {}",
+            topic.len(),
+            MAX_TOPIC_LEN
+        )));
+    }
+    if data.len() > MAX_CHUNKED_SIZE {
+        return Err(IpcError::PayloadTooLarge(format!(
+            "Message of {} bytes exceeds maximum {}",
+            data.len(),
+            MAX_CHUNKED_SIZE
+        )));
+    }
+    let transfer_id = generate_event_id();
+    let chunks = message_chunks(transfer_id, topic, data);
+    let last = chunks.len() - 1;
+    let deadline = Instant::now() + timeout;
+    let mut to_send: Vec<usize> = (0..chunks.len()).collect();
+    while Instant::now() < deadline {
+        for &part in &to_send {
+            publisher.publish(chunks[part].clone())?;
+            std::thread::sleep(CHUNK_INTERVAL);
+        }
+        // Without an ack, the last part goes again to ask for one
+        to_send = vec![last];
+        let wait_until = (Instant::now() + CHUNK_ACK_WAIT).min(deadline);
+        while Instant::now() < wait_until {
+            for event in subscriber.try_receive()? {
+                let Event::MessageChunkAck {
+                    transfer_id: acked,
+                    missing,
+                    error,
+                    ..
+                } = event
+                else {
+                    continue;
+                };
+                if acked != transfer_id {
+                    continue;
+                }
+                if let Some(error) = error {
+                    return Err(IpcError::SendError(format!("Transfer rejected: {}", error)));
+                }
+                if missing.is_empty() {
+                    return Ok(transfer_id);
+                }
+                // Several receivers may each lack different parts
+                for part in missing {
+                    let part = part as usize;
+                    if part < last && !to_send.contains(&part) {
+                        to_send.push(part);
+                    }
+                }
+            }
+            if to_send.len() > 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        to_send.sort_unstable();
+    }
+    Err(IpcError::SendError(format!(
+        "Timed out sending {} bytes on '{}'",
+        data.len(),
+        topic
+    )))
+}
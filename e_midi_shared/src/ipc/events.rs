@@ -58,6 +58,14 @@ pub enum Event {
     MidiCommandSongListRequest {
         timestamp: u64,
     },
+    /// Add the song file sent as a chunked message and play it
+    MidiCommandLoadSong {
+        /// `transfer_id` of the `TOPIC_SONG` message holding the file
+        transfer_id: EventId,
+        /// File name; its extension selects MIDI or MusicXML
+        file_name: String,
+        timestamp: u64,
+    },
 
     /// MIDI status events (from player to TUI)
     MidiPlaybackStarted {
@@ -92,6 +100,19 @@ pub enum Event {
         song_count: usize,
         timestamp: u64,
     },
+    /// A beat of the playing song (also sent zero-copy as `MidiBeatEvent`)
+    MidiBeat {
+        song_index: usize,
+        /// Bar number, counting from 1
+        bar: u32,
+        /// Beat within the bar, counting from 1
+        beat: u8,
+        beats_per_bar: u8,
+        beat_unit: u8,
+        tempo_bpm: u32,
+        position_ms: u32,
+        timestamp: u64,
+    },
 
     /// Grid events (for future e_grid integration)
     GridCellSelected {
@@ -117,6 +138,39 @@ pub enum Event {
     SystemHeartbeat {
         app_id: AppId,
         timestamp: u64,
+        /// Sending process, so instances of one app can be told apart
+        #[serde(default)]
+        process_id: u32,
+    },
+    /// An app instance started sending heartbeats
+    ServiceJoined {
+        app_id: AppId,
+        process_id: u32,
+        timestamp: u64,
+    },
+    /// An app instance stopped sending heartbeats
+    ServiceLeft {
+        app_id: AppId,
+        process_id: u32,
+        timestamp: u64,
+    },
+    /// Capability handshake: sent by an app when it starts listening
+    Hello {
+        app_id: AppId,
+        process_id: u32,
+        protocol_version: u16,
+        capabilities: Vec<String>,
+        timestamp: u64,
+    },
+    /// Answer to a `Hello` from every app already listening
+    Welcome {
+        app_id: AppId,
+        process_id: u32,
+        protocol_version: u16,
+        capabilities: Vec<String>,
+        timestamp: u64,
+        /// Event id of the `Hello` answered
+        in_reply_to: EventId,
     },
 
     /// State synchronization events
@@ -129,6 +183,14 @@ pub enum Event {
         state_type: StateType,
         data: Vec<u8>,
         timestamp: u64,
+        /// Event id of the `StateRequest` answered (0 if unsolicited)
+        #[serde(default)]
+        request_id: EventId,
+        /// This part's number when the data is split over several responses
+        #[serde(default)]
+        part: u32,
+        #[serde(default = "single_part")]
+        parts: u32,
     },
 
     /// MIDI note events
@@ -148,10 +210,50 @@ pub enum Event {
         program: u8,
         timestamp: u64,
     },
+
+    /// Chunked transfer of messages larger than one payload (see `ipc::chunks`)
+    MessageChunk {
+        /// Shared by all chunks of one message
+        transfer_id: EventId,
+        /// What the message is for, e.g. `TOPIC_SONG`
+        topic: String,
+        part: u32,
+        parts: u32,
+        /// Length of the whole message
+        total_len: u64,
+        /// CRC-32 of the whole message
+        checksum: u32,
+        data: Vec<u8>,
+        timestamp: u64,
+    },
+    /// A receiver's progress on a chunked message
+    MessageChunkAck {
+        transfer_id: EventId,
+        /// Parts not received yet; empty once the message is complete
+        missing: Vec<u32>,
+        /// Why the message was rejected, e.g. a checksum mismatch
+        #[serde(default)]
+        error: Option<String>,
+        timestamp: u64,
+    },
+
+    /// An event this build can't decode, e.g. one added in a newer version
+    Unknown {
+        /// Variant name
+        variant: String,
+        /// The event's JSON as received; publishing it sends this unchanged
+        raw: String,
+        /// The event's `timestamp` field, if it has one
+        timestamp: u64,
+    },
+}
+
+fn single_part() -> u32 {
+    1
 }
 
 /// Types of state that can be synchronized
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StateType {
     WindowStates,
     MidiPlayback,
@@ -180,6 +282,7 @@ impl Event {
             Event::MidiCommandPrevious { timestamp } => *timestamp,
             Event::MidiCommandSetTempo { timestamp, .. } => *timestamp,
             Event::MidiCommandSongListRequest { timestamp } => *timestamp,
+            Event::MidiCommandLoadSong { timestamp, .. } => *timestamp,
             Event::MidiPlaybackStarted { timestamp, .. } => *timestamp,
             Event::MidiPlaybackStopped { timestamp } => *timestamp,
             Event::MidiPlaybackPaused { timestamp } => *timestamp,
@@ -188,16 +291,24 @@ impl Event {
             Event::MidiSongChanged { timestamp, .. } => *timestamp,
             Event::MidiProgressUpdate { timestamp, .. } => *timestamp,
             Event::MidiSongListUpdated { timestamp, .. } => *timestamp,
+            Event::MidiBeat { timestamp, .. } => *timestamp,
             Event::GridCellSelected { timestamp, .. } => *timestamp,
             Event::GridCellUpdated { timestamp, .. } => *timestamp,
             Event::GridStateChanged { timestamp, .. } => *timestamp,
             Event::SystemShutdown { timestamp } => *timestamp,
             Event::SystemHeartbeat { timestamp, .. } => *timestamp,
+            Event::ServiceJoined { timestamp, .. } => *timestamp,
+            Event::ServiceLeft { timestamp, .. } => *timestamp,
+            Event::Hello { timestamp, .. } => *timestamp,
+            Event::Welcome { timestamp, .. } => *timestamp,
             Event::StateRequest { timestamp, .. } => *timestamp,
             Event::StateResponse { timestamp, .. } => *timestamp,
             Event::MidiNoteOn { timestamp, .. } => *timestamp,
             Event::MidiNoteOff { timestamp, .. } => *timestamp,
             Event::MidiProgramChange { timestamp, .. } => *timestamp,
+            Event::MessageChunk { timestamp, .. } => *timestamp,
+            Event::MessageChunkAck { timestamp, .. } => *timestamp,
+            Event::Unknown { timestamp, .. } => *timestamp,
         }
     }
     /// Determine which app typically generates this event
@@ -210,7 +321,8 @@ impl Event {
             | Event::MidiCommandNext { .. }
             | Event::MidiCommandPrevious { .. }
             | Event::MidiCommandSetTempo { .. }
-            | Event::MidiCommandSongListRequest { .. } => AppId::EMidi, // TUI commands
+            | Event::MidiCommandSongListRequest { .. }
+            | Event::MidiCommandLoadSong { .. } => AppId::EMidi, // TUI commands
 
             Event::MidiPlaybackStarted { .. }
             | Event::MidiPlaybackStopped { .. }
@@ -219,13 +331,20 @@ impl Event {
             | Event::MidiTempoChanged { .. }
             | Event::MidiSongChanged { .. }
             | Event::MidiProgressUpdate { .. }
-            | Event::MidiSongListUpdated { .. } => AppId::EMidi, // Player status
+            | Event::MidiSongListUpdated { .. }
+            | Event::MidiBeat { .. } => AppId::EMidi, // Player status
 
             Event::GridCellSelected { .. }
             | Event::GridCellUpdated { .. }
             | Event::GridStateChanged { .. } => AppId::EGrid,
 
-            Event::WindowFocused { app_id, .. } | Event::WindowClosed { app_id, .. } => *app_id,
+            Event::WindowFocused { app_id, .. }
+            | Event::WindowClosed { app_id, .. }
+            | Event::SystemHeartbeat { app_id, .. }
+            | Event::ServiceJoined { app_id, .. }
+            | Event::ServiceLeft { app_id, .. }
+            | Event::Hello { app_id, .. }
+            | Event::Welcome { app_id, .. } => *app_id,
 
             Event::StateRequest { requesting_app, .. } => *requesting_app,
 
@@ -265,10 +384,73 @@ impl Event {
         }
     }
 
+    /// A state request; its timestamp doubles as the id responses echo back
+    pub fn state_request(requesting_app: AppId, state_type: StateType) -> Self {
+        Event::StateRequest {
+            requesting_app,
+            state_type,
+            timestamp: generate_event_id(),
+        }
+    }
+
     pub fn system_heartbeat(app_id: AppId) -> Self {
         Event::SystemHeartbeat {
             app_id,
             timestamp: generate_event_id(),
+            process_id: std::process::id(),
+        }
+    }
+
+    pub fn service_joined(app_id: AppId, process_id: u32) -> Self {
+        Event::ServiceJoined {
+            app_id,
+            process_id,
+            timestamp: generate_event_id(),
+        }
+    }
+
+    pub fn service_left(app_id: AppId, process_id: u32) -> Self {
+        Event::ServiceLeft {
+            app_id,
+            process_id,
+            timestamp: generate_event_id(),
+        }
+    }
+
+    /// Acknowledge a chunked message, see `ChunkAssembler::handle`
+    pub fn message_chunk_ack(
+        transfer_id: EventId,
+        missing: Vec<u32>,
+        error: Option<String>,
+    ) -> Self {
+        Event::MessageChunkAck {
+            transfer_id,
+            missing,
+            error,
+            timestamp: generate_event_id(),
+        }
+    }
+
+    /// This process's `Hello` for the capability handshake
+    pub fn hello(app_id: AppId, capabilities: Vec<String>) -> Self {
+        Event::Hello {
+            app_id,
+            process_id: std::process::id(),
+            protocol_version: crate::ipc::PROTOCOL_VERSION,
+            capabilities,
+            timestamp: generate_event_id(),
+        }
+    }
+
+    /// This process's answer to the `Hello` with event id `in_reply_to`
+    pub fn welcome(app_id: AppId, capabilities: Vec<String>, in_reply_to: EventId) -> Self {
+        Event::Welcome {
+            app_id,
+            process_id: std::process::id(),
+            protocol_version: crate::ipc::PROTOCOL_VERSION,
+            capabilities,
+            timestamp: generate_event_id(),
+            in_reply_to,
         }
     }
 
@@ -310,6 +492,18 @@ impl Event {
         }
     }
 
+    /// Load the song sent as transfer `transfer_id` (see `send_chunked`)
+    pub fn midi_command_load_song(transfer_id: EventId, file_name: String) -> Self {
+        Self::MidiCommandLoadSong {
+            transfer_id,
+            file_name,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+
     pub fn midi_command_set_tempo(new_tempo: u32) -> Self {
         Self::MidiCommandSetTempo {
             new_tempo,
@@ -0,0 +1,272 @@
+//! Shared heartbeat clock for synchronized playback
+//!
+//! One process runs the `HeartbeatClock` master, which publishes a numbered
+//! `ClockHeartbeat` at a fixed rate. Followers feed received heartbeats into a
+//! `ClockFollower`, which estimates the offset between the master's clock and
+//! their own and converts heartbeat numbers to local instants, so every
+//! process can act on the same heartbeat at the same moment.
+
+use iceoryx2::port::publisher::Publisher;
+use iceoryx2::port::subscriber::Subscriber;
+use iceoryx2::prelude::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ipc_protocol::ClockHeartbeat;
+
+pub const E_MIDI_HEARTBEAT_CLOCK_SERVICE: &str = "e_midi_heartbeat_clock";
+
+/// Heartbeat rate used when none is given
+pub const DEFAULT_CLOCK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Offset samples kept for the estimate
+pub const CLOCK_OFFSET_WINDOW: usize = 32;
+
+/// Wall clock in microseconds since the UNIX epoch
+pub fn unix_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Clock master: publishes heartbeats on a fixed grid
+#[derive(Debug)]
+pub struct HeartbeatClock {
+    publisher: Publisher<ipc::Service, ClockHeartbeat, ()>,
+    interval_us: u32,
+    /// Heartbeat 1 happens here
+    started: Instant,
+    started_us: u64,
+    next_beat: u32,
+}
+
+impl HeartbeatClock {
+    /// Start a clock whose first heartbeat is due immediately
+    pub fn new(interval: Duration) -> Result<Self, String> {
+        let interval_us = u32::try_from(interval.as_micros())
+            .ok()
+            .filter(|us| *us > 0)
+            .ok_or_else(|| format!("Invalid heartbeat interval: {:?}", interval))?;
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_HEARTBEAT_CLOCK_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<ClockHeartbeat>()
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let publisher = service
+            .publisher_builder()
+            .create()
+            .map_err(|e| format!("Failed to create publisher: {e:?}"))?;
+        Ok(Self {
+            publisher,
+            interval_us,
+            started: Instant::now(),
+            started_us: unix_micros(),
+            next_beat: 1,
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_micros(self.interval_us as u64)
+    }
+
+    /// Time since heartbeat 1 at which a heartbeat is due
+    fn beat_offset(&self, beat: u32) -> Duration {
+        Duration::from_micros((beat as u64 - 1) * self.interval_us as u64)
+    }
+
+    /// Publish the heartbeat that is due, if any, and return its number
+    ///
+    /// Heartbeats missed while the caller was busy are skipped, never sent late.
+    pub fn tick(&mut self) -> Result<Option<u32>, String> {
+        let elapsed = self.started.elapsed().as_micros() as u64;
+        let due = (elapsed / self.interval_us as u64 + 1) as u32;
+        if due < self.next_beat {
+            return Ok(None);
+        }
+        let heartbeat = ClockHeartbeat {
+            beat: due,
+            interval_us: self.interval_us,
+            // Derived from the monotonic clock so wall clock steps don't jitter the grid
+            master_time_us: self.started_us + self.beat_offset(due).as_micros() as u64,
+            master_pid: std::process::id(),
+            _reserved: [0; 4],
+        };
+        self.publisher
+            .send_copy(heartbeat)
+            .map_err(|e| format!("Failed to publish heartbeat: {e:?}"))?;
+        self.next_beat = due + 1;
+        Ok(Some(due))
+    }
+
+    /// Time until the next heartbeat is due
+    pub fn until_next(&self) -> Duration {
+        (self.started + self.beat_offset(self.next_beat)).saturating_duration_since(Instant::now())
+    }
+}
+
+unsafe impl Send for HeartbeatClock {}
+
+/// Estimates the master's clock from received heartbeats
+///
+/// Each heartbeat gives `receive time - master time`, the clock offset plus
+/// the delivery delay. Delays are never negative, so the smallest sample in
+/// the window is the best estimate of the offset.
+#[derive(Debug, Clone, Default)]
+pub struct ClockFollower {
+    samples: VecDeque<i64>,
+    latest: Option<ClockHeartbeat>,
+}
+
+impl ClockFollower {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a heartbeat received at `received_us` (local UNIX microseconds)
+    pub fn observe(&mut self, heartbeat: &ClockHeartbeat, received_us: u64) {
+        if heartbeat.interval_us == 0 {
+            return;
+        }
+        if let Some(latest) = &self.latest {
+            if heartbeat.master_pid != latest.master_pid
+                || heartbeat.interval_us != latest.interval_us
+                || heartbeat.beat < latest.beat
+            {
+                // A different or restarted master: earlier samples don't apply
+                self.samples.clear();
+            }
+        }
+        self.samples
+            .push_back(received_us as i64 - heartbeat.master_time_us as i64);
+        while self.samples.len() > CLOCK_OFFSET_WINDOW {
+            self.samples.pop_front();
+        }
+        self.latest = Some(*heartbeat);
+    }
+
+    /// Whether a heartbeat has been seen
+    pub fn is_synced(&self) -> bool {
+        self.latest.is_some()
+    }
+
+    /// Local clock minus master clock, in microseconds
+    pub fn offset_us(&self) -> Option<i64> {
+        self.samples.iter().min().copied()
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.latest
+            .map(|h| Duration::from_micros(h.interval_us as u64))
+    }
+
+    pub fn latest(&self) -> Option<&ClockHeartbeat> {
+        self.latest.as_ref()
+    }
+
+    /// Local UNIX microseconds at which a heartbeat happens (or happened)
+    pub fn local_time_of(&self, beat: u32) -> Option<i64> {
+        let latest = self.latest?;
+        let beats = beat as i64 - latest.beat as i64;
+        Some(latest.master_time_us as i64 + beats * latest.interval_us as i64 + self.offset_us()?)
+    }
+
+    /// Heartbeat in progress at a local time
+    pub fn beat_at(&self, local_us: u64) -> Option<u32> {
+        let latest = self.latest?;
+        let master_us = local_us as i64 - self.offset_us()?;
+        let beats =
+            (master_us - latest.master_time_us as i64).div_euclid(latest.interval_us as i64);
+        u32::try_from((latest.beat as i64 + beats).max(1)).ok()
+    }
+
+    /// Heartbeat in progress now
+    pub fn current_beat(&self) -> Option<u32> {
+        self.beat_at(unix_micros())
+    }
+
+    /// When a heartbeat happens on the local monotonic clock
+    ///
+    /// Past heartbeats give instants in the past.
+    pub fn instant_of(&self, beat: u32) -> Option<Instant> {
+        let now = Instant::now();
+        let delta = self.local_time_of(beat)? - unix_micros() as i64;
+        let offset = Duration::from_micros(delta.unsigned_abs());
+        if delta >= 0 {
+            Some(now + offset)
+        } else {
+            Some(now.checked_sub(offset).unwrap_or(now))
+        }
+    }
+}
+
+/// Receives heartbeats and keeps a `ClockFollower` up to date
+#[derive(Debug)]
+pub struct HeartbeatClockSubscriber {
+    subscriber: Subscriber<ipc::Service, ClockHeartbeat, ()>,
+    follower: ClockFollower,
+}
+
+impl HeartbeatClockSubscriber {
+    pub fn new() -> Result<Self, String> {
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_HEARTBEAT_CLOCK_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<ClockHeartbeat>()
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let subscriber = service
+            .subscriber_builder()
+            .create()
+            .map_err(|e| format!("Failed to create subscriber: {e:?}"))?;
+        Ok(Self {
+            subscriber,
+            follower: ClockFollower::new(),
+        })
+    }
+
+    /// Take in pending heartbeats, returning how many arrived
+    pub fn poll(&mut self) -> Result<usize, String> {
+        let mut count = 0;
+        while let Some(sample) = self
+            .subscriber
+            .receive()
+            .map_err(|e| format!("Receive error: {e:?}"))?
+        {
+            self.follower.observe(&sample, unix_micros());
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Poll until a heartbeat arrives; false if none did within the timeout
+    pub fn wait_for_heartbeat(&mut self, timeout: Duration) -> Result<bool, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.poll()? > 0 {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub fn follower(&self) -> &ClockFollower {
+        &self.follower
+    }
+}
+
+unsafe impl Send for HeartbeatClockSubscriber {}
@@ -0,0 +1,176 @@
+//! Zero-copy stream of every outgoing MIDI message
+//!
+//! Players publish each message they send as a `MidiMessageEvent` on
+//! `E_MIDI_MESSAGES_SERVICE`: notes, controllers, program changes, pitch bend
+//! and system messages, tagged with song, track and song position. Sequence
+//! numbers are counted per process, so `MidiMessageSubscriber` can tell how
+//! many samples it missed.
+
+use iceoryx2::port::publisher::Publisher;
+use iceoryx2::port::subscriber::Subscriber;
+use iceoryx2::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::unix_micros;
+use crate::ipc_protocol::{MidiMessageEvent, MIDI_MESSAGE_NONE};
+
+pub const E_MIDI_MESSAGES_SERVICE: &str = "e_midi_midi_messages";
+
+/// Shared by every publisher in the process so sequence numbers never repeat
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Publishers are !Send, so each sending thread gets its own. `None`
+    /// after a failed attempt, so a missing service isn't retried per message.
+    static THREAD_PUBLISHER: RefCell<Option<Option<MidiMessagePublisher>>> =
+        const { RefCell::new(None) };
+}
+
+/// Publish an outgoing message from any thread (best-effort)
+///
+/// Messages that aren't a single 1-3 byte MIDI message (SysEx) are skipped.
+pub fn publish_midi_message(
+    msg: &[u8],
+    song_index: Option<usize>,
+    track: Option<u8>,
+    song_time_ms: u32,
+) {
+    let Some(mut event) = MidiMessageEvent::from_bytes(msg) else {
+        return;
+    };
+    event.song_index =
+        song_index.map_or(MIDI_MESSAGE_NONE, |i| i.min(u16::MAX as usize - 1) as u16);
+    event.track = track.map_or(MIDI_MESSAGE_NONE, |t| t as u16);
+    event.song_time_ms = song_time_ms;
+    THREAD_PUBLISHER.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let publisher = cell.get_or_insert_with(|| MidiMessagePublisher::new().ok());
+        if let Some(publisher) = publisher {
+            let _ = publisher.publish(event);
+        }
+    });
+}
+
+#[derive(Debug)]
+pub struct MidiMessagePublisher {
+    publisher: Publisher<ipc::Service, MidiMessageEvent, ()>,
+}
+
+impl MidiMessagePublisher {
+    pub fn new() -> Result<Self, String> {
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_MESSAGES_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<MidiMessageEvent>()
+            .max_publishers(16)
+            .max_subscribers(16)
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let publisher = service
+            .publisher_builder()
+            .create()
+            .map_err(|e| format!("Failed to create publisher: {e:?}"))?;
+        Ok(Self { publisher })
+    }
+
+    /// Stamp the message with sequence number, time and pid, and send it
+    pub fn publish(&self, mut event: MidiMessageEvent) -> Result<(), String> {
+        event.sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        event.timestamp_us = unix_micros();
+        event.source_pid = std::process::id();
+        self.publisher
+            .send_copy(event)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to publish: {e:?}"))
+    }
+}
+
+/// Counts sequence gaps per publishing process
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    last: HashMap<u32, u64>,
+    dropped: u64,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a received message, returning how many were missed before it
+    pub fn observe(&mut self, event: &MidiMessageEvent) -> u64 {
+        let missed = match self.last.get(&event.source_pid) {
+            Some(&last) if event.sequence > last => event.sequence - last - 1,
+            // A lower number means the publisher restarted
+            _ => 0,
+        };
+        self.last.insert(event.source_pid, event.sequence);
+        self.dropped += missed;
+        missed
+    }
+
+    /// Messages missed since this tracker was created
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[derive(Debug)]
+pub struct MidiMessageSubscriber {
+    subscriber: Subscriber<ipc::Service, MidiMessageEvent, ()>,
+    sequences: SequenceTracker,
+}
+
+impl MidiMessageSubscriber {
+    pub fn new() -> Result<Self, String> {
+        let node = NodeBuilder::new()
+            .create::<ipc::Service>()
+            .map_err(|e| format!("Node creation failed: {e:?}"))?;
+        let service = node
+            .service_builder(
+                &ServiceName::new(E_MIDI_MESSAGES_SERVICE)
+                    .map_err(|e| format!("Invalid service name: {e:?}"))?,
+            )
+            .publish_subscribe::<MidiMessageEvent>()
+            .max_publishers(16)
+            .max_subscribers(16)
+            .open_or_create()
+            .map_err(|e| format!("Failed to create/open service: {e:?}"))?;
+        let subscriber = service
+            .subscriber_builder()
+            .create()
+            .map_err(|e| format!("Failed to create subscriber: {e:?}"))?;
+        Ok(Self {
+            subscriber,
+            sequences: SequenceTracker::new(),
+        })
+    }
+
+    /// Receive pending messages (non-blocking)
+    pub fn try_receive(&mut self) -> Result<Vec<MidiMessageEvent>, String> {
+        let mut messages = Vec::new();
+        while let Some(sample) = self
+            .subscriber
+            .receive()
+            .map_err(|e| format!("Receive error: {e:?}"))?
+        {
+            self.sequences.observe(&sample);
+            messages.push(*sample);
+        }
+        Ok(messages)
+    }
+
+    /// Messages missed so far, from gaps in the sequence numbers
+    pub fn dropped(&self) -> u64 {
+        self.sequences.dropped()
+    }
+}
+
+unsafe impl Send for MidiMessageSubscriber {}
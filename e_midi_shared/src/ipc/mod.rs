@@ -3,20 +3,32 @@
 //! This module provides lock-free, zero-copy communication between the MIDI player
 //! and other applications in the e_* ecosystem (e_grid, state server, etc.)
 
+pub mod beats;
+pub mod chunks;
 pub mod events;
+pub mod heartbeat_clock;
+pub mod midi_messages;
 pub mod music_sync_publisher;
 pub mod music_sync_subscriber;
+pub mod protocol;
 pub mod publisher;
 pub mod service;
+pub mod state;
 pub mod subscriber;
 pub mod types;
 
 pub use crate::ipc::types::*;
+pub use beats::*;
+pub use chunks::*;
 pub use events::*;
+pub use heartbeat_clock::*;
+pub use midi_messages::*;
 pub use music_sync_publisher::*;
 pub use music_sync_subscriber::*;
+pub use protocol::*;
 pub use publisher::*;
 pub use service::*;
+pub use state::*;
 pub use subscriber::*;
 
 use std::error::Error;
@@ -43,3 +55,10 @@ use once_cell::sync::OnceCell;
 
 pub static IPC_EVENT_SENDER: OnceCell<IpcEventSender> = OnceCell::new();
 pub const EMIDI_EVENTS_SERVICE: &str = "e_midi_events";
+/// Zero-copy `MidiNoteEvent` stream; iceoryx2 ties a service name to one
+/// payload type, so it cannot share `EMIDI_EVENTS_SERVICE`
+///
+/// Note events were published on `EMIDI_EVENTS_SERVICE` before; subscribers
+/// that still open that name for `MidiNoteEvent` no longer connect. Like the
+/// `MidiMessageEvent` stream, notes are only published with IPC enabled.
+pub const EMIDI_NOTE_EVENTS_SERVICE: &str = "e_midi_note_events";
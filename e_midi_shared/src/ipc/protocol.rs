@@ -0,0 +1,287 @@
+//! Versioned wire format and capability handshake
+//!
+//! Every payload on the event service starts with an 8-byte header: the magic
+//! bytes `EM`, the protocol version (u16, little endian) and the length of the
+//! JSON body that follows (u32, little endian). The body is an `Event` or a
+//! JSON array of them, in serde's externally tagged form.
+//!
+//! Versions only ever add variants and fields, so decoding is tolerant: unknown
+//! fields are ignored, and an event this build doesn't know (a newer variant,
+//! or a known one missing a field) becomes `Event::Unknown` carrying its JSON.
+//! Publishing an `Unknown` sends that JSON unchanged. Payloads without the
+//! header are the version 0 format, plain zero-padded JSON.
+//!
+//! Since version 2, `EventPublisher` writes the sending app's `AppId` as a JSON
+//! string right after the body. Version 1 readers stop at the body length, so
+//! they never see it; payloads without it have no known sender.
+//!
+//! Apps introduce themselves with `Event::Hello` when they start listening and
+//! every app already listening answers with `Event::Welcome`; both carry the
+//! sender's protocol version and capabilities.
+
+use super::{
+    AppId, Event, EventId, EventPublisher, EventSubscriber, IpcError, IpcPayload, IpcResult,
+    MAX_PAYLOAD_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Protocol version written by this build
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// First bytes of a versioned payload
+pub const PAYLOAD_MAGIC: [u8; 2] = *b"EM";
+
+/// Magic, version and body length
+pub const PAYLOAD_HEADER_SIZE: usize = 8;
+
+/// Largest JSON body that fits in one payload
+pub const MAX_BODY_SIZE: usize = MAX_PAYLOAD_SIZE - PAYLOAD_HEADER_SIZE;
+
+/// Answers `MidiCommand*` events
+pub const CAP_MIDI_COMMANDS: &str = "midi.commands";
+/// Answers `StateRequest` events
+pub const CAP_STATE: &str = "state";
+/// Publishes `MidiMessageEvent`s
+pub const CAP_MIDI_MESSAGES: &str = "midi.messages";
+/// Publishes beat events
+pub const CAP_BEATS: &str = "midi.beats";
+/// Answers `MidiCommandLoadSong` events
+pub const CAP_LOAD_SONG: &str = "midi.load_song";
+/// Follows the shared heartbeat clock
+pub const CAP_HEARTBEAT_CLOCK: &str = "sync.heartbeat_clock";
+
+/// JSON for one event; `Unknown` events are sent as they were received
+pub fn event_json(event: &Event) -> IpcResult<Vec<u8>> {
+    match event {
+        Event::Unknown { raw, .. } => Ok(raw.clone().into_bytes()),
+        event => serde_json::to_vec(event)
+            .map_err(|e| IpcError::SerializationError(format!("Serialization failed: {:?}", e))),
+    }
+}
+
+/// Wrap a JSON body in a versioned payload
+pub fn encode_payload(body: &[u8]) -> IpcResult<IpcPayload> {
+    if body.len() > MAX_BODY_SIZE {
+        return Err(IpcError::PayloadTooLarge(format!(
+            "Payload size {} exceeds maximum {}",
+            body.len(),
+            MAX_BODY_SIZE
+        )));
+    }
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    payload[..2].copy_from_slice(&PAYLOAD_MAGIC);
+    payload[2..4].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    payload[4..8].copy_from_slice(&(body.len() as u32).to_le_bytes());
+    payload[PAYLOAD_HEADER_SIZE..PAYLOAD_HEADER_SIZE + body.len()].copy_from_slice(body);
+    Ok(payload)
+}
+
+/// Record the sending app after the body of an encoded payload
+///
+/// Returns false, leaving the payload without a sender, if a body close to
+/// `MAX_BODY_SIZE` leaves no room for it.
+pub fn set_payload_sender(payload: &mut IpcPayload, sender: AppId) -> IpcResult<bool> {
+    if payload[..2] != PAYLOAD_MAGIC {
+        return Ok(false);
+    }
+    let at = PAYLOAD_HEADER_SIZE + split_payload(payload)?.1.len();
+    let sender = serde_json::to_vec(&sender)
+        .map_err(|e| IpcError::SerializationError(format!("Serialization failed: {:?}", e)))?;
+    // Keep a zero after the sender so readers know where it ends
+    if at + sender.len() >= MAX_PAYLOAD_SIZE {
+        return Ok(false);
+    }
+    payload[at..at + sender.len()].copy_from_slice(&sender);
+    Ok(true)
+}
+
+/// The app that published a payload, if its publisher recorded one
+pub fn payload_sender(payload: &[u8]) -> Option<AppId> {
+    let (version, body) = split_payload(payload).ok()?;
+    if version < 2 {
+        return None;
+    }
+    let trailer = payload.get(PAYLOAD_HEADER_SIZE + body.len()..)?;
+    let len = trailer
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(trailer.len());
+    serde_json::from_slice(&trailer[..len]).ok()
+}
+
+/// Payload carrying one event
+pub fn encode_event(event: &Event) -> IpcResult<IpcPayload> {
+    encode_payload(&event_json(event)?)
+}
+
+/// Payload carrying several events as a JSON array
+pub fn encode_events(events: &[Event]) -> IpcResult<IpcPayload> {
+    let mut body = vec![b'['];
+    for (i, event) in events.iter().enumerate() {
+        if i > 0 {
+            body.push(b',');
+        }
+        body.extend(event_json(event)?);
+    }
+    body.push(b']');
+    encode_payload(&body)
+}
+
+/// Protocol version and JSON body of a payload
+///
+/// Payloads without the header are version 0: JSON padded with zeros.
+pub fn split_payload(payload: &[u8]) -> IpcResult<(u16, &[u8])> {
+    if payload.len() >= PAYLOAD_HEADER_SIZE && payload[..2] == PAYLOAD_MAGIC {
+        let version = u16::from_le_bytes([payload[2], payload[3]]);
+        let len = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]) as usize;
+        let body = payload
+            .get(PAYLOAD_HEADER_SIZE..PAYLOAD_HEADER_SIZE + len)
+            .ok_or_else(|| {
+                IpcError::DeserializationError(format!(
+                    "Body length {} exceeds the payload ({} bytes)",
+                    len,
+                    payload.len()
+                ))
+            })?;
+        return Ok((version, body));
+    }
+    let len = payload.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    Ok((0, &payload[..len]))
+}
+
+/// Decode one event, keeping events this build doesn't know as `Unknown`
+pub fn decode_event(json: &[u8]) -> IpcResult<Event> {
+    if let Ok(event) = serde_json::from_slice::<Event>(json) {
+        return Ok(event);
+    }
+    let value: Value = serde_json::from_slice(json)
+        .map_err(|e| IpcError::DeserializationError(format!("Invalid JSON: {}", e)))?;
+    unknown_event(value)
+}
+
+fn decode_value(value: Value) -> IpcResult<Event> {
+    match serde_json::from_value::<Event>(value.clone()) {
+        Ok(event) => Ok(event),
+        Err(_) => unknown_event(value),
+    }
+}
+
+/// `Event::Unknown` for an externally tagged enum value
+fn unknown_event(value: Value) -> IpcResult<Event> {
+    let (variant, timestamp) = match &value {
+        // Unit variant
+        Value::String(name) => (name.clone(), 0),
+        Value::Object(map) if map.len() == 1 => {
+            let (name, fields) = map.iter().next().expect("one entry");
+            let timestamp = fields.get("timestamp").and_then(Value::as_u64).unwrap_or(0);
+            (name.clone(), timestamp)
+        }
+        _ => {
+            return Err(IpcError::DeserializationError(
+                "Event is not a tagged enum value".to_string(),
+            ))
+        }
+    };
+    Ok(Event::Unknown {
+        variant,
+        raw: value.to_string(),
+        timestamp,
+    })
+}
+
+/// Decode a payload holding one event or an array of them
+///
+/// Returns the payload's protocol version with the events.
+pub fn decode_payload(payload: &[u8]) -> IpcResult<(u16, Vec<Event>)> {
+    let (version, body) = split_payload(payload)?;
+    if body.first() == Some(&b'[') {
+        if let Ok(events) = serde_json::from_slice::<Vec<Event>>(body) {
+            return Ok((version, events));
+        }
+        let values: Vec<Value> = serde_json::from_slice(body)
+            .map_err(|e| IpcError::DeserializationError(format!("Invalid JSON: {}", e)))?;
+        let events = values
+            .into_iter()
+            .map(decode_value)
+            .collect::<IpcResult<Vec<_>>>()?;
+        return Ok((version, events));
+    }
+    Ok((version, vec![decode_event(body)?]))
+}
+
+/// What the handshake learned about another app instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub app_id: AppId,
+    pub process_id: u32,
+    pub protocol_version: u16,
+    pub capabilities: Vec<String>,
+}
+
+impl PeerInfo {
+    /// The peer described by a `Hello` or `Welcome` event
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match event {
+            Event::Hello {
+                app_id,
+                process_id,
+                protocol_version,
+                capabilities,
+                ..
+            }
+            | Event::Welcome {
+                app_id,
+                process_id,
+                protocol_version,
+                capabilities,
+                ..
+            } => Some(PeerInfo {
+                app_id: *app_id,
+                process_id: *process_id,
+                protocol_version: *protocol_version,
+                capabilities: capabilities.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Introduce this app and collect the `Welcome` answers until the timeout
+///
+/// Apps that predate the handshake don't answer; they show up only through
+/// their heartbeats.
+pub fn handshake(
+    publisher: &EventPublisher,
+    subscriber: &mut EventSubscriber,
+    app_id: AppId,
+    capabilities: &[&str],
+    timeout: Duration,
+) -> IpcResult<Vec<PeerInfo>> {
+    let hello = Event::hello(app_id, capabilities.iter().map(|c| c.to_string()).collect());
+    let hello_id: EventId = hello.event_id();
+    publisher.publish(hello)?;
+    let deadline = Instant::now() + timeout;
+    let mut peers: Vec<PeerInfo> = Vec::new();
+    while Instant::now() < deadline {
+        for event in subscriber.try_receive()? {
+            if let Event::Welcome { in_reply_to, .. } = &event {
+                if *in_reply_to != hello_id {
+                    continue;
+                }
+                if let Some(peer) = PeerInfo::from_event(&event) {
+                    if !peers.contains(&peer) {
+                        peers.push(peer);
+                    }
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(peers)
+}
@@ -7,7 +7,9 @@ use iceoryx2::prelude::*;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use super::{serialize_to_payload, AppId, Event, IpcError, IpcPayload, IpcResult};
+use super::{
+    encode_event, encode_events, set_payload_sender, AppId, Event, IpcError, IpcPayload, IpcResult,
+};
 
 /// Lock-free event publisher
 ///
@@ -22,10 +24,6 @@ impl EventPublisher {
     /// Create a new event publisher for the specified app
     pub fn new(app_id: AppId) -> IpcResult<Self> {
         let service_name = "e_midi_events".to_string();
-        println!(
-            "[IPC PUBLISHER DEBUG] Using service name: {} (app_id: {:?})",
-            service_name, app_id
-        );
 
         // Create node (suppress debug output)
         let node = NodeBuilder::new().create::<ipc::Service>().map_err(|e| {
@@ -64,13 +62,13 @@ impl EventPublisher {
             return Err(IpcError::SendError("Publisher is not active".to_string()));
         }
 
-        // Serialize event to payload
-        let payload = serialize_to_payload(&event)?;
+        // Serialize event to a versioned payload stamped with this app
+        let mut payload = encode_event(&event)?;
+        set_payload_sender(&mut payload, self.app_id)?;
         // Send via iceoryx2 using send_copy for simplicity
         match self.publisher.send_copy(payload) {
-            Ok(_) => {
-                println!("[IPC PUBLISHER DEBUG] Successfully sent event: {:?}", event);
-            }
+            // Publishing happens while the TUI owns the terminal; stay quiet
+            Ok(_) => {}
             Err(e) => {
                 eprintln!(
                     "[IPC PUBLISHER ERROR] Failed to send event: {:?} (error: {:?})",
@@ -92,7 +90,8 @@ impl EventPublisher {
         }
 
         // Serialize all events to a single payload
-        let payload = serialize_to_payload(&events)?;
+        let mut payload = encode_events(&events)?;
+        set_payload_sender(&mut payload, self.app_id)?;
         self.publisher
             .send_copy(payload)
             .map_err(|_| IpcError::SendError("Failed to send batch".to_string()))?;
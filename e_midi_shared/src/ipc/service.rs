@@ -3,9 +3,9 @@
 //! Provides lock-free service registration and discovery
 
 // use serde::{Deserialize, Serialize};
-use super::{AppId, EventPublisher, EventSubscriber, IpcResult};
+use super::{AppId, EventPublisher, EventSubscriber, IpcResult, PeerInfo};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::OnceLock;
@@ -20,13 +20,31 @@ pub struct ServiceInfo {
     pub capabilities: Vec<String>,
     pub last_heartbeat: u64,
     pub process_id: u32,
+    /// Protocol version from the capability handshake (0 if not known)
+    #[serde(default)]
+    pub protocol_version: u16,
+}
+
+/// Apps not heard from for this long are considered gone
+pub const DEFAULT_SERVICE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How many answered `Hello` events are remembered to avoid answering twice
+const ANSWERED_HELLOS: usize = 64;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
 }
 
 /// Service registry for managing available services
 ///
+/// Services are tracked per instance, so several processes of the same app
+/// (two players, say) are listed separately.
 #[derive(Debug)]
 pub struct ServiceRegistry {
-    services: HashMap<AppId, ServiceInfo>,
+    services: HashMap<(AppId, u32), ServiceInfo>,
     is_active: Arc<AtomicBool>,
     last_cleanup: Instant,
 }
@@ -47,36 +65,113 @@ impl ServiceRegistry {
     /// Register a service (lock-free when possible)
     pub fn register_service(&mut self, service_info: ServiceInfo) {
         if self.is_active.load(Ordering::Relaxed) {
-            self.services.insert(service_info.app_id, service_info);
+            self.services
+                .insert((service_info.app_id, service_info.process_id), service_info);
         }
     }
 
-    /// Get service information
+    /// Get service information, from the instance heard from most recently
     pub fn get_service(&self, app_id: AppId) -> Option<&ServiceInfo> {
-        if self.is_active.load(Ordering::Relaxed) {
-            self.services.get(&app_id)
-        } else {
-            None
-        }
+        self.instances(app_id)
+            .into_iter()
+            .max_by_key(|service| service.last_heartbeat)
+    }
+
+    /// Every running instance of an app
+    pub fn instances(&self, app_id: AppId) -> Vec<&ServiceInfo> {
+        let mut instances: Vec<&ServiceInfo> = self
+            .list_services()
+            .into_iter()
+            .filter(|service| service.app_id == app_id)
+            .collect();
+        instances.sort_by_key(|service| service.process_id);
+        instances
     }
 
-    /// List all active services
+    /// List all active services, ordered by process id
     pub fn list_services(&self) -> Vec<&ServiceInfo> {
         if self.is_active.load(Ordering::Relaxed) {
-            self.services.values().collect()
+            let mut services: Vec<&ServiceInfo> = self.services.values().collect();
+            services.sort_by_key(|service| service.process_id);
+            services
         } else {
             Vec::new()
         }
     }
 
-    /// Update heartbeat for a service
+    /// Update heartbeat for every instance of a service
     pub fn update_heartbeat(&mut self, app_id: AppId) {
-        if let Some(service) = self.services.get_mut(&app_id) {
-            service.last_heartbeat = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
+        let now = now_secs();
+        for service in self.services.values_mut() {
+            if service.app_id == app_id {
+                service.last_heartbeat = now;
+            }
+        }
+    }
+
+    /// Record a heartbeat from an app instance, registering it if it's new
+    ///
+    /// Heartbeats from older builds carry no process id (0). Those refresh
+    /// every known instance of the app and only register one when none is
+    /// known yet. Returns the service info when the instance just joined.
+    pub fn observe_heartbeat(&mut self, app_id: AppId, process_id: u32) -> Option<ServiceInfo> {
+        if !self.is_active.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = now_secs();
+        if let Some(service) = self.services.get_mut(&(app_id, process_id)) {
+            service.last_heartbeat = now;
+            return None;
         }
+        if process_id == 0 && self.services.keys().any(|(app, _)| *app == app_id) {
+            self.update_heartbeat(app_id);
+            return None;
+        }
+        // An instance that now reports its pid replaces the pid-less entry
+        if process_id != 0 {
+            self.services.remove(&(app_id, 0));
+        }
+        let service = ServiceInfo {
+            app_id,
+            service_name: super::EMIDI_EVENTS_SERVICE.to_string(),
+            version: String::new(),
+            capabilities: Vec::new(),
+            last_heartbeat: now,
+            process_id,
+            protocol_version: 0,
+        };
+        self.register_service(service.clone());
+        Some(service)
+    }
+
+    /// Record what the capability handshake told about an app instance
+    ///
+    /// Counts as a heartbeat; returns the service info when the instance
+    /// just joined.
+    pub fn observe_peer(&mut self, peer: &PeerInfo) -> Option<ServiceInfo> {
+        let joined = self.observe_heartbeat(peer.app_id, peer.process_id);
+        let service = self.services.get_mut(&(peer.app_id, peer.process_id))?;
+        service.protocol_version = peer.protocol_version;
+        service.capabilities = peer.capabilities.clone();
+        joined.map(|_| service.clone())
+    }
+
+    /// Remove services not heard from within `max_age`, returning them
+    pub fn remove_stale_services(&mut self, max_age: Duration) -> Vec<ServiceInfo> {
+        let current_time = now_secs();
+        let max_age_secs = max_age.as_secs();
+        let stale: Vec<(AppId, u32)> = self
+            .services
+            .iter()
+            .filter(|(_, service)| {
+                current_time.saturating_sub(service.last_heartbeat) >= max_age_secs
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        stale
+            .into_iter()
+            .filter_map(|key| self.services.remove(&key))
+            .collect()
     }
 
     /// Clean up stale services (call periodically)
@@ -85,28 +180,15 @@ impl ServiceRegistry {
             return; // Don't cleanup too frequently
         }
 
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-
-        let max_age_secs = max_age.as_secs();
-
-        self.services
-            .retain(|_, service| current_time - service.last_heartbeat < max_age_secs);
+        self.remove_stale_services(max_age);
 
         self.last_cleanup = Instant::now();
     }
 
-    /// Check if a service is alive
+    /// Check if any instance of a service is alive
     pub fn is_service_alive(&self, app_id: AppId, max_age: Duration) -> bool {
         if let Some(service) = self.get_service(app_id) {
-            let current_time = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-
-            current_time - service.last_heartbeat < max_age.as_secs()
+            now_secs().saturating_sub(service.last_heartbeat) < max_age.as_secs()
         } else {
             false
         }
@@ -127,6 +209,10 @@ pub struct IpcServiceManager {
     registry: ServiceRegistry,
     heartbeat_interval: Duration,
     last_heartbeat: Instant,
+    service_timeout: Duration,
+    capabilities: Vec<String>,
+    /// `Hello` events already answered, as (process id, event id)
+    answered_hellos: VecDeque<(u32, super::EventId)>,
     is_active: Arc<AtomicBool>,
 }
 
@@ -148,6 +234,9 @@ impl IpcServiceManager {
             registry: ServiceRegistry::new(),
             heartbeat_interval: Duration::from_secs(5),
             last_heartbeat: Instant::now(),
+            service_timeout: DEFAULT_SERVICE_TIMEOUT,
+            capabilities: Vec::new(),
+            answered_hellos: VecDeque::new(),
             is_active: Arc::new(AtomicBool::new(true)),
         });
         let manager_clone = Arc::clone(&manager);
@@ -169,14 +258,34 @@ impl IpcServiceManager {
     }
 
     /// Subscribe to events from another app
+    ///
+    /// The first subscription introduces this app with a `Hello`.
     pub fn subscribe_to(&mut self, source_app: AppId) -> IpcResult<()> {
         if !self.subscribers.contains_key(&source_app) {
             let subscriber = EventSubscriber::new(source_app, self.app_id)?;
+            let first = self.subscribers.is_empty();
             self.subscribers.insert(source_app, subscriber);
+            if first && self.publisher.is_some() {
+                self.say_hello()?;
+            }
         }
         Ok(())
     }
 
+    /// Capabilities announced in the handshake (see `protocol::CAP_*`)
+    pub fn set_capabilities(&mut self, capabilities: &[&str]) {
+        self.capabilities = capabilities.iter().map(|c| c.to_string()).collect();
+    }
+
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    /// Introduce this app; apps already listening answer with `Welcome`
+    pub fn say_hello(&self) -> IpcResult<()> {
+        self.publish_event(super::Event::hello(self.app_id, self.capabilities.clone()))
+    }
+
     /// Get publisher reference
     pub fn publisher(&self) -> Option<&EventPublisher> {
         self.publisher.as_ref()
@@ -192,7 +301,8 @@ impl IpcServiceManager {
         if self.last_heartbeat.elapsed() >= self.heartbeat_interval {
             if let Some(publisher) = &self.publisher {
                 publisher.heartbeat()?;
-                self.registry.update_heartbeat(self.app_id);
+                self.registry
+                    .observe_heartbeat(self.app_id, std::process::id());
                 self.last_heartbeat = Instant::now();
             }
         }
@@ -200,6 +310,8 @@ impl IpcServiceManager {
     }
 
     /// Process all incoming events from all subscribers
+    ///
+    /// Heartbeats among them keep the service registry up to date.
     pub fn process_events(&mut self) -> IpcResult<Vec<(AppId, Vec<super::Event>)>> {
         let mut all_events = Vec::new();
         for (source_app, subscriber) in self.subscribers.iter_mut() {
@@ -208,10 +320,102 @@ impl IpcServiceManager {
                 all_events.push((*source_app, events));
             }
         }
+        for event in all_events.iter().flat_map(|(_, events)| events) {
+            self.observe_event(event);
+        }
+        self.expire_services();
 
         Ok(all_events)
     }
 
+    /// Track an app from its `SystemHeartbeat`, `Hello` or `Welcome`
+    ///
+    /// A `Hello` from another process is answered with `Welcome`, once even
+    /// when several subscribers deliver it. An instance heard from for the
+    /// first time is announced with a `ServiceJoined` event, which is also
+    /// returned.
+    pub fn observe_event(&mut self, event: &super::Event) -> Option<super::Event> {
+        let service = match event {
+            super::Event::SystemHeartbeat {
+                app_id, process_id, ..
+            } => self.registry.observe_heartbeat(*app_id, *process_id),
+            super::Event::Hello { .. } | super::Event::Welcome { .. } => {
+                let peer = PeerInfo::from_event(event)?;
+                if peer.process_id == std::process::id() {
+                    return None;
+                }
+                if let super::Event::Hello { timestamp, .. } = event {
+                    if self.first_hello(peer.process_id, *timestamp) {
+                        let _ = self.publish_event(super::Event::welcome(
+                            self.app_id,
+                            self.capabilities.clone(),
+                            *timestamp,
+                        ));
+                    }
+                }
+                self.registry.observe_peer(&peer)
+            }
+            _ => None,
+        }?;
+        let joined = super::Event::service_joined(service.app_id, service.process_id);
+        let _ = self.publish_event(joined.clone());
+        Some(joined)
+    }
+
+    /// Whether this `Hello` is seen for the first time, remembering it if so
+    fn first_hello(&mut self, process_id: u32, event_id: super::EventId) -> bool {
+        if self.answered_hellos.contains(&(process_id, event_id)) {
+            return false;
+        }
+        if self.answered_hellos.len() == ANSWERED_HELLOS {
+            self.answered_hellos.pop_front();
+        }
+        self.answered_hellos.push_back((process_id, event_id));
+        true
+    }
+
+    /// Drop apps whose heartbeats stopped, announcing each with `ServiceLeft`
+    pub fn expire_services(&mut self) -> Vec<super::Event> {
+        let left: Vec<super::Event> = self
+            .registry
+            .remove_stale_services(self.service_timeout)
+            .into_iter()
+            .map(|service| super::Event::service_left(service.app_id, service.process_id))
+            .collect();
+        for event in &left {
+            let _ = self.publish_event(event.clone());
+        }
+        left
+    }
+
+    /// How long an app may stay silent before it counts as gone
+    pub fn set_service_timeout(&mut self, timeout: Duration) {
+        self.service_timeout = timeout;
+    }
+
+    /// Every app instance currently heard from
+    pub fn services(&self) -> Vec<&ServiceInfo> {
+        self.registry.list_services()
+    }
+
+    /// The most recently heard instance of an app
+    pub fn service(&self, app_id: AppId) -> Option<&ServiceInfo> {
+        self.registry.get_service(app_id)
+    }
+
+    /// Every running instance of an app
+    pub fn instances(&self, app_id: AppId) -> Vec<&ServiceInfo> {
+        self.registry.instances(app_id)
+    }
+
+    pub fn is_connected(&self, app_id: AppId) -> bool {
+        self.registry.get_service(app_id).is_some()
+    }
+
+    pub fn registry(&self) -> &ServiceRegistry {
+        &self.registry
+    }
+
     /// Publish an event via the managed publisher
     pub fn publish_event(&self, event: super::Event) -> IpcResult<()> {
         if let Some(ref publisher) = self.publisher {
@@ -271,6 +475,9 @@ impl Clone for IpcServiceManager {
             registry: ServiceRegistry::new(), // New registry for relay
             heartbeat_interval: self.heartbeat_interval,
             last_heartbeat: self.last_heartbeat,
+            service_timeout: self.service_timeout,
+            capabilities: self.capabilities.clone(),
+            answered_hellos: VecDeque::new(),
             is_active: Arc::clone(&self.is_active),
         }
     }
@@ -284,3 +491,160 @@ impl Drop for IpcServiceManager {
 
 /// Global reference to the main IpcServiceManager for static publishing
 pub static IPC_SERVICE_MANAGER: OnceLock<IpcServiceManager> = OnceLock::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{Event, EMIDI_EVENTS_SERVICE, PROTOCOL_VERSION};
+
+    fn service(app_id: AppId, process_id: u32, last_heartbeat: u64) -> ServiceInfo {
+        ServiceInfo {
+            app_id,
+            service_name: EMIDI_EVENTS_SERVICE.to_string(),
+            version: String::new(),
+            capabilities: Vec::new(),
+            last_heartbeat,
+            process_id,
+            protocol_version: 0,
+        }
+    }
+
+    #[test]
+    fn instances_of_one_app_are_kept_per_process() {
+        let mut registry = ServiceRegistry::new();
+        assert!(registry.observe_heartbeat(AppId::EMidi, 200).is_some());
+        assert!(registry.observe_heartbeat(AppId::EMidi, 100).is_some());
+        assert!(registry.observe_heartbeat(AppId::EGrid, 300).is_some());
+        assert!(registry.observe_heartbeat(AppId::EMidi, 200).is_none());
+
+        let pids: Vec<u32> = registry
+            .instances(AppId::EMidi)
+            .iter()
+            .map(|s| s.process_id)
+            .collect();
+        assert_eq!(pids, vec![100, 200]);
+        assert_eq!(registry.list_services().len(), 3);
+    }
+
+    #[test]
+    fn get_service_returns_the_most_recent_instance() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_service(service(AppId::EMidi, 100, 50));
+        registry.register_service(service(AppId::EMidi, 200, 10));
+        assert_eq!(registry.get_service(AppId::EMidi).unwrap().process_id, 100);
+        assert!(registry.get_service(AppId::EGrid).is_none());
+    }
+
+    #[test]
+    fn heartbeat_without_process_id_refreshes_known_instances() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_service(service(AppId::EMidi, 100, 0));
+        registry.register_service(service(AppId::EMidi, 200, 0));
+
+        assert!(registry.observe_heartbeat(AppId::EMidi, 0).is_none());
+        let instances = registry.instances(AppId::EMidi);
+        assert_eq!(instances.len(), 2);
+        assert!(instances.iter().all(|s| s.last_heartbeat > 0));
+    }
+
+    #[test]
+    fn heartbeat_without_process_id_registers_a_new_app_once() {
+        let mut registry = ServiceRegistry::new();
+        assert!(registry.observe_heartbeat(AppId::EGrid, 0).is_some());
+        assert!(registry.observe_heartbeat(AppId::EGrid, 0).is_none());
+        assert_eq!(registry.instances(AppId::EGrid).len(), 1);
+
+        // Once the app reports its pid, the pid-less entry goes away
+        assert!(registry.observe_heartbeat(AppId::EGrid, 300).is_some());
+        let pids: Vec<u32> = registry
+            .instances(AppId::EGrid)
+            .iter()
+            .map(|s| s.process_id)
+            .collect();
+        assert_eq!(pids, vec![300]);
+    }
+
+    #[test]
+    fn peer_info_is_recorded_and_joins_once() {
+        let mut registry = ServiceRegistry::new();
+        let peer = PeerInfo {
+            app_id: AppId::EMidiCtl,
+            process_id: 400,
+            protocol_version: 3,
+            capabilities: vec!["notes".to_string()],
+        };
+        let joined = registry.observe_peer(&peer).unwrap();
+        assert_eq!(joined.protocol_version, 3);
+        assert_eq!(joined.capabilities, vec!["notes".to_string()]);
+        assert!(registry.observe_peer(&peer).is_none());
+
+        // A heartbeat-only instance learns its capabilities from a later handshake
+        registry.observe_heartbeat(AppId::EGrid, 500);
+        let peer = PeerInfo {
+            app_id: AppId::EGrid,
+            process_id: 500,
+            ..peer
+        };
+        assert!(registry.observe_peer(&peer).is_none());
+        assert_eq!(
+            registry.get_service(AppId::EGrid).unwrap().protocol_version,
+            3
+        );
+    }
+
+    #[test]
+    fn stale_instances_are_removed_individually() {
+        let mut registry = ServiceRegistry::new();
+        registry.register_service(service(AppId::EMidi, 100, 0));
+        registry.observe_heartbeat(AppId::EMidi, 200);
+
+        let removed = registry.remove_stale_services(Duration::from_secs(60));
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].process_id, 100);
+        assert!(registry.is_service_alive(AppId::EMidi, Duration::from_secs(60)));
+
+        let removed = registry.remove_stale_services(Duration::ZERO);
+        assert_eq!(removed.len(), 1);
+        assert!(!registry.is_service_alive(AppId::EMidi, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn deactivated_registry_ignores_services() {
+        let mut registry = ServiceRegistry::new();
+        registry.observe_heartbeat(AppId::EMidi, 100);
+        registry.deactivate();
+        assert!(registry.list_services().is_empty());
+        assert!(registry.observe_heartbeat(AppId::EGrid, 200).is_none());
+    }
+
+    #[test]
+    fn each_hello_is_answered_once() {
+        let mut manager = IpcServiceManager::new(AppId::EMidi).unwrap();
+        assert!(manager.first_hello(100, 1));
+        assert!(!manager.first_hello(100, 1));
+        assert!(manager.first_hello(200, 1));
+        assert!(manager.first_hello(100, 2));
+
+        for id in 10..10 + ANSWERED_HELLOS as u64 {
+            manager.first_hello(100, id);
+        }
+        assert_eq!(manager.answered_hellos.len(), ANSWERED_HELLOS);
+        assert!(manager.first_hello(100, 1));
+    }
+
+    #[test]
+    fn repeated_hello_joins_the_sender_once() {
+        let mut manager = IpcServiceManager::new(AppId::EMidi).unwrap();
+        let hello = Event::Hello {
+            app_id: AppId::EGrid,
+            process_id: std::process::id() + 1,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+            timestamp: 7,
+        };
+        assert!(manager.observe_event(&hello).is_some());
+        assert!(manager.observe_event(&hello).is_none());
+        assert_eq!(manager.answered_hellos.len(), 1);
+        assert_eq!(manager.instances(AppId::EGrid).len(), 1);
+    }
+}
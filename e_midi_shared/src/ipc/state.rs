@@ -0,0 +1,412 @@
+//! Request/response state synchronization
+//!
+//! A late-joining app publishes `StateRequest` and collects the matching
+//! `StateResponse` events. The request's event id is echoed back as
+//! `request_id`, so answers are matched to requests even when several apps
+//! ask at once, and data larger than one IPC payload is split into numbered
+//! parts that the requester reassembles.
+//!
+//! Apps that own state register one provider per `StateType` with
+//! `StateProviders`; requesters track pending requests and timeouts with
+//! `StateRequests`.
+
+use super::{
+    AppId, Event, EventId, EventPublisher, EventSubscriber, IpcError, IpcResult, StateType,
+    MAX_PAYLOAD_SIZE,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a requester waits for an answer by default
+pub const DEFAULT_STATE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// State bytes per response: JSON spells each byte with up to 4 characters,
+/// and the rest of the event needs some room
+pub const STATE_CHUNK_SIZE: usize = (MAX_PAYLOAD_SIZE - 256) / 4;
+
+/// Produces the serialized state for one `StateType` from a context `C`
+pub type StateProvider<C> = Box<dyn FnMut(&C) -> Result<Vec<u8>, String> + Send>;
+
+/// Split state data into the `StateResponse` events that carry it
+pub fn state_responses(request_id: EventId, state_type: StateType, data: &[u8]) -> Vec<Event> {
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(STATE_CHUNK_SIZE).collect()
+    };
+    let parts = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(part, chunk)| Event::StateResponse {
+            state_type: state_type.clone(),
+            data: chunk.to_vec(),
+            timestamp: super::generate_event_id(),
+            request_id,
+            part: part as u32,
+            parts,
+        })
+        .collect()
+}
+
+/// State handlers registered by the app that owns the state
+pub struct StateProviders<C: ?Sized> {
+    providers: Vec<(StateType, StateProvider<C>)>,
+}
+
+impl<C: ?Sized> Default for StateProviders<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: ?Sized> StateProviders<C> {
+    pub fn new() -> Self {
+        Self {
+            providers: Vec::new(),
+        }
+    }
+
+    /// Register the handler for a state type, replacing any earlier one
+    pub fn register<F>(&mut self, state_type: StateType, provider: F)
+    where
+        F: FnMut(&C) -> Result<Vec<u8>, String> + Send + 'static,
+    {
+        self.providers.retain(|(t, _)| *t != state_type);
+        self.providers.push((state_type, Box::new(provider)));
+    }
+
+    /// Register a handler whose value is sent as JSON
+    pub fn register_json<T, F>(&mut self, state_type: StateType, mut provider: F)
+    where
+        T: Serialize,
+        F: FnMut(&C) -> T + Send + 'static,
+    {
+        self.register(state_type, move |context| {
+            serde_json::to_vec(&provider(context)).map_err(|e| e.to_string())
+        });
+    }
+
+    /// Whether a request for this state type would be answered
+    pub fn provides(&self, state_type: &StateType) -> bool {
+        match state_type {
+            StateType::AllStates => !self.providers.is_empty(),
+            _ => self.providers.iter().any(|(t, _)| t == state_type),
+        }
+    }
+
+    /// Build the responses to a `StateRequest`; other events give none
+    ///
+    /// `AllStates` is answered with one response stream per registered type.
+    pub fn respond(&mut self, context: &C, event: &Event) -> Vec<Event> {
+        let Event::StateRequest {
+            state_type,
+            timestamp,
+            ..
+        } = event
+        else {
+            return Vec::new();
+        };
+        let mut responses = Vec::new();
+        for (provided, provider) in self.providers.iter_mut() {
+            if *state_type != StateType::AllStates && provided != state_type {
+                continue;
+            }
+            match provider(context) {
+                Ok(data) => responses.extend(state_responses(*timestamp, provided.clone(), &data)),
+                Err(e) => eprintln!("[IPC STATE ERROR] {:?} provider failed: {}", provided, e),
+            }
+        }
+        responses
+    }
+}
+
+/// A complete answer to a state request
+#[derive(Debug, Clone)]
+pub struct StateReply {
+    pub request_id: EventId,
+    pub state_type: StateType,
+    pub data: Vec<u8>,
+}
+
+impl StateReply {
+    /// Decode JSON state, e.g. `MidiPlaybackState` or `Vec<IpcSongInfo>`
+    pub fn decode<T: DeserializeOwned>(&self) -> IpcResult<T> {
+        serde_json::from_slice(&self.data).map_err(|e| {
+            IpcError::DeserializationError(format!("{:?} state: {}", self.state_type, e))
+        })
+    }
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    state_type: StateType,
+    sent: Instant,
+    /// Parts received so far, per answered state type
+    parts: HashMap<StateType, Vec<Option<Vec<u8>>>>,
+}
+
+/// Outstanding state requests of one app
+#[derive(Debug)]
+pub struct StateRequests {
+    app_id: AppId,
+    timeout: Duration,
+    pending: HashMap<EventId, PendingRequest>,
+}
+
+impl StateRequests {
+    pub fn new(app_id: AppId) -> Self {
+        Self::with_timeout(app_id, DEFAULT_STATE_TIMEOUT)
+    }
+
+    pub fn with_timeout(app_id: AppId, timeout: Duration) -> Self {
+        Self {
+            app_id,
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Create a request event to publish and start waiting for its answer
+    pub fn request(&mut self, state_type: StateType) -> Event {
+        let mut event = Event::state_request(self.app_id, state_type.clone());
+        if let Event::StateRequest { timestamp, .. } = &mut event {
+            // Ids come from the clock; keep them unique among our own requests
+            while self.pending.contains_key(timestamp) {
+                *timestamp += 1;
+            }
+            self.pending.insert(
+                *timestamp,
+                PendingRequest {
+                    state_type,
+                    sent: Instant::now(),
+                    parts: HashMap::new(),
+                },
+            );
+        }
+        event
+    }
+
+    /// Feed a received event; returns a reply once all its parts are in
+    ///
+    /// A request for one state type is finished by its first complete reply.
+    /// An `AllStates` request keeps collecting until it times out.
+    pub fn handle(&mut self, event: &Event) -> Option<StateReply> {
+        let Event::StateResponse {
+            state_type,
+            data,
+            request_id,
+            part,
+            parts,
+            ..
+        } = event
+        else {
+            return None;
+        };
+        let pending = self.pending.get_mut(request_id)?;
+        if pending.state_type != StateType::AllStates && pending.state_type != *state_type {
+            return None;
+        }
+        let (part, parts) = (*part as usize, (*parts).max(1) as usize);
+        if part >= parts {
+            return None;
+        }
+        let slots = pending
+            .parts
+            .entry(state_type.clone())
+            .or_insert_with(|| vec![None; parts]);
+        if slots.len() != parts {
+            // A second responder split its answer differently; start over
+            *slots = vec![None; parts];
+        }
+        slots[part] = Some(data.clone());
+        if slots.iter().any(Option::is_none) {
+            return None;
+        }
+        let data = pending
+            .parts
+            .remove(state_type)?
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        if pending.state_type != StateType::AllStates {
+            self.pending.remove(request_id);
+        }
+        Some(StateReply {
+            request_id: *request_id,
+            state_type: state_type.clone(),
+            data,
+        })
+    }
+
+    /// Drop requests older than the timeout, returning their ids and types
+    pub fn expire(&mut self) -> Vec<(EventId, StateType)> {
+        let timeout = self.timeout;
+        let expired: Vec<EventId> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.sent.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id).map(|p| (id, p.state_type)))
+            .collect()
+    }
+
+    pub fn is_pending(&self, request_id: EventId) -> bool {
+        self.pending.contains_key(&request_id)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Request state and wait for the answer
+///
+/// Other events received while waiting are discarded. A single state type
+/// returns its first complete reply or a timeout error; `AllStates` returns
+/// whatever arrived before the timeout.
+pub fn request_state(
+    publisher: &EventPublisher,
+    subscriber: &mut EventSubscriber,
+    app_id: AppId,
+    state_type: StateType,
+    timeout: Duration,
+) -> IpcResult<Vec<StateReply>> {
+    let mut requests = StateRequests::with_timeout(app_id, timeout);
+    let all = state_type == StateType::AllStates;
+    publisher.publish(requests.request(state_type.clone()))?;
+    let mut replies = Vec::new();
+    while requests.pending_count() > 0 {
+        for event in subscriber.try_receive()? {
+            if let Some(reply) = requests.handle(&event) {
+                replies.push(reply);
+            }
+        }
+        if !all && !replies.is_empty() {
+            break;
+        }
+        if !requests.expire().is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    if replies.is_empty() && !all {
+        return Err(IpcError::ReceiveError(format!(
+            "Timed out waiting for {:?} state",
+            state_type
+        )));
+    }
+    Ok(replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_id(event: &Event) -> EventId {
+        match event {
+            Event::StateRequest { timestamp, .. } => *timestamp,
+            other => panic!("not a state request: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parts_arriving_out_of_order_are_reassembled() {
+        let mut requests = StateRequests::new(AppId::EGrid);
+        let id = request_id(&requests.request(StateType::MidiSongList));
+        let data: Vec<u8> = (0..2 * STATE_CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let responses = state_responses(id, StateType::MidiSongList, &data);
+        assert_eq!(responses.len(), 3);
+
+        assert!(requests.handle(&responses[2]).is_none());
+        assert!(requests.handle(&responses[0]).is_none());
+        // A repeated part doesn't complete the reply
+        assert!(requests.handle(&responses[0]).is_none());
+        let reply = requests.handle(&responses[1]).expect("all parts are in");
+        assert_eq!(reply.request_id, id);
+        assert_eq!(reply.state_type, StateType::MidiSongList);
+        assert_eq!(reply.data, data);
+        assert!(!requests.is_pending(id));
+    }
+
+    #[test]
+    fn all_states_is_answered_for_every_registered_type() {
+        let mut providers: StateProviders<u32> = StateProviders::new();
+        providers.register_json(StateType::MidiPlayback, |position: &u32| *position);
+        providers.register_json(StateType::MidiSongList, |_: &u32| vec!["a", "b"]);
+        assert!(providers.provides(&StateType::AllStates));
+        assert!(!providers.provides(&StateType::WindowStates));
+
+        let mut requests = StateRequests::new(AppId::EGrid);
+        let request = requests.request(StateType::AllStates);
+        let responses = providers.respond(&1234, &request);
+        assert_eq!(responses.len(), 2);
+
+        let replies: Vec<StateReply> = responses
+            .iter()
+            .filter_map(|r| requests.handle(r))
+            .collect();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].state_type, StateType::MidiPlayback);
+        assert_eq!(replies[0].decode::<u32>().unwrap(), 1234);
+        assert_eq!(replies[1].state_type, StateType::MidiSongList);
+        assert_eq!(
+            replies[1].decode::<Vec<String>>().unwrap(),
+            vec!["a".to_string(), "b".to_string()]
+        );
+        // AllStates keeps listening for other owners until it times out
+        assert!(requests.is_pending(request_id(&request)));
+
+        // A single type gets only its own provider's answer
+        let request = requests.request(StateType::MidiPlayback);
+        let responses = providers.respond(&1, &request);
+        assert_eq!(responses.len(), 1);
+        assert!(requests.handle(&responses[0]).is_some());
+        assert!(!requests.is_pending(request_id(&request)));
+    }
+
+    #[test]
+    fn responses_to_other_requests_are_ignored() {
+        let mut requests = StateRequests::new(AppId::EGrid);
+        let id = request_id(&requests.request(StateType::MidiPlayback));
+
+        // Another app's request, or no request at all
+        for foreign in [id + 1, 0] {
+            let response = &state_responses(foreign, StateType::MidiPlayback, b"{}")[0];
+            assert!(requests.handle(response).is_none());
+        }
+        // The right request id but a different state type
+        let response = &state_responses(id, StateType::MidiSongList, b"[]")[0];
+        assert!(requests.handle(response).is_none());
+        // Not a response
+        assert!(requests
+            .handle(&Event::state_request(AppId::EMidi, StateType::MidiPlayback))
+            .is_none());
+        assert!(requests.is_pending(id));
+
+        let response = &state_responses(id, StateType::MidiPlayback, b"{}")[0];
+        assert_eq!(requests.handle(response).unwrap().data, b"{}".to_vec());
+    }
+
+    #[test]
+    fn timed_out_requests_expire() {
+        let mut requests = StateRequests::with_timeout(AppId::EGrid, Duration::from_secs(3600));
+        requests.request(StateType::MidiPlayback);
+        assert!(requests.expire().is_empty());
+        assert_eq!(requests.pending_count(), 1);
+
+        let mut requests = StateRequests::with_timeout(AppId::EGrid, Duration::ZERO);
+        let id = request_id(&requests.request(StateType::MidiSongList));
+        assert_eq!(requests.expire(), vec![(id, StateType::MidiSongList)]);
+        assert_eq!(requests.pending_count(), 0);
+        // A late answer to an expired request is dropped
+        let response = &state_responses(id, StateType::MidiSongList, b"[]")[0];
+        assert!(requests.handle(response).is_none());
+    }
+}
@@ -7,7 +7,9 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::{AppId, Event, IpcError, IpcPayload, IpcResult};
+use super::{
+    decode_payload, payload_sender, AppId, Event, IpcError, IpcPayload, IpcResult, PROTOCOL_VERSION,
+};
 
 /// Lock-free event subscriber
 ///
@@ -18,13 +20,15 @@ pub struct EventSubscriber {
     is_active: Arc<AtomicBool>,
     //event_buffer: VecDeque<Event>,
     last_heartbeat: Instant,
+    /// Newest protocol version received so far
+    newest_version: u16,
 }
 
 impl EventSubscriber {
     /// Create a new event subscriber for events from the specified app
     pub fn new(source_app: AppId, subscriber_app: AppId) -> IpcResult<Self> {
         let service_name = "e_midi_events".to_string();
-        println!("[IPC SUBSCRIBER DEBUG] Using service name: {} (source_app: {:?}, subscriber_app: {:?})", service_name, source_app, subscriber_app);
+        eprintln!("[IPC SUBSCRIBER DEBUG] Using service name: {} (source_app: {:?}, subscriber_app: {:?})", service_name, source_app, subscriber_app);
 
         // Create node (suppress debug output)
         let node = NodeBuilder::new().create::<ipc::Service>().map_err(|e| {
@@ -67,42 +71,52 @@ impl EventSubscriber {
             is_active: Arc::new(AtomicBool::new(true)),
             //event_buffer: VecDeque::new(),
             last_heartbeat: Instant::now(),
+            newest_version: 0,
         })
     }
 
     /// Try to receive events (non-blocking, lock-free)
     pub fn try_receive(&mut self) -> IpcResult<Vec<Event>> {
+        Ok(self
+            .try_receive_with_sender()?
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect())
+    }
+
+    /// Like `try_receive`, with the app that published each event
+    ///
+    /// The sender is None for publishers older than protocol version 2.
+    pub fn try_receive_with_sender(&mut self) -> IpcResult<Vec<(Option<AppId>, Event)>> {
         if !self.is_active.load(Ordering::Relaxed) {
             return Ok(Vec::new());
         }
 
         let mut events = Vec::new(); // Check for new samples
-        let mut sample_count = 0;
         while let Some(sample) = self
             .subscriber
             .receive()
             .map_err(|_| IpcError::ReceiveError("Failed to receive".to_string()))?
         {
-            sample_count += 1;
-            println!("[IPC SUBSCRIBER DEBUG] Received sample #{}", sample_count);
-            // Deserialize the received data
-            let json_data = sample.payload();
-
-            // Try to deserialize as single event first
-            if let Ok(event) = serde_json::from_slice::<Event>(json_data) {
-                events.push(event);
-            } else if let Ok(batch) = serde_json::from_slice::<Vec<Event>>(json_data) {
-                // If single event fails, try as batch
-                events.extend(batch);
-            } else {
+            // Events this build doesn't know arrive as `Event::Unknown`
+            let (version, received) = decode_payload(sample.payload()).map_err(|e| {
                 eprintln!(
-                    "[IPC SUBSCRIBER ERROR] Failed to deserialize event data: {:?}",
-                    json_data
+                    "[IPC SUBSCRIBER ERROR] Failed to deserialize event data: {}",
+                    e
                 );
-                return Err(IpcError::DeserializationError(
-                    "Failed to deserialize event data".to_string(),
-                ));
+                e
+            })?;
+            if version > self.newest_version {
+                if version > PROTOCOL_VERSION {
+                    eprintln!(
+                        "[IPC SUBSCRIBER] Receiving protocol v{} (this build speaks v{}); newer events pass through as Event::Unknown",
+                        version, PROTOCOL_VERSION
+                    );
+                }
+                self.newest_version = version;
             }
+            let sender = payload_sender(sample.payload());
+            events.extend(received.into_iter().map(|event| (sender, event)));
         }
 
         // Update heartbeat tracking
@@ -150,6 +164,11 @@ impl EventSubscriber {
         self.time_since_last_event() < timeout
     }
 
+    /// Newest protocol version seen on the service (0 before any event)
+    pub fn newest_protocol_version(&self) -> u16 {
+        self.newest_version
+    }
+
     /// Get the app ID this subscriber represents
     pub fn app_id(&self) -> AppId {
         self.app_id
@@ -216,7 +235,8 @@ impl EventFilter {
                     | Event::MidiCommandNext { .. }
                     | Event::MidiCommandPrevious { .. }
                     | Event::MidiCommandSetTempo { .. }
-                    | Event::MidiCommandSongListRequest { .. } => self.midi_events,
+                    | Event::MidiCommandSongListRequest { .. }
+                    | Event::MidiCommandLoadSong { .. } => self.midi_events,
 
                     // MIDI status events (player to TUI)
                     Event::MidiPlaybackStarted { .. }
@@ -226,7 +246,8 @@ impl EventFilter {
                     | Event::MidiTempoChanged { .. }
                     | Event::MidiSongChanged { .. }
                     | Event::MidiProgressUpdate { .. }
-                    | Event::MidiSongListUpdated { .. } => self.midi_events,
+                    | Event::MidiSongListUpdated { .. }
+                    | Event::MidiBeat { .. } => self.midi_events,
 
                     Event::WindowFocused { .. }
                     | Event::WindowClosed { .. }
@@ -238,8 +259,15 @@ impl EventFilter {
 
                     Event::SystemShutdown { .. }
                     | Event::SystemHeartbeat { .. }
+                    | Event::ServiceJoined { .. }
+                    | Event::ServiceLeft { .. }
+                    | Event::Hello { .. }
+                    | Event::Welcome { .. }
+                    | Event::Unknown { .. }
                     | Event::StateRequest { .. }
-                    | Event::StateResponse { .. } => self.system_events,
+                    | Event::StateResponse { .. }
+                    | Event::MessageChunk { .. }
+                    | Event::MessageChunkAck { .. } => self.system_events,
 
                     // Additional MIDI events
                     Event::MidiNoteOn { .. }
@@ -26,6 +26,8 @@ pub enum AppId {
     EGrid,
     StateServer,
     Demo05, // Added for event listener demo
+    EMidiCtl,
+    EMidiSession,
     #[default]
     Unknown,
 }
@@ -137,7 +139,9 @@ pub enum IpcError {
     PayloadTooLarge(String),
 }
 
-/// Convert serializable data to IPC payload
+/// Convert serializable data to an unversioned IPC payload
+///
+/// Events go through `encode_event`, which adds the protocol header.
 pub fn serialize_to_payload<T: serde::Serialize>(data: &T) -> IpcResult<IpcPayload> {
     let json_bytes = serde_json::to_vec(data)
         .map_err(|e| IpcError::SerializationError(format!("Serialization failed: {:?}", e)))?;
@@ -156,18 +160,24 @@ pub fn serialize_to_payload<T: serde::Serialize>(data: &T) -> IpcResult<IpcPaylo
 }
 
 /// Extract size from IPC payload and deserialize
+///
+/// Versioned payloads (see `protocol`) carry their own body length, so `size`
+/// only applies to unversioned ones.
 pub fn deserialize_from_payload<T: serde::de::DeserializeOwned>(
     payload: &IpcPayload,
     size: usize,
 ) -> IpcResult<T> {
-    if size > MAX_PAYLOAD_SIZE {
-        return Err(IpcError::DeserializationError(format!(
-            "Payload size {} exceeds maximum {}",
-            size, MAX_PAYLOAD_SIZE
-        )));
-    }
-
-    let json_bytes = &payload[..size];
+    let json_bytes = if payload.starts_with(&crate::ipc::PAYLOAD_MAGIC) {
+        crate::ipc::split_payload(payload)?.1
+    } else {
+        if size > MAX_PAYLOAD_SIZE {
+            return Err(IpcError::DeserializationError(format!(
+                "Payload size {} exceeds maximum {}",
+                size, MAX_PAYLOAD_SIZE
+            )));
+        }
+        &payload[..size]
+    };
     serde_json::from_slice(json_bytes)
         .map_err(|e| IpcError::DeserializationError(format!("Deserialization failed: {:?}", e)))
 }
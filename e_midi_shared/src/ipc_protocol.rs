@@ -1,7 +1,10 @@
 //! C-compatible, zero-copy IPC protocol definitions for e_midi
 //!
 //! This module defines the PlaySongAtHeartbeat and TrackVoiceOverride structs
-//! for robust, synchronized, multi-client MIDI playback.
+//! for robust, synchronized, multi-client MIDI playback, and the ClockHeartbeat
+//! that numbers the heartbeats they refer to. MidiMessageEvent carries every
+//! outgoing MIDI message for visualizers and light controllers, MidiBeatEvent
+//! the song's beats and bars.
 //!
 //! All structs are #[repr(C)] and use only fixed-size, ABI-stable types.
 use iceoryx2::prelude::*;
@@ -19,7 +22,7 @@ pub struct MidiNoteEvent {
 #[repr(C)]
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, ZeroCopySend)]
 pub struct TrackVoiceOverride {
-    /// Track index (0-based)
+    /// Track index (0-based position in the song's track list)
     pub track_index: u8,
     /// MIDI voice/program number (0-127)
     pub voice: u8,
@@ -32,7 +35,8 @@ pub struct TrackVoiceOverride {
 pub struct PlaySongAtHeartbeat {
     /// Song index to play
     pub song_index: u16,
-    /// Start at this heartbeat (global sync)
+    /// Start at this heartbeat (global sync; 0 = only change the stop of
+    /// the current song, or stop now if `stop_heartbeat` is 0 too)
     pub start_heartbeat: u32,
     /// Stop at this heartbeat (optional, 0 = ignore)
     pub stop_heartbeat: u32,
@@ -46,6 +50,177 @@ pub struct PlaySongAtHeartbeat {
     pub track_overrides: [TrackVoiceOverride; 16],
 }
 
+/// No song or track for a `MidiMessageEvent` (live input, direct sends)
+pub const MIDI_MESSAGE_NONE: u16 = u16::MAX;
+
+/// Any outgoing MIDI message of up to three bytes, with its song context
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, ZeroCopySend)]
+pub struct MidiMessageEvent {
+    /// Per-process counter, one higher for every published message; a gap
+    /// means the subscriber dropped samples
+    pub sequence: u64,
+    /// Wall clock when sent, microseconds since the UNIX epoch
+    pub timestamp_us: u64,
+    /// Position in the song when sent, in ms
+    pub song_time_ms: u32,
+    /// Process id of the publisher (sequences are counted per process)
+    pub source_pid: u32,
+    /// Song index, or MIDI_MESSAGE_NONE
+    pub song_index: u16,
+    /// Track the message came from (SMF track, or MusicXML part), or
+    /// MIDI_MESSAGE_NONE for messages that belong to no single track
+    pub track: u16,
+    /// Status byte, channel in the low nibble for channel messages
+    pub status: u8,
+    pub data1: u8,
+    pub data2: u8,
+    /// Valid bytes among status/data1/data2 (1-3)
+    pub len: u8,
+}
+
+/// What a `MidiMessageEvent` carries, decoded from its status byte
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessageKind {
+    NoteOff {
+        pitch: u8,
+    },
+    NoteOn {
+        pitch: u8,
+        velocity: u8,
+    },
+    PolyPressure {
+        pitch: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        program: u8,
+    },
+    ChannelPressure {
+        pressure: u8,
+    },
+    /// -8192..=8191, 0 is centered
+    PitchBend {
+        value: i16,
+    },
+    /// System common and realtime (MTC quarter frames, clock, ...)
+    System,
+}
+
+impl MidiMessageEvent {
+    /// Wrap raw MIDI bytes; `None` unless they are one message of 1-3 bytes
+    pub fn from_bytes(msg: &[u8]) -> Option<Self> {
+        if msg.is_empty() || msg.len() > 3 || msg[0] < 0x80 || msg[0] == 0xF0 {
+            return None;
+        }
+        Some(Self {
+            status: msg[0],
+            data1: msg.get(1).copied().unwrap_or(0),
+            data2: msg.get(2).copied().unwrap_or(0),
+            len: msg.len() as u8,
+            song_index: MIDI_MESSAGE_NONE,
+            track: MIDI_MESSAGE_NONE,
+            ..Self::default()
+        })
+    }
+
+    /// The raw MIDI bytes
+    pub fn bytes(&self) -> Vec<u8> {
+        let len = (self.len as usize).min(3);
+        [self.status, self.data1, self.data2][..len].to_vec()
+    }
+
+    /// Channel (0-15) of a channel message
+    pub fn channel(&self) -> Option<u8> {
+        (self.status < 0xF0).then_some(self.status & 0x0F)
+    }
+
+    pub fn song_index(&self) -> Option<usize> {
+        (self.song_index != MIDI_MESSAGE_NONE).then_some(self.song_index as usize)
+    }
+
+    pub fn track(&self) -> Option<u16> {
+        (self.track != MIDI_MESSAGE_NONE).then_some(self.track)
+    }
+
+    pub fn kind(&self) -> MidiMessageKind {
+        let (d1, d2) = (self.data1, self.data2);
+        match self.status & 0xF0 {
+            0x80 => MidiMessageKind::NoteOff { pitch: d1 },
+            // Note-on with velocity 0 is a note-off
+            0x90 if d2 == 0 => MidiMessageKind::NoteOff { pitch: d1 },
+            0x90 => MidiMessageKind::NoteOn {
+                pitch: d1,
+                velocity: d2,
+            },
+            0xA0 => MidiMessageKind::PolyPressure {
+                pitch: d1,
+                pressure: d2,
+            },
+            0xB0 => MidiMessageKind::ControlChange {
+                controller: d1,
+                value: d2,
+            },
+            0xC0 => MidiMessageKind::ProgramChange { program: d1 },
+            0xD0 => MidiMessageKind::ChannelPressure { pressure: d1 },
+            0xE0 => MidiMessageKind::PitchBend {
+                value: (((d2 as i16) << 7) | d1 as i16) - 8192,
+            },
+            _ => MidiMessageKind::System,
+        }
+    }
+}
+
+/// A beat of the playing song
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, ZeroCopySend)]
+pub struct MidiBeatEvent {
+    /// Wall clock at the beat, microseconds since the UNIX epoch
+    pub timestamp_us: u64,
+    /// Song position of the beat in ms
+    pub position_ms: u32,
+    /// Bar number, counting from 1
+    pub bar: u32,
+    pub song_index: u16,
+    /// Beat within the bar, counting from 1
+    pub beat: u8,
+    /// Time signature numerator
+    pub beats_per_bar: u8,
+    /// Time signature denominator (4 = quarter-note beats)
+    pub beat_unit: u8,
+    /// Reserved for alignment/future use
+    pub _reserved: u8,
+    /// Playback tempo in quarter notes per minute
+    pub tempo_bpm: u16,
+}
+
+impl MidiBeatEvent {
+    /// First beat of a bar
+    pub fn is_downbeat(&self) -> bool {
+        self.beat == 1
+    }
+}
+
+/// One tick of the shared heartbeat clock
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, ZeroCopySend)]
+pub struct ClockHeartbeat {
+    /// Heartbeat number, counting from 1 when the clock master started
+    pub beat: u32,
+    /// Time between heartbeats in microseconds
+    pub interval_us: u32,
+    /// Master's wall clock at this heartbeat, microseconds since the UNIX epoch
+    pub master_time_us: u64,
+    /// Process id of the clock master
+    pub master_pid: u32,
+    /// Reserved for alignment/future use
+    pub _reserved: [u8; 4],
+}
+
 /// SAFETY: These helpers allow zero-copy conversion between the struct and a byte array.
 /// The struct must be #[repr(C)] and contain no pointers or references.
 impl PlaySongAtHeartbeat {
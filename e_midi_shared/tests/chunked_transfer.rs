@@ -0,0 +1,148 @@
+//! Reassembly and integrity checks of chunked messages.
+
+use e_midi_shared::ipc::{
+    crc32, encode_event, message_chunks, ChunkAssembler, Event, CHUNK_SIZE, MAX_CHUNKED_SIZE,
+    TOPIC_SONG,
+};
+
+fn message(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+/// `missing` and `error` of an ack
+fn ack(event: Option<Event>) -> (Vec<u32>, Option<String>) {
+    match event {
+        Some(Event::MessageChunkAck { missing, error, .. }) => (missing, error),
+        other => panic!("expected an ack, got {:?}", other),
+    }
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn chunks_fit_in_a_payload() {
+    let data = vec![255u8; CHUNK_SIZE * 2 + 1];
+    let chunks = message_chunks(u64::MAX, TOPIC_SONG, &data);
+    assert_eq!(chunks.len(), 3);
+    for chunk in &chunks {
+        encode_event(chunk).unwrap();
+    }
+}
+
+#[test]
+fn out_of_order_and_duplicate_chunks() {
+    let data = message(CHUNK_SIZE * 3 + 10);
+    let mut chunks = message_chunks(7, TOPIC_SONG, &data);
+    chunks.swap(0, 2);
+    let mut assembler = ChunkAssembler::new();
+    assert!(assembler.handle(&chunks[0]).is_none());
+    assert!(assembler.handle(&chunks[0]).is_none());
+    assert!(assembler.handle(&chunks[1]).is_none());
+    assert!(assembler.handle(&chunks[2]).is_none());
+    assert!(assembler.is_pending(7));
+    assert_eq!(ack(assembler.handle(&chunks[3])), (vec![], None));
+    // A resent chunk is acknowledged again
+    assert_eq!(ack(assembler.handle(&chunks[1])), (vec![], None));
+    let received = assembler.take(7).unwrap();
+    assert_eq!(received.topic, TOPIC_SONG);
+    assert_eq!(received.data, data);
+    assert!(assembler.take(7).is_none());
+}
+
+#[test]
+fn missing_parts_are_reported_after_the_last() {
+    let chunks = message_chunks(1, TOPIC_SONG, &message(CHUNK_SIZE * 4));
+    let mut assembler = ChunkAssembler::new();
+    assembler.handle(&chunks[0]);
+    assembler.handle(&chunks[2]);
+    assert_eq!(ack(assembler.handle(&chunks[3])), (vec![1], None));
+    assert_eq!(ack(assembler.handle(&chunks[1])), (vec![], None));
+}
+
+#[test]
+fn empty_message() {
+    let chunks = message_chunks(2, "empty", &[]);
+    let mut assembler = ChunkAssembler::new();
+    assert_eq!(ack(assembler.handle(&chunks[0])), (vec![], None));
+    assert!(assembler.take(2).unwrap().data.is_empty());
+}
+
+#[test]
+fn corrupted_message_is_rejected() {
+    let mut chunks = message_chunks(3, TOPIC_SONG, &message(CHUNK_SIZE + 1));
+    if let Event::MessageChunk { data, .. } = &mut chunks[0] {
+        data[0] ^= 1;
+    }
+    let mut assembler = ChunkAssembler::new();
+    assembler.handle(&chunks[0]);
+    let (_, error) = ack(assembler.handle(&chunks[1]));
+    assert_eq!(error.as_deref(), Some("Checksum mismatch"));
+    assert!(assembler.take(3).is_none());
+    assert!(!assembler.is_pending(3));
+}
+
+#[test]
+fn inconsistent_chunks_are_rejected() {
+    let data = message(CHUNK_SIZE * 2);
+    let chunks = message_chunks(4, TOPIC_SONG, &data);
+    let other = message_chunks(4, TOPIC_SONG, &data[1..]);
+    let mut assembler = ChunkAssembler::new();
+    assembler.handle(&chunks[0]);
+    assert!(ack(assembler.handle(&other[1])).1.is_some());
+    assert!(!assembler.is_pending(4));
+}
+
+#[test]
+fn oversized_and_malformed_chunks_are_rejected() {
+    let chunk = |part, parts, total_len| Event::MessageChunk {
+        transfer_id: 5,
+        topic: TOPIC_SONG.to_string(),
+        part,
+        parts,
+        total_len,
+        checksum: 0,
+        data: vec![0; 4],
+        timestamp: 0,
+    };
+    let mut assembler = ChunkAssembler::new();
+    for event in [
+        chunk(0, 1, MAX_CHUNKED_SIZE as u64 + 1),
+        chunk(2, 2, 8),
+        chunk(0, 0, 8),
+        chunk(0, 100, 8),
+        chunk(0, 1, 2),
+        chunk(0, 2, 8),
+    ] {
+        assert!(ack(assembler.handle(&event)).1.is_some(), "{:?}", event);
+    }
+    assert!(!assembler.is_pending(5));
+}
+
+#[test]
+fn forged_part_count_is_rejected_before_allocating() {
+    let forged = Event::MessageChunk {
+        transfer_id: 6,
+        topic: TOPIC_SONG.to_string(),
+        part: 0,
+        parts: MAX_CHUNKED_SIZE as u32,
+        total_len: MAX_CHUNKED_SIZE as u64,
+        checksum: 0,
+        data: vec![0; 4],
+        timestamp: 0,
+    };
+    let mut assembler = ChunkAssembler::new();
+    assert!(ack(assembler.handle(&forged)).1.is_some());
+    assert!(!assembler.is_pending(6));
+
+    // The genuine split of the same length is still accepted
+    let chunks = message_chunks(6, TOPIC_SONG, &message(CHUNK_SIZE * 2 + 1));
+    if let Event::MessageChunk { parts, .. } = &chunks[0] {
+        assert_eq!(*parts as usize, (CHUNK_SIZE * 2 + 1).div_ceil(CHUNK_SIZE));
+    }
+    assert!(assembler.handle(&chunks[0]).is_none());
+    assert!(assembler.is_pending(6));
+}
@@ -0,0 +1,222 @@
+//! Pins the event wire format, so a change that would break apps built from
+//! other versions shows up here first.
+
+use e_midi_shared::ipc::{
+    decode_event, decode_payload, encode_event, encode_events, payload_sender, set_payload_sender,
+    split_payload, AppId, Event, IpcError, MAX_BODY_SIZE, MAX_PAYLOAD_SIZE, PAYLOAD_HEADER_SIZE,
+    PROTOCOL_VERSION,
+};
+
+fn play(song_index: usize) -> Event {
+    Event::MidiCommandPlay {
+        song_index,
+        timestamp: 42,
+    }
+}
+
+#[test]
+fn header_layout() {
+    let payload = encode_event(&play(3)).unwrap();
+    let body = br#"{"MidiCommandPlay":{"song_index":3,"timestamp":42}}"#;
+    assert_eq!(PROTOCOL_VERSION, 2);
+    assert_eq!(&payload[..2], b"EM");
+    assert_eq!(&payload[2..4], &[2, 0]);
+    assert_eq!(&payload[4..8], &(body.len() as u32).to_le_bytes());
+    assert_eq!(&payload[8..8 + body.len()], body);
+    assert!(payload[8 + body.len()..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn sender_follows_the_body() {
+    let mut payload = encode_event(&play(3)).unwrap();
+    assert_eq!(payload_sender(&payload), None);
+    assert!(set_payload_sender(&mut payload, AppId::EMidiCtl).unwrap());
+    let body = br#"{"MidiCommandPlay":{"song_index":3,"timestamp":42}}"#;
+    let trailer = br#""EMidiCtl""#;
+    let at = 8 + body.len();
+    assert_eq!(&payload[at..at + trailer.len()], trailer);
+    assert!(payload[at + trailer.len()..].iter().all(|&b| b == 0));
+    assert_eq!(payload_sender(&payload), Some(AppId::EMidiCtl));
+    // Readers that stop at the body length are unaffected
+    assert_eq!(split_payload(&payload).unwrap().1, body);
+    let (_, events) = decode_payload(&payload).unwrap();
+    assert!(matches!(
+        events[..],
+        [Event::MidiCommandPlay { song_index: 3, .. }]
+    ));
+}
+
+#[test]
+fn version_1_payloads_have_no_sender() {
+    let mut payload = encode_event(&play(3)).unwrap();
+    assert!(set_payload_sender(&mut payload, AppId::EGrid).unwrap());
+    payload[2..4].copy_from_slice(&1u16.to_le_bytes());
+    assert_eq!(payload_sender(&payload), None);
+}
+
+#[test]
+fn event_json_is_externally_tagged() {
+    let cases = [
+        (
+            play(3),
+            r#"{"MidiCommandPlay":{"song_index":3,"timestamp":42}}"#,
+        ),
+        (
+            Event::MidiCommandStop { timestamp: 7 },
+            r#"{"MidiCommandStop":{"timestamp":7}}"#,
+        ),
+        (
+            Event::MidiPlaybackStarted {
+                song_index: 1,
+                song_name: "Tetris".to_string(),
+                timestamp: 9,
+            },
+            r#"{"MidiPlaybackStarted":{"song_index":1,"song_name":"Tetris","timestamp":9}}"#,
+        ),
+        (
+            Event::SystemHeartbeat {
+                app_id: AppId::EGrid,
+                timestamp: 5,
+                process_id: 77,
+            },
+            r#"{"SystemHeartbeat":{"app_id":"EGrid","timestamp":5,"process_id":77}}"#,
+        ),
+        (
+            Event::Hello {
+                app_id: AppId::EMidi,
+                process_id: 12,
+                protocol_version: 1,
+                capabilities: vec!["midi.commands".to_string()],
+                timestamp: 3,
+            },
+            r#"{"Hello":{"app_id":"EMidi","process_id":12,"protocol_version":1,"capabilities":["midi.commands"],"timestamp":3}}"#,
+        ),
+    ];
+    for (event, json) in cases {
+        let payload = encode_event(&event).unwrap();
+        let (version, body) = split_payload(&payload).unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+        assert_eq!(std::str::from_utf8(body).unwrap(), json);
+    }
+}
+
+#[test]
+fn round_trip() {
+    let (version, events) = decode_payload(&encode_event(&play(4)).unwrap()).unwrap();
+    assert_eq!(version, PROTOCOL_VERSION);
+    assert!(matches!(
+        events[..],
+        [Event::MidiCommandPlay {
+            song_index: 4,
+            timestamp: 42
+        }]
+    ));
+}
+
+#[test]
+fn batch_is_a_json_array() {
+    let payload = encode_events(&[play(1), Event::MidiCommandStop { timestamp: 2 }]).unwrap();
+    let (_, body) = split_payload(&payload).unwrap();
+    assert_eq!(
+        std::str::from_utf8(body).unwrap(),
+        r#"[{"MidiCommandPlay":{"song_index":1,"timestamp":42}},{"MidiCommandStop":{"timestamp":2}}]"#
+    );
+    let (_, events) = decode_payload(&payload).unwrap();
+    assert_eq!(events.len(), 2);
+}
+
+#[test]
+fn version_0_payloads_still_decode() {
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    let json = br#"{"SystemHeartbeat":{"app_id":"EMidi","timestamp":1}}"#;
+    payload[..json.len()].copy_from_slice(json);
+    let (version, events) = decode_payload(&payload).unwrap();
+    assert_eq!(version, 0);
+    // Fields added since default
+    assert!(matches!(
+        events[..],
+        [Event::SystemHeartbeat {
+            app_id: AppId::EMidi,
+            timestamp: 1,
+            process_id: 0
+        }]
+    ));
+}
+
+#[test]
+fn unknown_variant_keeps_its_json() {
+    let json = r#"{"MidiLyric":{"text":"la","timestamp":99}}"#;
+    match decode_event(json.as_bytes()).unwrap() {
+        Event::Unknown {
+            variant,
+            raw,
+            timestamp,
+        } => {
+            assert_eq!(variant, "MidiLyric");
+            assert_eq!(raw, json);
+            assert_eq!(timestamp, 99);
+        }
+        other => panic!("expected Unknown, got {:?}", other),
+    }
+    assert!(matches!(
+        decode_event(br#""SomethingNew""#).unwrap(),
+        Event::Unknown { variant, timestamp: 0, .. } if variant == "SomethingNew"
+    ));
+}
+
+#[test]
+fn unknown_events_are_republished_unchanged() {
+    let json = r#"{"MidiLyric":{"text":"la","timestamp":99}}"#;
+    let unknown = decode_event(json.as_bytes()).unwrap();
+    let payload = encode_event(&unknown).unwrap();
+    let (_, body) = split_payload(&payload).unwrap();
+    assert_eq!(body, json.as_bytes());
+}
+
+#[test]
+fn unknown_fields_are_ignored() {
+    let json = br#"{"MidiCommandStop":{"timestamp":8,"reason":"user"}}"#;
+    assert!(matches!(
+        decode_event(json).unwrap(),
+        Event::MidiCommandStop { timestamp: 8 }
+    ));
+}
+
+#[test]
+fn batch_with_unknown_events() {
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    let json = br#"[{"MidiCommandStop":{"timestamp":1}},{"MidiLyric":{"text":"la"}}]"#;
+    payload[..2].copy_from_slice(b"EM");
+    payload[2..4].copy_from_slice(&2u16.to_le_bytes());
+    payload[4..8].copy_from_slice(&(json.len() as u32).to_le_bytes());
+    payload[8..8 + json.len()].copy_from_slice(json);
+    let (version, events) = decode_payload(&payload).unwrap();
+    assert_eq!(version, 2);
+    assert!(matches!(events[0], Event::MidiCommandStop { timestamp: 1 }));
+    assert!(matches!(&events[1], Event::Unknown { variant, .. } if variant == "MidiLyric"));
+}
+
+#[test]
+fn malformed_payloads_are_errors() {
+    assert!(decode_event(b"not json").is_err());
+    // Not an enum value
+    assert!(decode_event(b"[1,2]").is_err());
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    payload[..2].copy_from_slice(b"EM");
+    payload[4..8].copy_from_slice(&(MAX_PAYLOAD_SIZE as u32).to_le_bytes());
+    assert!(split_payload(&payload).is_err());
+}
+
+#[test]
+fn body_size_limit() {
+    assert_eq!(PAYLOAD_HEADER_SIZE, 8);
+    let event = Event::MidiPlaybackStarted {
+        song_index: 0,
+        song_name: "x".repeat(MAX_BODY_SIZE),
+        timestamp: 0,
+    };
+    assert!(matches!(
+        encode_event(&event),
+        Err(IpcError::PayloadTooLarge(_))
+    ));
+}